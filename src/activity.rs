@@ -0,0 +1,176 @@
+use crate::analysis::{peak_dbfs, AnalysisFrame};
+
+/// How much each measure counts towards the score. They add
+/// up to 1, so the score stays in `0..=1`.
+pub const FLUX_WEIGHT: f32 = 0.5;
+pub const RMS_WEIGHT: f32 = 0.3;
+pub const SPREAD_WEIGHT: f32 = 0.2;
+
+/// Frames quieter than this score 0, a mic's noise floor
+/// changes from frame to frame as much as anything does
+pub const ACTIVITY_FLOOR_DBFS: f32 = -60.0;
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>()
+        / samples.len() as f32)
+        .sqrt()
+}
+
+/// How much of the spectrum changed since `previous`, 0 for
+/// the same spectrum and 1 when nothing lines up at all
+pub fn spectral_change(
+    previous: &[f32],
+    current: &[f32],
+) -> f32 {
+    let total: f32 = previous.iter().sum::<f32>()
+        + current.iter().sum::<f32>();
+    if previous.len() != current.len() || total <= 0.0 {
+        return 0.0;
+    }
+    let difference: f32 = previous
+        .iter()
+        .zip(current)
+        .map(|(before, now)| (now - before).abs())
+        .sum();
+    (difference / total).min(1.0)
+}
+
+/// How evenly the energy is spread over the bands: 0 when
+/// it's all in one, 1 when every band has the same. This is
+/// the bands' entropy over the most they could have.
+pub fn band_spread(bands: &[f32]) -> f32 {
+    let total: f32 =
+        bands.iter().map(|band| band.max(0.0)).sum();
+    if bands.len() < 2 || total <= 0.0 {
+        return 0.0;
+    }
+    let entropy: f32 = bands
+        .iter()
+        .map(|band| band.max(0.0) / total)
+        .filter(|p| *p > 0.0)
+        .map(|p| -p * p.ln())
+        .sum();
+    (entropy / (bands.len() as f32).ln()).clamp(0.0, 1.0)
+}
+
+/// A single 0 to 1 "how much is happening" score for
+/// `frame`, given the frame before it.
+///
+/// Combines how much the spectrum changed (weighted by
+/// [`FLUX_WEIGHT`]), how much the rms level changed relative
+/// to the louder of the two ([`RMS_WEIGHT`]) and how widely
+/// the energy is spread over the bands ([`SPREAD_WEIGHT`]).
+/// A held tone scores near 0, speech or music that keeps
+/// changing scores high. Frames under
+/// [`ACTIVITY_FLOOR_DBFS`] score 0.
+pub fn activity_score(
+    frame: &AnalysisFrame,
+    prev: &AnalysisFrame,
+) -> f32 {
+    if peak_dbfs(&frame.samples) < ACTIVITY_FLOOR_DBFS {
+        return 0.0;
+    }
+    let flux =
+        spectral_change(&prev.spectrum, &frame.spectrum);
+    let (before, now) =
+        (rms(&prev.samples), rms(&frame.samples));
+    let rms_change = if before.max(now) > 0.0 {
+        (now - before).abs() / before.max(now)
+    } else {
+        0.0
+    };
+    let spread = band_spread(&frame.bands);
+    (FLUX_WEIGHT * flux
+        + RMS_WEIGHT * rms_change
+        + SPREAD_WEIGHT * spread)
+        .clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::analysis::{default_bands, Analyzer};
+    use std::sync::{Arc, Mutex};
+
+    /// Scores for each frame after the first of `buffers`
+    fn scores(buffers: &[Vec<f32>]) -> Vec<f32> {
+        let bands = default_bands();
+        let gains =
+            Arc::new(Mutex::new(vec![1.0; bands.len()]));
+        let mut analyzer =
+            Analyzer::new(48000, 1024, bands, gains);
+        let frames: Vec<AnalysisFrame> = buffers
+            .iter()
+            .map(|buffer| analyzer.process(buffer))
+            .collect();
+        frames
+            .windows(2)
+            .map(|pair| activity_score(&pair[1], &pair[0]))
+            .collect()
+    }
+
+    /// 1024 samples of a sine at `hz` and `amplitude`,
+    /// starting `offset` samples in
+    fn tone(
+        hz: f32,
+        amplitude: f32,
+        offset: usize,
+    ) -> Vec<f32> {
+        (offset..offset + 1024)
+            .map(|n| {
+                amplitude
+                    * (std::f32::consts::TAU
+                        * hz
+                        * n as f32
+                        / 48000.0)
+                        .sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_held_tone_is_calm_and_changing_content_is_busy() {
+        // 1031.25Hz is a whole number of cycles per buffer
+        let held: Vec<Vec<f32>> = (0..8)
+            .map(|i| tone(1031.25, 0.5, i * 1024))
+            .collect();
+        for score in scores(&held) {
+            assert!(
+                score < 0.1,
+                "held tone scored {score}"
+            );
+        }
+
+        let changing: Vec<Vec<f32>> = [
+            (100.0, 0.9),
+            (3000.0, 0.1),
+            (8000.0, 0.6),
+            (400.0, 0.05),
+            (12000.0, 0.8),
+            (60.0, 0.2),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (hz, amplitude))| {
+            tone(*hz, *amplitude, i * 1024)
+        })
+        .collect();
+        for score in scores(&changing) {
+            assert!(
+                score > 0.5,
+                "changing content scored {score}"
+            );
+        }
+
+        // and silence, changing or not, is nothing
+        let quiet = vec![
+            tone(100.0, 1e-4, 0),
+            tone(9000.0, 1e-4, 0),
+        ];
+        assert_eq!(scores(&quiet), [0.0]);
+    }
+}
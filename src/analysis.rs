@@ -0,0 +1,1943 @@
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    activity::activity_score,
+    denoise::SharedNoiseProfile,
+    gate::NoiseGate,
+    multires::{MultiResAnalyzer, MultiResolution},
+    profile::{lap, SharedProfile},
+    zoom::{ZoomAnalyzer, ZoomBand, ZoomSpectrum},
+};
+
+/// The fft size used unless configured otherwise
+pub const FFT_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreqRange {
+    pub name: Cow<'static, str>,
+    pub low: usize,
+    pub high: usize,
+}
+
+pub const BASS: FreqRange = FreqRange {
+    name: Cow::Borrowed("Bass"),
+    low: 20,
+    high: 140,
+};
+pub const LOW_MID: FreqRange = FreqRange {
+    name: Cow::Borrowed("Low Mid"),
+    low: 140,
+    high: 400,
+};
+pub const MID: FreqRange = FreqRange {
+    name: Cow::Borrowed("Mid"),
+    low: 400,
+    high: 2600,
+};
+pub const HIGH_MID: FreqRange = FreqRange {
+    name: Cow::Borrowed("High Mid"),
+    low: 2600,
+    high: 5200,
+};
+pub const TREBLE: FreqRange = FreqRange {
+    name: Cow::Borrowed("Treble"),
+    low: 5200,
+    high: 14000,
+};
+
+pub fn default_bands() -> Vec<FreqRange> {
+    vec![BASS, LOW_MID, MID, HIGH_MID, TREBLE]
+}
+
+/// Named band layouts for `--bands-preset`
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum BandPreset {
+    /// fundamental, warmth, presence and sibilance of a
+    /// speaking voice
+    Voice,
+    /// bass, low mid, mid, high mid and treble
+    #[default]
+    #[value(name = "music-5")]
+    Music5,
+    /// the ten ISO 266 octave bands from 31.5Hz to 16kHz
+    #[value(name = "octave-10")]
+    Octave10,
+    /// the thirty ISO 266 third-octave bands from 25Hz to
+    /// 20kHz
+    ThirdOctave,
+}
+
+// nominal center frequencies, which is what they're called
+// on every graphic eq even though the exact centers differ
+const OCTAVE_NAMES: [&str; 10] = [
+    "31.5", "63", "125", "250", "500", "1k", "2k", "4k",
+    "8k", "16k",
+];
+const THIRD_OCTAVE_NAMES: [&str; 30] = [
+    "25", "31.5", "40", "50", "63", "80", "100", "125",
+    "160", "200", "250", "315", "400", "500", "630", "800",
+    "1k", "1.25k", "1.6k", "2k", "2.5k", "3.15k", "4k",
+    "5k", "6.3k", "8k", "10k", "12.5k", "16k", "20k",
+];
+
+impl BandPreset {
+    pub fn bands(self) -> Vec<FreqRange> {
+        match self {
+            BandPreset::Voice => vec![
+                FreqRange {
+                    name: Cow::Borrowed("Fundamental"),
+                    low: 85,
+                    high: 255,
+                },
+                FreqRange {
+                    name: Cow::Borrowed("Warmth"),
+                    low: 255,
+                    high: 1000,
+                },
+                FreqRange {
+                    name: Cow::Borrowed("Presence"),
+                    low: 1000,
+                    high: 4000,
+                },
+                FreqRange {
+                    name: Cow::Borrowed("Sibilance"),
+                    low: 4000,
+                    high: 8000,
+                },
+            ],
+            BandPreset::Music5 => default_bands(),
+            BandPreset::Octave10 => {
+                iso_bands(&OCTAVE_NAMES, -15, 3)
+            }
+            BandPreset::ThirdOctave => {
+                iso_bands(&THIRD_OCTAVE_NAMES, -16, 1)
+            }
+        }
+    }
+}
+
+/// ISO 266 base-10 bands: the nth band is centered on
+/// 1000 * 10^(n/10) Hz, so every third band is an octave.
+/// `first` is the index of the first band and `step` how
+/// many tenths of a decade apart bands are, 3 for octaves
+/// and 1 for third octaves.
+pub fn iso_bands(
+    names: &[&'static str],
+    first: i32,
+    step: i32,
+) -> Vec<FreqRange> {
+    let half_width = 10f32.powf(step as f32 / 20.0);
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let n = first + i as i32 * step;
+            let center =
+                1000.0 * 10f32.powf(n as f32 / 10.0);
+            FreqRange {
+                name: Cow::Borrowed(name),
+                low: (center / half_width).round() as usize,
+                high: (center * half_width).round()
+                    as usize,
+            }
+        })
+        .collect()
+}
+
+/// How the bins in a band are combined into one value
+///
+/// Bins are evenly spaced in Hz, so once a band spans many
+/// bins the two agree. They come apart for narrow bands,
+/// where rounding the edges to whole bins can make a band
+/// cover twice its width or more. The mean doesn't care
+/// how much of the spectrum the bins cover. Density spreads
+/// the total over the band's nominal width, so narrow bands
+/// with a lot of extra bin coverage don't read hot next to
+/// wide ones.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum BandWeighting {
+    /// average magnitude of the bins in the band
+    #[default]
+    Mean,
+    /// total magnitude per Hz of the band's nominal width,
+    /// scaled by the bin width so it reads on the same
+    /// scale as the mean
+    Density,
+}
+
+/// What a band adds up its bins as
+///
+/// Summing magnitudes undercounts a band whose energy sits
+/// in a few bins: a lone peak among n bins averages to 1/n
+/// of its magnitude, where its share of the band's energy
+/// is 1/n of its power, i.e. 1/√n of its magnitude. Power
+/// sums what's really there, and taking the square root of
+/// the result keeps bands on the magnitude scale either way,
+/// so gains, thresholds and the meters read the same for a
+/// band that's flat across its bins. The spectrum itself is
+/// always magnitudes, its views are in dB where the two
+/// agree.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum SpectrumType {
+    /// each bin's magnitude
+    #[default]
+    Magnitude,
+    /// each bin's magnitude squared, with the square root
+    /// taken of the band's result
+    Power,
+}
+
+impl SpectrumType {
+    /// `spectrum`, a spectrum of magnitudes, as the values a
+    /// band adds up
+    pub fn bins(self, spectrum: &[f32]) -> Cow<'_, [f32]> {
+        match self {
+            SpectrumType::Magnitude => {
+                Cow::Borrowed(spectrum)
+            }
+            SpectrumType::Power => Cow::Owned(
+                spectrum
+                    .iter()
+                    .map(|bin| bin * bin)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// A band added up from `bins`, back on the magnitude
+    /// scale
+    pub fn band_level(self, band: f32) -> f32 {
+        match self {
+            SpectrumType::Magnitude => band,
+            SpectrumType::Power => band.max(0.0).sqrt(),
+        }
+    }
+}
+
+/// How the spectrum of real input gets computed
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum FftBackend {
+    /// a complex fft of the whole buffer with every
+    /// imaginary part zero, half of which mirrors the other
+    /// half and is thrown away
+    #[default]
+    Complex,
+    /// pairs of samples packed into one complex fft of half
+    /// the size and unpacked into just the non-mirrored
+    /// half, for about half the work. IQ input, and odd fft
+    /// sizes, always take the complex path.
+    Real,
+}
+
+/// A real input fft of size n done as a complex fft of n/2,
+/// with even samples as the real parts and odd samples as
+/// the imaginary parts
+struct RealFft {
+    fft: Arc<dyn Fft<f32>>,
+    buffer: Vec<Complex<f32>>,
+    /// e^(-2πik/n) for k in 0..n/2
+    twiddles: Vec<Complex<f32>>,
+}
+
+impl RealFft {
+    fn new(fft_size: usize) -> Self {
+        let half = fft_size / 2;
+        let fft = FftPlanner::new().plan_fft_forward(half);
+        let twiddles = (0..half)
+            .map(|k| {
+                Complex::from_polar(
+                    1.0,
+                    -std::f32::consts::TAU * k as f32
+                        / fft_size as f32,
+                )
+            })
+            .collect();
+        RealFft {
+            fft,
+            buffer: vec![Complex::new(0.0, 0.0); half],
+            twiddles,
+        }
+    }
+
+    /// Magnitudes of bins 0 through n/2 of `input`, n
+    /// samples whose imaginary parts are ignored
+    fn magnitudes(
+        &mut self,
+        input: &[Complex<f32>],
+    ) -> Vec<f32> {
+        let half = self.buffer.len();
+        for (slot, pair) in self
+            .buffer
+            .iter_mut()
+            .zip(input.chunks_exact(2))
+        {
+            *slot = Complex::new(pair[0].re, pair[1].re);
+        }
+        self.fft.process(&mut self.buffer);
+        // untangle the spectra of the even and odd samples,
+        // then combine them like one radix-2 butterfly. Bin
+        // k pairs with bin n/2 - k, and dc with nyquist.
+        let dc = self.buffer[0];
+        let mut magnitudes = Vec::with_capacity(half + 1);
+        magnitudes.push((dc.re + dc.im).abs());
+        magnitudes.extend(
+            self.buffer[1..]
+                .iter()
+                .zip(self.buffer[1..].iter().rev())
+                .zip(&self.twiddles[1..half])
+                .map(|((z, mirror), twiddle)| {
+                    let mirror = mirror.conj();
+                    let even = (z + mirror) * 0.5;
+                    let odd = (z - mirror)
+                        * Complex::new(0.0, -0.5);
+                    (even + twiddle * odd).norm()
+                }),
+        );
+        magnitudes.push((dc.re - dc.im).abs());
+        magnitudes
+    }
+}
+
+/// How input samples are turned into fft input
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum InputMode {
+    /// ordinary audio, every sample real. The spectrum is
+    /// 0Hz to nyquist since the rest is a mirror image.
+    #[default]
+    Real,
+    /// interleaved in-phase and quadrature pairs, e.g. an
+    /// sdr on a stereo sound card. Positive and negative
+    /// frequencies differ so the spectrum covers -nyquist
+    /// to nyquist with 0Hz in the middle, and is fft_size
+    /// bins long rather than half that.
+    Iq,
+}
+
+/// Everything the analyzer produced for one input buffer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnalysisFrame {
+    /// the raw samples the frame was computed from,
+    /// interleaved if there's more than one channel
+    pub samples: Vec<f32>,
+    /// how many channels `samples` interleaves
+    pub channels: u16,
+    /// fft magnitude of each bin from 0Hz up to nyquist, or
+    /// from -nyquist in `InputMode::Iq`
+    pub spectrum: Vec<f32>,
+    /// average energy in each of the analyzer's bands, in
+    /// the same order as its `FreqRange`s
+    pub bands: Vec<f32>,
+    /// running total of samples that didn't fit in the fft
+    /// buffer and were left out of the analysis, or were
+    /// dropped on the way because something fell behind
+    pub dropped_samples: u64,
+    /// how the fft size and buffer size worked out for
+    /// this frame
+    pub stats: AnalysisStats,
+    /// how many sample frames (one per channel, or one IQ
+    /// pair) the analyzer saw before this frame's first
+    pub sample_index: u64,
+    /// input time at the start of the frame, counted in
+    /// samples rather than taken from the clock so it stays
+    /// in step with the audio however late frames arrive
+    pub timestamp: Duration,
+    /// how much is happening, 0 to 1, see
+    /// [`activity_score`]
+    pub activity: f32,
+    /// the zoomed in band, when the analyzer has one, see
+    /// `ZoomAnalyzer`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub zoom: Option<ZoomSpectrum>,
+}
+
+/// What an fft size and hop add up to, shown in the TUI's
+/// stats panel while tuning them
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+)]
+// sessions recorded before a field existed still load
+#[serde(default)]
+pub struct AnalysisStats {
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    /// spacing between bins
+    pub resolution_hz: f32,
+    /// noise equivalent bandwidth of one bin. The fft runs
+    /// without a window, and a rectangular window's is
+    /// exactly one bin.
+    pub bin_bandwidth_hz: f32,
+    /// how long each fft listens for
+    pub window_seconds: f32,
+    /// frames between the starts of consecutive ffts
+    pub hop: usize,
+    /// fraction of each fft's samples already seen by the
+    /// previous one
+    pub overlap: f32,
+    /// fraction of each fft that's zero padding
+    pub zero_padding: f32,
+    /// fraction of each hop skipped because it's longer
+    /// than the fft
+    pub dropped: f32,
+    /// ffts per second
+    pub update_hz: f32,
+}
+
+/// Stats for ffts of `fft_size` samples started every `hop`
+/// frames, the way `Analyzer` runs them: each fft takes at
+/// most `fft_size` fresh samples from its own buffer, so
+/// there's never any overlap. A shorter hop zero pads the
+/// fft instead and a longer one skips the excess.
+pub fn analysis_stats(
+    sample_rate: u32,
+    fft_size: usize,
+    hop: usize,
+) -> AnalysisStats {
+    let rate = sample_rate as f32;
+    let fft = fft_size.max(1) as f32;
+    let hop_len = hop.max(1) as f32;
+    let resolution_hz = rate / fft;
+    AnalysisStats {
+        sample_rate,
+        fft_size,
+        resolution_hz,
+        bin_bandwidth_hz: resolution_hz,
+        window_seconds: fft / rate.max(1.0),
+        hop,
+        overlap: 0.0,
+        zero_padding: (1.0 - hop_len / fft).max(0.0),
+        dropped: (1.0 - fft / hop_len).max(0.0),
+        update_hz: rate / hop_len,
+    }
+}
+
+/// Linear gain for each band, applied after aggregation.
+/// Shared so the UI can adjust it while the analyzer runs
+/// in the audio callback.
+pub type BandGains = Arc<Mutex<Vec<f32>>>;
+
+/// Scales each band by its gain. Bands without a matching
+/// gain are left alone.
+pub fn apply_band_gains(bands: &mut [f32], gains: &[f32]) {
+    for (band, gain) in bands.iter_mut().zip(gains) {
+        *band *= gain;
+    }
+}
+
+pub struct Analyzer {
+    fft: Arc<dyn Fft<f32>>,
+    /// set when `FftBackend::Real` is in use
+    real_fft: Option<RealFft>,
+    buffer: Vec<Complex<f32>>,
+    sample_rate: u32,
+    bands: Vec<FreqRange>,
+    /// spectrum bins covered by each band at `sample_rate`
+    band_bins: Vec<RangeInclusive<usize>>,
+    gains: BandGains,
+    dropped_samples: u64,
+    profile: Option<SharedProfile>,
+    noise_profile: Option<SharedNoiseProfile>,
+    weighting: BandWeighting,
+    spectrum_type: SpectrumType,
+    channels: u16,
+    input_mode: InputMode,
+    /// the blocker's pole and one state per channel
+    dc_block: Option<(f32, Vec<DcBlockState>)>,
+    gate: Option<NoiseGate>,
+    /// sample frames seen so far
+    sample_index: u64,
+    /// input time seen so far, accumulated per buffer so a
+    /// sample rate change doesn't make it jump
+    elapsed: Duration,
+    /// the last frame produced, for the activity score
+    previous: Option<AnalysisFrame>,
+    /// replaces the single fft for real input when set
+    multi_res: Option<MultiResAnalyzer>,
+    zoom: Option<ZoomAnalyzer>,
+    /// the newest fft's worth of input, oldest first, when
+    /// each buffer is a hop rather than a whole fft
+    window: Option<VecDeque<Complex<f32>>>,
+}
+
+impl Analyzer {
+    pub fn new(
+        sample_rate: u32,
+        fft_size: usize,
+        bands: Vec<FreqRange>,
+        gains: BandGains,
+    ) -> Self {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(fft_size);
+
+        let mut analyzer = Analyzer {
+            fft,
+            real_fft: None,
+            buffer: vec![
+                Complex {
+                    re: 0.0f32,
+                    im: 0.0f32
+                };
+                fft_size
+            ],
+            sample_rate,
+            bands,
+            band_bins: vec![],
+            gains,
+            dropped_samples: 0,
+            profile: None,
+            noise_profile: None,
+            weighting: BandWeighting::default(),
+            spectrum_type: SpectrumType::default(),
+            channels: 1,
+            input_mode: InputMode::default(),
+            dc_block: None,
+            gate: None,
+            sample_index: 0,
+            elapsed: Duration::ZERO,
+            previous: None,
+            multi_res: None,
+            zoom: None,
+            window: None,
+        };
+        analyzer.set_sample_rate(sample_rate);
+        analyzer
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Rebuilds everything that depends on the sample rate,
+    /// for devices that renegotiate it mid-stream. Input
+    /// buffered at a different rate is thrown away rather
+    /// than analyzed as if it were at the new one, while the
+    /// running counts (dropped samples, input time) carry on.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            if let Some(window) = &mut self.window {
+                window.iter_mut().for_each(|value| {
+                    *value = Complex::new(0.0, 0.0)
+                });
+            }
+            // bins at another rate are other frequencies
+            self.previous = None;
+        }
+        self.sample_rate = sample_rate;
+        if let Some(multi_res) = &mut self.multi_res {
+            multi_res.set_sample_rate(sample_rate);
+        }
+        if let Some(zoom) = &mut self.zoom {
+            zoom.set_sample_rate(sample_rate);
+        }
+        let spectrum_len = self.spectrum_fft_size() / 2 + 1;
+        self.band_bins = self
+            .bands
+            .iter()
+            .map(|range| {
+                bin_range(
+                    range,
+                    sample_rate / 2,
+                    spectrum_len,
+                )
+            })
+            .collect();
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The fft size the spectrum is laid out for: the
+    /// largest of a multi-resolution analysis, otherwise
+    /// `fft_size`
+    pub fn spectrum_fft_size(&self) -> usize {
+        match (&self.multi_res, self.input_mode) {
+            (Some(multi_res), InputMode::Real) => {
+                multi_res.resolution().largest()
+            }
+            _ => self.fft_size(),
+        }
+    }
+
+    pub fn band_bins(&self) -> &[RangeInclusive<usize>] {
+        &self.band_bins
+    }
+
+    /// Records how long each stage of `process` takes
+    pub fn with_profile(
+        mut self,
+        profile: SharedProfile,
+    ) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Subtracts `noise_profile` from every spectrum before
+    /// the bands are aggregated
+    pub fn with_noise_profile(
+        mut self,
+        noise_profile: SharedNoiseProfile,
+    ) -> Self {
+        self.noise_profile = Some(noise_profile);
+        self
+    }
+
+    pub fn with_weighting(
+        mut self,
+        weighting: BandWeighting,
+    ) -> Self {
+        self.weighting = weighting;
+        self
+    }
+
+    pub fn with_spectrum_type(
+        mut self,
+        spectrum_type: SpectrumType,
+    ) -> Self {
+        self.spectrum_type = spectrum_type;
+        self
+    }
+
+    /// How many channels the input interleaves, passed
+    /// along with each frame's samples
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn with_input_mode(
+        mut self,
+        input_mode: InputMode,
+    ) -> Self {
+        self.input_mode = input_mode;
+        self
+    }
+
+    pub fn with_fft_backend(
+        mut self,
+        backend: FftBackend,
+    ) -> Self {
+        let fft_size = self.fft_size();
+        self.real_fft = match backend {
+            FftBackend::Real
+                if fft_size >= 2
+                    && fft_size.is_multiple_of(2) =>
+            {
+                Some(RealFft::new(fft_size))
+            }
+            _ => None,
+        };
+        self
+    }
+
+    /// Analyzes real input with several fft sizes stitched
+    /// together instead of the single fft, see
+    /// `MultiResAnalyzer`. I/Q input keeps the single fft.
+    pub fn with_multi_resolution(
+        mut self,
+        resolution: MultiResolution,
+    ) -> Self {
+        self.multi_res = Some(MultiResAnalyzer::new(
+            resolution,
+            self.sample_rate,
+        ));
+        // the spectrum is laid out for the largest fft now
+        self.set_sample_rate(self.sample_rate);
+        self
+    }
+
+    /// Also looks closely at `band`, putting its spectrum in
+    /// each frame's `zoom`, see `ZoomAnalyzer`. I/Q input
+    /// doesn't get one.
+    pub fn with_zoom(mut self, band: ZoomBand) -> Self {
+        self.zoom =
+            Some(ZoomAnalyzer::new(band, self.sample_rate));
+        self
+    }
+
+    /// Runs the fft over the newest `fft_size` samples
+    /// rather than over each buffer by itself, so buffers
+    /// can be any size: each one is how far the window
+    /// moves along, and nothing past the fft size is
+    /// dropped. Starts out as silence.
+    pub fn with_sliding_window(mut self) -> Self {
+        self.window = Some(VecDeque::from(vec![
+            Complex::new(0.0, 0.0);
+            self.fft_size()
+        ]));
+        self
+    }
+
+    /// Runs every channel through `dc_block` with pole `r`
+    /// before anything else looks at the samples
+    pub fn with_dc_block(mut self, r: f32) -> Self {
+        self.dc_block = Some((r, vec![]));
+        self
+    }
+
+    /// Turns the input down through `gate` while it's
+    /// quiet, after any dc blocking. The frame's samples are
+    /// the gated ones, so the level meters show it too.
+    pub fn with_gate(mut self, gate: NoiseGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    pub fn bands(&self) -> &[FreqRange] {
+        &self.bands
+    }
+
+    /// Interleaved samples to each sample frame: one per
+    /// channel, or an I/Q pair
+    pub fn samples_per_frame(&self) -> usize {
+        match self.input_mode {
+            InputMode::Real => {
+                self.channels.max(1) as usize
+            }
+            InputMode::Iq => 2,
+        }
+    }
+
+    /// Moves the input clock on past `samples` interleaved
+    /// samples, returning where it was: the sample index
+    /// and time at their start
+    fn advance(
+        &mut self,
+        samples: usize,
+    ) -> (u64, Duration) {
+        let start = (self.sample_index, self.elapsed);
+        let frames = samples / self.samples_per_frame();
+        self.sample_index += frames as u64;
+        self.elapsed += Duration::from_secs_f64(
+            frames as f64 / self.sample_rate.max(1) as f64,
+        );
+        start
+    }
+
+    /// Lets `data` go by without analyzing it, for
+    /// decimation. The timestamps of later frames still
+    /// count it, and a multi-resolution ring buffer or a
+    /// zoom still takes it in so its long fft stays
+    /// continuous.
+    pub fn skip(&mut self, data: &[f32]) {
+        if let (Some(multi_res), InputMode::Real) =
+            (&mut self.multi_res, self.input_mode)
+        {
+            multi_res.push(data);
+        }
+        if let Some(window) = &mut self.window {
+            slide(window, data, self.input_mode);
+        }
+        if let (Some(zoom), InputMode::Real) =
+            (&mut self.zoom, self.input_mode)
+        {
+            zoom.push(data);
+        }
+        self.advance(data.len());
+    }
+
+    pub fn process(
+        &mut self,
+        data: &[f32],
+    ) -> AnalysisFrame {
+        let mut timer =
+            self.profile.as_ref().map(|_| Instant::now());
+
+        let data = match &mut self.dc_block {
+            Some((r, states)) => {
+                let channels =
+                    self.channels.max(1) as usize;
+                states.resize(
+                    channels,
+                    DcBlockState::default(),
+                );
+                let mut filtered = data.to_vec();
+                // filter each interleaved channel on its own
+                // so they don't bleed into each other
+                for (channel, state) in
+                    states.iter_mut().enumerate()
+                {
+                    let mut samples: Vec<f32> = filtered
+                        .iter()
+                        .skip(channel)
+                        .step_by(channels)
+                        .copied()
+                        .collect();
+                    dc_block(&mut samples, state, *r);
+                    for (slot, sample) in filtered
+                        .iter_mut()
+                        .skip(channel)
+                        .step_by(channels)
+                        .zip(samples)
+                    {
+                        *slot = sample;
+                    }
+                }
+                Cow::Owned(filtered)
+            }
+            None => Cow::Borrowed(data),
+        };
+        let data = match &mut self.gate {
+            Some(gate) => {
+                let mut gated = data.into_owned();
+                gate.process(&mut gated);
+                Cow::Owned(gated)
+            }
+            None => data,
+        };
+        let data = data.as_ref();
+
+        // devices are free to hand us bigger buffers than
+        // the fft, anything past the fft size gets counted
+        // and skipped rather than written out of bounds
+        let (used, consumed) = match (
+            &mut self.multi_res,
+            &mut self.window,
+            self.input_mode,
+        ) {
+            // the ring buffer takes everything and the
+            // single fft's buffer goes unused
+            (Some(multi_res), _, InputMode::Real) => {
+                multi_res.push(data);
+                (self.buffer.len(), data.len())
+            }
+            // and so does a sliding window, which is
+            // always full
+            (_, Some(window), input_mode) => {
+                slide(window, data, input_mode);
+                for (slot, value) in
+                    self.buffer.iter_mut().zip(&*window)
+                {
+                    *slot = *value;
+                }
+                (self.buffer.len(), data.len())
+            }
+            (None, None, InputMode::Real) => {
+                let used = data.len().min(self.fft_size());
+                for (slot, v) in self
+                    .buffer
+                    .iter_mut()
+                    .zip(&data[..used])
+                {
+                    *slot = Complex::new(*v, 0.0f32);
+                }
+                (used, used)
+            }
+            (_, None, InputMode::Iq) => {
+                let used =
+                    (data.len() / 2).min(self.fft_size());
+                for (slot, iq) in self
+                    .buffer
+                    .iter_mut()
+                    .zip(data.chunks_exact(2).take(used))
+                {
+                    *slot = Complex::new(iq[0], iq[1]);
+                }
+                (used, used * 2)
+            }
+        };
+        self.dropped_samples +=
+            (data.len() - consumed) as u64;
+        // and smaller buffers get zero padded instead of
+        // picking up the previous fft's output
+        for slot in &mut self.buffer[used..] {
+            *slot = Complex::new(0.0f32, 0.0f32);
+        }
+        let fill_time = lap(&mut timer);
+
+        let fft_time;
+        let mut results = match (
+            &self.multi_res,
+            &mut self.real_fft,
+            self.input_mode,
+        ) {
+            (Some(multi_res), _, InputMode::Real) => {
+                let results = multi_res.spectrum();
+                fft_time = lap(&mut timer);
+                results
+            }
+            (None, Some(real_fft), InputMode::Real) => {
+                let results =
+                    real_fft.magnitudes(&self.buffer);
+                fft_time = lap(&mut timer);
+                results
+            }
+            (_, _, input_mode) => {
+                self.fft.process(&mut self.buffer);
+                fft_time = lap(&mut timer);
+
+                let results = self
+                    .buffer
+                    .iter()
+                    .map(|v| v.norm())
+                    .collect::<Vec<f32>>();
+                match input_mode {
+                    InputMode::Real => {
+                        remove_mirroring(&results)
+                    }
+                    InputMode::Iq => fft_shift(&results),
+                }
+            }
+        };
+        if let Some(noise_profile) = &self.noise_profile {
+            noise_profile
+                .lock()
+                .unwrap()
+                .apply(&mut results);
+        }
+
+        let bin_hz = self.sample_rate as f32
+            / self.spectrum_fft_size() as f32;
+        // bands only look at positive frequencies, which
+        // start half way along a shifted iq spectrum
+        let band_bins: Vec<RangeInclusive<usize>> =
+            match self.input_mode {
+                InputMode::Real => self.band_bins.clone(),
+                InputMode::Iq => {
+                    let dc = self.fft_size() / 2;
+                    let last = results.len() - 1;
+                    self.band_bins
+                        .iter()
+                        .map(|bins| {
+                            (bins.start() + dc).min(last)
+                                ..=(bins.end() + dc)
+                                    .min(last)
+                        })
+                        .collect()
+                }
+            };
+        let summed = self.spectrum_type.bins(&results);
+        let mut bands = band_bins
+            .iter()
+            .zip(&self.bands)
+            .map(|(bins, range)| match self.weighting {
+                BandWeighting::Mean => {
+                    band_energy(&summed, bins.clone())
+                }
+                BandWeighting::Density => band_density(
+                    &summed,
+                    bins.clone(),
+                    bin_hz,
+                    range,
+                ),
+            })
+            .map(|band| self.spectrum_type.band_level(band))
+            .collect::<Vec<f32>>();
+        apply_band_gains(
+            &mut bands,
+            &self.gains.lock().unwrap(),
+        );
+        let bands_time = lap(&mut timer);
+
+        if let Some(profile) = &self.profile {
+            let mut profile = profile.lock().unwrap();
+            profile.fill.record(fill_time);
+            profile.fft.record(fft_time);
+            profile.bands.record(bands_time);
+        }
+
+        // both the hop and the clock count frames
+        let frames = data.len() / self.samples_per_frame();
+        let (sample_index, timestamp) =
+            self.advance(data.len());
+        let mut frame = AnalysisFrame {
+            samples: data.to_vec(),
+            channels: self.channels,
+            spectrum: results,
+            bands,
+            dropped_samples: self.dropped_samples,
+            stats: analysis_stats(
+                self.sample_rate,
+                self.spectrum_fft_size(),
+                frames,
+            ),
+            sample_index,
+            timestamp,
+            activity: 0.0,
+            zoom: match (&mut self.zoom, self.input_mode) {
+                (Some(zoom), InputMode::Real) => {
+                    Some(zoom.process(data))
+                }
+                _ => None,
+            },
+        };
+        if let Some(previous) = &self.previous {
+            frame.activity =
+                activity_score(&frame, previous);
+        }
+        self.previous = Some(frame.clone());
+        frame
+    }
+}
+
+/// Adds `data` to the end of a sliding fft window, as real
+/// samples or I/Q pairs, and forgets as much from the start
+/// so it stays the same length
+fn slide(
+    window: &mut VecDeque<Complex<f32>>,
+    data: &[f32],
+    input_mode: InputMode,
+) {
+    let len = window.len();
+    match input_mode {
+        InputMode::Real => window.extend(
+            data.iter().map(|v| Complex::new(*v, 0.0)),
+        ),
+        InputMode::Iq => window.extend(
+            data.chunks_exact(2)
+                .map(|iq| Complex::new(iq[0], iq[1])),
+        ),
+    }
+    let excess = window.len() - len;
+    window.drain(..excess);
+}
+
+/// The pole of the dc blocker unless configured otherwise.
+/// At 48kHz this puts the -3dB point around 40Hz.
+pub const DC_BLOCK_POLE: f32 = 0.995;
+
+/// What `dc_block` carries from one buffer to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DcBlockState {
+    prev_input: f32,
+    prev_output: f32,
+}
+
+/// First order dc blocking high-pass,
+/// `y[n] = x[n] - x[n-1] + r * y[n-1]`, filtering `samples`
+/// in place. `r` just under 1 (see `DC_BLOCK_POLE`) only
+/// takes out the very bottom of the spectrum, lower values
+/// cut higher.
+pub fn dc_block(
+    samples: &mut [f32],
+    state: &mut DcBlockState,
+    r: f32,
+) {
+    for sample in samples {
+        let input = *sample;
+        let output = input - state.prev_input
+            + r * state.prev_output;
+        state.prev_input = input;
+        state.prev_output = output;
+        *sample = output;
+    }
+}
+
+/// The spectrum bins that fall inside `range`.
+///
+/// `spectrum_len` is the length of the non-mirrored half of
+/// the fft output so its last bin sits at the nyquist
+/// frequency.
+pub fn bin_range(
+    range: &FreqRange,
+    nyquist: u32,
+    spectrum_len: usize,
+) -> RangeInclusive<usize> {
+    let last = spectrum_len.saturating_sub(1);
+    // var lowIndex = Math.round((frequency1 / nyquist) * this.freqDomain.length);
+    let low_index = ((range.low as f32 / nyquist as f32
+        * spectrum_len as f32)
+        .round() as usize)
+        .min(last);
+    // var highIndex = Math.round((frequency2 / nyquist) * this.freqDomain.length);
+    let high_index = ((range.high as f32 / nyquist as f32
+        * spectrum_len as f32)
+        .round() as usize)
+        .min(last);
+    low_index..=high_index
+}
+
+/// Average magnitude of the spectrum over `bins`
+pub fn band_energy(
+    spectrum: &[f32],
+    bins: RangeInclusive<usize>,
+) -> f32 {
+    let freq_slice = &spectrum[bins];
+    // add up all of the values for the frequencies and
+    // divide by total number of frequencies
+    let total = freq_slice.iter().sum::<f32>();
+    total / (freq_slice.len() as f32)
+}
+
+/// Total magnitude over `bins` per Hz of `range`'s width,
+/// in units of `bin_hz` so it reads on the same scale as
+/// `band_energy`
+pub fn band_density(
+    spectrum: &[f32],
+    bins: RangeInclusive<usize>,
+    bin_hz: f32,
+    range: &FreqRange,
+) -> f32 {
+    let total = spectrum[bins].iter().sum::<f32>();
+    let width_hz = (range.high.saturating_sub(range.low))
+        .max(1) as f32;
+    total * bin_hz / width_hz
+}
+
+/// Per-bin maximum of every spectrum seen since the last
+/// reset, decaying slowly so resonances that stop ringing
+/// eventually fade out of the trace.
+#[derive(Debug, Clone)]
+pub struct PeakHold {
+    levels: Vec<f32>,
+    /// multiplier applied to the held levels every frame
+    decay: f32,
+}
+
+impl PeakHold {
+    pub fn new(decay: f32) -> Self {
+        PeakHold {
+            levels: vec![],
+            decay,
+        }
+    }
+
+    pub fn update(&mut self, spectrum: &[f32]) {
+        if self.levels.len() != spectrum.len() {
+            self.levels = spectrum.to_vec();
+            return;
+        }
+        for (held, current) in
+            self.levels.iter_mut().zip(spectrum)
+        {
+            *held = (*held * self.decay).max(*current);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.levels.clear();
+    }
+
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+}
+
+/// How often the level has sat in each of a set of equal
+/// width bins, over the last `capacity` readings
+#[derive(Debug, Clone)]
+pub struct LevelHistogram {
+    /// (lowest, highest) level covered by the bins
+    range: (f32, f32),
+    counts: Vec<u32>,
+    /// the bin each reading in the window landed in, oldest
+    /// first
+    window: VecDeque<usize>,
+    capacity: usize,
+}
+
+impl LevelHistogram {
+    pub fn new(
+        range: (f32, f32),
+        bins: usize,
+        capacity: usize,
+    ) -> Self {
+        LevelHistogram {
+            range,
+            counts: vec![0; bins.max(1)],
+            window: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// The bin `level` falls in. Levels outside the range
+    /// pile up in the bin at that end, NaN in the lowest.
+    pub fn bin(&self, level: f32) -> usize {
+        let (low, high) = self.range;
+        let position = (level - low) / (high - low)
+            * self.counts.len() as f32;
+        if position.is_nan() {
+            return 0;
+        }
+        (position.max(0.0) as usize)
+            .min(self.counts.len() - 1)
+    }
+
+    /// Counts `level`, forgetting the oldest reading once
+    /// the window is full
+    pub fn push(&mut self, level: f32) {
+        if self.window.len() == self.capacity {
+            if let Some(oldest) = self.window.pop_front() {
+                self.counts[oldest] -= 1;
+            }
+        }
+        let bin = self.bin(level);
+        self.counts[bin] += 1;
+        self.window.push_back(bin);
+    }
+
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    /// Readings currently in the window
+    pub fn total(&self) -> usize {
+        self.window.len()
+    }
+
+    /// The lower and upper edge of bin `index`
+    pub fn edges(&self, index: usize) -> (f32, f32) {
+        let (low, high) = self.range;
+        let width = (high - low) / self.counts.len() as f32;
+        (
+            low + width * index as f32,
+            low + width * (index + 1) as f32,
+        )
+    }
+}
+
+/// The frequency of the loudest bin of `spectrum`, a real
+/// fft's magnitudes from 0Hz up, refined to between bins by
+/// fitting a parabola through it and its neighbours. The
+/// fit is on log magnitudes, which sit closer to a parabola
+/// around a peak than the magnitudes themselves. Bin 0 is
+/// left out, a dc offset isn't a tone. `None` for silence.
+pub fn peak_frequency(
+    spectrum: &[f32],
+    sample_rate: u32,
+    fft_size: usize,
+) -> Option<f32> {
+    let (peak, magnitude) = spectrum
+        .iter()
+        .enumerate()
+        .skip(1)
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if !(magnitude.is_finite() && *magnitude > 0.0) {
+        return None;
+    }
+    let offset = match (
+        spectrum.get(peak - 1),
+        spectrum.get(peak + 1),
+    ) {
+        (Some(below), Some(above)) => {
+            let [below, magnitude, above] =
+                [below, magnitude, above]
+                    .map(|m| m.max(f32::MIN_POSITIVE).ln());
+            let curve = below - 2.0 * magnitude + above;
+            if curve < 0.0 {
+                // the vertex, within half a bin either way
+                (0.5 * (below - above) / curve)
+                    .clamp(-0.5, 0.5)
+            } else {
+                0.0
+            }
+        }
+        _ => 0.0,
+    };
+    Some(
+        (peak as f32 + offset) * sample_rate as f32
+            / fft_size.max(1) as f32,
+    )
+}
+
+/// The loudest sample in a buffer, in dBFS.
+///
+/// Negative samples have no log so they read as -100.
+pub fn peak_dbfs(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .map(|sample| {
+            let value = 20.0 * sample.log10();
+            if value.is_nan() {
+                -100.0
+            } else {
+                value
+            }
+        })
+        .max_by(|x, y| x.total_cmp(y))
+        .unwrap_or(0.0)
+}
+
+/// The rms level of a buffer, in dBFS. Silence, or no
+/// samples at all, reads as -inf.
+pub fn rms_dbfs(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let mean_square = samples
+        .iter()
+        .map(|sample| sample * sample)
+        .sum::<f32>()
+        / samples.len() as f32;
+    10.0 * mean_square.log10()
+}
+
+/// The offset to add to dBFS readings so they read as dB
+/// SPL, given readings taken while a calibrator was playing
+/// `reference_spl` (typically 94 dB SPL) into the mic.
+///
+/// Readings are averaged in the dB domain. Silent buffers
+/// read as -inf and are ignored; `None` means there was
+/// nothing usable to average.
+pub fn calibration_offset(
+    reference_spl: f32,
+    measured_dbfs: &[f32],
+) -> Option<f32> {
+    let readings: Vec<f32> = measured_dbfs
+        .iter()
+        .copied()
+        .filter(|db| db.is_finite())
+        .collect();
+    if readings.is_empty() {
+        return None;
+    }
+    let mean = readings.iter().sum::<f32>()
+        / readings.len() as f32;
+    Some(reference_spl - mean)
+}
+
+/// Reorders a full fft output so it runs from -nyquist up
+/// to just below nyquist with 0Hz at index len / 2
+pub fn fft_shift(data: &[f32]) -> Vec<f32> {
+    let mut shifted = data.to_vec();
+    shifted.rotate_right(data.len() / 2);
+    shifted
+}
+
+// any data in the top "half" of the data vec is an alias
+// (aka a mirrored exact copy) of the bottom half
+// if you took bins 0..10 and 10..20 then data at each
+// index:
+// 0,1,2,3,4,5,6,7,8,9 == 19,18,17,16,15,14,13,12,11,10
+pub fn remove_mirroring(data: &[f32]) -> Vec<f32> {
+    let len = data.len() / 2 + 1;
+    data[..len].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An analyzer over the default bands
+    fn analyzer(
+        sample_rate: u32,
+        fft_size: usize,
+    ) -> Analyzer {
+        let bands = default_bands();
+        let gains =
+            Arc::new(Mutex::new(vec![1.0; bands.len()]));
+        Analyzer::new(sample_rate, fft_size, bands, gains)
+    }
+
+    /// `len` samples of a sine at `hz`
+    fn sine(
+        hz: f32,
+        sample_rate: u32,
+        len: usize,
+    ) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                (2.0 * std::f32::consts::PI * hz * i as f32
+                    / sample_rate as f32)
+                    .sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calibration_offset_turns_dbfs_into_spl() {
+        // a 94 dB SPL calibrator reading around -20 dBFS,
+        // with a silent buffer that has to be ignored
+        let offset = calibration_offset(
+            94.0,
+            &[-20.5, -19.5, f32::NEG_INFINITY],
+        )
+        .unwrap();
+        assert!((offset - 114.0).abs() < 1e-4);
+
+        // a later buffer peaking at half scale is 6dB down
+        // on full scale, so 108 dB SPL
+        let later = [0.25, 0.5, -0.5, 0.1];
+        let spl = peak_dbfs(&later) + offset;
+        assert!((spl - 107.98).abs() < 0.01, "{spl}");
+
+        assert_eq!(
+            calibration_offset(94.0, &[f32::NEG_INFINITY]),
+            None
+        );
+        assert_eq!(calibration_offset(94.0, &[]), None);
+    }
+
+    #[test]
+    fn an_oversized_buffer_is_counted_not_written_past() {
+        let mut analyzer = analyzer(48000, FFT_SIZE);
+        let frame = analyzer.process(&sine(
+            1000.0,
+            48000,
+            FFT_SIZE + 476,
+        ));
+        assert_eq!(frame.dropped_samples, 476);
+        assert_eq!(frame.spectrum.len(), FFT_SIZE / 2 + 1);
+        // the count is a running total
+        let frame =
+            analyzer.process(&vec![0.0; FFT_SIZE + 76]);
+        assert_eq!(frame.dropped_samples, 552);
+        // and a buffer that fits drops nothing more
+        let frame =
+            analyzer.process(&vec![0.0; FFT_SIZE / 2]);
+        assert_eq!(frame.dropped_samples, 552);
+    }
+
+    #[test]
+    fn peak_hold_keeps_each_bins_peak_and_decays() {
+        let mut hold = PeakHold::new(0.5);
+        hold.update(&[1.0, 0.0, 2.0]);
+        hold.update(&[4.0, 1.0, 0.0]);
+        hold.update(&[0.0, 8.0, 0.0]);
+        // bin 0 peaked at 4 a frame before last, bin 1
+        // just now, bin 2 at 2 on the first frame
+        assert_eq!(hold.levels(), [2.0, 8.0, 0.5]);
+
+        // once the input goes quiet everything fades
+        for _ in 0..3 {
+            hold.update(&[0.0; 3]);
+        }
+        assert_eq!(hold.levels(), [0.25, 1.0, 0.0625]);
+
+        hold.reset();
+        assert!(hold.levels().is_empty());
+        hold.update(&[3.0, 2.0, 1.0]);
+        assert_eq!(hold.levels(), [3.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn band_gains_scale_each_band_on_its_own() {
+        let mut bands = [1.0, 2.0, 3.0];
+        apply_band_gains(&mut bands, &[2.0, 0.5, 0.0]);
+        assert_eq!(bands, [2.0, 1.0, 0.0]);
+
+        // through the analyzer, against the same input at
+        // unity gain
+        let input: Vec<f32> =
+            [60.0, 300.0, 1000.0, 3000.0, 8000.0]
+                .iter()
+                .map(|hz| sine(*hz, 48000, 1024))
+                .fold(vec![0.0; 1024], |sum, tone| {
+                    sum.iter()
+                        .zip(tone)
+                        .map(|(a, b)| a + b)
+                        .collect()
+                });
+        let unity =
+            analyzer(48000, FFT_SIZE).process(&input).bands;
+        let gains = vec![1.0, 2.0, 0.5, 0.0, 3.0];
+        let mut gained = analyzer(48000, FFT_SIZE);
+        *gained.gains.lock().unwrap() = gains.clone();
+        let gained = gained.process(&input).bands;
+        for ((unity, gained), gain) in
+            unity.iter().zip(&gained).zip(&gains)
+        {
+            assert!(*unity > 0.0);
+            assert!(
+                (gained - unity * gain).abs() < 1e-4,
+                "{gained} vs {unity} * {gain}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_learned_noise_profile_cancels_that_noise() {
+        use crate::denoise::{
+            NoiseProfile, SPECTRAL_FLOOR,
+        };
+
+        // a fan: a hum plus a steady hiss, the same every
+        // frame
+        let mut seed = 1u32;
+        let noise: Vec<f32> = sine(120.0, 48000, 1024)
+            .into_iter()
+            .map(|hum| {
+                seed = seed
+                    .wrapping_mul(1_664_525)
+                    .wrapping_add(1_013_904_223);
+                0.5 * hum
+                    + 0.1
+                        * (seed as f32 / u32::MAX as f32
+                            - 0.5)
+            })
+            .collect();
+        let raw = analyzer(48000, 1024).process(&noise);
+
+        let profile =
+            Arc::new(Mutex::new(NoiseProfile::default()));
+        let mut denoised = analyzer(48000, 1024)
+            .with_noise_profile(profile.clone());
+        profile.lock().unwrap().learn(10);
+        for _ in 0..10 {
+            denoised.process(&noise);
+        }
+        assert!(!profile.lock().unwrap().is_learning());
+        let frame = denoised.process(&noise);
+        for (band, raw) in
+            frame.bands.iter().zip(&raw.bands)
+        {
+            assert!(
+                *band <= raw * SPECTRAL_FLOOR * 1.01,
+                "{band} left of {raw}"
+            );
+        }
+        // anything louder than the noise still shows
+        let voice: Vec<f32> = sine(1000.0, 48000, 1024)
+            .iter()
+            .zip(&noise)
+            .map(|(voice, noise)| voice + noise)
+            .collect();
+        let frame = denoised.process(&voice);
+        assert!(frame.bands[2] > raw.bands[2] * 10.0);
+    }
+
+    #[test]
+    fn the_third_octave_preset_follows_iso_266() {
+        let bands = BandPreset::ThirdOctave.bands();
+        assert_eq!(bands.len(), 30);
+        for (band, name) in
+            bands.iter().zip(THIRD_OCTAVE_NAMES)
+        {
+            assert_eq!(band.name, name);
+            let nominal: f32 = match name.strip_suffix('k')
+            {
+                Some(khz) => {
+                    khz.parse::<f32>().unwrap() * 1000.0
+                }
+                None => name.parse().unwrap(),
+            };
+            // nominal centers are rounded off the exact ones
+            // by up to a few percent
+            let center =
+                ((band.low * band.high) as f32).sqrt();
+            assert!(
+                (center / nominal - 1.0).abs() < 0.03,
+                "{name} centered on {center}"
+            );
+            // a third of an octave wide, give or take
+            // rounding to whole Hz
+            let width = band.high as f32 / band.low as f32;
+            assert!(
+                (width - 10f32.powf(0.1)).abs() < 0.05,
+                "{name} is {width} wide"
+            );
+        }
+        // the bands tile the range without gaps
+        for pair in bands.windows(2) {
+            assert!(
+                pair[0].high.abs_diff(pair[1].low) <= 1
+            );
+        }
+        assert_eq!(bands[0].low, 22);
+        assert_eq!(bands[29].high, 22387);
+    }
+
+    #[test]
+    fn density_weighs_bands_by_width_not_bin_count() {
+        // 10Hz bins with the same total in a narrow band
+        // that rounds out to three bins for its 20Hz, and a
+        // wide one of 101 bins for its 1000Hz
+        let bin_hz = 10.0;
+        let narrow = FreqRange {
+            name: "narrow".into(),
+            low: 100,
+            high: 120,
+        };
+        let wide = FreqRange {
+            name: "wide".into(),
+            low: 1000,
+            high: 2000,
+        };
+        let mut spectrum = vec![0.0; 256];
+        spectrum[11] = 6.0;
+        spectrum[150] = 6.0;
+        let (narrow_bins, wide_bins) = (10..=12, 100..=200);
+
+        // the mean spreads the total over the bins, so the
+        // narrow band's reading depends on its rounding
+        let mean = |bins| band_energy(&spectrum, bins);
+        assert_eq!(mean(narrow_bins.clone()), 2.0);
+        assert_eq!(mean(wide_bins.clone()), 6.0 / 101.0);
+
+        // density spreads it over the nominal width, so
+        // scaling back up by the width gives the same total
+        // for both
+        let total = |bins, range: &FreqRange| {
+            band_density(&spectrum, bins, bin_hz, range)
+                * (range.high - range.low) as f32
+                / bin_hz
+        };
+        assert_eq!(total(narrow_bins, &narrow), 6.0);
+        assert!(
+            (total(wide_bins, &wide) - 6.0).abs() < 1e-5
+        );
+    }
+
+    #[test]
+    fn a_complex_exponential_peaks_on_one_side_only() {
+        let fft_size = 1024;
+        // e^(±i 2π k n / N), interleaved as i/q pairs
+        let exponential = |bin: f32| -> Vec<f32> {
+            (0..fft_size)
+                .flat_map(|n| {
+                    let phase = std::f32::consts::TAU
+                        * bin
+                        * n as f32
+                        / fft_size as f32;
+                    [phase.cos(), phase.sin()]
+                })
+                .collect()
+        };
+        let dc = fft_size / 2;
+        for (bin, peak) in
+            [(64.0, dc + 64), (-64.0, dc - 64)]
+        {
+            let frame = analyzer(48000, fft_size)
+                .with_input_mode(InputMode::Iq)
+                .process(&exponential(bin));
+            assert_eq!(frame.spectrum.len(), fft_size);
+            let loudest = frame
+                .spectrum
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap()
+                .0;
+            assert_eq!(loudest, peak);
+            // where a real input's mirror image would be
+            let mirror = 2 * dc - peak;
+            assert!(
+                frame.spectrum[mirror]
+                    < frame.spectrum[peak] * 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn the_dc_blocker_removes_offset_and_keeps_tones() {
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|v| v * v).sum::<f32>()
+                / samples.len() as f32)
+                .sqrt()
+        };
+        // a constant offset, filtered a buffer at a time
+        let mut state = DcBlockState::default();
+        let mut last = vec![];
+        for _ in 0..20 {
+            let mut buffer = vec![0.5; 1024];
+            dc_block(
+                &mut buffer,
+                &mut state,
+                DC_BLOCK_POLE,
+            );
+            last = buffer;
+        }
+        let mean =
+            last.iter().sum::<f32>() / last.len() as f32;
+        assert!(mean.abs() < 1e-3, "mean of {mean}");
+
+        // a centered 1kHz sine is far above the cutoff
+        let tone = sine(1000.0, 48000, 20 * 1024);
+        let mut state = DcBlockState::default();
+        let mut filtered = tone.clone();
+        for buffer in filtered.chunks_mut(1024) {
+            dc_block(buffer, &mut state, DC_BLOCK_POLE);
+        }
+        let settled = 10 * 1024..;
+        let ratio = rms(&filtered[settled.clone()])
+            / rms(&tone[settled.clone()]);
+        assert!(
+            (ratio - 1.0).abs() < 0.01,
+            "gain of {ratio}"
+        );
+        let worst = filtered[settled.clone()]
+            .iter()
+            .zip(&tone[settled])
+            .map(|(out, input)| (out - input).abs())
+            .fold(0.0, f32::max);
+        assert!(worst < 0.05, "off by up to {worst}");
+    }
+
+    #[test]
+    fn analysis_stats_for_known_configs() {
+        // a mono input whose buffers match the fft
+        assert_eq!(
+            analysis_stats(48000, 1024, 1024),
+            AnalysisStats {
+                sample_rate: 48000,
+                fft_size: 1024,
+                resolution_hz: 46.875,
+                bin_bandwidth_hz: 46.875,
+                window_seconds: 1024.0 / 48000.0,
+                hop: 1024,
+                overlap: 0.0,
+                zero_padding: 0.0,
+                dropped: 0.0,
+                update_hz: 46.875,
+            }
+        );
+        // short buffers are padded out
+        let short = analysis_stats(44100, 2048, 512);
+        assert_eq!(short.zero_padding, 0.75);
+        assert_eq!(short.dropped, 0.0);
+        assert_eq!(short.update_hz, 44100.0 / 512.0);
+        // long ones lose what doesn't fit
+        let long = analysis_stats(48000, 512, 2048);
+        assert_eq!(long.zero_padding, 0.0);
+        assert_eq!(long.dropped, 0.75);
+        assert_eq!(long.resolution_hz, 93.75);
+        // and nothing divides by zero
+        let empty = analysis_stats(0, 0, 0);
+        assert!(empty.window_seconds.is_finite());
+        assert!(empty.update_hz.is_finite());
+    }
+
+    #[test]
+    fn the_stats_hop_counts_frames_not_samples() {
+        // 1024 frames of stereo arrive 46.875 times a
+        // second at 48kHz, not half that
+        let mut stereo =
+            analyzer(48000, 1024).with_channels(2);
+        let frame = stereo.process(&[0.0; 2048]);
+        assert_eq!(frame.stats.hop, 1024);
+        assert_eq!(frame.stats.update_hz, 46.875);
+    }
+
+    #[test]
+    fn frames_are_stamped_by_the_samples_before_them() {
+        let mut analyzer = analyzer(48000, 1024);
+        let buffer = sine(1000.0, 48000, 480);
+        let frames: Vec<AnalysisFrame> = (0..5)
+            .map(|_| analyzer.process(&buffer))
+            .collect();
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.sample_index, i as u64 * 480);
+            assert_eq!(
+                frame.timestamp,
+                Duration::from_millis(i as u64 * 10)
+            );
+        }
+        assert!(frames
+            .windows(2)
+            .all(|pair| pair[0].timestamp
+                < pair[1].timestamp));
+        // skipped buffers still take up time
+        analyzer.skip(&buffer);
+        let frame = analyzer.process(&buffer);
+        assert_eq!(frame.sample_index, 6 * 480);
+        assert_eq!(
+            frame.timestamp,
+            Duration::from_millis(60)
+        );
+    }
+
+    #[test]
+    fn the_level_histogram_counts_time_at_each_level() {
+        // 10dB bins over the dBFS meter's range
+        let mut histogram =
+            LevelHistogram::new((-60.0, 0.0), 6, 100);
+        let levels = [
+            (-55.0, 10),
+            (-25.0, 30),
+            (-12.0, 5),
+            (-0.5, 1),
+            // off the scale at either end, and silence
+            (-90.0, 2),
+            (3.0, 1),
+            (f32::NEG_INFINITY, 1),
+        ];
+        for (level, times) in levels {
+            for _ in 0..times {
+                histogram.push(level);
+            }
+        }
+        assert_eq!(
+            histogram.counts(),
+            [13, 0, 0, 30, 5, 2]
+        );
+        assert_eq!(histogram.total(), 50);
+        assert_eq!(histogram.edges(0), (-60.0, -50.0));
+        assert_eq!(histogram.edges(5), (-10.0, 0.0));
+
+        // the window forgets the oldest readings, here the
+        // ten at -55
+        for _ in 0..60 {
+            histogram.push(-35.0);
+        }
+        assert_eq!(histogram.total(), 100);
+        assert_eq!(
+            histogram.counts(),
+            [3, 0, 60, 30, 5, 2]
+        );
+    }
+
+    #[test]
+    fn the_real_fft_matches_the_complex_one() {
+        let tones: Vec<f32> = sine(440.0, 48000, 1024)
+            .iter()
+            .zip(sine(5000.0, 48000, 1024))
+            .map(|(a, b)| a + 0.25 * b + 0.1)
+            .collect();
+        // a full buffer, and a short one that gets padded
+        for samples in [&tones[..], &tones[..700]] {
+            let complex =
+                analyzer(48000, 1024).process(samples);
+            let real = analyzer(48000, 1024)
+                .with_fft_backend(FftBackend::Real)
+                .process(samples);
+            assert_eq!(
+                real.spectrum.len(),
+                complex.spectrum.len()
+            );
+            for (bin, (real, complex)) in real
+                .spectrum
+                .iter()
+                .zip(&complex.spectrum)
+                .enumerate()
+            {
+                assert!(
+                    (real - complex).abs()
+                        <= 1e-3 * complex.max(1.0),
+                    "bin {bin}: {real} against {complex}"
+                );
+            }
+            for (real, complex) in
+                real.bands.iter().zip(&complex.bands)
+            {
+                assert!(
+                    (real - complex).abs()
+                        <= 1e-3 * complex.max(1.0)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn power_aggregation_counts_a_lone_peak_fully() {
+        let band = |spectrum_type: SpectrumType,
+                    spectrum: &[f32]| {
+            spectrum_type.band_level(band_energy(
+                &spectrum_type.bins(spectrum),
+                0..=spectrum.len() - 1,
+            ))
+        };
+        // one peak among four bins: 1/4 of its magnitude as
+        // magnitudes, 1/√4 of it as power
+        let peak = [4.0, 0.0, 0.0, 0.0];
+        assert_eq!(
+            band(SpectrumType::Magnitude, &peak),
+            1.0
+        );
+        assert_eq!(band(SpectrumType::Power, &peak), 2.0);
+        // the two agree on a flat band
+        let flat = [2.0; 4];
+        assert_eq!(
+            band(SpectrumType::Magnitude, &flat),
+            2.0
+        );
+        assert_eq!(band(SpectrumType::Power, &flat), 2.0);
+
+        // and a sine reads louder with power in the band it
+        // falls in, the same bins either way
+        let tone = sine(1000.0, 48000, 1024);
+        let magnitude =
+            analyzer(48000, 1024).process(&tone);
+        let power = analyzer(48000, 1024)
+            .with_spectrum_type(SpectrumType::Power)
+            .process(&tone);
+        assert_eq!(magnitude.spectrum, power.spectrum);
+        let loudest = |bands: &[f32]| {
+            bands.iter().copied().fold(0.0, f32::max)
+        };
+        assert!(
+            loudest(&power.bands)
+                > loudest(&magnitude.bands) * 1.5
+        );
+    }
+
+    #[test]
+    fn the_peak_frequency_lands_between_bins() {
+        let (rate, fft_size) = (48000, 4096);
+        let bin_hz = rate as f32 / fft_size as f32;
+        for hz in [443.0, 1001.0, 2506.0] {
+            let frame = analyzer(rate, fft_size)
+                .process(&sine(hz, rate, fft_size));
+            let estimate = peak_frequency(
+                &frame.spectrum,
+                rate,
+                fft_size,
+            )
+            .unwrap();
+            // the centre of the loudest bin
+            let nearest = (hz / bin_hz).round() * bin_hz;
+            assert!(
+                (estimate - hz).abs()
+                    < (nearest - hz).abs(),
+                "{hz}: {estimate} vs {nearest}"
+            );
+            assert!((estimate - hz).abs() < bin_hz / 4.0);
+        }
+        assert_eq!(
+            peak_frequency(&[0.0; 2049], rate, fft_size),
+            None
+        );
+        // dc isn't a tone
+        let mut dc = vec![0.0; 2049];
+        dc[0] = 100.0;
+        assert_eq!(
+            peak_frequency(&dc, rate, fft_size),
+            None
+        );
+    }
+}
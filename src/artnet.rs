@@ -0,0 +1,323 @@
+//! Sends band levels to stage lighting as DMX over Art-Net,
+//! one ArtDmx packet per frame.
+
+use miette::Diagnostic;
+use std::{io, net::SocketAddr};
+use thiserror::Error;
+use tokio::net::UdpSocket;
+
+use crate::{
+    analysis::AnalysisFrame, tui::normalize_magnitude,
+};
+
+/// The UDP port Art-Net nodes listen on
+pub const ARTNET_PORT: u16 = 6454;
+/// Channels in one DMX universe
+pub const DMX_CHANNELS: usize = 512;
+/// Art-Net protocol revision the packets claim
+const PROTOCOL_VERSION: u16 = 14;
+/// OpArtDmx, the opcode of a packet of DMX data
+const OP_DMX: u16 = 0x5000;
+/// Highest 15 bit port-address, net, sub-net and universe
+/// together
+pub const MAX_UNIVERSE: u16 = 0x7fff;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ArtNetError {
+    #[error(
+        "{bands} bands from channel {start} don't fit in a \
+         universe"
+    )]
+    #[diagnostic(
+        code(pngtubers::artnet::channels),
+        help(
+            "bands take consecutive channels from the start \
+             channel, which runs from 1 and can't go past 512"
+        )
+    )]
+    Channels { start: u16, bands: usize },
+    #[error("universe {0} is out of range")]
+    #[diagnostic(
+        code(pngtubers::artnet::universe),
+        help("Art-Net addresses universes 0 to 32767")
+    )]
+    Universe(u16),
+    #[error("could not send Art-Net packets")]
+    #[diagnostic(code(pngtubers::artnet::io))]
+    Io(#[from] io::Error),
+}
+
+/// Where the bands land: band n drives DMX channel
+/// `start_channel + n` of `universe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArtNetLayout {
+    /// the 15 bit port-address, net in the top 7 bits,
+    /// sub-net and universe in the bottom 8
+    pub universe: u16,
+    /// the first band's channel, counting from 1 like a
+    /// lighting desk does
+    pub start_channel: u16,
+}
+
+impl Default for ArtNetLayout {
+    fn default() -> Self {
+        ArtNetLayout {
+            universe: 0,
+            start_channel: 1,
+        }
+    }
+}
+
+impl ArtNetLayout {
+    /// Checks that `bands` bands fit in the universe
+    pub fn validate(
+        self,
+        bands: usize,
+    ) -> Result<Self, ArtNetError> {
+        if self.universe > MAX_UNIVERSE {
+            return Err(ArtNetError::Universe(
+                self.universe,
+            ));
+        }
+        let start = self.start_channel as usize;
+        if start == 0 || start - 1 + bands > DMX_CHANNELS {
+            return Err(ArtNetError::Channels {
+                start: self.start_channel,
+                bands,
+            });
+        }
+        Ok(self)
+    }
+
+    /// The DMX data for `levels`, each 0 to 1, one per
+    /// band: every channel before the bands is 0, and the
+    /// data stops at the last band's channel, padded to the
+    /// even length ArtDmx wants
+    pub fn dmx_data(self, levels: &[f32]) -> Vec<u8> {
+        let start =
+            (self.start_channel.max(1) - 1) as usize;
+        let mut data = vec![0; start];
+        data.extend(
+            levels.iter().map(|level| dmx_value(*level)),
+        );
+        data.truncate(DMX_CHANNELS);
+        // at least 2 channels and an even count
+        data.resize(
+            data.len().max(2).next_multiple_of(2),
+            0,
+        );
+        data
+    }
+}
+
+/// `frame`'s bands as 0 to 1 levels, scaled against its
+/// spectrum the way the band meters are
+pub fn band_levels(frame: &AnalysisFrame) -> Vec<f32> {
+    frame
+        .bands
+        .iter()
+        .map(|band| {
+            normalize_magnitude(*band, frame.spectrum.len())
+        })
+        .collect()
+}
+
+/// A 0 to 1 level as a DMX channel value, 0 to 255.
+/// Anything out of range (or NaN) is clamped.
+pub fn dmx_value(level: f32) -> u8 {
+    let level = if level.is_nan() { 0.0 } else { level };
+    (level.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// An ArtDmx packet carrying `data` to `universe`.
+///
+/// The header is the "Art-Net" id, the opcode (little
+/// endian), the protocol version (big endian), the
+/// sequence number, the physical input it came from (always
+/// 0 here), the sub-net and universe byte, the net byte and
+/// the data length (big endian). `sequence` 0 tells the
+/// receiver not to reorder packets, anything else counts
+/// 1 to 255 and wraps.
+pub fn art_dmx_packet(
+    sequence: u8,
+    universe: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + data.len());
+    packet.extend(b"Art-Net\0");
+    packet.extend(OP_DMX.to_le_bytes());
+    packet.extend(PROTOCOL_VERSION.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0);
+    packet.push((universe & 0xff) as u8);
+    packet.push(((universe >> 8) & 0x7f) as u8);
+    packet.extend((data.len() as u16).to_be_bytes());
+    packet.extend(data);
+    packet
+}
+
+/// Sends one ArtDmx packet per `send` to a node, or to a
+/// broadcast address for every node on the network
+#[derive(Debug)]
+pub struct ArtNetSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+    layout: ArtNetLayout,
+    /// the last packet's sequence number
+    sequence: u8,
+}
+
+impl ArtNetSender {
+    /// Binds a socket for sending to `target`, the node's
+    /// address and (usually) `ARTNET_PORT`, after checking
+    /// that `bands` bands fit `layout`
+    pub async fn bind(
+        target: SocketAddr,
+        layout: ArtNetLayout,
+        bands: usize,
+    ) -> Result<Self, ArtNetError> {
+        let layout = layout.validate(bands)?;
+        let local: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.set_broadcast(true)?;
+        Ok(ArtNetSender {
+            socket,
+            target,
+            layout,
+            sequence: 0,
+        })
+    }
+
+    /// Sends `levels`, 0 to 1 per band, as the next packet
+    pub async fn send(
+        &mut self,
+        levels: &[f32],
+    ) -> Result<(), ArtNetError> {
+        self.sequence = self.sequence % 255 + 1;
+        let packet = art_dmx_packet(
+            self.sequence,
+            self.layout.universe,
+            &self.layout.dmx_data(levels),
+        );
+        self.socket.send_to(&packet, self.target).await?;
+        Ok(())
+    }
+}
+
+/// Whether sending has been working, so that a failure is
+/// reported when it starts and when it stops rather than
+/// on every frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendStatus {
+    failing: bool,
+}
+
+impl SendStatus {
+    /// What to tell the user after a send that came to
+    /// `result`, `None` while it keeps going the same way
+    pub fn update(
+        &mut self,
+        result: &Result<(), ArtNetError>,
+    ) -> Option<String> {
+        match (result, self.failing) {
+            (Err(err), false) => {
+                self.failing = true;
+                Some(match err {
+                    ArtNetError::Io(source) => format!(
+                        "Art-Net sends are failing: {source}"
+                    ),
+                    err => format!("{err}"),
+                })
+            }
+            (Ok(()), true) => {
+                self.failing = false;
+                Some("Art-Net is sending again".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_levels_become_an_art_dmx_packet() {
+        let layout = ArtNetLayout {
+            universe: 0x0123,
+            start_channel: 4,
+        }
+        .validate(5)
+        .unwrap();
+        let data = layout.dmx_data(&[
+            0.0,
+            0.5,
+            1.0,
+            2.0,
+            f32::NAN,
+        ]);
+        // three channels before the bands, clamped
+        assert_eq!(data, [0, 0, 0, 0, 128, 255, 255, 0]);
+        // and padded to an even length
+        assert_eq!(layout.dmx_data(&[1.0; 4]).len(), 8);
+        assert_eq!(
+            ArtNetLayout::default().dmx_data(&[1.0]),
+            [255, 0]
+        );
+
+        let packet =
+            art_dmx_packet(7, layout.universe, &data);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        // OpDmx little endian, version 14 big endian
+        assert_eq!(&packet[8..12], [0x00, 0x50, 0, 14]);
+        assert_eq!(packet[12], 7);
+        assert_eq!(packet[13], 0);
+        // sub-net and universe, then net
+        assert_eq!(&packet[14..16], [0x23, 0x01]);
+        assert_eq!(&packet[16..18], [0, 8]);
+        assert_eq!(&packet[18..], data);
+
+        // the bands have to fit
+        assert!(ArtNetLayout {
+            universe: 0,
+            start_channel: 509,
+        }
+        .validate(5)
+        .is_err());
+        assert!(ArtNetLayout {
+            universe: 0x8000,
+            start_channel: 1,
+        }
+        .validate(5)
+        .is_err());
+    }
+
+    #[test]
+    fn send_failures_are_reported_once_per_change() {
+        let failed = || {
+            Err(ArtNetError::Io(io::Error::from(
+                io::ErrorKind::ConnectionRefused,
+            )))
+        };
+        let mut status = SendStatus::default();
+        assert_eq!(status.update(&Ok(())), None);
+        assert_eq!(
+            status.update(&failed()).as_deref(),
+            Some(
+                "Art-Net sends are failing: connection \
+                 refused"
+            )
+        );
+        assert_eq!(status.update(&failed()), None);
+        assert_eq!(
+            status.update(&Ok(())).as_deref(),
+            Some("Art-Net is sending again")
+        );
+        assert_eq!(status.update(&Ok(())), None);
+    }
+}
@@ -2,44 +2,44 @@
 //!
 //! The input data is recorded to "$CARGO_MANIFEST_DIR/recorded.wav".
 
-use clap::Parser;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BuildStreamError, DeviceNameError, DevicesError,
-    PlayStreamError,
+    BuildStreamError, DefaultStreamConfigError,
+    DeviceNameError, DevicesError, PlayStreamError,
+    SupportedStreamConfigRange,
+    SupportedStreamConfigsError,
 };
 use cpal::{FromSample, Sample};
-use miette::{miette, IntoDiagnostic};
-use rustfft::num_complex::ComplexFloat;
-use rustfft::{num_complex::Complex, FftPlanner};
+use crossbeam_queue::ArrayQueue;
+use std::collections::{BTreeSet, VecDeque};
 use std::fs::File;
 use std::io::BufWriter;
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::Sender;
-
-struct FreqRange {
-    low: usize,
-    high: usize,
-}
-const BASS: FreqRange = FreqRange { low: 20, high: 140 };
-const LOW_MID: FreqRange = FreqRange {
-    low: 140,
-    high: 400,
-};
-const MID: FreqRange = FreqRange {
-    low: 400,
-    high: 2600,
+use std::sync::{
+    atomic::{AtomicU32, AtomicU64, Ordering},
+    mpsc, Arc, Mutex,
 };
-const HIGH_MID: FreqRange = FreqRange {
-    low: 2600,
-    high: 5200,
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+use crate::analysis::{
+    analysis_stats, default_bands, AnalysisFrame, Analyzer,
+    BandGains, BandWeighting, FftBackend, FreqRange,
+    InputMode, SpectrumType, FFT_SIZE,
 };
-const TREBLE: FreqRange = FreqRange {
-    low: 5200,
-    high: 14000,
+use crate::clip::ClipGuard;
+use crate::config::ConfigError;
+use crate::denoise::SharedNoiseProfile;
+use crate::gate::{GateOptions, NoiseGate};
+use crate::mix::{
+    downmix, ChannelLayout, DownmixTarget, Mixer, Resampler,
 };
+use crate::multires::MultiResolution;
+use crate::profile::SharedProfile;
+use crate::zoom::ZoomBand;
 
 use miette::Diagnostic;
+use serde::{Serialize, Serializer};
 use thiserror::Error;
 
 #[derive(Error, Debug, Diagnostic)]
@@ -61,6 +61,37 @@ pub enum PngTuberAudioError {
         pngtubers::audio::cpal::device_name
     ))]
     CpalDeviceNameError(#[from] DeviceNameError),
+    #[error("cpal default config error")]
+    #[diagnostic(code(
+        pngtubers::audio::cpal::default_config
+    ))]
+    CpalDefaultConfigError(
+        #[from] DefaultStreamConfigError,
+    ),
+    #[error("cpal supported configs error")]
+    #[diagnostic(code(
+        pngtubers::audio::cpal::supported_configs
+    ))]
+    CpalSupportedConfigsError(
+        #[from] SupportedStreamConfigsError,
+    ),
+    #[error("no audio device named {name:?}")]
+    #[diagnostic(
+        code(pngtubers::audio::device_not_found),
+        help(
+            "\"default\" picks the system default device"
+        )
+    )]
+    DeviceNotFound { name: String },
+    #[error("can't downmix {channels} channels")]
+    #[diagnostic(
+        code(pngtubers::audio::channel_layout),
+        help(
+            "downmixing knows 1, 2, 3, 4, 6 (5.1) and 8 \
+             (7.1) channels"
+        )
+    )]
+    UnsupportedLayout { channels: u16 },
     #[error("Unsupported Sample Format")]
     #[diagnostic(code(
         pngtubers::audio::unsupported_sample_format
@@ -69,207 +100,1756 @@ pub enum PngTuberAudioError {
         sample_format: cpal::SampleFormat,
         message: String,
     },
+    /// the device doesn't suit the settings it was opened
+    /// with
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Config(#[from] ConfigError),
 }
 
-pub fn run(
-    desired_device_name: &str,
-    tx: Sender<Vec<f32>>,
-) -> miette::Result<(), PngTuberAudioError> {
-    // Conditionally compile with jack if the feature is specified.
-    #[cfg(all(
-        any(
-            target_os = "linux",
-            target_os = "dragonfly",
-            target_os = "freebsd",
-            target_os = "netbsd"
-        ),
-        feature = "jack"
-    ))]
-    // Manually check for flags. Can be passed through cargo with -- e.g.
-    // cargo run --release --example beep --features jack -- --jack
-    let host = if args.jack {
-        cpal::host_from_id(cpal::available_hosts()
-            .into_iter()
-            .find(|id| *id == cpal::HostId::Jack)
-            .expect(
-                "make sure --features jack is specified. only works on OSes where jack is available",
-            )).expect("jack host unavailable")
-    } else {
-        cpal::default_host()
-    };
+/// Plays the captured input back out of an output device
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    pub device: String,
+    /// how much audio can queue up between the input and
+    /// output callbacks before the oldest is thrown away
+    pub latency_ms: u32,
+    pub gain: f32,
+}
 
-    #[cfg(any(
-        not(any(
-            target_os = "linux",
-            target_os = "dragonfly",
-            target_os = "freebsd",
-            target_os = "netbsd"
-        )),
-        not(feature = "jack")
-    ))]
-    let host = cpal::default_host();
+/// A second input device summed into the first before
+/// analysis
+#[derive(Debug, Clone)]
+pub struct MixOptions {
+    pub device: String,
+    /// gain on this device
+    pub gain: f32,
+    /// gain on the primary input
+    pub input_gain: f32,
+}
 
-    // Set up the input device and stream with the default input config.
-    let device = if desired_device_name == "default" {
-        host.default_input_device()
-    } else {
-        host.input_devices()?.find(|x| {dbg!(&x.name());
-            x.name()
-                .map(|y| y == desired_device_name)
-                .unwrap_or(false)
-        })
+/// What `AudioConfigBuilder::on_frame` takes
+pub type FrameCallback = Box<dyn Fn(&AnalysisFrame) + Send>;
+
+/// A closure run on every analysis frame, see
+/// `AudioConfigBuilder::on_frame`. Locked so the config can
+/// be cloned while the closure itself only needs `Send`.
+#[derive(Clone)]
+pub struct FrameHook(Arc<Mutex<FrameCallback>>);
+
+impl FrameHook {
+    pub fn new(hook: FrameCallback) -> Self {
+        FrameHook(Arc::new(Mutex::new(hook)))
     }
-    .expect("failed to find input device");
 
-    println!("Input device: {}", device.name()?);
+    pub fn call(&self, frame: &AnalysisFrame) {
+        (self.0.lock().unwrap())(frame)
+    }
+}
 
-    let config = device
-        .default_input_config()
-        .expect("Failed to get default input config");
-    println!("Default input config: {:?}", config);
+impl std::fmt::Debug for FrameHook {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.write_str("FrameHook")
+    }
+}
 
-    // The WAV file we're recording to.
-    const PATH: &str = concat!(
-        env!("CARGO_MANIFEST_DIR"),
-        "/recorded.wav"
-    );
-    let spec = wav_spec_from_config(&config);
-    // let writer = hound::WavWriter::create(PATH, spec)?;
-    // let writer = Arc::new(Mutex::new(Some(writer)));
+/// The streams that have to stay alive for capture (and
+/// monitoring) to keep running, on the thread `run` opens
+/// them on. Dropping this stops them.
+pub struct AudioStreams {
+    /// tells the stream thread to stop
+    events: mpsc::Sender<StreamEvent>,
+    stream_thread: Option<thread::JoinHandle<()>>,
+    /// runs the fft on buffers queued by the input
+    /// callback, `None` when the callback runs it itself
+    pub analysis_thread: Option<thread::JoinHandle<()>>,
+}
 
-    // A flag to indicate that recording is in progress.
-    println!("Begin recording...");
+impl Drop for AudioStreams {
+    fn drop(&mut self) {
+        // the streams stop when the thread drops them
+        let _ = self.events.send(StreamEvent::Stop);
+        if let Some(thread) = self.stream_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
 
-    // Run the input stream on a separate thread.
-    // let writer_2 = writer.clone();
+/// A bounded queue of interleaved samples shared between
+/// the input callback (which pushes) and the monitor output
+/// callback (which pulls).
+#[derive(Debug, Clone)]
+pub struct MonitorBuffer {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    capacity: usize,
+}
 
-    let err_fn = move |err| {
-        eprintln!("an error occurred on stream: {}", err);
-    };
+impl MonitorBuffer {
+    pub fn new(capacity: usize) -> Self {
+        MonitorBuffer {
+            samples: Arc::new(Mutex::new(
+                VecDeque::with_capacity(capacity),
+            )),
+            capacity,
+        }
+    }
+
+    /// Queues samples for playback. If the output side has
+    /// fallen behind, the oldest samples are discarded so
+    /// latency never grows past the capacity.
+    pub fn push(&self, input: &[f32]) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.extend(input);
+        let overflow =
+            samples.len().saturating_sub(self.capacity);
+        samples.drain(..overflow);
+    }
+
+    /// Fills `output` with queued samples scaled by `gain`,
+    /// padding with silence when the queue runs dry.
+    pub fn fill(&self, output: &mut [f32], gain: f32) {
+        let mut samples = self.samples.lock().unwrap();
+        for out in output.iter_mut() {
+            *out =
+                samples.pop_front().unwrap_or(0.0) * gain;
+        }
+    }
+}
+
+/// The latest thing the stream thread had to say about the
+/// input, such as that it was reopened at a new rate, and
+/// when it said it. Shared so the TUI can show it, stderr
+/// would land in the middle of the display.
+pub type StreamNotice =
+    Arc<Mutex<Option<(String, Instant)>>>;
+
+/// Everything `run` needs to open the input and set up the
+/// analysis. Build one with `AudioConfig::builder()`.
+#[derive(Debug, Clone)]
+pub struct AudioConfig {
+    /// input device name, "default" for the host's default
+    /// input
+    pub device: String,
+    pub fft_size: usize,
+    pub bands: Vec<FreqRange>,
+    /// one linear gain per band
+    pub gains: BandGains,
+    pub monitor: Option<MonitorOptions>,
+    /// collect analyzer stage timings into this
+    pub profile: Option<SharedProfile>,
+    /// subtract this from every spectrum
+    pub noise_profile: Option<SharedNoiseProfile>,
+    pub band_weighting: BandWeighting,
+    pub spectrum_type: SpectrumType,
+    /// frames per callback to ask the device for, see
+    /// `stream_config`
+    pub buffer_size: Option<u32>,
+    pub input_mode: InputMode,
+    /// pole of the dc blocker, off when `None`
+    pub dc_block: Option<f32>,
+    /// turn the input down below a threshold, see
+    /// `gate::NoiseGate`
+    pub gate: Option<GateOptions>,
+    pub mix: Option<MixOptions>,
+    /// back the gain off on the analysis input and the
+    /// monitor when they clip, see `clip::ClipGuard`
+    pub clip_guard: bool,
+    pub on_frame: Option<FrameHook>,
+    pub fft_backend: FftBackend,
+    /// between the audio callback and the analysis thread
+    pub queue: QueueOptions,
+    /// where the fft runs, see `AnalysisLocation`
+    pub analysis_location: AnalysisLocation,
+    /// several fft sizes instead of one, see
+    /// `MultiResAnalyzer`
+    pub multi_resolution: Option<MultiResolution>,
+    /// a narrow band to resolve finely, see `ZoomAnalyzer`
+    pub zoom: Option<ZoomBand>,
+    /// frames per second to analyze regardless of the
+    /// device's buffers, see
+    /// `AnalysisPipeline::with_analysis_rate`
+    pub analysis_rate: Option<f32>,
+    /// fold a surround input down to this with the standard
+    /// coefficients before anything else sees it
+    pub downmix: Option<DownmixTarget>,
+    /// open the device through the JACK host instead of the
+    /// default one, only honoured with the `jack` feature
+    pub jack: bool,
+    /// where the stream thread reports what happened to the
+    /// input
+    pub notice: StreamNotice,
+}
+
+impl AudioConfig {
+    pub fn builder() -> AudioConfigBuilder {
+        AudioConfigBuilder::default()
+    }
 
-    let mut planner = FftPlanner::new();
-    let fft = planner.plan_fft_forward(1024);
+    /// Checks an input with `channels` channels can be
+    /// read in this config's input mode
+    pub fn check_channels(
+        &self,
+        channels: u16,
+    ) -> Result<(), ConfigError> {
+        match self.input_mode {
+            InputMode::Iq if channels != 2 => {
+                Err(ConfigError::IqChannels { channels })
+            }
+            _ => Ok(()),
+        }
+    }
 
-    let mut buffer = vec![
-        Complex {
-            re: 0.0f32,
-            im: 0.0f32
+    /// An analyzer for input at `sample_rate` using this
+    /// config's fft size, bands, gains and profile
+    pub fn analyzer(&self, sample_rate: u32) -> Analyzer {
+        let analyzer = Analyzer::new(
+            sample_rate,
+            self.fft_size,
+            self.bands.clone(),
+            self.gains.clone(),
+        )
+        .with_weighting(self.band_weighting)
+        .with_spectrum_type(self.spectrum_type)
+        .with_input_mode(self.input_mode)
+        .with_fft_backend(self.fft_backend);
+        let analyzer = match self.dc_block {
+            Some(r) => analyzer.with_dc_block(r),
+            None => analyzer,
+        };
+        let analyzer = match self.gate {
+            Some(options) => {
+                analyzer.with_gate(NoiseGate::new(options))
+            }
+            None => analyzer,
+        };
+        let analyzer = match &self.multi_resolution {
+            Some(resolution) => analyzer
+                .with_multi_resolution(resolution.clone()),
+            None => analyzer,
         };
-        1024
-    ];
-    // dbg!(&config);
-    let sample_rate = config.sample_rate().0;
-    let nyquist = sample_rate / 2;
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => device
-            .build_input_stream(
-                &config.into(),
-                move |data: &[f32], _: &_| {
-                    tx.blocking_send(data.to_vec()).unwrap();
-                    // dbg!(&data.len());
-                    // println!("{:?}", &data);
-                    for (i, v) in data.iter().enumerate() {
-                        // let print_num =
-                        //     (20.0 * v.log10()).floor();
-                        // print!(
-                        //     "{:?} ",
-                        //     if print_num.is_nan() {
-                        //         " ".to_string()
-                        //     } else {
-                        //         print_num.to_string()
-                        //     }
-                        // );
-                        // println!("{}", v);
-                        buffer[i] =
-                            Complex::new(*v, 0.0f32);
+        let analyzer = match self.zoom {
+            Some(band) => analyzer.with_zoom(band),
+            None => analyzer,
+        };
+        let analyzer = match self.analysis_rate {
+            Some(_) => analyzer.with_sliding_window(),
+            None => analyzer,
+        };
+        let analyzer = match &self.profile {
+            Some(profile) => {
+                analyzer.with_profile(profile.clone())
+            }
+            None => analyzer,
+        };
+        match &self.noise_profile {
+            Some(noise_profile) => analyzer
+                .with_noise_profile(noise_profile.clone()),
+            None => analyzer,
+        }
+    }
+
+    /// A pipeline from `analyzer` to `tx` with this config's
+    /// frame hook, decimation and analysis rate
+    pub fn pipeline(
+        &self,
+        analyzer: Analyzer,
+        sample_rate: Arc<AtomicU32>,
+        tx: Sender<AnalysisFrame>,
+    ) -> AnalysisPipeline {
+        let pipeline = AnalysisPipeline::new(
+            analyzer,
+            sample_rate,
+            self.on_frame.clone(),
+            self.queue.decimation,
+            tx,
+        );
+        match self.analysis_rate {
+            Some(rate) => pipeline.with_analysis_rate(rate),
+            None => pipeline,
+        }
+    }
+}
+
+/// Builds an `AudioConfig`. Anything left unset gets:
+///
+/// - device: "default"
+/// - fft_size: `FFT_SIZE` (1024)
+/// - bands: `analysis::default_bands()`
+/// - gains: 1.0 for every band
+/// - monitor: off
+/// - profile: off
+/// - noise_profile: off
+/// - band_weighting: `BandWeighting::Mean`
+/// - spectrum_type: `SpectrumType::Magnitude`
+/// - buffer_size: whatever the device picks
+/// - input_mode: `InputMode::Real`
+/// - dc_block: off
+/// - gate: off
+/// - mix: off
+/// - clip_guard: off
+/// - on_frame: none
+/// - fft_backend: `FftBackend::Complex`
+/// - queue: `SAMPLE_QUEUE_CAPACITY` buffers, dropping the
+///   oldest
+/// - analysis_location: `AnalysisLocation::Thread`
+/// - multi_resolution: off, a single fft of fft_size
+/// - zoom: off
+/// - analysis_rate: off, one frame per device buffer
+/// - downmix: off, every channel is analyzed
+/// - jack: off, the default host
+/// - notice: one nobody reads
+#[derive(Debug, Clone, Default)]
+pub struct AudioConfigBuilder {
+    device: Option<String>,
+    fft_size: Option<usize>,
+    bands: Option<Vec<FreqRange>>,
+    gains: Option<BandGains>,
+    monitor: Option<MonitorOptions>,
+    profile: Option<SharedProfile>,
+    noise_profile: Option<SharedNoiseProfile>,
+    band_weighting: Option<BandWeighting>,
+    spectrum_type: Option<SpectrumType>,
+    buffer_size: Option<u32>,
+    input_mode: Option<InputMode>,
+    dc_block: Option<f32>,
+    gate: Option<GateOptions>,
+    mix: Option<MixOptions>,
+    clip_guard: bool,
+    on_frame: Option<FrameHook>,
+    fft_backend: Option<FftBackend>,
+    queue: Option<QueueOptions>,
+    analysis_location: Option<AnalysisLocation>,
+    multi_resolution: Option<MultiResolution>,
+    zoom: Option<ZoomBand>,
+    analysis_rate: Option<f32>,
+    downmix: Option<DownmixTarget>,
+    jack: bool,
+    notice: Option<StreamNotice>,
+}
+
+impl AudioConfigBuilder {
+    pub fn device(
+        mut self,
+        device: impl Into<String>,
+    ) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+
+    pub fn fft_size(mut self, fft_size: usize) -> Self {
+        self.fft_size = Some(fft_size);
+        self
+    }
+
+    pub fn bands(mut self, bands: Vec<FreqRange>) -> Self {
+        self.bands = Some(bands);
+        self
+    }
+
+    pub fn gains(mut self, gains: BandGains) -> Self {
+        self.gains = Some(gains);
+        self
+    }
+
+    pub fn monitor(
+        mut self,
+        monitor: MonitorOptions,
+    ) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    pub fn profile(
+        mut self,
+        profile: SharedProfile,
+    ) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn noise_profile(
+        mut self,
+        noise_profile: SharedNoiseProfile,
+    ) -> Self {
+        self.noise_profile = Some(noise_profile);
+        self
+    }
+
+    pub fn band_weighting(
+        mut self,
+        band_weighting: BandWeighting,
+    ) -> Self {
+        self.band_weighting = Some(band_weighting);
+        self
+    }
+
+    pub fn spectrum_type(
+        mut self,
+        spectrum_type: SpectrumType,
+    ) -> Self {
+        self.spectrum_type = Some(spectrum_type);
+        self
+    }
+
+    pub fn buffer_size(mut self, frames: u32) -> Self {
+        self.buffer_size = Some(frames);
+        self
+    }
+
+    pub fn input_mode(
+        mut self,
+        input_mode: InputMode,
+    ) -> Self {
+        self.input_mode = Some(input_mode);
+        self
+    }
+
+    /// Filters dc out of the input with this pole, see
+    /// `analysis::dc_block`
+    pub fn dc_block(mut self, r: f32) -> Self {
+        self.dc_block = Some(r);
+        self
+    }
+
+    /// Gates the input with these options, see
+    /// `gate::NoiseGate`
+    pub fn gate(mut self, options: GateOptions) -> Self {
+        self.gate = Some(options);
+        self
+    }
+
+    pub fn mix(mut self, mix: MixOptions) -> Self {
+        self.mix = Some(mix);
+        self
+    }
+
+    pub fn clip_guard(mut self, clip_guard: bool) -> Self {
+        self.clip_guard = clip_guard;
+        self
+    }
+
+    pub fn fft_backend(
+        mut self,
+        fft_backend: FftBackend,
+    ) -> Self {
+        self.fft_backend = Some(fft_backend);
+        self
+    }
+
+    pub fn queue(mut self, queue: QueueOptions) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    pub fn analysis_location(
+        mut self,
+        location: AnalysisLocation,
+    ) -> Self {
+        self.analysis_location = Some(location);
+        self
+    }
+
+    /// Analyzes the bass and treble with different fft
+    /// sizes, see `MultiResAnalyzer`
+    pub fn multi_resolution(
+        mut self,
+        resolution: MultiResolution,
+    ) -> Self {
+        self.multi_resolution = Some(resolution);
+        self
+    }
+
+    /// Resolves `band` far more finely than the main fft,
+    /// see `ZoomAnalyzer`
+    pub fn zoom(mut self, band: ZoomBand) -> Self {
+        self.zoom = Some(band);
+        self
+    }
+
+    /// Analyzes `rate` frames per second, however big the
+    /// device's buffers are
+    pub fn analysis_rate(mut self, rate: f32) -> Self {
+        self.analysis_rate = Some(rate);
+        self
+    }
+
+    /// Downmixes the input from the layout its channel count
+    /// implies, see `ChannelLayout::downmix`
+    pub fn downmix(
+        mut self,
+        target: DownmixTarget,
+    ) -> Self {
+        self.downmix = Some(target);
+        self
+    }
+
+    /// Runs `hook` on every frame, for embedding without
+    /// reading the channel.
+    ///
+    /// The hook is called on the analysis thread, never the
+    /// audio callback, once per frame just before the frame
+    /// is sent, so it sees frames in order and one at a
+    /// time. Analysis waits while it runs: anything slow
+    /// should hand the work to another thread. It has to be
+    /// `Send` to get onto that thread, and anything it
+    /// shares with the rest of the program needs its own
+    /// locking.
+    pub fn on_frame(mut self, hook: FrameCallback) -> Self {
+        self.on_frame = Some(FrameHook::new(hook));
+        self
+    }
+
+    pub fn jack(mut self, jack: bool) -> Self {
+        self.jack = jack;
+        self
+    }
+
+    pub fn notice(mut self, notice: StreamNotice) -> Self {
+        self.notice = Some(notice);
+        self
+    }
+
+    pub fn build(self) -> AudioConfig {
+        let bands =
+            self.bands.unwrap_or_else(default_bands);
+        let gains = self.gains.unwrap_or_else(|| {
+            Arc::new(Mutex::new(vec![1.0; bands.len()]))
+        });
+        AudioConfig {
+            device: self
+                .device
+                .unwrap_or_else(|| "default".to_string()),
+            fft_size: self.fft_size.unwrap_or(FFT_SIZE),
+            bands,
+            gains,
+            monitor: self.monitor,
+            profile: self.profile,
+            noise_profile: self.noise_profile,
+            band_weighting: self
+                .band_weighting
+                .unwrap_or_default(),
+            spectrum_type: self
+                .spectrum_type
+                .unwrap_or_default(),
+            buffer_size: self.buffer_size,
+            input_mode: self.input_mode.unwrap_or_default(),
+            dc_block: self.dc_block,
+            gate: self.gate,
+            mix: self.mix,
+            clip_guard: self.clip_guard,
+            on_frame: self.on_frame,
+            fft_backend: self
+                .fft_backend
+                .unwrap_or_default(),
+            queue: self.queue.unwrap_or_default(),
+            analysis_location: self
+                .analysis_location
+                .unwrap_or_default(),
+            multi_resolution: self.multi_resolution,
+            zoom: self.zoom,
+            analysis_rate: self.analysis_rate,
+            downmix: self.downmix,
+            jack: self.jack,
+            notice: self.notice.unwrap_or_default(),
+        }
+    }
+}
+
+/// Opens the input device (and any monitor and mix device)
+/// and analyzes what it captures into `tx`, until the
+/// returned streams are dropped.
+///
+/// The streams live on a thread of their own. Their error
+/// callback only passes the error on to that thread, which
+/// is where the device gets asked about its config: some usb
+/// interfaces renegotiate their sample rate when another app
+/// grabs them, which usually shows up as a stream error. If
+/// the rate has changed, the streams are opened again at the
+/// new rate and only then does the analyzer switch to it.
+/// What happens is reported through the config's `notice`.
+pub fn run(
+    audio_config: AudioConfig,
+    tx: Sender<AnalysisFrame>,
+) -> miette::Result<AudioStreams, PngTuberAudioError> {
+    let (events, events_rx) = mpsc::channel();
+    let (opened_tx, opened_rx) = mpsc::channel();
+    let stream_events = events.clone();
+    let stream_thread = thread::spawn(move || {
+        let mut input = match Input::open(
+            &audio_config,
+            tx,
+            stream_events,
+        ) {
+            Ok((input, analysis_thread)) => {
+                let _ = opened_tx.send(Ok(analysis_thread));
+                input
+            }
+            Err(err) => {
+                let _ = opened_tx.send(Err(err));
+                return;
+            }
+        };
+        while let Ok(StreamEvent::Error(err)) =
+            events_rx.recv()
+        {
+            input.notify(format!(
+                "an error occurred on stream: {err}"
+            ));
+            input.reopen_if_changed();
+        }
+    });
+    match opened_rx.recv() {
+        Ok(Ok(analysis_thread)) => Ok(AudioStreams {
+            events,
+            stream_thread: Some(stream_thread),
+            analysis_thread,
+        }),
+        Ok(Err(err)) => {
+            let _ = stream_thread.join();
+            Err(err)
+        }
+        // the thread panicked before it could say either way
+        Err(_) => match stream_thread.join() {
+            Err(panic) => std::panic::resume_unwind(panic),
+            Ok(()) => unreachable!(
+                "the stream thread always reports how \
+                 opening went"
+            ),
+        },
+    }
+}
+
+/// What the stream thread waits on
+enum StreamEvent {
+    /// from a stream's error callback, which hands it over
+    /// rather than dealing with it so the callback stays
+    /// cheap
+    Error(cpal::StreamError),
+    /// from dropping `AudioStreams`
+    Stop,
+}
+
+/// The streams open for one input config
+struct Streams {
+    input: cpal::Stream,
+    _monitor: Option<cpal::Stream>,
+    _mix: Option<cpal::Stream>,
+}
+
+/// The input device, its streams and everything it takes to
+/// open them again, on the stream thread
+struct Input {
+    host: cpal::Host,
+    device: cpal::Device,
+    /// the config the streams are open with
+    config: cpal::SupportedStreamConfig,
+    audio_config: AudioConfig,
+    handoff: Handoff,
+    /// how the callback downmixes, if it does
+    layout: Option<(ChannelLayout, DownmixTarget)>,
+    /// what the callback hands on after any downmix
+    channels: u16,
+    /// the analysis thread picks up changes to this and
+    /// rebuilds the analyzer for the new rate
+    sample_rate: Arc<AtomicU32>,
+    events: mpsc::Sender<StreamEvent>,
+    streams: Option<Streams>,
+}
+
+impl Input {
+    /// Finds the device, sets up the analysis and opens the
+    /// streams
+    fn open(
+        audio_config: &AudioConfig,
+        tx: Sender<AnalysisFrame>,
+        events: mpsc::Sender<StreamEvent>,
+    ) -> Result<
+        (Input, Option<thread::JoinHandle<()>>),
+        PngTuberAudioError,
+    > {
+        let desired_device_name =
+            audio_config.device.clone();
+
+        // Conditionally compile with jack if the feature is specified.
+        #[cfg(all(
+            any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd"
+            ),
+            feature = "jack"
+        ))]
+        // Set from the --jack flag, e.g.
+        // cargo run --release --features jack -- --jack
+        let host = if audio_config.jack {
+            cpal::host_from_id(cpal::available_hosts()
+                .into_iter()
+                .find(|id| *id == cpal::HostId::Jack)
+                .expect(
+                    "make sure --features jack is specified. only works on OSes where jack is available",
+                )).expect("jack host unavailable")
+        } else {
+            cpal::default_host()
+        };
+
+        #[cfg(any(
+            not(any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd"
+            )),
+            not(feature = "jack")
+        ))]
+        let host = cpal::default_host();
+
+        // Set up the input device and stream with the default input config.
+        let device =
+            find_input_device(&host, &desired_device_name)?
+                .ok_or_else(|| {
+                    PngTuberAudioError::DeviceNotFound {
+                        name: desired_device_name.clone(),
                     }
+                })?;
 
-                    fft.process(&mut buffer);
+        eprintln!("Input device: {}", device.name()?);
 
-                    let results = &buffer
-                        .iter()
-                        // .take(10)
-                        .map(|v| {
-                            let n = v.norm();
-                            // let n = 20.0 * v.norm().log10();
-                            // * (1.0 / 512.0.sqrt());
-                            // n.floor() as i32
-                            n
-                        })
-                        .collect::<Vec<f32>>();
-                    let results =
-                        remove_mirroring(&results);
-                    // println!("{:?}", results);
-                    let energy_ranges = [
-                        BASS, LOW_MID, MID, HIGH_MID,
-                        TREBLE,
-                    ]
-                    .into_iter()
-                    .map(|FreqRange { low, high }| {
-                        // dbg!(low, high);
-                        let low_index = (low as f32
-                            / nyquist as f32
-                            * results.len() as f32)
-                            .round()
-                            as usize;
-                        // var lowIndex = Math.round((frequency1 / nyquist) * this.freqDomain.length);
-                        let high_index = (high as f32
-                            / nyquist as f32
-                            * results.len() as f32)
-                            .round()
-                            as usize;
-                        // var highIndex = Math.round((frequency2 / nyquist) * this.freqDomain.length);
-
-                        let freq_slice = &results
-                            [low_index..=high_index];
-
-                        // var total = 0;
-                        let num_frequencies =
-                            freq_slice.len();
-                        // var numFrequencies = 0;
-                        // // add up all of the values for the frequencies
-                        let total = results
-                            [low_index..=high_index]
-                            .iter()
-                            .sum::<f32>();
-                        // for (var i = lowIndex; i <= highIndex; i++) {
-                        //   total += this.freqDomain[i];
-                        //   numFrequencies += 1;
-                        // }
-                        // // divide by total number of frequencies
-                        // var toReturn = total / numFrequencies;
-                        // dbg!(results.len());
-                        total / (num_frequencies as f32)
-                    })
-                    .collect::<Vec<f32>>();
-                    // println!("{:?}", energy_ranges);
-
-                    // write_input_data::<f32, f32>(
-                    //     data, &writer_2,
-                    // )
-                },
-                err_fn,
-                None,
-            )?,
-        sample_format => {
+        let config = device.default_input_config()?;
+        eprintln!("Default input config: {:?}", config);
+        audio_config.check_channels(config.channels())?;
+
+        // The WAV file we're recording to.
+        #[allow(dead_code)]
+        const PATH: &str = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/recorded.wav"
+        );
+        let _spec = wav_spec_from_config(&config);
+        // let writer = hound::WavWriter::create(PATH, spec)?;
+        // let writer = Arc::new(Mutex::new(Some(writer)));
+
+        // A flag to indicate that recording is in progress.
+        eprintln!("Begin recording...");
+
+        // Run the input stream on a separate thread.
+        // let writer_2 = writer.clone();
+
+        let device_channels = config.channels();
+        let layout = audio_config
+            .downmix
+            .map(|target| {
+                ChannelLayout::from_channels(
+                    device_channels,
+                )
+                .map(|layout| (layout, target))
+                .ok_or(
+                    PngTuberAudioError::UnsupportedLayout {
+                        channels: device_channels,
+                    },
+                )
+            })
+            .transpose()?;
+        let channels = layout
+            .map_or(device_channels, |(_, target)| {
+                target.channels()
+            });
+
+        let sample_rate = Arc::new(AtomicU32::new(
+            config.sample_rate().0,
+        ));
+        // dbg!(&config);
+        // with a second input everything past the callback
+        // sees one mono stream of the two summed
+        let analyzer = audio_config
+            .analyzer(config.sample_rate().0)
+            .with_channels(if audio_config.mix.is_some() {
+                1
+            } else {
+                channels
+            });
+        let (handoff, analysis_thread) = handoff(
+            audio_config.pipeline(
+                analyzer,
+                sample_rate.clone(),
+                tx,
+            ),
+            audio_config.analysis_location,
+            audio_config.queue,
+        );
+        let mut input = Input {
+            host,
+            device,
+            config: config.clone(),
+            audio_config: audio_config.clone(),
+            handoff,
+            layout,
+            channels,
+            sample_rate,
+            events,
+            streams: None,
+        };
+        let streams = input.build_streams(&config)?;
+        streams.input.play()?;
+        input.streams = Some(streams);
+        Ok((input, analysis_thread))
+    }
+
+    /// Opens the monitor, the mix and the input with
+    /// `config`. Everything but the input is already
+    /// playing.
+    fn build_streams(
+        &self,
+        config: &cpal::SupportedStreamConfig,
+    ) -> Result<Streams, PngTuberAudioError> {
+        let monitor = self
+            .audio_config
+            .monitor
+            .as_ref()
+            .map(|options| {
+                build_monitor_stream(
+                    &self.host,
+                    options,
+                    &config.config(),
+                    self.audio_config.clip_guard,
+                )
+            })
+            .transpose()?;
+        let monitor_buffer = monitor
+            .as_ref()
+            .map(|(buffer, _)| buffer.clone());
+
+        let sample_format = config.sample_format();
+        if sample_format != cpal::SampleFormat::F32 {
             return Err(
                 PngTuberAudioError::UnsupportedSampleFormat{
                     sample_format,
-                    message: "Unsupported sample format '{sample_format}'".to_string()
+                    message: "Unsupported sample format \
+                              '{sample_format}'"
+                        .to_string(),
                 },
-            )
+            );
+        }
+        let mix = self
+            .audio_config
+            .mix
+            .as_ref()
+            .map(|options| {
+                build_mix_stream(
+                    &self.host,
+                    options,
+                    config.sample_rate().0,
+                )
+            })
+            .transpose()?;
+        let mixer =
+            mix.as_ref().map(|(mixer, _)| mixer.clone());
+        let (input_gain, mix_gain) = self
+            .audio_config
+            .mix
+            .as_ref()
+            .map_or((1.0, 1.0), |options| {
+                (options.input_gain, options.gain)
+            });
+        let (layout, channels) =
+            (self.layout, self.channels);
+
+        let build_stream =
+            |stream_config: &cpal::StreamConfig| {
+                let monitor_buffer = monitor_buffer.clone();
+                let mixer = mixer.clone();
+                let handoff = self.handoff.clone();
+                let mut clip_guard = self
+                    .audio_config
+                    .clip_guard
+                    .then(|| {
+                        ClipGuard::new(
+                            stream_config.sample_rate.0,
+                        )
+                    });
+                self.device.build_input_stream(
+                    stream_config,
+                    move |data: &[f32], _: &_| {
+                        if let Some(buffer) =
+                            &monitor_buffer
+                        {
+                            buffer.push(data);
+                        }
+                        let downmixed = layout.map(
+                            |(layout, target)| {
+                                layout.downmix(data, target)
+                            },
+                        );
+                        let data = downmixed
+                            .as_deref()
+                            .unwrap_or(data);
+                        // keep the real-time callback
+                        // cheap, the fft happens on the
+                        // analysis thread unless
+                        // configured otherwise
+                        let mut samples = match &mixer {
+                            Some(mixer) => mixer.mix(
+                                &downmix(data, channels),
+                                input_gain,
+                                mix_gain,
+                            ),
+                            None => data.to_vec(),
+                        };
+                        if let Some(guard) = &mut clip_guard
+                        {
+                            guard.process(
+                                &mut samples,
+                                if mixer.is_some() {
+                                    1
+                                } else {
+                                    channels
+                                },
+                            );
+                        }
+                        handoff.push(samples);
+
+                        // write_input_data::<f32, f32>(
+                        //     data, &writer_2,
+                        // )
+                    },
+                    {
+                        let events = self.events.clone();
+                        move |err| {
+                            let _ = events.send(
+                                StreamEvent::Error(err),
+                            );
+                        }
+                    },
+                    None,
+                )
+            };
+        let stream_config = stream_config(
+            config,
+            self.audio_config.buffer_size,
+        );
+        let stream = match build_stream(&stream_config) {
+            // devices that don't report their supported
+            // sizes only tell us by failing here
+            Err(err)
+                if stream_config.buffer_size
+                    != cpal::BufferSize::Default =>
+            {
+                eprintln!(
+                    "warning: input rejected the requested \
+                     buffer size ({err}), using its default"
+                );
+                build_stream(&config.config())?
+            }
+            stream => stream?,
+        };
+        Ok(Streams {
+            input: stream,
+            _monitor: monitor.map(|(_, stream)| stream),
+            _mix: mix.map(|(_, stream)| stream),
+        })
+    }
+
+    /// Passes `message` on to whoever shows the notices
+    fn notify(&self, message: String) {
+        *self.audio_config.notice.lock().unwrap() =
+            Some((message, Instant::now()));
+    }
+
+    /// Opens the streams again if the device's default
+    /// config has moved to another sample rate, keeping the
+    /// analysis (and its history) going. The analyzer only
+    /// switches to the new rate once the new streams are
+    /// open at it.
+    fn reopen_if_changed(&mut self) {
+        let Ok(config) = self.device.default_input_config()
+        else {
+            return;
+        };
+        let rate = config.sample_rate().0;
+        if rate == self.config.sample_rate().0 {
+            return;
+        }
+        if config.channels() != self.config.channels()
+            || config.sample_format()
+                != self.config.sample_format()
+        {
+            self.notify(format!(
+                "the input changed to {} channels of {} at \
+                 {rate}Hz, restart to pick that up",
+                config.channels(),
+                config.sample_format()
+            ));
+            return;
+        }
+        // the old streams have to let go of the device first
+        self.streams = None;
+        let streams = match self.build_streams(&config) {
+            Ok(streams) => streams,
+            Err(err) => {
+                self.notify(format!(
+                    "could not reopen the input at {rate}Hz: \
+                     {err}"
+                ));
+                return;
+            }
+        };
+        self.sample_rate.store(rate, Ordering::Relaxed);
+        if let Err(err) = streams.input.play() {
+            self.notify(format!(
+                "could not restart the input at {rate}Hz: \
+                 {err}"
+            ));
+            return;
         }
-        _ => panic!("unsupported cpal::SmapleFormat"),
+        self.notify(format!(
+            "input sample rate changed to {rate}Hz, reopened \
+             the input"
+        ));
+        self.streams = Some(streams);
+        self.config = config;
+    }
+}
+
+/// How many device buffers can wait for the analysis thread
+/// before they start getting thrown away, unless configured
+/// otherwise
+pub const SAMPLE_QUEUE_CAPACITY: usize = 32;
+
+/// Which buffer to throw away when the queue is full
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum DropPolicy {
+    /// make room for the new buffer, so the display stays
+    /// current
+    #[default]
+    Oldest,
+    /// keep what's queued and lose the new buffer, so what
+    /// does get analysed is one unbroken stretch
+    Newest,
+}
+
+/// How much the sample queue holds, what it drops once it's
+/// full and how many of its buffers get analyzed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueOptions {
+    /// device buffers, at least one
+    pub capacity: usize,
+    pub drop_policy: DropPolicy,
+    /// analyze one buffer in this many and let the rest go
+    /// by, cutting the cpu the analysis takes by about as
+    /// much. Frames come that much less often, so short
+    /// sounds can fall between them and the tempo and
+    /// activity readings get coarser. 0 and 1 both analyze
+    /// every buffer.
+    pub decimation: usize,
+}
+
+impl Default for QueueOptions {
+    fn default() -> Self {
+        QueueOptions {
+            capacity: SAMPLE_QUEUE_CAPACITY,
+            drop_policy: DropPolicy::default(),
+            decimation: 1,
+        }
+    }
+}
+
+/// Hands buffers from the audio callback to the analysis
+/// thread without locking. When the analysis thread falls
+/// behind a buffer is dropped (and counted) according to
+/// the drop policy, so the callback never waits.
+#[derive(Debug, Clone)]
+pub struct SampleQueue {
+    buffers: Arc<ArrayQueue<Vec<f32>>>,
+    drop_policy: DropPolicy,
+    dropped_samples: Arc<AtomicU64>,
+    consumer: thread::Thread,
+}
+
+impl SampleQueue {
+    pub fn new(
+        options: QueueOptions,
+        consumer: thread::Thread,
+    ) -> Self {
+        SampleQueue {
+            buffers: Arc::new(ArrayQueue::new(
+                options.capacity.max(1),
+            )),
+            drop_policy: options.drop_policy,
+            dropped_samples: Arc::new(AtomicU64::new(0)),
+            consumer,
+        }
+    }
+
+    pub fn push(&self, samples: Vec<f32>) {
+        let dropped = match self.drop_policy {
+            DropPolicy::Oldest => {
+                self.buffers.force_push(samples)
+            }
+            DropPolicy::Newest => {
+                self.buffers.push(samples).err()
+            }
+        };
+        if let Some(dropped) = dropped {
+            self.dropped_samples.fetch_add(
+                dropped.len() as u64,
+                Ordering::Relaxed,
+            );
+        }
+        self.consumer.unpark();
+    }
+
+    pub fn pop(&self) -> Option<Vec<f32>> {
+        self.buffers.pop()
+    }
+
+    /// samples thrown away because the queue was full
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+}
+
+/// Where captured buffers get analyzed
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum AnalysisLocation {
+    /// queued for a dedicated analysis thread, so the
+    /// audio callback only copies samples and a slow fft
+    /// costs dropped buffers rather than glitches
+    #[default]
+    Thread,
+    /// in the audio callback itself, which sends finished
+    /// frames. Saves a thread and a copy, and suits small
+    /// ffts on hosts that give the callback plenty of time;
+    /// a big fft there can make the input glitch.
+    Callback,
+}
+
+/// Everything done to a buffer once it's been captured:
+/// analysis (or skipping it, for decimation), the frame
+/// hook, and handing the frame to the receiver. It runs
+/// wherever `AnalysisLocation` puts it.
+///
+/// It never waits on a slow receiver: a frame that doesn't
+/// fit in `tx` is dropped and its samples counted in the
+/// next frame's `dropped_samples`. Buffers passed over for
+/// decimation aren't dropped, they show up in each frame's
+/// stats as a longer hop instead.
+pub struct AnalysisPipeline {
+    analyzer: Analyzer,
+    /// the analyzer is rebuilt for the new rate whenever
+    /// this changes
+    sample_rate: Arc<AtomicU32>,
+    on_frame: Option<FrameHook>,
+    /// analyze one buffer in this many
+    decimation: usize,
+    /// buffers seen so far
+    buffers: usize,
+    /// samples in frames the receiver had no room for
+    unsent: u64,
+    /// frames per second to analyze at whatever the
+    /// buffers, see `with_analysis_rate`
+    analysis_rate: Option<f32>,
+    /// input waiting for the next hop to fill
+    pending: Vec<f32>,
+    /// hops since the sample rate last changed
+    hops: u64,
+    tx: Sender<AnalysisFrame>,
+}
+
+impl AnalysisPipeline {
+    /// See `QueueOptions::decimation` for `decimation`
+    pub fn new(
+        analyzer: Analyzer,
+        sample_rate: Arc<AtomicU32>,
+        on_frame: Option<FrameHook>,
+        decimation: usize,
+        tx: Sender<AnalysisFrame>,
+    ) -> Self {
+        AnalysisPipeline {
+            analyzer,
+            sample_rate,
+            on_frame,
+            decimation: decimation.max(1),
+            buffers: 0,
+            unsent: 0,
+            analysis_rate: None,
+            pending: vec![],
+            hops: 0,
+            tx,
+        }
+    }
+
+    /// Analyzes `rate` frames per second of input however
+    /// the device happens to buffer it, by cutting the input
+    /// into hops of `hop_len` and analyzing each one as a
+    /// buffer. The analyzer needs a sliding window so each
+    /// fft still covers the fft size.
+    pub fn with_analysis_rate(mut self, rate: f32) -> Self {
+        self.analysis_rate = (rate.is_finite()
+            && rate > 0.0)
+            .then_some(rate);
+        self
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.analyzer.fft_size()
+    }
+
+    /// The receiver has gone away, nothing more will be
+    /// sent
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Analyzes `samples` and sends the frame on, counting
+    /// `dropped` samples lost before they got here. `false`
+    /// once the receiver is gone.
+    pub fn handle(
+        &mut self,
+        samples: &[f32],
+        dropped: u64,
+    ) -> bool {
+        let rate = self.sample_rate.load(Ordering::Relaxed);
+        if rate != self.analyzer.sample_rate() {
+            self.analyzer.set_sample_rate(rate);
+            self.hops = 0;
+            // hops counted out at the old rate
+            self.pending.clear();
+        }
+        let Some(analysis_rate) = self.analysis_rate else {
+            return self.analyze(samples, dropped);
+        };
+        self.pending.extend_from_slice(samples);
+        loop {
+            let hop =
+                hop_len(rate, analysis_rate, self.hops)
+                    * self.analyzer.samples_per_frame();
+            if self.pending.len() < hop {
+                return !self.tx.is_closed();
+            }
+            let samples: Vec<f32> =
+                self.pending.drain(..hop).collect();
+            self.hops += 1;
+            // `dropped` is a running total, every frame
+            // reports it
+            if !self.analyze(&samples, dropped) {
+                return false;
+            }
+        }
+    }
+
+    /// `handle` for one buffer, or one hop
+    fn analyze(
+        &mut self,
+        samples: &[f32],
+        dropped: u64,
+    ) -> bool {
+        self.buffers += 1;
+        if !self.buffers.is_multiple_of(self.decimation) {
+            self.analyzer.skip(samples);
+            return !self.tx.is_closed();
+        }
+        let mut frame = self.analyzer.process(samples);
+        if self.decimation > 1 {
+            frame.stats = analysis_stats(
+                frame.stats.sample_rate,
+                frame.stats.fft_size,
+                frame.stats.hop * self.decimation,
+            );
+        }
+        frame.dropped_samples += dropped + self.unsent;
+        if let Some(hook) = &self.on_frame {
+            hook.call(&frame);
+        }
+        match self.tx.try_send(frame) {
+            Ok(()) => true,
+            Err(TrySendError::Full(frame)) => {
+                self.unsent += frame.samples.len() as u64;
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// How many sample frames the `hop`th hop covers, counting
+/// from 0, to analyze `analysis_rate` frames per second of
+/// input at `sample_rate`. Where the rate doesn't divide the
+/// sample rate evenly the hops differ by one, so that over
+/// time they average out to exactly the analysis rate.
+pub fn hop_len(
+    sample_rate: u32,
+    analysis_rate: f32,
+    hop: u64,
+) -> usize {
+    let start = |hop: u64| {
+        (hop as f64 * sample_rate as f64
+            / analysis_rate as f64)
+            .round() as u64
     };
+    // never slower than a sample at a time
+    (start(hop + 1) - start(hop)).max(1) as usize
+}
+
+/// How the input callback passes its buffers on, one way or
+/// the other depending on the `AnalysisLocation`
+#[derive(Clone)]
+pub enum Handoff {
+    /// to the analysis thread
+    Queue(SampleQueue),
+    /// straight through the pipeline, right there in the
+    /// callback
+    Inline(Arc<Mutex<AnalysisPipeline>>),
+}
+
+impl Handoff {
+    pub fn push(&self, samples: Vec<f32>) {
+        match self {
+            Handoff::Queue(queue) => queue.push(samples),
+            Handoff::Inline(pipeline) => {
+                // a frame hook that panicked poisons the
+                // lock, nothing more gets analyzed after it
+                if let Ok(mut pipeline) = pipeline.lock() {
+                    pipeline.handle(&samples, 0);
+                }
+            }
+        }
+    }
+
+    /// The inline pipeline's receiver has gone away. Queued
+    /// buffers can't tell, the analysis thread finishing is
+    /// the sign there.
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Handoff::Queue(_) => false,
+            Handoff::Inline(pipeline) => {
+                pipeline.lock().map_or(true, |pipeline| {
+                    pipeline.is_closed()
+                })
+            }
+        }
+    }
+}
+
+/// Sets up `pipeline` to run at `location`: on an analysis
+/// thread fed by a queue made with `queue_options`, or
+/// inline with no thread at all
+pub fn handoff(
+    pipeline: AnalysisPipeline,
+    location: AnalysisLocation,
+    queue_options: QueueOptions,
+) -> (Handoff, Option<thread::JoinHandle<()>>) {
+    match location {
+        AnalysisLocation::Thread => {
+            let (queue, thread) = spawn_analysis_thread(
+                pipeline,
+                queue_options,
+            );
+            (Handoff::Queue(queue), Some(thread))
+        }
+        AnalysisLocation::Callback => (
+            Handoff::Inline(Arc::new(Mutex::new(pipeline))),
+            None,
+        ),
+    }
+}
+
+/// Runs `pipeline` over every buffer pushed into the
+/// returned queue on a dedicated thread. The thread stops
+/// once the pipeline's receiver is gone.
+pub fn spawn_analysis_thread(
+    mut pipeline: AnalysisPipeline,
+    queue_options: QueueOptions,
+) -> (SampleQueue, thread::JoinHandle<()>) {
+    let (queue_tx, queue_rx) = std::sync::mpsc::channel();
+    let handle = thread::spawn(move || {
+        let queue: SampleQueue = queue_rx.recv().unwrap();
+        while !pipeline.is_closed() {
+            while let Some(samples) = queue.pop() {
+                if !pipeline.handle(
+                    &samples,
+                    queue.dropped_samples(),
+                ) {
+                    return;
+                }
+            }
+            // woken early by every push, the timeout is only
+            // there to notice the receiver going away
+            thread::park_timeout(Duration::from_millis(
+                100,
+            ));
+        }
+    });
+    let queue = SampleQueue::new(
+        queue_options,
+        handle.thread().clone(),
+    );
+    queue_tx.send(queue.clone()).unwrap();
+    (queue, handle)
+}
+
+/// The config to open the input with, asking for
+/// `buffer_size` frames per callback when the device says
+/// it supports that size.
+///
+/// Each callback buffer, `buffer_size` frames of every
+/// channel interleaved, becomes one fft, so the buffer size
+/// sets the analysis rate. Buffers with more samples than
+/// the fft size have the extra dropped (and reported as
+/// such), smaller ones are zero padded. A mono input with
+/// a buffer size equal to the fft size uses every sample
+/// exactly once.
+pub fn stream_config(
+    supported: &cpal::SupportedStreamConfig,
+    buffer_size: Option<u32>,
+) -> cpal::StreamConfig {
+    let mut config = supported.config();
+    let Some(frames) = buffer_size else {
+        return config;
+    };
+    match supported.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max }
+            if !(*min..=*max).contains(&frames) =>
+        {
+            eprintln!(
+                "warning: buffer size {frames} is outside the \
+                 {min}..={max} frames the input supports, \
+                 using its default"
+            );
+        }
+        _ => {
+            config.buffer_size =
+                cpal::BufferSize::Fixed(frames)
+        }
+    }
+    config
+}
+
+/// Looks up an input device by name, "default" being the
+/// host's default input
+fn find_input_device(
+    host: &cpal::Host,
+    name: &str,
+) -> Result<Option<cpal::Device>, PngTuberAudioError> {
+    Ok(if name == "default" {
+        host.default_input_device()
+    } else {
+        host.input_devices()?.find(|x| {
+            x.name().map(|y| y == name).unwrap_or(false)
+        })
+    })
+}
+
+/// An audio host cpal was built with, e.g. ALSA or JACK
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    pub id: cpal::HostId,
+    /// what `cpal::default_host` picks
+    pub is_default: bool,
+    /// why it couldn't be started, `None` if it could
+    pub error: Option<String>,
+}
+
+impl HostInfo {
+    pub fn name(&self) -> &'static str {
+        self.id.name()
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Every host cpal reports as available on this system,
+/// each started once to check. One that fails to start, such
+/// as JACK with no server running, is kept with its error
+/// rather than left out.
+pub fn list_hosts() -> Vec<HostInfo> {
+    let default = cpal::default_host().id();
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| HostInfo {
+            id,
+            is_default: id == default,
+            error: cpal::host_from_id(id)
+                .err()
+                .map(|err| err.to_string()),
+        })
+        .collect()
+}
+
+/// Sample rates offered as choices when a device supports a
+/// whole range of them
+pub const COMMON_SAMPLE_RATES: [u32; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200,
+    96000, 176400, 192000,
+];
+
+/// What an input device can be opened with, each list
+/// sorted and without repeats
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Serialize,
+)]
+pub struct DeviceCapabilities {
+    pub channels: Vec<u16>,
+    /// every `COMMON_SAMPLE_RATES` entry inside one of the
+    /// device's ranges, plus the ends of each range
+    pub sample_rates: Vec<u32>,
+    /// in the order the device first reported them
+    #[serde(serialize_with = "format_names")]
+    pub sample_formats: Vec<cpal::SampleFormat>,
+}
+
+/// Sample formats by name ("f32", "i16" and so on), cpal's
+/// own type doesn't serialize
+fn format_names<S: Serializer>(
+    formats: &[cpal::SampleFormat],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(
+        formats.iter().map(ToString::to_string),
+    )
+}
+
+impl DeviceCapabilities {
+    /// Collects the choices out of a device's supported
+    /// config ranges
+    pub fn from_ranges(
+        ranges: impl IntoIterator<
+            Item = SupportedStreamConfigRange,
+        >,
+    ) -> Self {
+        let mut channels = BTreeSet::new();
+        let mut sample_rates = BTreeSet::new();
+        let mut sample_formats = vec![];
+        for range in ranges {
+            channels.insert(range.channels());
+            let (min, max) = (
+                range.min_sample_rate().0,
+                range.max_sample_rate().0,
+            );
+            sample_rates.extend([min, max]);
+            sample_rates.extend(
+                COMMON_SAMPLE_RATES.iter().filter(|rate| {
+                    (min..=max).contains(*rate)
+                }),
+            );
+            if !sample_formats
+                .contains(&range.sample_format())
+            {
+                sample_formats.push(range.sample_format());
+            }
+        }
+        DeviceCapabilities {
+            channels: channels.into_iter().collect(),
+            sample_rates: sample_rates
+                .into_iter()
+                .collect(),
+            sample_formats,
+        }
+    }
+}
+
+/// The channel counts, sample rates and sample formats the
+/// input device `name` ("default" for the host's default)
+/// supports, for offering valid choices before `run`.
+///
+/// A device that can't be found, or that fails to list its
+/// configs (some drivers do while another app holds them),
+/// is an error rather than an empty set of capabilities.
+pub fn device_capabilities(
+    name: &str,
+) -> Result<DeviceCapabilities, PngTuberAudioError> {
+    let host = cpal::default_host();
+    let device = find_input_device(&host, name)?
+        .ok_or_else(|| {
+            PngTuberAudioError::DeviceNotFound {
+                name: name.to_string(),
+            }
+        })?;
+    Ok(DeviceCapabilities::from_ranges(
+        device.supported_input_configs()?,
+    ))
+}
+
+/// An input device and what it supports, for listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// the host's default input
+    pub is_default: bool,
+    /// `None` if the device couldn't list its configs
+    pub capabilities: Option<DeviceCapabilities>,
+    /// why it couldn't, `None` if it could
+    pub error: Option<String>,
+}
+
+/// Every input device on the default host with its
+/// capabilities. A device that fails to list its configs is
+/// kept with the error, like `list_hosts` keeps a host that
+/// won't start.
+pub fn list_input_devices(
+) -> Result<Vec<DeviceInfo>, PngTuberAudioError> {
+    let host = cpal::default_host();
+    let default = host
+        .default_input_device()
+        .and_then(|device| device.name().ok());
+    host.input_devices()?
+        .map(|device| {
+            let name = device.name()?;
+            let (capabilities, error) = match device
+                .supported_input_configs()
+            {
+                Ok(ranges) => (
+                    Some(DeviceCapabilities::from_ranges(
+                        ranges,
+                    )),
+                    None,
+                ),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            Ok(DeviceInfo {
+                is_default: default.as_ref() == Some(&name),
+                name,
+                capabilities,
+                error,
+            })
+        })
+        .collect()
+}
+
+/// Opens the second input for `MixOptions`, downmixing it
+/// and resampling it to `sample_rate` as it arrives
+fn build_mix_stream(
+    host: &cpal::Host,
+    options: &MixOptions,
+    sample_rate: u32,
+) -> Result<(Mixer, cpal::Stream), PngTuberAudioError> {
+    let device = find_input_device(host, &options.device)?
+        .ok_or_else(|| {
+            PngTuberAudioError::DeviceNotFound {
+                name: options.device.clone(),
+            }
+        })?;
+    eprintln!("Mix device: {}", device.name()?);
+
+    let config = device.default_input_config()?;
+    if config.sample_format() != cpal::SampleFormat::F32 {
+        return Err(PngTuberAudioError::UnsupportedSampleFormat {
+            sample_format: config.sample_format(),
+            message: "mix devices have to deliver f32 samples"
+                .to_string(),
+        });
+    }
+    let channels = config.channels();
+    let mut resampler =
+        Resampler::new(config.sample_rate().0, sample_rate);
+    // two devices' clocks never quite agree, capping the
+    // queue at 100ms keeps the drift from adding up
+    let mixer = Mixer::new(sample_rate as usize / 10);
+    let feed = mixer.clone();
+
+    let stream = device.build_input_stream(
+        &config.config(),
+        move |data: &[f32], _: &_| {
+            feed.feed(
+                &resampler
+                    .process(&downmix(data, channels)),
+            );
+        },
+        move |err| {
+            eprintln!(
+                "an error occurred on mix stream: {}",
+                err
+            );
+        },
+        None,
+    )?;
+    stream.play()?;
+
+    Ok((mixer, stream))
+}
+
+/// Opens the monitor output with the same rate and channel
+/// count as the input so samples can be copied straight
+/// across.
+fn build_monitor_stream(
+    host: &cpal::Host,
+    options: &MonitorOptions,
+    input_config: &cpal::StreamConfig,
+    clip_guard: bool,
+) -> Result<(MonitorBuffer, cpal::Stream), PngTuberAudioError>
+{
+    let device = if options.device == "default" {
+        host.default_output_device()
+    } else {
+        host.output_devices()?.find(|x| {
+            x.name()
+                .map(|y| y == options.device)
+                .unwrap_or(false)
+        })
+    }
+    .ok_or_else(|| {
+        PngTuberAudioError::DeviceNotFound {
+            name: options.device.clone(),
+        }
+    })?;
+
+    eprintln!("Monitor device: {}", device.name()?);
+    eprintln!(
+        "warning: monitoring a microphone through speakers \
+         can cause feedback, use headphones"
+    );
+
+    let capacity = (input_config.sample_rate.0 as usize
+        * input_config.channels as usize
+        * options.latency_ms as usize)
+        / 1000;
+    let buffer = MonitorBuffer::new(capacity.max(1));
+    let output_buffer = buffer.clone();
+    let gain = options.gain;
+    let channels = input_config.channels;
+    let mut clip_guard = clip_guard.then(|| {
+        ClipGuard::new(input_config.sample_rate.0)
+    });
 
+    let stream = device.build_output_stream(
+        input_config,
+        move |data: &mut [f32], _: &_| {
+            output_buffer.fill(data, gain);
+            if let Some(guard) = &mut clip_guard {
+                guard.process(data, channels);
+            }
+        },
+        move |err| {
+            eprintln!(
+                "an error occurred on monitor stream: {}",
+                err
+            );
+        },
+        None,
+    )?;
     stream.play()?;
 
-    // Let recording go for roughly three seconds.
-    // std::thread::sleep(std::time::Duration::from_secs(3));
-    // drop(stream);
-    // writer.lock().unwrap().take().unwrap().finalize()?;
-    // println!("Recording {} complete!", PATH);
-    Ok(())
+    Ok((buffer, stream))
 }
 
 fn sample_format(
@@ -298,9 +1878,11 @@ fn wav_spec_from_config(
     }
 }
 
+#[allow(dead_code)]
 type WavWriterHandle =
     Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>;
 
+#[allow(dead_code)]
 fn write_input_data<T, U>(
     input: &[T],
     writer: &WavWriterHandle,
@@ -318,12 +1900,666 @@ fn write_input_data<T, U>(
     }
 }
 
-// any data in the top "half" of the data vec is an alias
-// (aka a mirrored exact copy) of the bottom half
-// if you took bins 0..10 and 10..20 then data at each
-// index:
-// 0,1,2,3,4,5,6,7,8,9 == 19,18,17,16,15,14,13,12,11,10
-pub fn remove_mirroring(data: &[f32]) -> Vec<f32> {
-    let len = data.len() / 2 + 1;
-    data[..len].to_vec()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{channel, Receiver};
+
+    /// `len` samples of a sine at `hz`
+    fn sine(
+        hz: f32,
+        sample_rate: u32,
+        len: usize,
+    ) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                (2.0 * std::f32::consts::PI * hz * i as f32
+                    / sample_rate as f32)
+                    .sin()
+            })
+            .collect()
+    }
+
+    /// A pipeline for `config` at 48kHz and the receiving
+    /// end of its frames
+    fn pipeline(
+        config: &AudioConfig,
+        capacity: usize,
+    ) -> (AnalysisPipeline, Receiver<AnalysisFrame>) {
+        let (tx, rx) = channel(capacity);
+        let pipeline = config.pipeline(
+            config.analyzer(48000),
+            Arc::new(AtomicU32::new(48000)),
+            tx,
+        );
+        (pipeline, rx)
+    }
+
+    #[test]
+    fn the_monitor_buffer_carries_input_to_output() {
+        let buffer = MonitorBuffer::new(8);
+        // what the input callback would do
+        let input = buffer.clone();
+        input.push(&[0.1, 0.2, 0.3]);
+        // and the output callback, asking for more than is
+        // queued
+        let mut output = [1.0; 4];
+        buffer.fill(&mut output, 2.0);
+        assert_eq!(output, [0.2, 0.4, 0.6, 0.0]);
+
+        // an output that falls behind loses the oldest
+        // samples rather than building up latency
+        input.push(&[1.0; 6]);
+        input.push(&[2.0, 3.0, 4.0, 5.0]);
+        let mut output = [0.0; 10];
+        buffer.fill(&mut output, 1.0);
+        assert_eq!(
+            output,
+            [
+                1.0, 1.0, 1.0, 1.0, 2.0, 3.0, 4.0, 5.0,
+                0.0, 0.0
+            ]
+        );
+    }
+
+    #[test]
+    fn the_analysis_thread_analyzes_pushed_buffers() {
+        let config = AudioConfig::builder().build();
+        let (pipeline, mut rx) = pipeline(&config, 8);
+        let (queue, thread) = spawn_analysis_thread(
+            pipeline,
+            QueueOptions::default(),
+        );
+        let buffers = [
+            sine(1000.0, 48000, 1024),
+            sine(5000.0, 48000, 1024),
+        ];
+        for buffer in &buffers {
+            queue.push(buffer.clone());
+        }
+        let mut expected = config.analyzer(48000);
+        for buffer in &buffers {
+            let frame = rx.blocking_recv().unwrap();
+            let direct = expected.process(buffer);
+            assert_eq!(frame.samples, *buffer);
+            assert_eq!(frame.spectrum, direct.spectrum);
+            assert_eq!(frame.bands, direct.bands);
+            assert_eq!(
+                frame.sample_index,
+                direct.sample_index
+            );
+            assert_eq!(frame.dropped_samples, 0);
+        }
+        // the thread finishes once nobody's listening
+        drop(rx);
+        queue.push(vec![0.0; 1024]);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn a_full_queue_drops_and_counts_buffers() {
+        let options = |drop_policy| QueueOptions {
+            capacity: 2,
+            drop_policy,
+            decimation: 1,
+        };
+        // nothing consumes, so the queue overflows
+        let oldest = SampleQueue::new(
+            options(DropPolicy::Oldest),
+            thread::current(),
+        );
+        let newest = SampleQueue::new(
+            options(DropPolicy::Newest),
+            thread::current(),
+        );
+        for i in 0..5 {
+            oldest.push(vec![i as f32; 10]);
+            newest.push(vec![i as f32; 10]);
+        }
+        assert_eq!(oldest.dropped_samples(), 30);
+        assert_eq!(newest.dropped_samples(), 30);
+        let firsts = |queue: &SampleQueue| {
+            std::iter::from_fn(|| queue.pop())
+                .map(|buffer| buffer[0])
+                .collect::<Vec<f32>>()
+        };
+        assert_eq!(firsts(&oldest), [3.0, 4.0]);
+        assert_eq!(firsts(&newest), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn the_analyzer_follows_a_reopened_inputs_rate() {
+        let config = AudioConfig::builder().build();
+        let (tx, mut rx) = channel(8);
+        let sample_rate = Arc::new(AtomicU32::new(48000));
+        let mut pipeline = config.pipeline(
+            config.analyzer(48000),
+            sample_rate.clone(),
+            tx,
+        );
+        let buffer = sine(1000.0, 44100, 1024);
+        assert!(pipeline.handle(&buffer, 0));
+        let before = rx.blocking_recv().unwrap();
+        // what reopening the input at a new rate does
+        sample_rate.store(44100, Ordering::Relaxed);
+        assert!(pipeline.handle(&buffer, 0));
+        let after = rx.blocking_recv().unwrap();
+        let direct =
+            config.analyzer(44100).process(&buffer);
+        assert_eq!(after.stats.sample_rate, 44100);
+        assert_eq!(after.bands, direct.bands);
+        assert_ne!(after.bands, before.bands);
+    }
+
+    #[test]
+    fn the_builder_applies_defaults_and_overrides() {
+        let config = AudioConfig::builder().build();
+        assert_eq!(config.device, "default");
+        assert_eq!(config.fft_size, FFT_SIZE);
+        assert_eq!(config.bands, default_bands());
+        assert_eq!(
+            *config.gains.lock().unwrap(),
+            vec![1.0; config.bands.len()]
+        );
+        assert!(config.monitor.is_none());
+        assert!(config.profile.is_none());
+        assert!(!config.jack);
+        assert_eq!(
+            config.band_weighting,
+            BandWeighting::Mean
+        );
+        assert_eq!(config.buffer_size, None);
+        assert_eq!(config.input_mode, InputMode::Real);
+        assert!(!config.clip_guard);
+        assert_eq!(
+            config.queue.capacity,
+            SAMPLE_QUEUE_CAPACITY
+        );
+        assert_eq!(
+            config.queue.drop_policy,
+            DropPolicy::Oldest
+        );
+        assert_eq!(
+            config.spectrum_type,
+            SpectrumType::Magnitude
+        );
+        assert_eq!(
+            config.analysis_location,
+            AnalysisLocation::Thread
+        );
+        assert!(config.gate.is_none());
+
+        let bands = vec![FreqRange {
+            name: "all".into(),
+            low: 20,
+            high: 20000,
+        }];
+        let config = AudioConfig::builder()
+            .device("usb mic")
+            .fft_size(4096)
+            .bands(bands.clone())
+            .jack(true)
+            .buffer_size(256)
+            .clip_guard(true)
+            .spectrum_type(SpectrumType::Power)
+            .build();
+        assert_eq!(config.device, "usb mic");
+        assert_eq!(config.fft_size, 4096);
+        assert_eq!(config.bands, bands);
+        // gains follow the bands they're for
+        assert_eq!(
+            *config.gains.lock().unwrap(),
+            vec![1.0]
+        );
+        assert!(config.jack);
+        assert_eq!(config.buffer_size, Some(256));
+        assert!(config.clip_guard);
+        assert_eq!(
+            config.spectrum_type,
+            SpectrumType::Power
+        );
+        // and what wasn't set keeps its default
+        assert!(config.monitor.is_none());
+        assert_eq!(
+            config.band_weighting,
+            BandWeighting::Mean
+        );
+    }
+
+    #[test]
+    fn the_stream_config_takes_a_supported_buffer_size() {
+        let supported = |buffer_size| {
+            cpal::SupportedStreamConfig::new(
+                2,
+                cpal::SampleRate(48000),
+                buffer_size,
+                cpal::SampleFormat::F32,
+            )
+        };
+        let range =
+            supported(cpal::SupportedBufferSize::Range {
+                min: 64,
+                max: 4096,
+            });
+        assert_eq!(
+            stream_config(&range, Some(512)).buffer_size,
+            cpal::BufferSize::Fixed(512)
+        );
+        // out of range falls back to the device's choice
+        assert_eq!(
+            stream_config(&range, Some(8192)).buffer_size,
+            cpal::BufferSize::Default
+        );
+        assert_eq!(
+            stream_config(&range, None).buffer_size,
+            cpal::BufferSize::Default
+        );
+        // devices that can't say get asked anyway
+        let unknown =
+            supported(cpal::SupportedBufferSize::Unknown);
+        let config = stream_config(&unknown, Some(512));
+        assert_eq!(
+            config.buffer_size,
+            cpal::BufferSize::Fixed(512)
+        );
+        assert_eq!(config.channels, 2);
+        assert_eq!(config.sample_rate.0, 48000);
+    }
+
+    #[test]
+    fn iq_input_needs_two_channels() {
+        let real = AudioConfig::builder().build();
+        assert!(real.check_channels(1).is_ok());
+        let iq = AudioConfig::builder()
+            .input_mode(InputMode::Iq)
+            .build();
+        assert!(iq.check_channels(2).is_ok());
+        for channels in [1, 4] {
+            assert!(matches!(
+                iq.check_channels(channels),
+                Err(ConfigError::IqChannels { channels: c })
+                    if c == channels
+            ));
+        }
+    }
+
+    #[test]
+    fn the_frame_hook_sees_every_frame_once() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let hook_seen = seen.clone();
+        let config = AudioConfig::builder()
+            .on_frame(Box::new(move |frame| {
+                hook_seen.lock().unwrap().push((
+                    frame.sample_index,
+                    frame.bands.clone(),
+                ))
+            }))
+            .build();
+        let (mut pipeline, mut rx) = pipeline(&config, 8);
+        for hz in [200.0, 1000.0, 6000.0] {
+            assert!(
+                pipeline.handle(&sine(hz, 48000, 1024), 0)
+            );
+        }
+        let sent: Vec<_> = (0..3)
+            .map(|_| {
+                let frame = rx.blocking_recv().unwrap();
+                (frame.sample_index, frame.bands)
+            })
+            .collect();
+        assert_eq!(*seen.lock().unwrap(), sent);
+    }
+
+    #[test]
+    fn a_stalled_receiver_costs_frames_not_time() {
+        let config = AudioConfig::builder().build();
+        // room for one frame, and nobody reading it yet
+        let (mut pipeline, mut rx) = pipeline(&config, 1);
+        let buffer = sine(1000.0, 48000, 1024);
+        let start = std::time::Instant::now();
+        for _ in 0..4 {
+            assert!(pipeline.handle(&buffer, 0));
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            rx.blocking_recv().unwrap().dropped_samples,
+            0
+        );
+        // the three that didn't fit are counted in the next
+        // frame that does
+        assert!(pipeline.handle(&buffer, 0));
+        assert_eq!(
+            rx.blocking_recv().unwrap().dropped_samples,
+            3 * 1024
+        );
+    }
+
+    #[test]
+    fn capabilities_are_collected_without_repeats() {
+        use cpal::{
+            SampleFormat, SampleRate, SupportedBufferSize,
+        };
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(min),
+                SampleRate(max),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let capabilities =
+            DeviceCapabilities::from_ranges([
+                range(2, 44100, 48000, SampleFormat::F32),
+                range(1, 44100, 48000, SampleFormat::F32),
+                range(2, 8000, 12000, SampleFormat::I16),
+                range(2, 96000, 96000, SampleFormat::F32),
+            ]);
+        assert_eq!(
+            capabilities,
+            DeviceCapabilities {
+                channels: vec![1, 2],
+                sample_rates: vec![
+                    8000, 11025, 12000, 44100, 48000, 96000
+                ],
+                sample_formats: vec![
+                    SampleFormat::F32,
+                    SampleFormat::I16
+                ],
+            }
+        );
+        assert_eq!(
+            DeviceCapabilities::from_ranges([]),
+            DeviceCapabilities::default()
+        );
+    }
+
+    #[test]
+    fn the_default_host_is_listed() {
+        let hosts = list_hosts();
+        let default: Vec<&HostInfo> = hosts
+            .iter()
+            .filter(|host| host.is_default)
+            .collect();
+        assert_eq!(default.len(), 1, "{hosts:?}");
+        assert_eq!(
+            default[0].id,
+            cpal::default_host().id()
+        );
+        assert!(default[0].is_available());
+    }
+
+    #[test]
+    fn decimation_analyzes_one_buffer_in_n() {
+        let frames = |decimation| {
+            let config = AudioConfig::builder()
+                .queue(QueueOptions {
+                    decimation,
+                    ..QueueOptions::default()
+                })
+                .build();
+            let (mut pipeline, mut rx) =
+                pipeline(&config, 20);
+            for _ in 0..9 {
+                assert!(pipeline
+                    .handle(&sine(1000.0, 48000, 1024), 0));
+            }
+            let mut frames = vec![];
+            while let Ok(frame) = rx.try_recv() {
+                frames.push(frame);
+            }
+            frames
+        };
+        let every = frames(1);
+        assert_eq!(every.len(), 9);
+        assert_eq!(every[0].stats.update_hz, 46.875);
+
+        let third = frames(3);
+        let indices: Vec<u64> =
+            third.iter().map(|f| f.sample_index).collect();
+        // the skipped buffers still move the clock on
+        assert_eq!(indices, [2048, 5120, 8192]);
+        for frame in &third {
+            assert_eq!(frame.stats.update_hz, 46.875 / 3.0);
+            assert_eq!(frame.stats.hop, 3 * 1024);
+            // skipping isn't dropping
+            assert_eq!(frame.dropped_samples, 0);
+        }
+    }
+
+    #[test]
+    fn each_analysis_location_runs_only_its_own_path() {
+        for location in [
+            AnalysisLocation::Thread,
+            AnalysisLocation::Callback,
+        ] {
+            // which thread each frame was analyzed on
+            let threads = Arc::new(Mutex::new(vec![]));
+            let hook_threads = threads.clone();
+            let config = AudioConfig::builder()
+                .analysis_location(location)
+                .on_frame(Box::new(move |_| {
+                    hook_threads
+                        .lock()
+                        .unwrap()
+                        .push(thread::current().id())
+                }))
+                .build();
+            let (pipeline, mut rx) = pipeline(&config, 8);
+            let (handoff, analysis_thread) = handoff(
+                pipeline,
+                config.analysis_location,
+                config.queue,
+            );
+            for _ in 0..3 {
+                handoff.push(sine(1000.0, 48000, 1024));
+            }
+            for _ in 0..3 {
+                assert!(rx.blocking_recv().is_some());
+            }
+            let threads = threads.lock().unwrap().clone();
+            assert_eq!(threads.len(), 3);
+            match location {
+                AnalysisLocation::Thread => {
+                    assert!(matches!(
+                        handoff,
+                        Handoff::Queue(_)
+                    ));
+                    let analysis_thread =
+                        analysis_thread.unwrap();
+                    assert!(threads.iter().all(|id| {
+                        *id == analysis_thread.thread().id()
+                    }));
+                    drop(rx);
+                    analysis_thread.join().unwrap();
+                }
+                AnalysisLocation::Callback => {
+                    // no thread, the frames are made right
+                    // there in the push
+                    assert!(analysis_thread.is_none());
+                    assert!(matches!(
+                        handoff,
+                        Handoff::Inline(_)
+                    ));
+                    assert!(threads
+                        .iter()
+                        .all(|id| *id
+                            == thread::current().id()));
+                    drop(rx);
+                    assert!(handoff.is_closed());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_analysis_rate_holds_whatever_the_sample_rate() {
+        let config = AudioConfig::builder()
+            .analysis_rate(60.0)
+            .build();
+        for rate in [22050, 44100, 48000, 96000] {
+            let (tx, mut rx) = channel(200);
+            let mut pipeline = config.pipeline(
+                config.analyzer(rate),
+                Arc::new(AtomicU32::new(rate)),
+                tx,
+            );
+            // a second of input in awkward device buffers
+            let input = sine(1000.0, rate, rate as usize);
+            for buffer in input.chunks(441) {
+                assert!(pipeline.handle(buffer, 0));
+            }
+            let mut frames = 0;
+            while rx.try_recv().is_ok() {
+                frames += 1;
+            }
+            assert_eq!(frames, 60, "{rate}");
+        }
+    }
+
+    #[test]
+    fn every_hop_reports_the_running_dropped_total() {
+        let config = AudioConfig::builder()
+            .analysis_rate(100.0)
+            .build();
+        let (mut pipeline, mut rx) = pipeline(&config, 20);
+        // one buffer of four hops, after 300 samples were
+        // lost on the way
+        assert!(pipeline
+            .handle(&sine(1000.0, 48000, 1920), 300));
+        let dropped: Vec<u64> = (0..4)
+            .map(|_| rx.try_recv().unwrap().dropped_samples)
+            .collect();
+        assert_eq!(dropped, [300; 4]);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn device_listing_serializes_for_scripts() {
+        use cpal::SampleFormat;
+        let devices = vec![
+            DeviceInfo {
+                name: "USB Mic".into(),
+                is_default: true,
+                capabilities: Some(DeviceCapabilities {
+                    channels: vec![1, 2],
+                    sample_rates: vec![44100, 48000],
+                    sample_formats: vec![
+                        SampleFormat::F32,
+                        SampleFormat::I16,
+                    ],
+                }),
+                error: None,
+            },
+            DeviceInfo {
+                name: "Broken".into(),
+                is_default: false,
+                capabilities: None,
+                error: Some("device busy".into()),
+            },
+        ];
+        let json: serde_json::Value = serde_json::from_str(
+            &serde_json::to_string_pretty(&devices)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {
+                    "name": "USB Mic",
+                    "is_default": true,
+                    "capabilities": {
+                        "channels": [1, 2],
+                        "sample_rates": [44100, 48000],
+                        "sample_formats": ["f32", "i16"],
+                    },
+                    "error": null,
+                },
+                {
+                    "name": "Broken",
+                    "is_default": false,
+                    "capabilities": null,
+                    "error": "device busy",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn a_rate_change_keeps_the_history_and_rebuilds_the_bins(
+    ) {
+        use crate::{
+            analysis::{self, default_bands},
+            AppState,
+        };
+        let config = AudioConfig::builder().build();
+        let (tx, mut rx) = channel(8);
+        let sample_rate = Arc::new(AtomicU32::new(48000));
+        let mut pipeline = config.pipeline(
+            config.analyzer(48000),
+            sample_rate.clone(),
+            tx,
+        );
+        let mut state = AppState::new(default_bands());
+        // what main does with each frame
+        let mut feed = |state: &mut AppState,
+                        pipeline: &mut AnalysisPipeline,
+                        rate| {
+            assert!(pipeline
+                .handle(&sine(1000.0, rate, 1024), 0));
+            let frame = rx.try_recv().unwrap();
+            assert_eq!(frame.stats.sample_rate, rate);
+            state.push_history(
+                analysis::peak_dbfs(&frame.samples),
+                frame.spectrum,
+            );
+        };
+        for _ in 0..3 {
+            feed(&mut state, &mut pipeline, 48000);
+        }
+        let before = (
+            state.decibels.clone(),
+            state.spectrogram.clone(),
+            state.level_histogram.clone(),
+        );
+
+        // the input comes back at a different rate
+        sample_rate.store(44100, Ordering::Relaxed);
+        feed(&mut state, &mut pipeline, 44100);
+        assert_eq!(state.decibels.len(), 4);
+        assert_eq!(state.decibels[..3], before.0[..]);
+        assert_eq!(state.spectrogram.len(), 4);
+        assert!(state
+            .spectrogram
+            .iter()
+            .skip(1)
+            .eq(before.1.iter()));
+        assert_eq!(
+            state.level_histogram.total(),
+            before.2.total() + 1
+        );
+        assert!(state
+            .level_histogram
+            .counts()
+            .iter()
+            .zip(before.2.counts())
+            .all(|(after, before)| after >= before));
+
+        // the bands are the same frequencies, now at the new
+        // rate's bins
+        assert_eq!(state.freq_ranges, default_bands());
+        assert_eq!(
+            pipeline.analyzer.bands(),
+            default_bands()
+        );
+        assert_eq!(
+            pipeline.analyzer.band_bins(),
+            config.analyzer(44100).band_bins()
+        );
+        assert_ne!(
+            pipeline.analyzer.band_bins(),
+            config.analyzer(48000).band_bins()
+        );
+    }
 }
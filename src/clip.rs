@@ -0,0 +1,150 @@
+/// A sample at or above this (in either direction) counts as
+/// clipped
+pub const CLIP_LEVEL: f32 = 0.999;
+
+/// Clipped frames in a row before it counts as clipping
+/// rather than a single peak that happened to touch full
+/// scale
+const CLIP_RUN: usize = 4;
+
+/// How far each burst of clipping backs the gain off
+const BACKOFF_DB: f32 = 3.0;
+/// and the most it will back off in total
+const MAX_REDUCTION_DB: f32 = 24.0;
+/// Clipping within this long of a backoff is the same burst
+/// and doesn't back off again
+const COOLDOWN_SECONDS: f32 = 0.01;
+
+/// How long the signal has to stay clean before the gain
+/// starts coming back
+const HOLD_SECONDS: f32 = 1.0;
+/// and how fast it comes back once it does
+const RECOVERY_DB_PER_SECOND: f32 = 3.0;
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Backs the gain off when the signal after it keeps
+/// clipping and ramps it back up once it stops.
+///
+/// It runs after whatever gain has been applied, and the
+/// reduction only ever scales that down, so the configured
+/// gain is never exceeded.
+#[derive(Debug, Clone)]
+pub struct ClipGuard {
+    /// linear multiplier on top of the configured gain,
+    /// 1 until clipping is seen
+    reduction: f32,
+    /// clipped frames in a row so far
+    run: usize,
+    /// frames since the last backoff
+    since_backoff: usize,
+    cooldown: usize,
+    hold: usize,
+    /// multiplier applied to `reduction` per frame while
+    /// recovering
+    recovery_step: f32,
+}
+
+impl ClipGuard {
+    pub fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate.max(1) as f32;
+        ClipGuard {
+            reduction: 1.0,
+            run: 0,
+            since_backoff: usize::MAX,
+            cooldown: (COOLDOWN_SECONDS * rate) as usize,
+            hold: (HOLD_SECONDS * rate) as usize,
+            recovery_step: db_to_gain(
+                RECOVERY_DB_PER_SECOND / rate,
+            ),
+        }
+    }
+
+    /// The current reduction as a linear gain, 1 when
+    /// nothing has clipped
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+
+    /// Scales interleaved `samples` by the reduction in
+    /// place, backing off or recovering as it goes. A frame
+    /// counts as clipped if any of its channels is.
+    pub fn process(
+        &mut self,
+        samples: &mut [f32],
+        channels: u16,
+    ) {
+        let floor = db_to_gain(-MAX_REDUCTION_DB);
+        for frame in
+            samples.chunks_mut(channels.max(1) as usize)
+        {
+            let mut clipped = false;
+            for sample in frame.iter_mut() {
+                *sample *= self.reduction;
+                clipped |= sample.abs() >= CLIP_LEVEL;
+            }
+
+            self.run =
+                if clipped { self.run + 1 } else { 0 };
+            self.since_backoff =
+                self.since_backoff.saturating_add(1);
+            if self.run >= CLIP_RUN
+                && self.since_backoff > self.cooldown
+            {
+                self.reduction = (self.reduction
+                    * db_to_gain(-BACKOFF_DB))
+                .max(floor);
+                self.since_backoff = 0;
+            } else if self.since_backoff > self.hold {
+                self.reduction = (self.reduction
+                    * self.recovery_step)
+                    .min(1.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sustained_clipping_backs_off_and_recovers_after() {
+        let mut guard = ClipGuard::new(48000);
+        // a lone full scale peak is left alone
+        let mut peak = [0.1, 1.0, 0.1, 0.1];
+        guard.process(&mut peak, 1);
+        assert_eq!(guard.reduction(), 1.0);
+
+        // 100ms of a signal at twice full scale backs off
+        // until it stops clipping, 3dB at a time
+        let mut hot = vec![2.0; 4800];
+        guard.process(&mut hot, 1);
+        let reduction = guard.reduction();
+        assert!(
+            (reduction - db_to_gain(-9.0)).abs() < 1e-4,
+            "{reduction}"
+        );
+        assert!(hot.last().unwrap().abs() < CLIP_LEVEL);
+
+        // clean for less than the hold, nothing changes
+        let clean = |seconds: f32| {
+            vec![0.1; (seconds * 48000.0) as usize]
+        };
+        guard.process(&mut clean(0.5), 1);
+        assert_eq!(guard.reduction(), reduction);
+        // then it ramps back at 3dB a second, the hold
+        // having started at the last backoff, to no more
+        // than the configured gain
+        guard.process(&mut clean(1.5), 1);
+        let recovering = guard.reduction();
+        assert!(
+            recovering > reduction && recovering < 1.0,
+            "{recovering}"
+        );
+        guard.process(&mut clean(5.0), 1);
+        assert_eq!(guard.reduction(), 1.0);
+    }
+}
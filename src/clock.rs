@@ -0,0 +1,155 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Where the render loop's timers get the time from, so
+/// something other than the wall clock can drive them
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when it's told to, for stepping
+/// through timed behaviour deterministically. Clones share
+/// the same time, so one can be handed to the code under
+/// test and another kept to advance it.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    /// Starts at the real time and stays there until
+    /// `advance`d
+    pub fn new() -> Self {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// How often something happens, from the gaps between its
+/// ticks. The gaps are smoothed rather than trusted one at a
+/// time, since ticks can bunch up behind a slow one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateMeter {
+    last: Option<Instant>,
+    /// the smoothed gap, in seconds, 0 before the first
+    interval: f32,
+}
+
+impl RateMeter {
+    /// Notes a tick at `now` and returns the gap since the
+    /// one before (zero for the first), and the smoothed
+    /// rate per second once there's been a gap to go on
+    pub fn tick(
+        &mut self,
+        now: Instant,
+    ) -> (Duration, Option<f32>) {
+        let Some(last) = self.last.replace(now) else {
+            return (Duration::ZERO, None);
+        };
+        let gap = now.saturating_duration_since(last);
+        let seconds = gap.as_secs_f32();
+        self.interval = if self.interval == 0.0 {
+            seconds
+        } else {
+            self.interval * 0.95 + seconds * 0.05
+        };
+        (
+            gap,
+            (self.interval > 0.0)
+                .then(|| 1.0 / self.interval),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tui::{
+        FlashEnvelope, IdleOptions, IdleTimer, FLASH_DECAY,
+    };
+
+    #[test]
+    fn a_manual_clock_only_moves_when_told() {
+        let clock = ManualClock::new();
+        let handed_out = clock.clone();
+        let start = handed_out.now();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(handed_out.now(), start);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(
+            handed_out.now() - start,
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn the_idle_break_starts_on_time() {
+        let clock = ManualClock::new();
+        let mut idle = IdleTimer::new(IdleOptions {
+            after: Duration::from_secs(10),
+            threshold_db: -50.0,
+            fps: 2.0,
+        });
+        assert!(!idle.update(-70.0, clock.now()));
+        clock.advance(Duration::from_millis(9999));
+        assert!(!idle.update(-70.0, clock.now()));
+        clock.advance(Duration::from_millis(1));
+        assert!(idle.update(-70.0, clock.now()));
+        // a sound ends it, and the wait starts over
+        assert!(!idle.update(-20.0, clock.now()));
+        clock.advance(Duration::from_secs(5));
+        assert!(!idle.update(-70.0, clock.now()));
+        clock.advance(Duration::from_secs(10));
+        assert!(idle.update(-70.0, clock.now()));
+    }
+
+    #[test]
+    fn the_flash_decays_on_schedule() {
+        let clock = ManualClock::new();
+        let mut meter = RateMeter::default();
+        let mut flash = FlashEnvelope::default();
+        meter.tick(clock.now());
+        flash.update(0, 1.0, Duration::ZERO);
+        flash.update(1, 1.0, Duration::ZERO);
+        // one decay time later it's down by e, however many
+        // frames that took
+        for _ in 0..10 {
+            clock.advance(FLASH_DECAY / 10);
+            let (gap, rate) = meter.tick(clock.now());
+            assert!((rate.unwrap() - 66.67).abs() < 0.1);
+            flash.update(1, 1.0, gap);
+        }
+        let expected = (-1.0f32).exp();
+        assert!((flash.level() - expected).abs() < 1e-4);
+    }
+}
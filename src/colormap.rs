@@ -0,0 +1,354 @@
+use ratatui::style::Color;
+
+/// Maps a normalized magnitude onto a color ramp.
+///
+/// viridis and magma are perceptually uniform, so equal
+/// steps in magnitude look like equal steps in brightness,
+/// which a single-hue ramp doesn't manage.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Magma,
+    Grayscale,
+}
+
+// evenly spaced stops sampled from matplotlib's colormaps
+const VIRIDIS: [(u8, u8, u8); 5] = [
+    (0x44, 0x01, 0x54),
+    (0x3b, 0x52, 0x8b),
+    (0x21, 0x91, 0x8c),
+    (0x5e, 0xc9, 0x62),
+    (0xfd, 0xe7, 0x25),
+];
+const MAGMA: [(u8, u8, u8); 6] = [
+    (0x00, 0x00, 0x04),
+    (0x3b, 0x0f, 0x70),
+    (0x8c, 0x29, 0x81),
+    (0xde, 0x49, 0x68),
+    (0xfe, 0x9f, 0x6d),
+    (0xfc, 0xfd, 0xbf),
+];
+const GRAYSCALE: [(u8, u8, u8); 2] =
+    [(0x00, 0x00, 0x00), (0xff, 0xff, 0xff)];
+
+impl Colormap {
+    /// The color at `t`, where 0 is the bottom of the ramp
+    /// and 1 the top. Values outside 0..=1 are clamped.
+    pub fn sample(&self, t: f32) -> Color {
+        let stops: &[(u8, u8, u8)] = match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Grayscale => &GRAYSCALE,
+        };
+        let (r, g, b) = interpolate(stops, t);
+        Color::Rgb(r, g, b)
+    }
+}
+
+fn interpolate(
+    stops: &[(u8, u8, u8)],
+    t: f32,
+) -> (u8, u8, u8) {
+    // NaN clamps to the bottom of the ramp too
+    let t =
+        if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    let position = t * (stops.len() - 1) as f32;
+    let index =
+        (position.floor() as usize).min(stops.len() - 2);
+    let fraction = position - index as f32;
+
+    let lerp = |a: u8, b: u8| {
+        (a as f32 + (b as f32 - a as f32) * fraction)
+            .round() as u8
+    };
+    let (from, to) = (stops[index], stops[index + 1]);
+    (
+        lerp(from.0, to.0),
+        lerp(from.1, to.1),
+        lerp(from.2, to.2),
+    )
+}
+
+/// How many colors the terminal can show. Colors from the
+/// colormaps are full 24-bit and get rounded to the nearest
+/// one the terminal has.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum ColorDepth {
+    #[default]
+    Truecolor,
+    /// the xterm 256-color palette
+    #[value(name = "256")]
+    Ansi256,
+    /// the 16 basic ANSI colors
+    #[value(name = "16")]
+    Ansi16,
+}
+
+/// Channel levels of the 6x6x6 cube in the 256-color
+/// palette, starting at index 16
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// xterm's default values for the 16 ANSI colors, in index
+/// order
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+const ANSI_16_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+];
+
+impl ColorDepth {
+    /// `color` as the terminal will be able to show it.
+    /// Anything that isn't an rgb or palette color is left
+    /// alone, the terminal picks those itself.
+    pub fn quantize(&self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::Truecolor, _) => color,
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => {
+                Color::Indexed(nearest_256((r, g, b)))
+            }
+            (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => {
+                ANSI_16_COLORS
+                    [nearest_16((r, g, b)) as usize]
+            }
+            (ColorDepth::Ansi16, Color::Indexed(index))
+                if index >= 16 =>
+            {
+                ANSI_16_COLORS[nearest_16(palette_rgb(
+                    index,
+                )) as usize]
+            }
+            _ => color,
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| {
+        let d = a as i32 - b as i32;
+        (d * d) as u32
+    };
+    channel(a.0, b.0)
+        + channel(a.1, b.1)
+        + channel(a.2, b.2)
+}
+
+/// Index of the closest of the 16 ANSI colors
+pub fn nearest_16(rgb: (u8, u8, u8)) -> u8 {
+    (0..16)
+        .min_by_key(|&index| distance(rgb, ANSI_16[index]))
+        .unwrap_or(0) as u8
+}
+
+/// Index of the closest color in the 6x6x6 cube or the
+/// grayscale ramp of the 256-color palette. The first 16
+/// entries are skipped since terminals theme them.
+pub fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    let level = |value: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| {
+                (CUBE_LEVELS[i] as i32 - value as i32).abs()
+            })
+            .unwrap_or(0) as u8
+    };
+    let cube = 16
+        + 36 * level(rgb.0)
+        + 6 * level(rgb.1)
+        + level(rgb.2);
+    // the ramp runs from 8 to 238 in steps of 10
+    let average =
+        (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232
+        + ((average.saturating_sub(3) / 10).min(23) as u8);
+    if distance(rgb, palette_rgb(gray))
+        < distance(rgb, palette_rgb(cube))
+    {
+        gray
+    } else {
+        cube
+    }
+}
+
+/// The rgb value `color` shows as, using xterm's defaults
+/// for the named colors. `Reset` is taken as black, the
+/// usual terminal background.
+pub fn color_rgb(color: Color) -> (u8, u8, u8) {
+    match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(index) => palette_rgb(index),
+        named => ANSI_16_COLORS
+            .iter()
+            .position(|ansi| *ansi == named)
+            .map_or((0, 0, 0), |index| ANSI_16[index]),
+    }
+}
+
+/// The rgb value of a palette index, using xterm's defaults
+/// for the first 16
+pub fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI_16[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            (level, level, level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Relative luminance of an rgb color, Rec. 709 weights
+    fn luminance(color: Color) -> f32 {
+        let Color::Rgb(r, g, b) = color else {
+            panic!("{color:?} isn't an rgb color");
+        };
+        0.2126 * r as f32
+            + 0.7152 * g as f32
+            + 0.0722 * b as f32
+    }
+
+    #[test]
+    fn colormaps_get_brighter_along_the_ramp() {
+        for colormap in [
+            Colormap::Viridis,
+            Colormap::Magma,
+            Colormap::Grayscale,
+        ] {
+            let [low, mid, high] =
+                [0.0, 0.5, 1.0].map(|t| colormap.sample(t));
+            assert!(
+                luminance(low) < luminance(mid)
+                    && luminance(mid) < luminance(high),
+                "{colormap:?}: {low:?} {mid:?} {high:?}"
+            );
+        }
+        assert_eq!(
+            Colormap::Viridis.sample(0.0),
+            Color::Rgb(0x44, 0x01, 0x54)
+        );
+        assert_eq!(
+            Colormap::Viridis.sample(0.5),
+            Color::Rgb(0x21, 0x91, 0x8c)
+        );
+        assert_eq!(
+            Colormap::Magma.sample(1.0),
+            Color::Rgb(0xfc, 0xfd, 0xbf)
+        );
+        assert_eq!(
+            Colormap::Grayscale.sample(0.5),
+            Color::Rgb(0x80, 0x80, 0x80)
+        );
+        // out of range and NaN clamp to the ends
+        assert_eq!(
+            Colormap::Magma.sample(2.0),
+            Colormap::Magma.sample(1.0)
+        );
+        assert_eq!(
+            Colormap::Magma.sample(f32::NAN),
+            Colormap::Magma.sample(0.0)
+        );
+    }
+
+    #[test]
+    fn truecolor_quantizes_to_the_nearest_palette_entry() {
+        // (rgb, 256-color index, 16-color index)
+        for (rgb, ansi_256, ansi_16) in [
+            ((255, 0, 0), 196, 9),
+            ((255, 135, 0), 208, 3),
+            ((0, 0, 128), 18, 4),
+            // grays land on the grayscale ramp
+            ((128, 128, 128), 244, 8),
+            ((0, 0, 0), 16, 0),
+        ] {
+            assert_eq!(
+                nearest_256(rgb),
+                ansi_256,
+                "{rgb:?}"
+            );
+            assert_eq!(nearest_16(rgb), ansi_16, "{rgb:?}");
+        }
+
+        let orange = Color::Rgb(255, 135, 0);
+        assert_eq!(
+            ColorDepth::Truecolor.quantize(orange),
+            orange
+        );
+        assert_eq!(
+            ColorDepth::Ansi256.quantize(orange),
+            Color::Indexed(208)
+        );
+        assert_eq!(
+            ColorDepth::Ansi16.quantize(orange),
+            Color::Yellow
+        );
+        // 256-color indexes come down to 16 as well, named
+        // colors are left to the terminal
+        assert_eq!(
+            ColorDepth::Ansi16
+                .quantize(Color::Indexed(196)),
+            Color::LightRed
+        );
+        assert_eq!(
+            ColorDepth::Ansi16.quantize(Color::Red),
+            Color::Red
+        );
+    }
+}
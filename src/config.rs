@@ -0,0 +1,127 @@
+use miette::Diagnostic;
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+use thiserror::Error;
+
+use crate::keymap::{KeyMap, KeyMapError};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum ConfigError {
+    #[error("could not read or write the config file")]
+    #[diagnostic(code(pngtubers::config::io))]
+    Io(#[from] io::Error),
+    #[error("config file is not valid toml")]
+    #[diagnostic(code(pngtubers::config::parse))]
+    Parse(#[from] toml::de::Error),
+    #[error("could not serialize the config")]
+    #[diagnostic(code(pngtubers::config::serialize))]
+    Serialize(#[from] toml::ser::Error),
+    #[error(
+        "--input-mode iq needs a 2 channel input, this one \
+         has {channels}"
+    )]
+    #[diagnostic(
+        code(pngtubers::config::iq_channels),
+        help(
+            "the I and Q samples come in as the left and \
+             right channels"
+        )
+    )]
+    IqChannels { channels: u16 },
+    #[error("{color:?} in band_colors isn't a color")]
+    #[diagnostic(
+        code(pngtubers::config::band_color),
+        help(
+            "use a name like red or light-blue, a palette \
+             index, or #rrggbb"
+        )
+    )]
+    BandColor { color: String },
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    KeyMap(#[from] KeyMapError),
+}
+
+/// Settings that persist between runs
+#[derive(
+    Debug, Clone, Default, PartialEq, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct Config {
+    /// dB added to every dBFS reading to turn it into dB SPL,
+    /// as measured by `--calibrate`
+    pub calibration_offset: Option<f32>,
+    /// linear gain per band, see `--band-gains`
+    pub band_gains: Option<Vec<f32>>,
+    /// one color per band, see `--band-colors`
+    pub band_colors: Option<Vec<String>>,
+    /// per band attack coefficients, see `--band-attack`
+    pub band_attack: Option<Vec<f32>>,
+    /// per band release coefficients, see `--band-release`
+    pub band_release: Option<Vec<f32>>,
+    /// level below which the input counts as silence, set
+    /// by the setup wizard and used by `--idle-after`
+    pub gate_db: Option<f32>,
+    /// level at or above which the input counts as talking,
+    /// set by the setup wizard
+    pub talking_threshold_db: Option<f32>,
+    /// keys for the TUI's actions, e.g. `quit = "Q"`, on top
+    /// of the defaults, see `keymap::Action`
+    pub keys: Option<BTreeMap<String, String>>,
+}
+
+impl Config {
+    /// Loads the config at `path`, falling back to the
+    /// defaults if the file doesn't exist yet.
+    pub fn load(
+        path: &Path,
+    ) -> Result<Config, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(Config::default())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `band_colors` parsed, `None` if it isn't set
+    pub fn band_colors(
+        &self,
+    ) -> Result<Option<Vec<Color>>, ConfigError> {
+        self.band_colors
+            .as_ref()
+            .map(|colors| {
+                colors
+                    .iter()
+                    .map(|color| {
+                        color.parse().map_err(|_| {
+                            ConfigError::BandColor {
+                                color: color.clone(),
+                            }
+                        })
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+
+    /// The TUI's keymap with `keys` applied
+    pub fn keymap(&self) -> Result<KeyMap, ConfigError> {
+        match &self.keys {
+            Some(keys) => Ok(KeyMap::new(keys)?),
+            None => Ok(KeyMap::default()),
+        }
+    }
+
+    pub fn save(
+        &self,
+        path: &Path,
+    ) -> Result<(), ConfigError> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
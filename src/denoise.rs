@@ -0,0 +1,114 @@
+use std::sync::{Arc, Mutex};
+
+/// Frames averaged into a noise profile unless told
+/// otherwise, a little over a second at 48kHz
+pub const LEARN_FRAMES: usize = 50;
+
+/// Fraction of each bin kept no matter how much noise is
+/// subtracted. Bins that dip to zero and back frame to
+/// frame are what makes spectral subtraction sound (and
+/// look) like twinkling "musical noise", a floor smooths
+/// that over.
+pub const SPECTRAL_FLOOR: f32 = 0.02;
+
+/// Spectral subtraction: learns the average spectrum of the
+/// background noise and removes it from every frame after.
+#[derive(Debug, Clone)]
+pub struct NoiseProfile {
+    /// the averaged noise spectrum, once one has been
+    /// learned
+    profile: Option<Vec<f32>>,
+    learning: Option<Learning>,
+    floor: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Learning {
+    remaining: usize,
+    sum: Vec<f32>,
+    frames: usize,
+}
+
+/// Shared so the UI can start learning while the analyzer
+/// runs on its own thread
+pub type SharedNoiseProfile = Arc<Mutex<NoiseProfile>>;
+
+impl Default for NoiseProfile {
+    fn default() -> Self {
+        NoiseProfile::new(SPECTRAL_FLOOR)
+    }
+}
+
+impl NoiseProfile {
+    pub fn new(floor: f32) -> Self {
+        NoiseProfile {
+            profile: None,
+            learning: None,
+            floor,
+        }
+    }
+
+    /// Averages the next `frames` spectra into a new
+    /// profile. The old profile, if any, keeps being
+    /// subtracted until the new one is ready.
+    pub fn learn(&mut self, frames: usize) {
+        self.learning = Some(Learning {
+            remaining: frames.max(1),
+            sum: vec![],
+            frames: 0,
+        });
+    }
+
+    pub fn is_learning(&self) -> bool {
+        self.learning.is_some()
+    }
+
+    pub fn profile(&self) -> Option<&[f32]> {
+        self.profile.as_deref()
+    }
+
+    /// Feeds `spectrum` to any learning in progress, then
+    /// subtracts the profile from it in place.
+    ///
+    /// Profiles learned at a different fft size don't line
+    /// up with the bins and are ignored.
+    pub fn apply(&mut self, spectrum: &mut [f32]) {
+        if let Some(learning) = &mut self.learning {
+            if learning.sum.len() != spectrum.len() {
+                learning.sum = vec![0.0; spectrum.len()];
+                learning.frames = 0;
+            }
+            for (sum, magnitude) in
+                learning.sum.iter_mut().zip(spectrum.iter())
+            {
+                *sum += magnitude;
+            }
+            learning.frames += 1;
+            learning.remaining -= 1;
+            if learning.remaining == 0 {
+                let frames = learning.frames as f32;
+                self.profile = Some(
+                    learning
+                        .sum
+                        .iter()
+                        .map(|sum| sum / frames)
+                        .collect(),
+                );
+                self.learning = None;
+            }
+        }
+
+        let Some(profile) = &self.profile else {
+            return;
+        };
+        if profile.len() != spectrum.len() {
+            return;
+        }
+        for (magnitude, noise) in
+            spectrum.iter_mut().zip(profile)
+        {
+            *magnitude = (*magnitude - noise)
+                .max(*magnitude * self.floor);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::{
+    analysis::{self, AnalysisFrame, FreqRange},
+    mix::{self, MidSide},
+    zoom::ZoomSpectrum,
+};
+
+/// `frame`'s samples downmixed to one channel, as raw 32 bit
+/// float little endian PCM, the way `--pcm-out` writes them.
+/// Frames follow on from one another, so writing every
+/// frame's bytes in order gives the whole stream.
+pub fn pcm_bytes(frame: &AnalysisFrame) -> Vec<u8> {
+    mix::downmix(&frame.samples, frame.channels)
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+/// The per-frame record written by the exporters. Raw
+/// samples and the full spectrum are left out to keep lines
+/// small.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExportFrame {
+    /// sample frames seen before this one, see
+    /// `AnalysisFrame::sample_index`
+    pub sample_index: u64,
+    /// input time at the start of the frame
+    pub timestamp_seconds: f64,
+    /// peak level of the frame, in dB SPL if a calibration
+    /// offset is applied and dBFS otherwise
+    pub level_db: f32,
+    pub bands: Vec<f32>,
+    /// 0 to 1, see `AnalysisFrame::activity`
+    pub activity: f32,
+    /// 0 for mono to 1 for all side, see
+    /// `mix::MidSide::width`. Left out for a mono input.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stereo_width: Option<f32>,
+    /// the strongest frequency in the zoomed band, in Hz,
+    /// see `zoom::ZoomSpectrum::peak_hz`. Left out without
+    /// a zoom.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zoom_peak_hz: Option<f32>,
+    pub dropped_samples: u64,
+}
+
+impl ExportFrame {
+    pub fn new(frame: &AnalysisFrame, offset: f32) -> Self {
+        ExportFrame {
+            sample_index: frame.sample_index,
+            timestamp_seconds: frame
+                .timestamp
+                .as_secs_f64(),
+            level_db: analysis::peak_dbfs(&frame.samples)
+                + offset,
+            bands: frame.bands.clone(),
+            activity: frame.activity,
+            stereo_width: MidSide::measure(
+                &frame.samples,
+                frame.channels,
+            )
+            .map(MidSide::width),
+            zoom_peak_hz: frame
+                .zoom
+                .as_ref()
+                .and_then(ZoomSpectrum::peak_hz),
+            dropped_samples: frame.dropped_samples,
+        }
+    }
+
+    /// One line of newline-delimited JSON, without the
+    /// trailing newline
+    pub fn to_ndjson(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+/// How the CSV export's first column gives each frame's time
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum TimestampFormat {
+    /// input time in seconds since the start
+    #[default]
+    Seconds,
+    /// UTC wall clock time to the microsecond, the time the
+    /// export started plus the input time
+    Iso8601,
+    /// sample frames seen before the frame, see
+    /// `AnalysisFrame::sample_index`
+    SampleIndex,
+}
+
+impl TimestampFormat {
+    /// The column's name in the header
+    pub fn column(self) -> &'static str {
+        match self {
+            TimestampFormat::Seconds => "timestamp_seconds",
+            TimestampFormat::Iso8601 => "timestamp",
+            TimestampFormat::SampleIndex => "sample_index",
+        }
+    }
+
+    /// `frame`'s time, for an export that started at
+    /// `started`. Every format counts from the input rather
+    /// than reading the clock per frame, so times never go
+    /// backwards however late frames arrive.
+    pub fn format(
+        self,
+        frame: &ExportFrame,
+        started: SystemTime,
+    ) -> String {
+        match self {
+            TimestampFormat::Seconds => {
+                format!("{:.6}", frame.timestamp_seconds)
+            }
+            TimestampFormat::Iso8601 => iso8601(
+                started
+                    + Duration::from_secs_f64(
+                        frame.timestamp_seconds.max(0.0),
+                    ),
+            ),
+            TimestampFormat::SampleIndex => {
+                frame.sample_index.to_string()
+            }
+        }
+    }
+}
+
+/// `time` in UTC as ISO 8601, e.g.
+/// `2024-03-01T12:30:05.250000Z`. Times before 1970 come out
+/// as the epoch.
+pub fn iso8601(time: SystemTime) -> String {
+    let since_epoch =
+        time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, of_day) =
+        (seconds / 86_400, seconds % 86_400);
+    // the days to a civil date, after Howard Hinnant's
+    // days_from_civil run backwards, with years starting in
+    // March so the leap day comes last
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460
+        + day_of_era / 36_524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year = day_of_era
+        - (365 * year_of_era + year_of_era / 4
+            - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era
+        + era * 400
+        + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:06}Z",
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+/// Writes frames as CSV: the timestamp, the level, one
+/// column per band, the activity and the dropped samples
+pub struct CsvRecorder {
+    writer: BufWriter<File>,
+    format: TimestampFormat,
+    offset: f32,
+    started: SystemTime,
+}
+
+impl CsvRecorder {
+    /// Creates `path` and writes the header, naming a
+    /// column after each of `bands`. `offset` is added to
+    /// the level like `ExportFrame::new`'s.
+    pub fn create(
+        path: &Path,
+        format: TimestampFormat,
+        bands: &[FreqRange],
+        offset: f32,
+    ) -> io::Result<Self> {
+        let mut writer =
+            BufWriter::new(File::create(path)?);
+        let mut header = vec![
+            format.column().to_string(),
+            "level_db".to_string(),
+        ];
+        header.extend(
+            bands.iter().map(|band| band.name.to_string()),
+        );
+        header.extend([
+            "activity".to_string(),
+            "dropped_samples".to_string(),
+        ]);
+        writeln!(writer, "{}", header.join(","))?;
+        writer.flush()?;
+        Ok(CsvRecorder {
+            writer,
+            format,
+            offset,
+            started: SystemTime::now(),
+        })
+    }
+
+    /// Appends `frame` as a row, flushed straight away like
+    /// `SessionRecorder::record`
+    pub fn record(
+        &mut self,
+        frame: &AnalysisFrame,
+    ) -> io::Result<()> {
+        let frame = ExportFrame::new(frame, self.offset);
+        let mut row = vec![
+            self.format.format(&frame, self.started),
+            format!("{:.2}", frame.level_db),
+        ];
+        row.extend(
+            frame.bands.iter().map(|band| band.to_string()),
+        );
+        row.extend([
+            format!("{:.3}", frame.activity),
+            frame.dropped_samples.to_string(),
+        ]);
+        writeln!(self.writer, "{}", row.join(","))?;
+        self.writer.flush()
+    }
+}
+
+/// Frames `record_csv` can have waiting to be passed on
+const CSV_CHANNEL_CAPACITY: usize = 100;
+
+/// Writes every frame from `rx` with `recorder` on its way
+/// through to the returned receiver, like
+/// `session::record`. A frame that can't be written is
+/// reported and the export stops, the frames keep flowing.
+pub fn record_csv(
+    mut rx: Receiver<AnalysisFrame>,
+    recorder: CsvRecorder,
+) -> Receiver<AnalysisFrame> {
+    let (tx, out) = channel(CSV_CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut recorder = Some(recorder);
+        while let Some(frame) = rx.recv().await {
+            if let Some(writer) = &mut recorder {
+                if let Err(err) = writer.record(&frame) {
+                    eprintln!(
+                        "could not write a CSV row, the \
+                         export stopped: {err}"
+                    );
+                    recorder = None;
+                }
+            }
+            if tx.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+    out
+}
+
+/// Lets frames through to an exporter at no more than a set
+/// rate, however fast the analysis produces them, so it
+/// doesn't flood whatever it's sending to. Time is the
+/// frames' own input time, so a replay or a file throttles
+/// the same as a live input.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    /// the shortest gap between frames let through, `None`
+    /// for no limit
+    interval: Option<Duration>,
+    /// when the next frame is due
+    next: Option<Duration>,
+}
+
+impl RateLimiter {
+    /// At most `max_hz` frames a second, no limit at all
+    /// when it isn't positive
+    pub fn new(max_hz: f32) -> Self {
+        RateLimiter {
+            interval: (max_hz.is_finite() && max_hz > 0.0)
+                .then(|| {
+                    Duration::from_secs_f32(1.0 / max_hz)
+                }),
+            next: None,
+        }
+    }
+
+    /// Whether the frame at input time `at` should go out.
+    ///
+    /// Each frame let through schedules the next one an
+    /// interval after the last was due rather than after it
+    /// arrived, so frames that don't line up with the limit
+    /// still come out at the full rate on average. After a
+    /// gap, or if time jumps backwards, it starts over from
+    /// `at`.
+    pub fn ready(&mut self, at: Duration) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        if let Some(next) = self.next {
+            if at < next && next - at <= interval {
+                return false;
+            }
+        }
+        self.next = Some(match self.next {
+            Some(next)
+                if at >= next && at < next + interval =>
+            {
+                next + interval
+            }
+            _ => at + interval,
+        });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame half a second in with known contents
+    fn frame() -> AnalysisFrame {
+        AnalysisFrame {
+            samples: vec![0.5, -0.25, 0.125],
+            bands: vec![1.0, 0.5],
+            dropped_samples: 3,
+            sample_index: 24000,
+            timestamp: Duration::from_millis(500),
+            activity: 0.25,
+            ..AnalysisFrame::default()
+        }
+    }
+
+    #[test]
+    fn a_frame_exports_as_one_line_of_json() {
+        let line = ExportFrame::new(&frame(), 0.0)
+            .to_ndjson()
+            .unwrap();
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value =
+            serde_json::from_str(&line).unwrap();
+        let object = value.as_object().unwrap();
+        let mut keys: Vec<&str> =
+            object.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "activity",
+                "bands",
+                "dropped_samples",
+                "level_db",
+                "sample_index",
+                "timestamp_seconds"
+            ]
+        );
+        assert_eq!(value["sample_index"], 24000);
+        assert_eq!(value["timestamp_seconds"], 0.5);
+        assert_eq!(
+            value["bands"],
+            serde_json::json!([1.0, 0.5])
+        );
+        assert_eq!(value["activity"], 0.25);
+        assert_eq!(value["dropped_samples"], 3);
+        let level = value["level_db"].as_f64().unwrap();
+        assert!((level + 6.0206).abs() < 1e-3, "{level}");
+    }
+
+    #[test]
+    fn the_rate_limiter_holds_output_to_its_maximum() {
+        // ten seconds of frames at `fps`, limited to `max_hz`
+        let sent = |fps: f64, max_hz: f32| {
+            let mut limiter = RateLimiter::new(max_hz);
+            (0..(fps * 10.0) as u64)
+                .filter(|frame| {
+                    limiter.ready(Duration::from_secs_f64(
+                        *frame as f64 / fps,
+                    ))
+                })
+                .count()
+        };
+        assert!((299..=301).contains(&sent(60.0, 30.0)));
+        // frames that don't line up with the limit still
+        // come out at the full rate on average
+        assert!((299..=301).contains(&sent(46.875, 30.0)));
+        // slower than the limit, everything goes
+        assert_eq!(sent(20.0, 30.0), 200);
+        // no limit
+        assert_eq!(sent(60.0, 0.0), 600);
+    }
+
+    #[test]
+    fn samples_serialize_as_little_endian_f32() {
+        assert_eq!(
+            pcm_bytes(&frame()),
+            [
+                0x00, 0x00, 0x00, 0x3f, // 0.5
+                0x00, 0x00, 0x80, 0xbe, // -0.25
+                0x00, 0x00, 0x00, 0x3e, // 0.125
+            ]
+        );
+        // stereo is averaged down to one sample per frame
+        let stereo = AnalysisFrame {
+            samples: vec![1.0, 0.0, -0.5, -0.5],
+            channels: 2,
+            ..AnalysisFrame::default()
+        };
+        assert_eq!(
+            pcm_bytes(&stereo),
+            [
+                0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00,
+                0xbf
+            ]
+        );
+    }
+
+    #[test]
+    fn each_timestamp_format_renders_a_known_frame() {
+        // 2024-03-01T12:30:05Z
+        let started =
+            UNIX_EPOCH + Duration::from_secs(1_709_296_205);
+        let first = ExportFrame::new(&frame(), 0.0);
+        assert_eq!(
+            TimestampFormat::Seconds
+                .format(&first, started),
+            "0.500000"
+        );
+        assert_eq!(
+            TimestampFormat::Iso8601
+                .format(&first, started),
+            "2024-03-01T12:30:05.500000Z"
+        );
+        assert_eq!(
+            TimestampFormat::SampleIndex
+                .format(&first, started),
+            "24000"
+        );
+        // a later frame reads later in every format
+        let later = ExportFrame::new(
+            &AnalysisFrame {
+                sample_index: 48000,
+                timestamp: Duration::from_secs(1),
+                ..frame()
+            },
+            0.0,
+        );
+        assert_eq!(
+            TimestampFormat::Iso8601
+                .format(&later, started),
+            "2024-03-01T12:30:06.000000Z"
+        );
+        for format in [
+            TimestampFormat::Seconds,
+            TimestampFormat::SampleIndex,
+        ] {
+            let time = |frame| {
+                format
+                    .format(frame, started)
+                    .parse::<f64>()
+                    .unwrap()
+            };
+            assert!(time(&later) > time(&first));
+        }
+        // a leap day, and the epoch for anything before it
+        assert_eq!(
+            iso8601(
+                UNIX_EPOCH
+                    + Duration::from_secs(951_868_799)
+            ),
+            "2000-02-29T23:59:59.000000Z"
+        );
+        assert_eq!(
+            iso8601(UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01T00:00:00.000000Z"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic, PartialEq)]
+pub enum GateError {
+    #[error("a gate range of {0}dB is out of range")]
+    #[diagnostic(
+        code(pngtubers::gate::range),
+        help(
+            "the range is how far the gate turns the signal \
+             down once closed, 0dB or more"
+        )
+    )]
+    Range(f32),
+    #[error("a gate knee of {0}dB is out of range")]
+    #[diagnostic(
+        code(pngtubers::gate::knee),
+        help(
+            "the knee is how wide the transition around the \
+             threshold is, 0dB or more, 0 for a hard gate"
+        )
+    )]
+    Knee(f32),
+}
+
+/// How a `NoiseGate` opens and closes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GateOptions {
+    /// the level the gate opens at, in dBFS
+    pub threshold_db: f32,
+    /// how far below its level the signal is turned down
+    /// while the gate is closed, in dB
+    pub range_db: f32,
+    /// how wide the soft knee around the threshold is, in
+    /// dB, 0 for a gate that's either open or closed
+    pub knee_db: f32,
+}
+
+impl GateOptions {
+    /// Checks that the range and knee make sense
+    pub fn validate(self) -> Result<Self, GateError> {
+        if !(self.range_db.is_finite()
+            && self.range_db >= 0.0)
+        {
+            return Err(GateError::Range(self.range_db));
+        }
+        if !(self.knee_db.is_finite()
+            && self.knee_db >= 0.0)
+        {
+            return Err(GateError::Knee(self.knee_db));
+        }
+        Ok(self)
+    }
+
+    /// The gain, in dB, for a buffer peaking at `level_db`:
+    /// 0 at or above the top of the knee, `-range_db` below
+    /// its bottom, and eased from one to the other in
+    /// between so the gate doesn't snap shut
+    pub fn gain_db(self, level_db: f32) -> f32 {
+        let bottom = self.threshold_db - self.knee_db / 2.0;
+        if self.knee_db <= 0.0 {
+            return if level_db >= self.threshold_db {
+                0.0
+            } else {
+                -self.range_db
+            };
+        }
+        let t = ((level_db - bottom) / self.knee_db)
+            .clamp(0.0, 1.0);
+        // smoothstep, flat at both ends of the knee
+        let open = t * t * (3.0 - 2.0 * t);
+        -self.range_db * (1.0 - open)
+    }
+}
+
+/// A noise gate that turns the signal down by a set range
+/// below its threshold instead of muting it, so quiet input
+/// drops into the background rather than vanishing.
+///
+/// The level is each buffer's peak. The gain for it ramps in
+/// over the buffer from wherever the last one left off, so
+/// the gate opening or closing never jumps mid-waveform.
+#[derive(Debug, Clone)]
+pub struct NoiseGate {
+    options: GateOptions,
+    /// the linear gain the last buffer ended on
+    gain: f32,
+}
+
+impl NoiseGate {
+    pub fn new(options: GateOptions) -> Self {
+        NoiseGate { options, gain: 1.0 }
+    }
+
+    pub fn options(&self) -> GateOptions {
+        self.options
+    }
+
+    /// Gates `samples` in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if samples.is_empty() {
+            return;
+        }
+        // the loudest sample either way, silence is -inf
+        let peak =
+            samples.iter().fold(0.0f32, |peak, sample| {
+                peak.max(sample.abs())
+            });
+        let target = 10f32.powf(
+            self.options.gain_db(20.0 * peak.log10())
+                / 20.0,
+        );
+        let step =
+            (target - self.gain) / samples.len() as f32;
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample *= self.gain + step * (i + 1) as f32;
+        }
+        self.gain = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: GateOptions = GateOptions {
+        threshold_db: -40.0,
+        range_db: 20.0,
+        knee_db: 10.0,
+    };
+
+    #[test]
+    fn a_quiet_signal_is_turned_down_by_the_range() {
+        let mut gate = NoiseGate::new(OPTIONS);
+        // -60dBFS, well under the knee
+        let quiet = [0.001, -0.001, 0.001, -0.001];
+        let mut first = quiet;
+        gate.process(&mut first);
+        // ramping down from open rather than stepping
+        assert!(first[0].abs() < 0.001);
+        assert!((first[3].abs() - 0.0001).abs() < 1e-7);
+        let mut second = quiet;
+        gate.process(&mut second);
+        for (gated, sample) in second.iter().zip(quiet) {
+            assert!((gated - sample * 0.1).abs() < 1e-8);
+            assert_ne!(*gated, 0.0);
+        }
+    }
+
+    #[test]
+    fn the_knee_eases_between_closed_and_open() {
+        assert_eq!(OPTIONS.gain_db(-60.0), -20.0);
+        assert_eq!(OPTIONS.gain_db(-45.0), -20.0);
+        assert!(
+            (OPTIONS.gain_db(-40.0) + 10.0).abs() < 1e-5
+        );
+        assert_eq!(OPTIONS.gain_db(-35.0), 0.0);
+        assert_eq!(OPTIONS.gain_db(-10.0), 0.0);
+        // rising through the knee without a jump
+        let gains: Vec<f32> = (0..=100)
+            .map(|step| {
+                OPTIONS.gain_db(-45.0 + step as f32 * 0.1)
+            })
+            .collect();
+        for pair in gains.windows(2) {
+            assert!(pair[1] >= pair[0]);
+            assert!(pair[1] - pair[0] < 0.5);
+        }
+
+        // without a knee the gate snaps at the threshold
+        let hard = GateOptions {
+            knee_db: 0.0,
+            ..OPTIONS
+        };
+        assert_eq!(hard.gain_db(-40.1), -20.0);
+        assert_eq!(hard.gain_db(-40.0), 0.0);
+    }
+
+    #[test]
+    fn a_negative_range_or_knee_is_refused() {
+        let range = GateOptions {
+            range_db: -1.0,
+            ..OPTIONS
+        };
+        assert_eq!(
+            range.validate(),
+            Err(GateError::Range(-1.0))
+        );
+        let knee = GateOptions {
+            knee_db: f32::NAN,
+            ..OPTIONS
+        };
+        assert!(matches!(
+            knee.validate(),
+            Err(GateError::Knee(_))
+        ));
+        assert_eq!(OPTIONS.validate(), Ok(OPTIONS));
+    }
+}
@@ -9,7 +9,6 @@ pub async fn run(
     event_loop: EventLoop<()>,
     window: Window,
 ) {
-    let vertex_buffer = [-1, 1, -1];
     let size = window.inner_size();
 
     let instance = wgpu::Instance::default();
@@ -0,0 +1,269 @@
+//! Publishes export frames to local subscribers over a Unix
+//! domain socket, or a named pipe on Windows, one JSON
+//! object per line.
+
+use std::{collections::HashSet, io, sync::Arc};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::broadcast::{self, error::RecvError},
+};
+
+use crate::export::ExportFrame;
+
+/// Lines each subscriber can fall behind by before it
+/// starts missing frames
+const BACKLOG: usize = 64;
+
+/// Fans frames out to every connected subscriber. Publishing
+/// never blocks on a slow or departed client.
+#[derive(Debug, Clone)]
+pub struct Publisher {
+    lines: broadcast::Sender<Arc<str>>,
+}
+
+impl Default for Publisher {
+    fn default() -> Self {
+        Publisher::new()
+    }
+}
+
+impl Publisher {
+    pub fn new() -> Self {
+        let (lines, _) = broadcast::channel(BACKLOG);
+        Publisher { lines }
+    }
+
+    /// Sends `frame` to every current subscriber, a no-op
+    /// when nobody is listening
+    pub fn publish(
+        &self,
+        frame: &ExportFrame,
+    ) -> serde_json::Result<()> {
+        if self.lines.receiver_count() == 0 {
+            return Ok(());
+        }
+        let mut line = frame.to_ndjson()?;
+        line.push('\n');
+        // only fails when every subscriber has gone
+        let _ = self.lines.send(line.into());
+        Ok(())
+    }
+
+    /// Starts writing published frames to `writer` until it
+    /// goes away
+    pub fn subscribe<W>(
+        &self,
+        writer: W,
+    ) -> tokio::task::JoinHandle<io::Result<()>>
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        tokio::spawn(serve(writer, self.lines.subscribe()))
+    }
+}
+
+/// Writes lines to one subscriber. A write error means the
+/// client disconnected, which ends its task and nothing
+/// else. A client that can't keep up skips the frames it
+/// missed rather than holding everyone else back.
+async fn serve<W>(
+    mut writer: W,
+    mut lines: broadcast::Receiver<Arc<str>>,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        match lines.recv().await {
+            Ok(line) => {
+                writer.write_all(line.as_bytes()).await?;
+                writer.flush().await?;
+            }
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Listens on a Unix domain socket at `path` and subscribes
+/// every client that connects. Accept errors are reported
+/// once per kind, since a failing accept tends to fail the
+/// same way every time and would otherwise scroll over the
+/// TUI.
+///
+/// A socket left behind by an earlier run is replaced, any
+/// other file at `path` is an error. The socket is removed
+/// again when the returned guard is dropped.
+#[cfg(unix)]
+pub fn listen(
+    path: &std::path::Path,
+    publisher: Publisher,
+) -> io::Result<Listener> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+    let accept = tokio::spawn(async move {
+        let mut reported = HashSet::new();
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    publisher.subscribe(stream);
+                }
+                Err(err) => {
+                    if reported.insert(err.kind()) {
+                        eprintln!(
+                            "accepting a subscriber: {err}"
+                        );
+                    }
+                }
+            }
+        }
+    });
+    Ok(Listener {
+        path: path.to_path_buf(),
+        accept,
+    })
+}
+
+/// Creates the named pipe `path`, e.g.
+/// `\\.\pipe\pngtubers`, and subscribes every client that
+/// connects. Each client gets its own pipe instance, and
+/// accept errors are reported once per kind as on Unix.
+#[cfg(windows)]
+pub fn listen(
+    path: &std::path::Path,
+    publisher: Publisher,
+) -> io::Result<Listener> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = path.as_os_str().to_os_string();
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)?;
+    let accept = tokio::spawn(async move {
+        let mut reported = HashSet::new();
+        loop {
+            if let Err(err) = server.connect().await {
+                if reported.insert(err.kind()) {
+                    eprintln!(
+                        "accepting a subscriber: {err}"
+                    );
+                }
+                continue;
+            }
+            // the next client needs a fresh instance
+            // waiting before this one is handed off
+            let next =
+                match ServerOptions::new().create(&name) {
+                    Ok(next) => next,
+                    Err(err) => {
+                        eprintln!(
+                        "creating a pipe instance: {err}"
+                    );
+                        return;
+                    }
+                };
+            publisher.subscribe(std::mem::replace(
+                &mut server,
+                next,
+            ));
+        }
+    });
+    Ok(Listener { accept })
+}
+
+/// Keeps the socket open; dropping it stops accepting
+/// subscribers
+#[derive(Debug)]
+pub struct Listener {
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+    accept: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.accept.abort();
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use crate::analysis::AnalysisFrame;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    #[tokio::test]
+    async fn subscribers_read_back_published_frames() {
+        use tokio::net::UnixStream;
+
+        let publisher = Publisher::new();
+        let (server, client) = UnixStream::pair().unwrap();
+        let (other_server, other_client) =
+            UnixStream::pair().unwrap();
+        publisher.subscribe(server);
+        let departed = publisher.subscribe(other_server);
+        // let both tasks subscribe before publishing
+        tokio::task::yield_now().await;
+
+        let frame = |index| {
+            ExportFrame::new(
+                &AnalysisFrame {
+                    dropped_samples: index,
+                    ..AnalysisFrame::default()
+                },
+                0.0,
+            )
+        };
+        publisher.publish(&frame(1)).unwrap();
+        let mut lines = BufReader::new(client).lines();
+        let mut other_lines =
+            BufReader::new(other_client).lines();
+        let dropped_samples = |line: Option<String>| {
+            let value: serde_json::Value =
+                serde_json::from_str(&line.unwrap())
+                    .unwrap();
+            value["dropped_samples"].as_u64().unwrap()
+        };
+        assert_eq!(
+            dropped_samples(
+                lines.next_line().await.unwrap()
+            ),
+            1
+        );
+        assert_eq!(
+            dropped_samples(
+                other_lines.next_line().await.unwrap()
+            ),
+            1
+        );
+
+        // one subscriber leaving ends its task and nobody
+        // else's
+        drop(other_lines);
+        publisher.publish(&frame(2)).unwrap();
+        assert!(departed.await.unwrap().is_err());
+        publisher.publish(&frame(3)).unwrap();
+        assert_eq!(
+            dropped_samples(
+                lines.next_line().await.unwrap()
+            ),
+            2
+        );
+        assert_eq!(
+            dropped_samples(
+                lines.next_line().await.unwrap()
+            ),
+            3
+        );
+    }
+}
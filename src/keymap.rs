@@ -0,0 +1,524 @@
+//! Which key does what in the TUI, with the defaults
+//! overridable from the config file.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use miette::Diagnostic;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic, PartialEq)]
+pub enum KeyMapError {
+    #[error("{0:?} in keys isn't an action")]
+    #[diagnostic(
+        code(pngtubers::keymap::action),
+        help(
+            "the actions are quit, help, pause, \
+             next-view, previous-view, reset-hold, solo, \
+             mute, relearn-noise, rearm, stats, wizard, \
+             save-plot, freeze-reference, clear-reference \
+             and units"
+        )
+    )]
+    Action(String),
+    #[error("{key:?} for {action} isn't a key")]
+    #[diagnostic(
+        code(pngtubers::keymap::key),
+        help(
+            "use a single character, or one of space, tab \
+             and backtab"
+        )
+    )]
+    Key { action: String, key: String },
+    #[error("{key} is bound to both {first} and {second}")]
+    #[diagnostic(
+        code(pngtubers::keymap::duplicate),
+        help("give each action a key of its own")
+    )]
+    Duplicate {
+        key: String,
+        first: &'static str,
+        second: &'static str,
+    },
+    #[error("{key} for {action} is already taken")]
+    #[diagnostic(
+        code(pngtubers::keymap::reserved),
+        help(
+            "the digits, the arrow keys, esc and enter \
+             can't be rebound"
+        )
+    )]
+    Reserved { action: &'static str, key: String },
+    #[error("{0} can't be moved off its key")]
+    #[diagnostic(
+        code(pngtubers::keymap::fixed),
+        help(
+            "the actions on the digits, the arrow keys, \
+             esc and enter stay where they are"
+        )
+    )]
+    Fixed(&'static str),
+}
+
+/// Something a key press does in `run`
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum Action {
+    Quit,
+    Help,
+    Close,
+    NextView,
+    PreviousView,
+    /// to the view numbered by the digit pressed
+    JumpToView,
+    PreviousBand,
+    NextBand,
+    RaiseGain,
+    LowerGain,
+    ResetHold,
+    Solo,
+    Mute,
+    RelearnNoise,
+    Rearm,
+    Stats,
+    Wizard,
+    SavePlot,
+    FreezeReference,
+    ClearReference,
+    Units,
+    Pause,
+    Confirm,
+}
+
+impl Action {
+    /// Every action, in the order the help overlay lists
+    /// them
+    pub const ALL: [Action; 23] = [
+        Action::Quit,
+        Action::Help,
+        Action::Close,
+        Action::NextView,
+        Action::PreviousView,
+        Action::JumpToView,
+        Action::PreviousBand,
+        Action::NextBand,
+        Action::RaiseGain,
+        Action::LowerGain,
+        Action::ResetHold,
+        Action::Solo,
+        Action::Mute,
+        Action::RelearnNoise,
+        Action::Rearm,
+        Action::Stats,
+        Action::Wizard,
+        Action::SavePlot,
+        Action::FreezeReference,
+        Action::ClearReference,
+        Action::Units,
+        Action::Pause,
+        Action::Confirm,
+    ];
+
+    /// What the action is called in the config's `keys`
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "help",
+            Action::Close => "close",
+            Action::NextView => "next-view",
+            Action::PreviousView => "previous-view",
+            Action::JumpToView => "jump-to-view",
+            Action::PreviousBand => "previous-band",
+            Action::NextBand => "next-band",
+            Action::RaiseGain => "raise-gain",
+            Action::LowerGain => "lower-gain",
+            Action::ResetHold => "reset-hold",
+            Action::Solo => "solo",
+            Action::Mute => "mute",
+            Action::RelearnNoise => "relearn-noise",
+            Action::Rearm => "rearm",
+            Action::Stats => "stats",
+            Action::Wizard => "wizard",
+            Action::SavePlot => "save-plot",
+            Action::FreezeReference => "freeze-reference",
+            Action::ClearReference => "clear-reference",
+            Action::Units => "units",
+            Action::Pause => "pause",
+            Action::Confirm => "confirm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+    }
+
+    /// The key it's on unless the config says otherwise.
+    /// `JumpToView` takes any digit, this being the first.
+    pub fn default_key(self) -> KeyCode {
+        match self {
+            Action::Quit => KeyCode::Char('q'),
+            Action::Help => KeyCode::Char('?'),
+            Action::Close => KeyCode::Esc,
+            Action::NextView => KeyCode::Tab,
+            Action::PreviousView => KeyCode::BackTab,
+            Action::JumpToView => KeyCode::Char('1'),
+            Action::PreviousBand => KeyCode::Left,
+            Action::NextBand => KeyCode::Right,
+            Action::RaiseGain => KeyCode::Up,
+            Action::LowerGain => KeyCode::Down,
+            Action::ResetHold => KeyCode::Char('h'),
+            Action::Solo => KeyCode::Char('s'),
+            Action::Mute => KeyCode::Char('m'),
+            Action::RelearnNoise => KeyCode::Char('n'),
+            Action::Rearm => KeyCode::Char('r'),
+            Action::Stats => KeyCode::Char('d'),
+            Action::Wizard => KeyCode::Char('w'),
+            Action::SavePlot => KeyCode::Char('p'),
+            Action::FreezeReference => KeyCode::Char('f'),
+            Action::ClearReference => KeyCode::Char('x'),
+            Action::Units => KeyCode::Char('u'),
+            Action::Pause => KeyCode::Char(' '),
+            Action::Confirm => KeyCode::Enter,
+        }
+    }
+
+    /// Whether it's on one of the keys `run` keeps for
+    /// itself, and so can't be rebound
+    pub fn is_fixed(self) -> bool {
+        is_reserved(self.default_key())
+    }
+
+    /// What the help says it does
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "show or hide this help",
+            Action::Close => "close the help or the wizard",
+            Action::NextView => "next view",
+            Action::PreviousView => "previous view",
+            Action::JumpToView => "jump to a view",
+            Action::PreviousBand => {
+                "select the band to the left"
+            }
+            Action::NextBand => {
+                "select the band to the right"
+            }
+            Action::RaiseGain => "raise the band's gain",
+            Action::LowerGain => "lower the band's gain",
+            Action::ResetHold => "reset the peak hold",
+            Action::Solo => {
+                "solo the band, again to unsolo"
+            }
+            Action::Mute => {
+                "mute the band, again to unmute"
+            }
+            Action::RelearnNoise => {
+                "relearn the noise profile"
+            }
+            Action::Rearm => "rearm the trigger",
+            Action::Stats => {
+                "show or hide the analysis stats"
+            }
+            Action::Wizard => {
+                "set up the gate and talking thresholds"
+            }
+            Action::SavePlot => {
+                "save the spectrum as a png plot"
+            }
+            Action::FreezeReference => {
+                "freeze the spectrum as a reference to \
+                 compare"
+            }
+            Action::ClearReference => {
+                "clear the reference spectrum"
+            }
+            Action::Units => {
+                "show levels in dB or as a percentage"
+            }
+            Action::Pause => {
+                "pause or resume, or the wizard's next step"
+            }
+            Action::Confirm => {
+                "save the wizard's thresholds"
+            }
+        }
+    }
+}
+
+/// A key as the config writes it: a single character, or
+/// space, tab or backtab
+pub fn parse_key(key: &str) -> Option<KeyCode> {
+    match key {
+        "space" => Some(KeyCode::Char(' ')),
+        "tab" => Some(KeyCode::Tab),
+        "backtab" => Some(KeyCode::BackTab),
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(KeyCode::Char(c)),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// How the help overlay names `key`
+pub fn key_name(key: KeyCode) -> String {
+    match key {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::BackTab => "shift-tab".to_string(),
+        KeyCode::Esc => "esc".to_string(),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        key => format!("{key:?}").to_lowercase(),
+    }
+}
+
+/// Keys `run` handles itself, whatever the keymap says
+fn is_reserved(key: KeyCode) -> bool {
+    matches!(
+        key,
+        KeyCode::Char('0'..='9')
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Esc
+            | KeyCode::Enter
+    )
+}
+
+/// The key for every action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: BTreeMap<Action, KeyCode>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap {
+            keys: Action::ALL
+                .into_iter()
+                .map(|action| {
+                    (action, action.default_key())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl KeyMap {
+    /// The defaults with `overrides`, action name to key,
+    /// applied on top. Every action ends up on a key of its
+    /// own, so moving one action onto another's default key
+    /// means moving that one too.
+    pub fn new(
+        overrides: &BTreeMap<String, String>,
+    ) -> Result<Self, KeyMapError> {
+        let mut keymap = KeyMap::default();
+        for (name, key) in overrides {
+            let action = Action::from_name(name)
+                .ok_or_else(|| {
+                    KeyMapError::Action(name.clone())
+                })?;
+            if action.is_fixed() {
+                return Err(KeyMapError::Fixed(
+                    action.name(),
+                ));
+            }
+            let code = parse_key(key).ok_or_else(|| {
+                KeyMapError::Key {
+                    action: name.clone(),
+                    key: key.clone(),
+                }
+            })?;
+            if is_reserved(code) {
+                return Err(KeyMapError::Reserved {
+                    action: action.name(),
+                    key: key_name(code),
+                });
+            }
+            keymap.keys.insert(action, code);
+        }
+        for (index, (first, key)) in
+            keymap.keys.iter().enumerate()
+        {
+            if let Some((second, _)) = keymap
+                .keys
+                .iter()
+                .skip(index + 1)
+                .find(|(_, other)| *other == key)
+            {
+                return Err(KeyMapError::Duplicate {
+                    key: key_name(*key),
+                    first: first.name(),
+                    second: second.name(),
+                });
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// The action `key` does, if any
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        match key.code {
+            // raw mode turns ctrl-c into a key press rather
+            // than a SIGINT
+            KeyCode::Char('c')
+                if key
+                    .modifiers
+                    .contains(KeyModifiers::CONTROL) =>
+            {
+                Some(Action::Quit)
+            }
+            KeyCode::Char(digit)
+                if digit.is_ascii_digit() =>
+            {
+                Some(Action::JumpToView)
+            }
+            code => self
+                .keys
+                .iter()
+                .find(|(_, bound)| **bound == code)
+                .map(|(action, _)| *action),
+        }
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_key())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(
+        pairs: &[(&str, &str)],
+    ) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(action, key)| {
+                (action.to_string(), key.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_custom_key_routes_to_its_action() {
+        let keymap = KeyMap::new(&keys(&[
+            ("quit", "Q"),
+            ("pause", "P"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            keymap.action(KeyCode::Char('Q').into()),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char('P').into()),
+            Some(Action::Pause)
+        );
+        // the old keys are free now
+        assert_eq!(
+            keymap.action(KeyCode::Char('q').into()),
+            None
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char(' ').into()),
+            None
+        );
+        // and everything unmapped keeps its default
+        assert_eq!(
+            keymap.action(KeyCode::Tab.into()),
+            Some(Action::NextView)
+        );
+        for action in Action::ALL {
+            if ![Action::Quit, Action::Pause]
+                .contains(&action)
+            {
+                assert_eq!(
+                    keymap.key(action),
+                    action.default_key()
+                );
+            }
+        }
+        assert_eq!(
+            KeyMap::new(&BTreeMap::new()).unwrap(),
+            KeyMap::default()
+        );
+    }
+
+    #[test]
+    fn the_fixed_keys_stay_put() {
+        let keymap =
+            KeyMap::new(&keys(&[("quit", "Q")])).unwrap();
+        assert_eq!(
+            keymap.action(KeyEvent::new(
+                KeyCode::Char('c'),
+                KeyModifiers::CONTROL
+            )),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char('3').into()),
+            Some(Action::JumpToView)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Esc.into()),
+            Some(Action::Close)
+        );
+        assert_eq!(
+            keymap.action(KeyCode::Char('z').into()),
+            None
+        );
+        assert_eq!(
+            KeyMap::new(&keys(&[("close", "c")])),
+            Err(KeyMapError::Fixed("close"))
+        );
+    }
+
+    #[test]
+    fn bad_bindings_are_refused() {
+        // m is mute's already
+        assert_eq!(
+            KeyMap::new(&keys(&[("quit", "m")])),
+            Err(KeyMapError::Duplicate {
+                key: "m".to_string(),
+                first: "quit",
+                second: "mute",
+            })
+        );
+        // unless mute moves too
+        assert!(KeyMap::new(&keys(&[
+            ("quit", "m"),
+            ("mute", "M")
+        ]))
+        .is_ok());
+        assert_eq!(
+            KeyMap::new(&keys(&[("jump", "j")])),
+            Err(KeyMapError::Action("jump".to_string()))
+        );
+        assert!(matches!(
+            KeyMap::new(&keys(&[("help", "f1")])),
+            Err(KeyMapError::Key { .. })
+        ));
+        assert!(matches!(
+            KeyMap::new(&keys(&[("help", "3")])),
+            Err(KeyMapError::Reserved {
+                action: "help",
+                ..
+            })
+        ));
+    }
+}
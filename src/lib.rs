@@ -1,15 +1,217 @@
+use analysis::{
+    AnalysisFrame, AnalysisStats, BandGains, FreqRange,
+    LevelHistogram, PeakHold,
+};
+use audio::StreamNotice;
+use clock::SystemClock;
+use denoise::SharedNoiseProfile;
 use std::{
+    collections::VecDeque,
     io,
     sync::{Arc, Mutex},
 };
+use tempo::TempoTracker;
 use tui::*;
 use winit::event_loop::EventLoop;
+pub mod activity;
+pub mod analysis;
+#[cfg(feature = "artnet")]
+pub mod artnet;
 pub mod audio;
+pub mod clip;
+pub mod clock;
+pub mod colormap;
+pub mod config;
+pub mod denoise;
+pub mod export;
+pub mod gate;
 pub mod graphics;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub mod keymap;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mix;
+pub mod multires;
+pub mod profile;
+pub mod session;
+pub mod smoothing;
+pub mod snapshot;
+pub mod target;
+pub mod tempo;
+pub mod test_tone;
 pub mod tui;
+pub mod tuner;
+pub mod voice;
+pub mod wav;
+pub mod wizard;
+pub mod zoom;
+
+/// How many spectra the waterfall keeps around
+pub const SPECTROGRAM_ROWS: usize = 256;
+
+/// Bins in the level histogram
+pub const HISTOGRAM_BINS: usize = 20;
+/// Readings the level histogram covers, about half a minute
+/// at 48kHz with the default fft size
+pub const HISTOGRAM_WINDOW: usize = 1400;
+
+/// The latest levels from one of the extra inputs opened
+/// with `--compare-device`, kept apart from the main input's
+/// and drawn in a column of their own beside it
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLevels {
+    /// the device's name, as given on the command line
+    pub name: String,
+    /// peak level of the latest frame in dBFS, `None` until
+    /// the first one arrives
+    pub level: Option<f32>,
+    /// latest energy per band, lined up with the main
+    /// input's `freq_ranges`
+    pub bands: Vec<f32>,
+    /// how long the spectrum the bands came from was, to
+    /// scale them against
+    pub spectrum_len: usize,
+    /// why frames stopped arriving, like
+    /// `AppState::audio_stopped`
+    pub audio_stopped: Option<String>,
+}
+
+impl DeviceLevels {
+    pub fn new(name: impl Into<String>) -> Self {
+        DeviceLevels {
+            name: name.into(),
+            ..DeviceLevels::default()
+        }
+    }
 
+    pub fn update(&mut self, frame: &AnalysisFrame) {
+        self.level =
+            Some(analysis::peak_dbfs(&frame.samples));
+        self.bands.clone_from(&frame.bands);
+        self.spectrum_len = frame.spectrum.len();
+    }
+}
+
+/// What the UI draws, built up from the analysis frames.
+/// It lives apart from the audio side, so when the input
+/// renegotiates its sample rate only the analyzer is rebuilt
+/// and the history here (the spectrogram, the sparkline's
+/// levels, the histogram) carries on.
+#[derive(Clone)]
 pub struct AppState {
     pub decibels: Vec<f32>,
+    /// recent spectra, newest at the front
+    pub spectrogram: VecDeque<Vec<f32>>,
+    /// latest energy per band, lined up with `freq_ranges`
+    pub bands: Vec<f32>,
+    /// the bands the analyzer is currently aggregating
+    pub freq_ranges: Vec<FreqRange>,
+    /// samples the analyzer had to skip because the device
+    /// buffer was bigger than the fft
+    pub dropped_samples: u64,
+    /// max-hold trace drawn over the live spectrum
+    pub peak_hold: PeakHold,
+    /// the spectrum with a fading trail, drawn instead of
+    /// the live one when phosphor persistence is on. The
+    /// same max of decayed and current as `peak_hold`, but
+    /// over what's displayed and usually decaying faster.
+    pub persistence: Option<PeakHold>,
+    /// the analyzer's band gains, adjustable from the TUI
+    pub band_gains: BandGains,
+    /// the analyzer's noise profile, relearned from the TUI
+    pub noise_profile: SharedNoiseProfile,
+    /// (left, right) sample pairs from the latest frame,
+    /// empty unless the input is stereo
+    pub stereo: Vec<(f32, f32)>,
+    /// the latest frame's stereo width, 0 for mono to 1 for
+    /// all side, see `mix::MidSide::width`
+    pub stereo_width: f32,
+    /// the loudest frequency in the latest spectrum, in Hz,
+    /// see `analysis::peak_frequency`. `None` for silence
+    /// and for I/Q input.
+    pub peak_hz: Option<f32>,
+    /// the latest frame's samples, just the first channel
+    /// if there's more than one
+    pub waveform: Vec<f32>,
+    /// measured analysis frames per second, 0 until the
+    /// first two frames have arrived
+    pub analysis_rate: f32,
+    /// onset envelope behind the tempo readout
+    pub tempo: TempoTracker,
+    /// how the latest frame's fft size and buffer size
+    /// worked out
+    pub stats: AnalysisStats,
+    /// why frames stopped arriving, set once the audio side
+    /// has gone away for good
+    pub audio_stopped: Option<String>,
+    /// how the level has been distributed recently, over
+    /// the same range the meter shows
+    pub level_histogram: LevelHistogram,
+    /// the latest frame's activity score, 0 to 1
+    pub activity: f32,
+    /// how speech-like the input sounds, 0 to 1, see
+    /// `voice::VoiceDetector`
+    pub speech: f32,
+    /// what the audio side last had to say about the input,
+    /// shown for a few seconds
+    pub stream_notice: StreamNotice,
+    /// the other inputs being compared against this one, in
+    /// the order they were given
+    pub devices: Vec<DeviceLevels>,
+}
+
+impl AppState {
+    pub fn new(freq_ranges: Vec<FreqRange>) -> Self {
+        let freq_ranges_len = freq_ranges.len();
+        AppState {
+            decibels: vec![],
+            spectrogram: VecDeque::new(),
+            bands: vec![0.0; freq_ranges.len()],
+            freq_ranges,
+            dropped_samples: 0,
+            peak_hold: PeakHold::new(0.999),
+            persistence: None,
+            band_gains: Arc::new(Mutex::new(vec![
+                1.0;
+                freq_ranges_len
+            ])),
+            noise_profile: SharedNoiseProfile::default(),
+            stereo: vec![],
+            stereo_width: 0.0,
+            peak_hz: None,
+            waveform: vec![],
+            analysis_rate: 0.0,
+            tempo: TempoTracker::new(),
+            stats: AnalysisStats::default(),
+            audio_stopped: None,
+            level_histogram: LevelHistogram::new(
+                meter_range(false),
+                HISTOGRAM_BINS,
+                HISTOGRAM_WINDOW,
+            ),
+            activity: 0.0,
+            speech: 0.0,
+            stream_notice: Arc::new(Mutex::new(None)),
+            devices: vec![],
+        }
+    }
+
+    /// Adds a frame's peak `level` and displayed `spectrum`
+    /// to the history the views draw from
+    pub fn push_history(
+        &mut self,
+        level: f32,
+        spectrum: Vec<f32>,
+    ) {
+        self.decibels.push(level);
+        self.level_histogram.push(level);
+        if let Some(persistence) = &mut self.persistence {
+            persistence.update(&spectrum);
+        }
+        self.spectrogram.push_front(spectrum);
+        self.spectrogram.truncate(SPECTROGRAM_ROWS);
+    }
 }
 
 pub async fn run_graphics() -> () {
@@ -23,9 +225,13 @@ pub async fn run_graphics() -> () {
 
 pub fn run_tui(
     state: Arc<Mutex<AppState>>,
+    options: DisplayOptions,
 ) -> Result<(), io::Error> {
     let mut terminal = setup_terminal()?;
-    run(&mut terminal, state)?;
+    // put the terminal back before reporting any error so
+    // the message isn't lost on the alternate screen
+    let result =
+        run(&mut terminal, state, &options, &SystemClock);
     restore_terminal(&mut terminal)?;
-    Ok(())
+    result
 }
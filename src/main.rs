@@ -2,11 +2,72 @@
 //!
 //! The input data is recorded to "$CARGO_MANIFEST_DIR/recorded.wav".
 
-use clap::Parser;
-use miette::IntoDiagnostic;
-use pngtubers::{audio, run_graphics, run_tui, AppState};
-use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc::channel;
+use clap::{Parser, ValueEnum};
+use miette::{miette, Diagnostic};
+#[cfg(feature = "artnet")]
+use pngtubers::artnet;
+#[cfg(feature = "ipc")]
+use pngtubers::ipc;
+#[cfg(feature = "metrics")]
+use pngtubers::metrics;
+use pngtubers::{
+    analysis::{
+        self, AnalysisFrame, BandPreset, BandWeighting,
+        FftBackend, InputMode, LevelHistogram, PeakHold,
+        SpectrumType, DC_BLOCK_POLE,
+    },
+    audio::{
+        self, AnalysisLocation, AudioConfig, DropPolicy,
+        MixOptions, MonitorOptions, PngTuberAudioError,
+        QueueOptions, SAMPLE_QUEUE_CAPACITY,
+    },
+    clock::{Clock, RateMeter, SystemClock},
+    colormap::{ColorDepth, Colormap},
+    config::{Config, ConfigError},
+    denoise::LEARN_FRAMES,
+    export::{
+        self, CsvRecorder, ExportFrame, RateLimiter,
+        TimestampFormat,
+    },
+    gate::GateOptions,
+    mix::{DownmixTarget, MidSide},
+    multires::MultiResolution,
+    profile::SharedProfile,
+    run_tui,
+    session::{self, SessionError, SessionRecorder},
+    smoothing::{
+        BandMatrixSmoothing, BandSmoothing, OneEuroOptions,
+        SpectrumSmoothing,
+    },
+    snapshot::{render_frame_to_png, SnapshotError},
+    target::TargetCurve,
+    test_tone::{self, TestSignal},
+    tui::{
+        meter_range, parse_region, parse_size,
+        DisplayOptions, IdleOptions, LevelScale,
+        Orientation, Shutdown, SpectrumNorm,
+        TriggerOptions, STALL_TIMEOUT,
+    },
+    tuner::A4_HZ,
+    voice::{VoiceDetector, VoiceWeights},
+    wav,
+    zoom::{ZoomBand, ZOOM_FFT_SIZE},
+    AppState, DeviceLevels, HISTOGRAM_BINS,
+    HISTOGRAM_WINDOW,
+};
+use ratatui::{layout::Rect, style::Color};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    sync::mpsc::channel,
+    time::{timeout_at, Instant},
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "CPAL record_wav example", long_about = None)]
@@ -26,44 +87,1642 @@ struct Args {
         feature = "jack"
     ))]
     #[arg(short, long)]
-    #[allow(dead_code)]
     jack: bool,
+
+    /// Draw the newest sample on the left
+    #[arg(long)]
+    mirror: bool,
+
+    /// Hang the sparkline bars from the top
+    #[arg(long)]
+    invert: bool,
+
+    /// Where persistent settings are stored
+    #[arg(long, default_value = "pngtubers.toml")]
+    config: PathBuf,
+
+    /// Measure the level of a calibrator playing this many
+    /// dB SPL, save the offset to the config and exit
+    #[arg(long, value_name = "REFERENCE_DB")]
+    calibrate: Option<f32>,
+
+    /// How long to listen to the calibrator for
+    #[arg(long, default_value_t = 3.0)]
+    calibration_seconds: f32,
+
+    /// Play the input through this output device (or
+    /// "default") while visualizing
+    #[arg(long, value_name = "OUTPUT_DEVICE")]
+    monitor: Option<String>,
+
+    /// Maximum delay between input and monitor output
+    #[arg(long, default_value_t = 50)]
+    monitor_latency_ms: u32,
+
+    /// Linear gain applied to the monitor output
+    #[arg(long, default_value_t = 1.0)]
+    monitor_gain: f32,
+
+    /// How much of the held spectrum survives each frame,
+    /// closer to 1 holds peaks for longer
+    #[arg(long, default_value_t = 0.999)]
+    peak_hold_decay: f32,
+
+    /// Leave a fading trail behind the spectrum like a
+    /// phosphor screen: this much of the trail survives
+    /// each frame, e.g. 0.9. Closer to 1 fades slower.
+    #[arg(long)]
+    persistence: Option<f32>,
+
+    /// Color ramp for the waterfall
+    #[arg(long, value_enum, default_value_t = Colormap::Viridis)]
+    colormap: Colormap,
+
+    /// Contrast of the waterfall and spectrum: below 1
+    /// brings out quiet detail such as faint harmonics,
+    /// above 1 leaves only the loudest bins bright
+    #[arg(long, default_value_t = 1.0)]
+    gamma: f32,
+
+    /// A target response to compare the waterfall view's
+    /// spectrum with, such as a house curve: a CSV of
+    /// frequency,dB lines. Only its shape counts, it's moved
+    /// to the spectrum's average level.
+    #[arg(long)]
+    target_curve: Option<PathBuf>,
+
+    /// Base of the log the spectrum is drawn on. The views
+    /// still span 80 "dB", so 10 is the usual decibels and
+    /// a smaller base covers a narrower range.
+    #[arg(long, default_value_t = 10.0)]
+    log_base: f32,
+
+    /// Draw the spectrum on a soft log instead, linear near
+    /// silence rather than hitting a floor and close to a
+    /// log above this fraction of full scale, e.g. 0.001. 0
+    /// keeps the plain log.
+    #[arg(long, default_value_t = 0.0)]
+    log_softness: f32,
+
+    /// Colors the terminal supports, for terminals that get
+    /// truecolor gradients wrong
+    #[arg(long, value_enum, default_value_t = ColorDepth::Truecolor)]
+    color_depth: ColorDepth,
+
+    /// How many times a second to redraw the TUI
+    #[arg(long, default_value_t = 4.0)]
+    fps: f32,
+
+    /// How many seconds of history the sparkline spans,
+    /// defaults to one analysis frame per column
+    #[arg(long)]
+    seconds_per_screen: Option<f32>,
+
+    /// Linear gain for each band, lowest band first, e.g.
+    /// 0.5,1,1,1,1 to tame a boomy mic. Overrides the config.
+    #[arg(long, value_delimiter = ',')]
+    band_gains: Option<Vec<f32>>,
+
+    /// A fixed color for each band in the bar chart, lowest
+    /// band first, e.g. red,yellow,green,cyan,blue. Names,
+    /// palette indices and #rrggbb all work, and the list
+    /// repeats if it's shorter than the bands. Overrides the
+    /// config.
+    #[arg(long, value_delimiter = ',')]
+    band_colors: Option<Vec<Color>>,
+
+    /// Mark these levels on the level meters with dashed
+    /// lines, e.g. -12,-1 for a speaking target and the clip
+    /// point. In dBFS, or dB SPL once calibrated.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        allow_negative_numbers = true
+    )]
+    reference_db: Vec<f32>,
+
+    /// Flash the background this color on every beat,
+    /// brighter for stronger ones, e.g. white or #ff00ff
+    #[arg(long)]
+    beat_flash: Option<Color>,
+
+    /// Analyze with several fft sizes, largest first, each
+    /// covering part of the spectrum split at
+    /// --multi-res-crossovers: e.g. 4096,1024 gives the bass
+    /// a long fft's detail and the treble a short one's
+    /// speed. Real input only.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        requires = "multi_res_crossovers"
+    )]
+    multi_res_sizes: Option<Vec<usize>>,
+
+    /// Where each fft size hands over to the next, in Hz,
+    /// lowest first
+    #[arg(
+        long,
+        value_delimiter = ',',
+        requires = "multi_res_sizes"
+    )]
+    multi_res_crossovers: Option<Vec<f32>>,
+
+    /// Zoom in on a narrow band around this frequency, in Hz,
+    /// with a zoom fft: e.g. 40 with --zoom-bandwidth 40
+    /// resolves 20 to 60Hz to a fraction of a Hz. The
+    /// strongest frequency in it goes out with each exported
+    /// frame. Real input only.
+    #[arg(long, requires = "zoom_bandwidth")]
+    zoom_center: Option<f32>,
+
+    /// How wide the zoomed band is, in Hz
+    #[arg(long, requires = "zoom_center")]
+    zoom_bandwidth: Option<f32>,
+
+    /// Size of the zoom fft. Bigger is finer but takes
+    /// longer to fill: the size over the bandwidth, in
+    /// seconds.
+    #[arg(long, default_value_t = ZOOM_FFT_SIZE)]
+    zoom_fft_size: usize,
+
+    /// Fold a surround input (3, 4, 5.1 or 7.1 channels in
+    /// WAV order) down to stereo or mono with the ITU
+    /// coefficients: center and surrounds at -3dB, LFE left
+    /// out. Mono and stereo inputs are just copied or
+    /// averaged.
+    #[arg(long, value_enum)]
+    downmix: Option<DownmixTarget>,
+
+    /// How slowly each band rises, lowest band first: the
+    /// fraction of the previous level kept each frame, from
+    /// 0 (instant) up to but not including 1. Overrides the
+    /// config.
+    #[arg(long, value_delimiter = ',')]
+    band_attack: Option<Vec<f32>>,
+
+    /// How slowly each band falls, like --band-attack.
+    /// Overrides the config.
+    #[arg(long, value_delimiter = ',')]
+    band_release: Option<Vec<f32>>,
+
+    /// Smooth the band levels over time: the fraction of
+    /// every band's previous level kept each frame, from 0
+    /// up to but not including 1. Applies after
+    /// --band-attack and --band-release.
+    #[arg(long, default_value_t = 0.0)]
+    band_smooth_time: f32,
+
+    /// Smooth the band levels across neighbouring bands:
+    /// the fraction of each band replaced by the mean of the
+    /// bands either side, from 0 up to but not including 1
+    #[arg(long, default_value_t = 0.0)]
+    band_smooth_across: f32,
+
+    /// Smooth the spectrum the views draw with a moving
+    /// average this many bins wide
+    #[arg(long, conflicts_with = "smooth_octave")]
+    smooth_bins: Option<usize>,
+
+    /// Smooth the spectrum the views draw over 1/N of an
+    /// octave, e.g. 3 for third octave smoothing, wider at
+    /// higher frequencies
+    #[arg(long)]
+    smooth_octave: Option<f32>,
+
+    /// Only show talking when the input also sounds like
+    /// speech (the right zero crossing rate, energy in the
+    /// voice band, a level that moves with syllables), so
+    /// steady music over the threshold doesn't count
+    #[arg(long)]
+    speech_gate: bool,
+
+    /// How much --speech-gate weighs the zero crossing
+    /// rate, the voice band and the level's modulation, in
+    /// that order
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 3,
+        requires = "speech_gate"
+    )]
+    speech_weights: Option<Vec<f32>>,
+
+    /// Scale the spectrum the views draw: none keeps its
+    /// level, peak stretches the loudest bin to the top,
+    /// area keeps the shape the same at any loudness
+    #[arg(long, value_enum, default_value_t)]
+    spectrum_norm: SpectrumNorm,
+
+    /// Time each analysis stage and print a summary on exit
+    #[arg(long)]
+    profile: bool,
+
+    /// Print one JSON object per frame to stdout instead of
+    /// running the TUI
+    #[arg(long, conflicts_with = "calibrate")]
+    ndjson: bool,
+
+    /// Write the input, downmixed to mono, to stdout as raw
+    /// 32 bit float little endian PCM at the input's sample
+    /// rate instead of running the TUI, for piping into
+    /// other audio tools
+    #[arg(long, conflicts_with_all = ["calibrate", "ndjson"])]
+    pcm_out: bool,
+
+    /// Most lines per second to print with --ndjson, 0 for
+    /// every frame
+    #[arg(long, default_value_t = 0.0)]
+    ndjson_max_rate: f32,
+
+    /// Analyze a generated signal instead of the input
+    /// device, for demos without a microphone
+    #[arg(long, value_enum, conflicts_with_all = ["calibrate", "monitor"])]
+    test_tone: Option<TestSignal>,
+
+    /// Write a CSV row per analysis frame to this file: the
+    /// timestamp, level, bands, activity and dropped samples
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    /// How --csv writes the timestamp column
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        requires = "csv"
+    )]
+    csv_timestamp: TimestampFormat,
+
+    /// Write every analysis frame to this file as it's
+    /// made, to replay later with --replay-session
+    #[arg(long)]
+    record_session: Option<PathBuf>,
+
+    /// Play back a session written by --record-session at
+    /// the rate it was recorded, instead of analyzing any
+    /// input
+    #[arg(long, conflicts_with_all = ["test_tone", "monitor", "mix_device"])]
+    replay_session: Option<PathBuf>,
+
+    /// Analyze a wav file, played in real time, instead of
+    /// the input device
+    #[arg(long, conflicts_with_all = ["test_tone", "replay_session", "monitor", "mix_device"])]
+    wav: Option<PathBuf>,
+
+    /// Start --wav over from the top every time it ends, so
+    /// a demo runs forever off one track. The end runs
+    /// straight on into the start with no gap, so a track
+    /// that itself loops cleanly plays without a seam.
+    #[arg(long = "loop", requires = "wav")]
+    loop_wav: bool,
+
+    /// Learn a noise profile from the first second of input
+    /// and subtract it from everything after. Press n in
+    /// the TUI to relearn it.
+    #[arg(long)]
+    denoise: bool,
+
+    /// Which bands to split the spectrum into
+    #[arg(long, value_enum, default_value_t)]
+    bands_preset: BandPreset,
+
+    /// How each band's bins are combined: mean averages
+    /// them, density spreads their total over the band's
+    /// width in Hz
+    #[arg(long, value_enum, default_value_t)]
+    band_weighting: BandWeighting,
+
+    /// What each band adds up its bins as: magnitude, or
+    /// power with the square root of the total taken, which
+    /// counts a band's energy correctly when it's in a few
+    /// of its bins rather than spread over all of them
+    #[arg(long, value_enum, default_value_t)]
+    spectrum_type: SpectrumType,
+
+    /// Frames per input callback to ask the device for.
+    /// Each callback is analyzed as one fft, so this sets
+    /// the analysis rate; buffers with more samples than
+    /// the fft size (1024) drop the rest. See
+    /// --analysis-rate for a rate that doesn't depend on it.
+    #[arg(long)]
+    buffer_size: Option<u32>,
+
+    /// Analyze this many frames per second whatever the
+    /// device's buffer size and sample rate, each fft over
+    /// the newest fft size of samples, so the display moves
+    /// at the same speed on any hardware. E.g. 60.
+    #[arg(long)]
+    analysis_rate: Option<f32>,
+
+    /// Drop to --idle-fps after the level has stayed below
+    /// --idle-threshold-db for this many seconds
+    #[arg(long)]
+    idle_after: Option<f32>,
+
+    /// Level counted as silence for --idle-after, in dBFS
+    /// (or dB SPL once calibrated). Defaults to the gate
+    /// the setup wizard (w) saved, or -50.
+    #[arg(long, allow_negative_numbers = true)]
+    idle_threshold_db: Option<f32>,
+
+    /// Redraw rate while idle
+    #[arg(long, default_value_t = 1.0)]
+    idle_fps: f32,
+
+    /// The tuner's reference pitch, the A above middle C,
+    /// in Hz
+    #[arg(long = "a4", default_value_t = A4_HZ)]
+    a4_hz: f32,
+
+    /// Redraw rate while the terminal window doesn't have
+    /// focus, to save power. Only terminals that report
+    /// focus changes slow down.
+    #[arg(long)]
+    unfocused_fps: Option<f32>,
+
+    /// Treat the input as real audio or as interleaved I/Q
+    /// pairs from an sdr
+    #[arg(long, value_enum, default_value_t)]
+    input_mode: InputMode,
+
+    /// Show the decibel history as a moving average over
+    /// this many readings. The stored readings stay raw.
+    #[arg(long, default_value_t = 1)]
+    smoothing: usize,
+
+    /// Freeze the display when the level reaches this many
+    /// dB, like a scope trigger. r rearms it.
+    #[arg(long, allow_negative_numbers = true)]
+    freeze_on_peak: Option<f32>,
+
+    /// Readings from before the trigger to keep on screen
+    #[arg(long, default_value_t = 100)]
+    trigger_pre_samples: usize,
+
+    /// Readings to wait for after the trigger before
+    /// freezing
+    #[arg(long, default_value_t = 20)]
+    trigger_post_samples: usize,
+
+    /// High-pass the input to remove any dc offset before
+    /// it's analyzed
+    #[arg(long)]
+    dc_block: bool,
+
+    /// Pole of the dc blocker, closer to 1 cuts less
+    #[arg(long, default_value_t = DC_BLOCK_POLE)]
+    dc_block_pole: f32,
+
+    /// Gate the input: turn it down by --gate-range-db
+    /// while it's below --gate-threshold-db, rather than
+    /// muting it
+    #[arg(long)]
+    gate: bool,
+
+    /// Level the gate opens at, in dBFS (or dB SPL once
+    /// calibrated). Defaults to the gate the setup wizard
+    /// (w) saved, or -50.
+    #[arg(long, allow_negative_numbers = true)]
+    gate_threshold_db: Option<f32>,
+
+    /// How far the closed gate turns the input down, in dB
+    #[arg(long, default_value_t = 20.0)]
+    gate_range_db: f32,
+
+    /// Width of the soft knee around the gate's threshold,
+    /// in dB, 0 for a hard gate
+    #[arg(long, default_value_t = 6.0)]
+    gate_knee_db: f32,
+
+    /// A second input device to sum with --device, e.g. a
+    /// desktop audio capture alongside the mic. Resampled to
+    /// --device's rate and mixed down to mono.
+    #[arg(long, conflicts_with = "test_tone")]
+    mix_device: Option<String>,
+
+    /// Also open this input, with an analyzer of its own, and
+    /// show its level and bands in a column beside the main
+    /// view to compare the two. Repeat for more.
+    #[arg(long, conflicts_with_all = ["ndjson", "pcm_out"])]
+    compare_device: Vec<String>,
+
+    /// Gain on --mix-device
+    #[arg(long, default_value_t = 1.0)]
+    mix_gain: f32,
+
+    /// Gain on --device when mixing
+    #[arg(long, default_value_t = 1.0)]
+    input_gain: f32,
+
+    /// Back the gain off when the input or the monitor
+    /// output keeps clipping, and bring it back once the
+    /// clipping stops
+    #[arg(long)]
+    gain_auto_off_on_clip: bool,
+
+    /// How to compute the spectrum: a complex fft, or a real
+    /// one of half the work that gives the same magnitudes
+    #[arg(long, value_enum, default_value_t = FftBackend::Complex)]
+    fft_backend: FftBackend,
+
+    /// Device buffers that can wait for analysis before some
+    /// get dropped, so a stall never holds up the audio
+    #[arg(long, default_value_t = SAMPLE_QUEUE_CAPACITY)]
+    backlog: usize,
+
+    /// Which buffer to drop once the backlog is full
+    #[arg(long, value_enum, default_value_t = DropPolicy::Oldest)]
+    drop_policy: DropPolicy,
+
+    /// Where the fft runs: on its own thread fed by the
+    /// backlog, or in the audio callback, saving a thread
+    /// and a copy at the risk of input glitches with big
+    /// ffts
+    #[arg(long, value_enum, default_value_t)]
+    analysis_location: AnalysisLocation,
+
+    /// Analyze only one device buffer in this many, for
+    /// machines the analysis keeps busy. Frames (and the
+    /// update rate) drop by the same factor, so quick sounds
+    /// can be missed between them; the skipped buffers aren't
+    /// counted as dropped.
+    #[arg(long, default_value_t = 1)]
+    decimation: usize,
+
+    /// Render the first frame's spectrum to a png at this
+    /// path and exit, instead of running the TUI
+    #[arg(long, conflicts_with_all = ["calibrate", "ndjson"])]
+    snapshot: Option<PathBuf>,
+
+    /// Where the p key saves a line plot of the spectrum
+    /// on screen, a png the size of --snapshot's
+    #[arg(long, default_value = "spectrum.png")]
+    dump_spectrum_image: PathBuf,
+
+    /// Width of the --snapshot and --dump-spectrum-image
+    /// images, in pixels
+    #[arg(long, default_value_t = 640)]
+    snapshot_width: u32,
+
+    /// Height of the --snapshot and --dump-spectrum-image
+    /// images, in pixels
+    #[arg(long, default_value_t = 360)]
+    snapshot_height: u32,
+
+    /// Draw only into this part of the terminal, as
+    /// WIDTHxHEIGHT+X+Y, e.g. 40x10+0+2. The rest is left
+    /// untouched for other output. Cut down to fit a
+    /// smaller terminal.
+    #[arg(long, value_parser = parse_region)]
+    region: Option<Rect>,
+
+    /// The smallest terminal (or --region) to draw in, as
+    /// WIDTHxHEIGHT. Anything smaller shows a message
+    /// instead of a squashed display.
+    #[arg(long, value_parser = parse_size, default_value = "40x10")]
+    min_size: (u16, u16),
+
+    /// Open with the display paused, e.g. to set up a
+    /// capture before anything moves. Space resumes.
+    #[arg(long)]
+    start_paused: bool,
+
+    /// How steady the tempo readout is held while it isn't
+    /// really changing, as a low pass cutoff in Hz. Lower
+    /// jitters less but follows more slowly.
+    #[arg(long, default_value_t = OneEuroOptions::default().min_cutoff)]
+    readout_min_cutoff: f32,
+
+    /// How much faster the tempo readout follows while it's
+    /// changing, per bpm per second of change
+    #[arg(long, default_value_t = OneEuroOptions::default().beta)]
+    readout_beta: f32,
+
+    /// Dim the display and say the input is disconnected
+    /// once no audio has arrived for this many seconds, 0
+    /// never does
+    #[arg(long, default_value_t = STALL_TIMEOUT.as_secs_f32())]
+    stall_timeout: f32,
+
+    /// Sample rate of the generated test tone
+    #[arg(long, default_value_t = 48000)]
+    test_tone_rate: u32,
+
+    /// Run a stepped sine sweep through the analysis and
+    /// print how flat its response is, then exit, failing if
+    /// any frequency is more than half a dB out
+    #[arg(long)]
+    self_test: bool,
+
+    /// Print the audio hosts available on this system and
+    /// exit
+    #[arg(long)]
+    list_hosts: bool,
+
+    /// Print the input devices on the default host with the
+    /// channel counts, sample rates and sample formats each
+    /// supports, and exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// With --list-devices, print them as a JSON array for
+    /// scripts to read instead
+    #[arg(long, requires = "list_devices")]
+    json: bool,
+
+    /// Publish every frame, in the same JSON as --ndjson, to
+    /// clients of a Unix domain socket at this path, or a
+    /// named pipe such as \\.\pipe\pngtubers on Windows
+    #[cfg(feature = "ipc")]
+    #[arg(long)]
+    publish_socket: Option<PathBuf>,
+
+    /// Most frames per second to publish on
+    /// --publish-socket, 0 for every frame
+    #[cfg(feature = "ipc")]
+    #[arg(long, default_value_t = 0.0)]
+    publish_max_rate: f32,
+
+    /// Serve Prometheus metrics (levels, dropped samples,
+    /// frames) at http://<this address>/metrics, e.g.
+    /// 127.0.0.1:9898
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// Send the band levels as DMX over Art-Net to this
+    /// node, e.g. 192.168.1.50:6454, or a broadcast address
+    /// for every node on the network
+    #[cfg(feature = "artnet")]
+    #[arg(long)]
+    artnet_target: Option<std::net::SocketAddr>,
+
+    /// Art-Net universe (port-address, 0 to 32767) the
+    /// band levels go to
+    #[cfg(feature = "artnet")]
+    #[arg(long, default_value_t = 0)]
+    artnet_universe: u16,
+
+    /// DMX channel, from 1, of the first band. The rest
+    /// follow on consecutive channels.
+    #[cfg(feature = "artnet")]
+    #[arg(long, default_value_t = 1)]
+    artnet_channel: u16,
+
+    /// Most Art-Net packets per second to send, 0 for one
+    /// every frame. Many nodes only refresh at around 44Hz.
+    #[cfg(feature = "artnet")]
+    #[arg(long, default_value_t = 0.0)]
+    artnet_max_rate: f32,
 }
 
-#[tokio::main]
-async fn main() -> miette::Result<()> {
+/// Whether to open the input through the JACK host. Only
+/// builds with the jack feature have the flag.
+#[cfg(all(
+    any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ),
+    feature = "jack"
+))]
+fn use_jack(args: &Args) -> bool {
+    args.jack
+}
+
+#[cfg(not(all(
+    any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ),
+    feature = "jack"
+)))]
+fn use_jack(_args: &Args) -> bool {
+    false
+}
+
+/// Failures writing frames or the TUI out
+#[derive(Error, Debug, Diagnostic)]
+enum OutputError {
+    #[error("could not write to the terminal")]
+    #[diagnostic(code(pngtubers::output::io))]
+    Io(#[from] io::Error),
+    #[error("could not serialize a frame")]
+    #[diagnostic(code(pngtubers::output::json))]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Session(#[from] SessionError),
+    #[cfg(feature = "ipc")]
+    #[error("could not listen on {}", path.display())]
+    #[diagnostic(code(pngtubers::output::socket))]
+    Socket {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[cfg(feature = "metrics")]
+    #[error("could not serve metrics on {addr}")]
+    #[diagnostic(code(pngtubers::output::metrics))]
+    Metrics {
+        addr: std::net::SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[cfg(feature = "artnet")]
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    ArtNet(#[from] artnet::ArtNetError),
+}
+
+/// Exit codes, so scripts can tell a missing mic from a
+/// broken terminal. clap exits with 2 on bad arguments
+/// before any of these can happen.
+const EXIT_OTHER: u8 = 1;
+const EXIT_AUDIO: u8 = 3;
+const EXIT_CONFIG: u8 = 4;
+const EXIT_OUTPUT: u8 = 5;
+
+/// `values` separated by commas, for listing a device's
+/// capabilities
+fn join(values: &[impl std::fmt::Display]) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Which exit code a failure maps to
+fn exit_code(report: &miette::Report) -> u8 {
+    if let Some(err) =
+        report.downcast_ref::<PngTuberAudioError>()
+    {
+        // a device that doesn't suit the settings is a
+        // problem with the settings
+        match err {
+            PngTuberAudioError::Config(_) => EXIT_CONFIG,
+            _ => EXIT_AUDIO,
+        }
+    } else if report.downcast_ref::<ConfigError>().is_some()
+    {
+        EXIT_CONFIG
+    } else if report.downcast_ref::<OutputError>().is_some()
+    {
+        EXIT_OUTPUT
+    } else {
+        EXIT_OTHER
+    }
+}
+
+fn main() -> ExitCode {
     env_logger::init();
-    let (tx, mut rx) = channel::<Vec<f32>>(100);
     let args = Args::parse();
-    let state = Arc::new(Mutex::new(AppState {
-        decibels: vec![],
-    }));
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(report) => {
+            eprintln!("{report:?}");
+            ExitCode::from(exit_code(&report))
+        }
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM, or Ctrl-C off
+/// unix
+async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate =
+            signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Asks the tui to shut down once `signal` arrives, the
+/// way q would
+async fn request_shutdown_on(
+    signal: impl std::future::Future<Output = io::Result<()>>,
+    shutdown: Shutdown,
+) {
+    match signal.await {
+        Ok(()) => shutdown.request(),
+        Err(err) => {
+            eprintln!("could not listen for signals: {err}")
+        }
+    }
+}
+
+/// Waits for the frame receiver to end and puts why on the
+/// UI's banner. The receiver only finishes when the analysis
+/// side has gone away, so any ending is worth telling the UI
+/// about rather than leaving it frozen on the last frame.
+async fn supervise(
+    receiver: tokio::task::JoinHandle<()>,
+    state: &Mutex<AppState>,
+) {
+    let reason = match receiver.await {
+        Ok(()) => "audio input stopped",
+        Err(err) if err.is_panic() => {
+            "audio receiver crashed"
+        }
+        Err(_) => "audio receiver was cancelled",
+    };
+    // a panic while holding the lock poisons it, the state
+    // is still good enough to show a banner over
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .audio_stopped = Some(reason.to_string());
+}
+
+#[tokio::main]
+async fn run(args: Args) -> miette::Result<()> {
+    if args.list_hosts {
+        for host in audio::list_hosts() {
+            let default = if host.is_default {
+                " (default)"
+            } else {
+                ""
+            };
+            match &host.error {
+                None => {
+                    println!("{}{default}", host.name())
+                }
+                Some(err) => println!(
+                    "{}{default}: unavailable, {err}",
+                    host.name()
+                ),
+            }
+        }
+        return Ok(());
+    }
+    if args.self_test {
+        let response = test_tone::frequency_response(
+            &AudioConfig::builder()
+                .fft_backend(args.fft_backend)
+                .build(),
+            args.test_tone_rate,
+        );
+        for point in &response {
+            println!(
+                "{:>8.1} Hz  {:+.2} dB",
+                point.hz, point.level_db
+            );
+        }
+        if !test_tone::is_flat(&response) {
+            return Err(miette!(
+                "FAIL: the response isn't flat within {}dB",
+                test_tone::SELF_TEST_TOLERANCE_DB
+            ));
+        }
+        println!(
+            "PASS: flat within {}dB",
+            test_tone::SELF_TEST_TOLERANCE_DB
+        );
+        return Ok(());
+    }
+    if args.list_devices {
+        let devices = audio::list_input_devices()?;
+        if args.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&devices)
+                    .map_err(OutputError::from)?
+            );
+            return Ok(());
+        }
+        if devices.is_empty() {
+            println!("no input devices found");
+        }
+        for device in devices {
+            let default = if device.is_default {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("{}{default}", device.name);
+            match (&device.capabilities, &device.error) {
+                (Some(capabilities), _) => {
+                    println!(
+                        "  channels:       {}",
+                        join(&capabilities.channels)
+                    );
+                    println!(
+                        "  sample rates:   {}",
+                        join(&capabilities.sample_rates)
+                    );
+                    println!(
+                        "  sample formats: {}",
+                        join(&capabilities.sample_formats)
+                    );
+                }
+                (None, Some(err)) => {
+                    println!("  unavailable, {err}")
+                }
+                (None, None) => {}
+            }
+        }
+        return Ok(());
+    }
+    let (tx, mut rx) = channel::<AnalysisFrame>(100);
+    let mut config = Config::load(&args.config)?;
+    // a bad file is better to hear about before any
+    // devices are opened
+    let keymap = config.keymap()?;
+    let target = args
+        .target_curve
+        .as_deref()
+        .map(TargetCurve::load)
+        .transpose()?;
+    let bands = args.bands_preset.bands();
+    let mut app_state = AppState::new(bands.clone());
+    app_state.peak_hold =
+        PeakHold::new(args.peak_hold_decay);
+    app_state.persistence =
+        args.persistence.map(PeakHold::new);
+    app_state.level_histogram = LevelHistogram::new(
+        meter_range(config.calibration_offset.is_some()),
+        HISTOGRAM_BINS,
+        HISTOGRAM_WINDOW,
+    );
+    if let Some(gains) = args
+        .band_gains
+        .as_ref()
+        .or(config.band_gains.as_ref())
+    {
+        let mut band_gains =
+            app_state.band_gains.lock().unwrap();
+        for (band_gain, gain) in
+            band_gains.iter_mut().zip(gains)
+        {
+            *band_gain = *gain;
+        }
+    }
+    let band_attack = args
+        .band_attack
+        .as_ref()
+        .or(config.band_attack.as_ref());
+    let band_release = args
+        .band_release
+        .as_ref()
+        .or(config.band_release.as_ref());
+    let mut voice_detector = VoiceDetector::new(
+        args.speech_weights.as_ref().map_or(
+            VoiceWeights::default(),
+            |weights| VoiceWeights {
+                zcr: weights[0],
+                voice_band: weights[1],
+                modulation: weights[2],
+            },
+        ),
+    );
+    let mut band_smoothing =
+        match (band_attack, band_release) {
+            (None, None) => None,
+            (attack, release) => Some(BandSmoothing::new(
+                attack.map_or(&[], Vec::as_slice),
+                release.map_or(&[], Vec::as_slice),
+                bands.len(),
+            )?),
+        };
+    let mut band_matrix_smoothing = (args.band_smooth_time
+        > 0.0
+        || args.band_smooth_across > 0.0)
+        .then(|| {
+            BandMatrixSmoothing::new(
+                args.band_smooth_time,
+                args.band_smooth_across,
+            )
+        })
+        .transpose()?;
+    let spectrum_smoothing = args
+        .smooth_bins
+        .map(SpectrumSmoothing::Bins)
+        .or(args
+            .smooth_octave
+            .map(SpectrumSmoothing::Octave));
+    let band_gains = app_state.band_gains.clone();
+    let stream_notice = app_state.stream_notice.clone();
+    let noise_profile = app_state.noise_profile.clone();
+    if args.denoise {
+        noise_profile.lock().unwrap().learn(LEARN_FRAMES);
+    }
+    app_state.devices = args
+        .compare_device
+        .iter()
+        .map(DeviceLevels::new)
+        .collect();
+    let state = Arc::new(Mutex::new(app_state));
 
     let audio_state = state.clone();
-    audio::run(&args.device, tx)?;
-    let _audio_sample_receiver_task =
+    // the compared inputs share the analysis settings but
+    // nothing that's about the main input, like its gains,
+    // noise profile, monitor or mix
+    let compare_config = AudioConfig::builder()
+        .jack(use_jack(&args))
+        .bands(bands.clone())
+        .band_weighting(args.band_weighting)
+        .spectrum_type(args.spectrum_type)
+        .fft_backend(args.fft_backend)
+        .analysis_location(args.analysis_location);
+    let mut audio_config = AudioConfig::builder()
+        .device(&args.device)
+        .jack(use_jack(&args))
+        .notice(stream_notice)
+        .bands(bands)
+        .gains(band_gains)
+        .noise_profile(noise_profile)
+        .band_weighting(args.band_weighting)
+        .spectrum_type(args.spectrum_type)
+        .input_mode(args.input_mode)
+        .clip_guard(args.gain_auto_off_on_clip)
+        .fft_backend(args.fft_backend)
+        .queue(QueueOptions {
+            capacity: args.backlog,
+            drop_policy: args.drop_policy,
+            decimation: args.decimation,
+        })
+        .analysis_location(args.analysis_location);
+    if let Some(device) = args.monitor.clone() {
+        audio_config =
+            audio_config.monitor(MonitorOptions {
+                device,
+                latency_ms: args.monitor_latency_ms,
+                gain: args.monitor_gain,
+            });
+    }
+    if let Some(device) = args.mix_device.clone() {
+        audio_config = audio_config.mix(MixOptions {
+            device,
+            gain: args.mix_gain,
+            input_gain: args.input_gain,
+        });
+    }
+    if let Some(target) = args.downmix {
+        audio_config = audio_config.downmix(target);
+    }
+    if args.dc_block {
+        audio_config =
+            audio_config.dc_block(args.dc_block_pole);
+    }
+    if args.gate {
+        // the threshold reads like the meters, the gate
+        // itself works in dBFS
+        let threshold_db = args
+            .gate_threshold_db
+            .or(config.gate_db)
+            .unwrap_or(-50.0)
+            - config.calibration_offset.unwrap_or(0.0);
+        audio_config = audio_config.gate(
+            GateOptions {
+                threshold_db,
+                range_db: args.gate_range_db,
+                knee_db: args.gate_knee_db,
+            }
+            .validate()?,
+        );
+    }
+    if let (Some(sizes), Some(crossovers)) = (
+        args.multi_res_sizes.clone(),
+        args.multi_res_crossovers.clone(),
+    ) {
+        audio_config = audio_config.multi_resolution(
+            MultiResolution::new(sizes, crossovers)?,
+        );
+    }
+    if let (Some(center), Some(bandwidth)) =
+        (args.zoom_center, args.zoom_bandwidth)
+    {
+        audio_config = audio_config.zoom(ZoomBand::new(
+            center,
+            bandwidth,
+            args.zoom_fft_size,
+        )?);
+    }
+    if let Some(frames) = args.buffer_size {
+        audio_config = audio_config.buffer_size(frames);
+    }
+    if let Some(rate) = args.analysis_rate {
+        audio_config = audio_config.analysis_rate(rate);
+    }
+    let profile = args.profile.then(SharedProfile::default);
+    if let Some(profile) = &profile {
+        audio_config =
+            audio_config.profile(profile.clone());
+    }
+    // cpal stops capturing when the streams are dropped so
+    // they have to live until we exit
+    let wav = args
+        .wav
+        .as_deref()
+        .map(|path| {
+            wav::WavSource::open(path, args.loop_wav)
+        })
+        .transpose()?;
+    let (_streams, _source) =
+        match (&args.replay_session, args.test_tone) {
+            _ if wav.is_some() => (
+                None,
+                wav.map(|source| {
+                    wav::run(
+                        source,
+                        audio_config.build(),
+                        tx,
+                    )
+                }),
+            ),
+            (Some(path), _) => (
+                None,
+                Some(session::replay(
+                    session::read_session(path)
+                        .map_err(OutputError::from)?,
+                    tx,
+                )),
+            ),
+            (None, Some(signal)) => {
+                let audio_config = audio_config.build();
+                // the test tone is a single channel
+                audio_config.check_channels(1)?;
+                (
+                    None,
+                    Some(test_tone::run(
+                        signal,
+                        args.test_tone_rate,
+                        audio_config,
+                        tx,
+                    )),
+                )
+            }
+            (None, None) => (
+                Some(audio::run(audio_config.build(), tx)?),
+                None,
+            ),
+        };
+    // each compared input has its own stream, analyzer and
+    // entry in the state, updated from a task of its own
+    let mut _compare_streams = vec![];
+    for (index, name) in
+        args.compare_device.iter().enumerate()
+    {
+        let (tx, mut rx) = channel::<AnalysisFrame>(100);
+        _compare_streams.push(audio::run(
+            compare_config.clone().device(name).build(),
+            tx,
+        )?);
+        let state = state.clone();
         tokio::spawn(async move {
-            while let Some(samples) = rx.recv().await {
-                let mut s = audio_state.lock().unwrap();
-                let max_volume = samples
-                    .into_iter()
-                    .map(|sample| {
-                        let value = (20.0 * sample.log10());
-                        if value.is_nan() {
-                            -100.0
-                        } else {
-                            value
-                        }
+            while let Some(frame) = rx.recv().await {
+                state.lock().unwrap().devices[index]
+                    .update(&frame);
+            }
+            state.lock().unwrap().devices[index]
+                .audio_stopped =
+                Some("audio input stopped".to_string());
+        });
+    }
+    if let Some(path) = &args.csv {
+        rx = export::record_csv(
+            rx,
+            CsvRecorder::create(
+                path,
+                args.csv_timestamp,
+                &args.bands_preset.bands(),
+                config.calibration_offset.unwrap_or(0.0),
+            )
+            .map_err(OutputError::from)?,
+        );
+    }
+    if let Some(path) = &args.record_session {
+        rx = session::record(
+            rx,
+            SessionRecorder::create(path)
+                .map_err(OutputError::from)?,
+        );
+    }
+
+    if let Some(reference) = args.calibrate {
+        println!(
+            "Calibrating against {reference} dB SPL for {}s...",
+            args.calibration_seconds
+        );
+        let deadline = Instant::now()
+            + Duration::from_secs_f32(
+                args.calibration_seconds,
+            );
+        let mut levels = vec![];
+        while let Ok(Some(frame)) =
+            timeout_at(deadline, rx.recv()).await
+        {
+            levels
+                .push(analysis::peak_dbfs(&frame.samples));
+        }
+        let offset = analysis::calibration_offset(
+            reference, &levels,
+        )
+        .ok_or_else(|| {
+            miette!("no audio arrived while calibrating")
+        })?;
+        config.calibration_offset = Some(offset);
+        config.save(&args.config)?;
+        println!(
+            "Calibration offset {offset:.2} dB saved to {}",
+            args.config.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(path) = &args.snapshot {
+        let frame = rx.recv().await.ok_or_else(|| {
+            miette!("no audio arrived for the snapshot")
+        })?;
+        render_frame_to_png(
+            &frame,
+            args.snapshot_width,
+            args.snapshot_height,
+            path,
+        )
+        .map_err(OutputError::from)?;
+        return Ok(());
+    }
+
+    let offset = config.calibration_offset.unwrap_or(0.0);
+
+    #[cfg(feature = "ipc")]
+    let publisher = ipc::Publisher::new();
+    #[cfg(feature = "ipc")]
+    let _listener = match &args.publish_socket {
+        Some(path) => Some(
+            ipc::listen(path, publisher.clone()).map_err(
+                |source| OutputError::Socket {
+                    path: path.clone(),
+                    source,
+                },
+            )?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "metrics")]
+    let metrics = metrics::Metrics::default();
+    #[cfg(feature = "metrics")]
+    let _metrics_server = match args.metrics_addr {
+        Some(addr) => Some(
+            metrics::serve(addr, metrics.clone())
+                .await
+                .map_err(|source| OutputError::Metrics {
+                    addr,
+                    source,
+                })?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "artnet")]
+    let mut artnet_sender = match args.artnet_target {
+        Some(target) => Some(
+            artnet::ArtNetSender::bind(
+                target,
+                artnet::ArtNetLayout {
+                    universe: args.artnet_universe,
+                    start_channel: args.artnet_channel,
+                },
+                args.bands_preset.bands().len(),
+            )
+            .await
+            .map_err(OutputError::from)?,
+        ),
+        None => None,
+    };
+
+    #[cfg(feature = "ipc")]
+    let mut publish_limiter =
+        RateLimiter::new(args.publish_max_rate);
+    #[cfg(feature = "artnet")]
+    let mut artnet_limiter =
+        RateLimiter::new(args.artnet_max_rate);
+
+    if args.pcm_out {
+        let mut stdout = io::stdout().lock();
+        while let Some(frame) = rx.recv().await {
+            let written = stdout
+                .write_all(&export::pcm_bytes(&frame))
+                .and_then(|()| stdout.flush());
+            match written {
+                // whatever was reading has had enough
+                Err(err)
+                    if err.kind()
+                        == io::ErrorKind::BrokenPipe =>
+                {
+                    break
+                }
+                written => {
+                    written.map_err(OutputError::from)?
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if args.ndjson {
+        let mut stdout = io::stdout().lock();
+        let mut ndjson_limiter =
+            RateLimiter::new(args.ndjson_max_rate);
+        while let Some(frame) = rx.recv().await {
+            #[cfg(feature = "metrics")]
+            metrics.record(&frame);
+            #[cfg(feature = "artnet")]
+            if let Some(sender) =
+                artnet_sender.as_mut().filter(|_| {
+                    artnet_limiter.ready(frame.timestamp)
+                })
+            {
+                sender
+                    .send(&artnet::band_levels(&frame))
+                    .await
+                    .map_err(OutputError::from)?;
+            }
+            #[cfg(feature = "ipc")]
+            if publish_limiter.ready(frame.timestamp) {
+                publisher
+                    .publish(&ExportFrame::new(
+                        &frame, offset,
+                    ))
+                    .map_err(OutputError::from)?;
+            }
+            if !ndjson_limiter.ready(frame.timestamp) {
+                continue;
+            }
+            let line = ExportFrame::new(&frame, offset)
+                .to_ndjson()
+                .map_err(OutputError::from)?;
+            writeln!(stdout, "{line}")
+                .map_err(OutputError::from)?;
+            stdout.flush().map_err(OutputError::from)?;
+        }
+        return Ok(());
+    }
+
+    let supervisor_state = state.clone();
+    #[cfg(feature = "metrics")]
+    let supervisor_metrics = metrics.clone();
+    let audio_sample_receiver_task =
+        tokio::spawn(async move {
+            // frames can queue up in the channel, so this
+            // smooths the gaps rather than trust any single
+            // one
+            let mut frame_rate = RateMeter::default();
+            // a frame that can't be serialized won't be the
+            // last, so only the first failure is reported
+            #[cfg(feature = "ipc")]
+            let mut publish_failed = false;
+            // told to the status line as it starts and
+            // stops failing, not on every frame
+            #[cfg(feature = "artnet")]
+            let mut artnet_status =
+                artnet::SendStatus::default();
+            while let Some(frame) = rx.recv().await {
+                #[cfg(feature = "ipc")]
+                if let Err(err) = publish_limiter
+                    .ready(frame.timestamp)
+                    .then(|| {
+                        publisher.publish(
+                            &ExportFrame::new(
+                                &frame, offset,
+                            ),
+                        )
                     })
-                    .max_by(|x, y| x.total_cmp(y));
+                    .transpose()
+                {
+                    if !publish_failed {
+                        eprintln!(
+                            "could not publish a frame: \
+                             {err}"
+                        );
+                    }
+                    publish_failed = true;
+                }
+                #[cfg(feature = "metrics")]
+                metrics.record(&frame);
+                #[cfg(feature = "artnet")]
+                let artnet_change = match artnet_sender
+                    .as_mut()
+                    .filter(|_| {
+                        artnet_limiter
+                            .ready(frame.timestamp)
+                    }) {
+                    Some(sender) => artnet_status.update(
+                        &sender
+                            .send(&artnet::band_levels(
+                                &frame,
+                            ))
+                            .await,
+                    ),
+                    None => None,
+                };
+                let mut s = audio_state.lock().unwrap();
+                let now = SystemClock.now();
+                #[cfg(feature = "artnet")]
+                if let Some(message) = artnet_change {
+                    *s.stream_notice.lock().unwrap() =
+                        Some((message, now));
+                }
+                if let (_, Some(rate)) =
+                    frame_rate.tick(now)
+                {
+                    s.analysis_rate = rate;
+                }
+                let max_volume =
+                    analysis::peak_dbfs(&frame.samples);
                 // dbg!(max_volume);
-                s.decibels.push(max_volume.unwrap_or(0.0));
+                s.peak_hold.update(&frame.spectrum);
+                s.tempo.update(&frame.spectrum);
+                s.speech = voice_detector.update(&frame);
+                s.peak_hz = match args.input_mode {
+                    InputMode::Real => {
+                        analysis::peak_frequency(
+                            &frame.spectrum,
+                            frame.stats.sample_rate,
+                            frame.stats.fft_size,
+                        )
+                    }
+                    InputMode::Iq => None,
+                };
+                let spectrum = args.spectrum_norm.apply(
+                    match spectrum_smoothing {
+                        Some(smoothing) => {
+                            smoothing.apply(&frame.spectrum)
+                        }
+                        None => frame.spectrum,
+                    },
+                );
+                s.push_history(
+                    max_volume + offset,
+                    spectrum,
+                );
+                s.stereo = if frame.channels >= 2 {
+                    frame
+                        .samples
+                        .chunks_exact(
+                            frame.channels as usize,
+                        )
+                        .map(|frame| (frame[0], frame[1]))
+                        .collect()
+                } else {
+                    vec![]
+                };
+                s.stereo_width = MidSide::measure(
+                    &frame.samples,
+                    frame.channels,
+                )
+                .map_or(0.0, MidSide::width);
+                s.waveform = frame
+                    .samples
+                    .iter()
+                    .step_by(frame.channels.max(1) as usize)
+                    .copied()
+                    .collect();
+                s.stats = frame.stats;
+                s.activity = frame.activity;
+                s.bands = match &mut band_smoothing {
+                    Some(smoothing) => smoothing
+                        .update(&frame.bands)
+                        .to_vec(),
+                    None => frame.bands,
+                };
+                if let Some(smoothing) =
+                    &mut band_matrix_smoothing
+                {
+                    s.bands =
+                        smoothing.update(&s.bands).to_vec();
+                }
+                s.dropped_samples = frame.dropped_samples;
                 // println!("got = {}", i.len());
             }
         });
+    let _supervisor = tokio::spawn(async move {
+        supervise(
+            audio_sample_receiver_task,
+            &supervisor_state,
+        )
+        .await;
+        #[cfg(feature = "metrics")]
+        supervisor_metrics.input_stopped();
+    });
 
     // run_graphics().await;
-    run_tui(state).into_diagnostic().unwrap();
+    let band_colors = match args.band_colors.clone() {
+        Some(colors) => colors,
+        None => config.band_colors()?.unwrap_or_default(),
+    };
+    let device =
+        match (&args.replay_session, args.test_tone) {
+            _ if args.wav.is_some() => args
+                .wav
+                .as_deref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            (Some(path), _) => {
+                format!("replay of {}", path.display())
+            }
+            (None, Some(signal)) => format!(
+                "test tone ({})",
+                signal
+                    .to_possible_value()
+                    .map(|value| value
+                        .get_name()
+                        .to_string())
+                    .unwrap_or_default()
+            ),
+            (None, None) => args.device.clone(),
+        };
+    let options = DisplayOptions {
+        device,
+        orientation: Orientation {
+            mirror: args.mirror,
+            invert: args.invert,
+        },
+        spl_calibrated: config.calibration_offset.is_some(),
+        colormap: args.colormap,
+        gamma: args.gamma,
+        target,
+        level_scale: LevelScale {
+            base: args.log_base,
+            softness: args.log_softness,
+        },
+        color_depth: args.color_depth,
+        fps: args.fps,
+        seconds_per_screen: args.seconds_per_screen,
+        idle: args.idle_after.map(|seconds| IdleOptions {
+            after: Duration::from_secs_f32(
+                seconds.max(0.0),
+            ),
+            threshold_db: args
+                .idle_threshold_db
+                .or(config.gate_db)
+                .unwrap_or(-50.0),
+            fps: args.idle_fps,
+        }),
+        unfocused_fps: args.unfocused_fps,
+        a4_hz: args.a4_hz,
+        smoothing: args.smoothing,
+        trigger: args.freeze_on_peak.map(|threshold_db| {
+            TriggerOptions {
+                threshold_db,
+                pre_samples: args.trigger_pre_samples,
+                post_samples: args.trigger_post_samples,
+            }
+        }),
+        config_path: Some(args.config.clone()),
+        talking_threshold_db: config.talking_threshold_db,
+        speech_gate: args.speech_gate,
+        region: args.region,
+        min_size: args.min_size,
+        band_colors,
+        beat_flash: args.beat_flash,
+        reference_levels: args.reference_db.clone(),
+        shutdown: Shutdown::default(),
+        keymap,
+        start_paused: args.start_paused,
+        readout_smoothing: OneEuroOptions {
+            min_cutoff: args.readout_min_cutoff,
+            beta: args.readout_beta,
+        },
+        stall_timeout: (args.stall_timeout > 0.0)
+            .then(|| {
+                Duration::try_from_secs_f32(
+                    args.stall_timeout,
+                )
+                .ok()
+            })
+            .flatten(),
+        spectrum_image: (
+            args.dump_spectrum_image.clone(),
+            args.snapshot_width,
+            args.snapshot_height,
+        ),
+    };
+    // only while the tui is up, everything else is fine
+    // being killed the usual way
+    tokio::spawn(request_shutdown_on(
+        shutdown_signal(),
+        options.shutdown.clone(),
+    ));
+    run_tui(state, options).map_err(OutputError::from)?;
+
+    if let Some(profile) = profile {
+        println!("{}", profile.lock().unwrap());
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miette::IntoDiagnostic;
+
+    #[test]
+    fn failures_map_to_their_exit_codes() {
+        let device = miette::Report::new(
+            PngTuberAudioError::DeviceNotFound {
+                name: "usb mic".to_string(),
+            },
+        );
+        assert_eq!(exit_code(&device), EXIT_AUDIO);
+        // still an audio failure with context on top
+        let device = device.wrap_err("while starting up");
+        assert_eq!(exit_code(&device), EXIT_AUDIO);
+
+        let broken_pipe =
+            || io::Error::from(io::ErrorKind::BrokenPipe);
+        let terminal = miette::Report::new(
+            OutputError::Io(broken_pipe()),
+        );
+        assert_eq!(exit_code(&terminal), EXIT_OUTPUT);
+        let config = miette::Report::new(ConfigError::Io(
+            broken_pipe(),
+        ));
+        assert_eq!(exit_code(&config), EXIT_CONFIG);
+        let channels = miette::Report::new(
+            PngTuberAudioError::Config(
+                ConfigError::IqChannels { channels: 1 },
+            ),
+        );
+        assert_eq!(exit_code(&channels), EXIT_CONFIG);
+        let other = Err::<(), _>(broken_pipe())
+            .into_diagnostic()
+            .unwrap_err();
+        assert_eq!(exit_code(&other), EXIT_OTHER);
+    }
+
+    #[tokio::test]
+    async fn the_supervisor_reports_why_the_receiver_ended()
+    {
+        let stopped = |state: &Mutex<AppState>| {
+            state.lock().unwrap().audio_stopped.clone()
+        };
+        let state = Mutex::new(AppState::new(vec![]));
+        // the analysis side going away closes the channel
+        let (tx, mut rx) = channel::<AnalysisFrame>(4);
+        let receiver = tokio::spawn(async move {
+            while rx.recv().await.is_some() {}
+        });
+        tx.send(AnalysisFrame::default()).await.unwrap();
+        assert_eq!(stopped(&state), None);
+        drop(tx);
+        supervise(receiver, &state).await;
+        assert_eq!(
+            stopped(&state).as_deref(),
+            Some("audio input stopped")
+        );
+
+        let state = Mutex::new(AppState::new(vec![]));
+        let receiver = tokio::spawn(async {
+            panic!("a bad frame");
+        });
+        supervise(receiver, &state).await;
+        assert_eq!(
+            stopped(&state).as_deref(),
+            Some("audio receiver crashed")
+        );
+    }
+
+    #[tokio::test]
+    async fn a_signal_requests_a_shutdown() {
+        let shutdown = Shutdown::default();
+        let (signal, received) =
+            tokio::sync::oneshot::channel::<()>();
+        let handler = tokio::spawn(request_shutdown_on(
+            async move {
+                received.await.map_err(io::Error::other)
+            },
+            shutdown.clone(),
+        ));
+        tokio::task::yield_now().await;
+        assert!(!shutdown.requested());
+        signal.send(()).unwrap();
+        handler.await.unwrap();
+        assert!(shutdown.requested());
+
+        // no way to listen for signals, no shutdown
+        let shutdown = Shutdown::default();
+        request_shutdown_on(
+            async { Err(io::Error::other("no signals")) },
+            shutdown.clone(),
+        )
+        .await;
+        assert!(!shutdown.requested());
+    }
+}
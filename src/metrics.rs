@@ -0,0 +1,334 @@
+//! Serves the current levels over http in the Prometheus
+//! text format, for scraping a long running rig.
+
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::analysis::{self, AnalysisFrame};
+
+/// Longest request head read before giving up on it, a
+/// scraper's fits many times over
+const MAX_REQUEST_BYTES: usize = 8192;
+/// How long a client gets to send its request head before
+/// it's hung up on, so one that connects and says nothing
+/// doesn't keep its task around forever
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the endpoint reports, as of the latest frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Readings {
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+    /// see `AnalysisFrame::dropped_samples`
+    pub dropped_samples: u64,
+    /// frames analyzed since starting
+    pub frames: u64,
+    /// the input went away and isn't coming back
+    pub input_stopped: bool,
+}
+
+/// The readings shared between whoever updates them and the
+/// endpoint serving them
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    readings: Arc<Mutex<Readings>>,
+}
+
+impl Metrics {
+    pub fn record(&self, frame: &AnalysisFrame) {
+        let mut readings = self.readings.lock().unwrap();
+        readings.rms_dbfs =
+            analysis::rms_dbfs(&frame.samples);
+        readings.peak_dbfs =
+            analysis::peak_dbfs(&frame.samples);
+        readings.dropped_samples = frame.dropped_samples;
+        readings.frames += 1;
+    }
+
+    pub fn input_stopped(&self) {
+        self.readings.lock().unwrap().input_stopped = true;
+    }
+
+    pub fn readings(&self) -> Readings {
+        *self.readings.lock().unwrap()
+    }
+}
+
+/// A sample value the way Prometheus spells it, which
+/// differs from Rust for the infinities and NaN
+fn sample_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// `readings` in the Prometheus text exposition format
+pub fn render(readings: &Readings) -> String {
+    let metrics: [(&str, &str, &str, f64); 5] = [
+        (
+            "pngtubers_rms_dbfs",
+            "gauge",
+            "rms level of the latest frame in dBFS",
+            readings.rms_dbfs as f64,
+        ),
+        (
+            "pngtubers_peak_dbfs",
+            "gauge",
+            "peak level of the latest frame in dBFS",
+            readings.peak_dbfs as f64,
+        ),
+        (
+            "pngtubers_dropped_samples_total",
+            "counter",
+            "samples left out of the analysis or dropped \
+             on the way",
+            readings.dropped_samples as f64,
+        ),
+        (
+            "pngtubers_frames_total",
+            "counter",
+            "frames analyzed since starting",
+            readings.frames as f64,
+        ),
+        (
+            "pngtubers_input_stopped",
+            "gauge",
+            "1 once the audio input has gone away",
+            if readings.input_stopped { 1.0 } else { 0.0 },
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        // writing to a string can't fail
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        let _ = writeln!(
+            text,
+            "{name} {}",
+            sample_value(value)
+        );
+    }
+    text
+}
+
+/// Reads up to the end of the request head, or as much of
+/// it as `MAX_REQUEST_BYTES` allows
+async fn read_request(
+    stream: &mut TcpStream,
+) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n")
+        && request.len() < MAX_REQUEST_BYTES
+    {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    Ok(request)
+}
+
+/// Answers one connection: the metrics for `GET /metrics`,
+/// a 404 or 405 for anything else, then hangs up. A client
+/// that hasn't sent its request within `request_timeout`
+/// is hung up on without an answer.
+async fn respond(
+    mut stream: TcpStream,
+    metrics: Metrics,
+    request_timeout: Duration,
+) -> io::Result<()> {
+    let request =
+        timeout(request_timeout, read_request(&mut stream))
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no request before the timeout",
+                )
+            })??;
+    let request = String::from_utf8_lossy(&request);
+    let mut parts =
+        request.lines().next().unwrap_or("").split(' ');
+    let (status, body) = match (parts.next(), parts.next())
+    {
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", render(&metrics.readings()))
+        }
+        (Some("GET"), _) => {
+            ("404 Not Found", "not found\n".to_string())
+        }
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Serves `metrics` at `http://addr/metrics`, each scrape on
+/// its own task so a slow one never holds up the others or
+/// the UI. Failures accepting or answering are reported
+/// once per kind, since they tend to repeat the same way
+/// and would otherwise scroll over the TUI. Dropping the
+/// returned guard stops serving.
+pub async fn serve(
+    addr: SocketAddr,
+    metrics: Metrics,
+) -> io::Result<Server> {
+    let listener = TcpListener::bind(addr).await?;
+    let accept = tokio::spawn(async move {
+        let mut reported = HashSet::new();
+        let answer_reported =
+            Arc::new(Mutex::new(HashSet::new()));
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let metrics = metrics.clone();
+                    let reported = answer_reported.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = respond(
+                            stream,
+                            metrics,
+                            REQUEST_TIMEOUT,
+                        )
+                        .await
+                        {
+                            if reported
+                                .lock()
+                                .unwrap()
+                                .insert(err.kind())
+                            {
+                                eprintln!(
+                                    "answering a scrape: \
+                                     {err}"
+                                );
+                            }
+                        }
+                    });
+                }
+                Err(err) => {
+                    if reported.insert(err.kind()) {
+                        eprintln!(
+                            "accepting a scrape: {err}"
+                        );
+                    }
+                }
+            }
+        }
+    });
+    Ok(Server { accept })
+}
+
+/// Keeps the endpoint up; dropping it stops serving
+#[derive(Debug)]
+pub struct Server {
+    accept: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.accept.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_text_names_every_metric_with_a_value() {
+        let metrics = Metrics::default();
+        metrics.record(&AnalysisFrame {
+            samples: vec![0.5, -0.5, 0.5, -0.5],
+            dropped_samples: 128,
+            ..AnalysisFrame::default()
+        });
+        metrics.input_stopped();
+        let text = render(&metrics.readings());
+        let value = |name: &str| -> f64 {
+            let line = text
+                .lines()
+                .find(|line| {
+                    line.split(' ').next() == Some(name)
+                })
+                .unwrap_or_else(|| {
+                    panic!("{name}:\n{text}")
+                });
+            line[name.len() + 1..].parse().unwrap()
+        };
+        assert!(
+            (value("pngtubers_rms_dbfs") + 6.02).abs()
+                < 0.01
+        );
+        assert!(
+            (value("pngtubers_peak_dbfs") + 6.02).abs()
+                < 0.01
+        );
+        assert_eq!(
+            value("pngtubers_dropped_samples_total"),
+            128.0
+        );
+        assert_eq!(value("pngtubers_frames_total"), 1.0);
+        assert_eq!(value("pngtubers_input_stopped"), 1.0);
+        assert!(text.contains(
+            "# TYPE pngtubers_frames_total counter"
+        ));
+        // silence is -inf, spelled the Prometheus way
+        assert!(render(&Readings {
+            rms_dbfs: f32::NEG_INFINITY,
+            ..Readings::default()
+        })
+        .contains("pngtubers_rms_dbfs -Inf"));
+    }
+
+    #[tokio::test]
+    async fn a_silent_client_is_hung_up_on() {
+        let listener =
+            TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(
+            listener.local_addr().unwrap(),
+        )
+        .await
+        .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let err = respond(
+            stream,
+            Metrics::default(),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // and the connection is closed, not answered
+        let mut answer = Vec::new();
+        client.read_to_end(&mut answer).await.unwrap();
+        assert!(answer.is_empty());
+    }
+}
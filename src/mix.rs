@@ -0,0 +1,418 @@
+use crate::audio::MonitorBuffer;
+
+/// Averages interleaved channels down to one
+pub fn downmix(data: &[f32], channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    data.chunks(channels)
+        .map(|frame| {
+            frame.iter().sum::<f32>() / frame.len() as f32
+        })
+        .collect()
+}
+
+/// -3dB, what ITU-R BS.775 mixes the center and surrounds
+/// into each front channel at
+pub const SURROUND_GAIN: f32 =
+    std::f32::consts::FRAC_1_SQRT_2;
+
+/// What a surround input is downmixed to before analysis
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum,
+)]
+pub enum DownmixTarget {
+    Stereo,
+    Mono,
+}
+
+impl DownmixTarget {
+    pub fn channels(self) -> u16 {
+        match self {
+            DownmixTarget::Stereo => 2,
+            DownmixTarget::Mono => 1,
+        }
+    }
+}
+
+/// The speaker layout an input's channel count implies, in
+/// the WAV/SMPTE channel order most hosts deliver. cpal
+/// doesn't report layouts, so the count is all there is to
+/// go on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    Mono,
+    /// L R
+    Stereo,
+    /// L R C
+    ThreeZero,
+    /// L R Ls Rs
+    Quad,
+    /// L R C LFE Ls Rs
+    FivePointOne,
+    /// L R C LFE Lb Rb Ls Rs
+    SevenPointOne,
+}
+
+impl ChannelLayout {
+    /// `None` for counts with no standard layout
+    pub fn from_channels(channels: u16) -> Option<Self> {
+        Some(match channels {
+            1 => ChannelLayout::Mono,
+            2 => ChannelLayout::Stereo,
+            3 => ChannelLayout::ThreeZero,
+            4 => ChannelLayout::Quad,
+            6 => ChannelLayout::FivePointOne,
+            8 => ChannelLayout::SevenPointOne,
+            _ => return None,
+        })
+    }
+
+    pub fn channels(self) -> u16 {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::ThreeZero => 3,
+            ChannelLayout::Quad => 4,
+            ChannelLayout::FivePointOne => 6,
+            ChannelLayout::SevenPointOne => 8,
+        }
+    }
+
+    /// One interleaved frame down to (left, right) with the
+    /// ITU-R BS.775 coefficients: the center and each
+    /// surround at -3dB into its side, the LFE left out
+    fn fold_stereo(self, frame: &[f32]) -> (f32, f32) {
+        let g = SURROUND_GAIN;
+        match (self, frame) {
+            (ChannelLayout::Mono, &[m]) => (m, m),
+            (ChannelLayout::Stereo, &[l, r]) => (l, r),
+            (ChannelLayout::ThreeZero, &[l, r, c]) => {
+                (l + g * c, r + g * c)
+            }
+            (ChannelLayout::Quad, &[l, r, ls, rs]) => {
+                (l + g * ls, r + g * rs)
+            }
+            (
+                ChannelLayout::FivePointOne,
+                &[l, r, c, _lfe, ls, rs],
+            ) => (l + g * c + g * ls, r + g * c + g * rs),
+            (
+                ChannelLayout::SevenPointOne,
+                &[l, r, c, _lfe, lb, rb, ls, rs],
+            ) => (
+                l + g * c + g * ls + g * lb,
+                r + g * c + g * rs + g * rb,
+            ),
+            // a partial frame at the end of a buffer
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// Downmixes interleaved `data` in this layout to
+    /// `target`. Mono is the average of the stereo downmix,
+    /// so a sound in both fronts keeps its level. Neither is
+    /// normalized, as the standard leaves it, so a loud
+    /// surround mix can go over full scale.
+    pub fn downmix(
+        self,
+        data: &[f32],
+        target: DownmixTarget,
+    ) -> Vec<f32> {
+        let frames = data.chunks(self.channels() as usize);
+        match target {
+            DownmixTarget::Stereo => frames
+                .flat_map(|frame| {
+                    let (l, r) = self.fold_stereo(frame);
+                    [l, r]
+                })
+                .collect(),
+            DownmixTarget::Mono => frames
+                .map(|frame| {
+                    let (l, r) = self.fold_stereo(frame);
+                    (l + r) / 2.0
+                })
+                .collect(),
+        }
+    }
+}
+
+/// How much of a stereo signal is in its mid (L + R) and its
+/// side (L - R), as the rms of half the sum and half the
+/// difference, so a mono signal's mid is its own level
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MidSide {
+    pub mid: f32,
+    pub side: f32,
+}
+
+impl MidSide {
+    /// Measures the first two of `channels` interleaved
+    /// channels as left and right. `None` for a mono input.
+    pub fn measure(
+        samples: &[f32],
+        channels: u16,
+    ) -> Option<Self> {
+        if channels < 2 {
+            return None;
+        }
+        let (mut mid, mut side, mut frames) = (0.0, 0.0, 0);
+        for frame in samples.chunks_exact(channels as usize)
+        {
+            let (left, right) = (frame[0], frame[1]);
+            mid += ((left + right) / 2.0).powi(2);
+            side += ((left - right) / 2.0).powi(2);
+            frames += 1;
+        }
+        let frames = frames.max(1) as f32;
+        Some(MidSide {
+            mid: (mid / frames).sqrt(),
+            side: (side / frames).sqrt(),
+        })
+    }
+
+    /// The side's share of the energy, from 0 for mono
+    /// through about 0.5 for unrelated left and right to 1
+    /// for channels exactly out of phase. Silence has no
+    /// width to speak of and reads as mono.
+    pub fn width(self) -> f32 {
+        let (mid, side) =
+            (self.mid.powi(2), self.side.powi(2));
+        if mid + side <= 0.0 {
+            return 0.0;
+        }
+        (side / (mid + side)).clamp(0.0, 1.0)
+    }
+}
+
+/// Linear interpolating sample rate converter. Keeps its
+/// place between buffers so consecutive calls join up
+/// without clicks.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    /// input samples per output sample
+    step: f64,
+    /// where the next output falls, in input samples, with
+    /// 0 being `previous` and 1 the first sample of the next
+    /// buffer
+    position: f64,
+    /// last input sample of the previous buffer
+    previous: f32,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler {
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            position: 1.0,
+            previous: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut output = vec![];
+        while self.position < input.len() as f64 {
+            let index = self.position.floor() as usize;
+            let fraction =
+                (self.position - index as f64) as f32;
+            let from = match index {
+                0 => self.previous,
+                _ => input[index - 1],
+            };
+            let to = input[index];
+            output.push(from + (to - from) * fraction);
+            self.position += self.step;
+        }
+        if let Some(last) = input.last() {
+            self.position -= input.len() as f64;
+            self.previous = *last;
+        }
+        output
+    }
+}
+
+/// Sums a second source into the primary input.
+///
+/// The primary is the clock: every buffer it delivers is
+/// mixed with however much of the second source has queued
+/// up, already resampled to the primary's rate, and
+/// anything missing counts as silence. Both sides are mono.
+#[derive(Debug, Clone)]
+pub struct Mixer {
+    second: MonitorBuffer,
+}
+
+impl Mixer {
+    /// `capacity` is how many samples of the second source
+    /// can wait for the primary before the oldest are
+    /// thrown away
+    pub fn new(capacity: usize) -> Self {
+        Mixer {
+            second: MonitorBuffer::new(capacity.max(1)),
+        }
+    }
+
+    /// Queues samples from the second source
+    pub fn feed(&self, samples: &[f32]) {
+        self.second.push(samples);
+    }
+
+    /// The primary's samples times `gain` plus the same
+    /// number of queued second source samples times
+    /// `second_gain`
+    pub fn mix(
+        &self,
+        primary: &[f32],
+        gain: f32,
+        second_gain: f32,
+    ) -> Vec<f32> {
+        let mut mixed = vec![0.0; primary.len()];
+        self.second.fill(&mut mixed, second_gain);
+        for (out, sample) in mixed.iter_mut().zip(primary) {
+            *out += sample * gain;
+        }
+        mixed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_mixer_sums_both_sources_with_their_gains() {
+        // a desktop capture at 44.1kHz mixed into a 48kHz mic
+        let mut resampler = Resampler::new(44100, 48000);
+        let mixer = Mixer::new(4800);
+        let second = resampler.process(&[0.4; 441]);
+        // 10ms either way
+        assert!(second.len().abs_diff(480) <= 1);
+        mixer.feed(&second);
+        let mixed = mixer.mix(&[0.2; 480], 1.0, 0.5);
+        for sample in &mixed[..second.len()] {
+            assert!(
+                (sample - 0.4).abs() < 1e-6,
+                "{sample}"
+            );
+        }
+
+        // two tones, one from each, add up in power
+        let tone = |hz: f32, rate: u32, amplitude: f32| {
+            (0..rate as usize / 10)
+                .map(|n| {
+                    amplitude
+                        * (std::f32::consts::TAU
+                            * hz
+                            * n as f32
+                            / rate as f32)
+                            .sin()
+                })
+                .collect::<Vec<f32>>()
+        };
+        let rms = |samples: &[f32]| {
+            (samples.iter().map(|v| v * v).sum::<f32>()
+                / samples.len() as f32)
+                .sqrt()
+        };
+        let mixer = Mixer::new(4800);
+        mixer.feed(&tone(3000.0, 48000, 1.0));
+        let mixed =
+            mixer.mix(&tone(1000.0, 48000, 0.5), 1.0, 0.5);
+        // both at half scale: sqrt(0.5² / 2 + 0.5² / 2)
+        assert!((rms(&mixed) - 0.5).abs() < 1e-3);
+
+        // nothing queued from the second source is silence
+        let mixed = mixer.mix(&[0.2; 4], 2.0, 0.5);
+        assert_eq!(mixed, [0.4; 4]);
+    }
+
+    #[test]
+    fn five_one_downmixes_with_the_itu_coefficients() {
+        let layout =
+            ChannelLayout::from_channels(6).unwrap();
+        assert_eq!(layout, ChannelLayout::FivePointOne);
+        let g = SURROUND_GAIN;
+        // L R C LFE Ls Rs, twice, then a partial frame
+        let data = [
+            0.1, 0.2, 0.4, 0.9, 0.3, 0.5, //
+            0.0, 0.0, 1.0, 0.0, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, //
+            0.5, 0.5,
+        ];
+        let stereo =
+            layout.downmix(&data, DownmixTarget::Stereo);
+        let expected = [
+            0.1 + g * 0.4 + g * 0.3,
+            0.2 + g * 0.4 + g * 0.5,
+            // the center at -3dB into both sides
+            g,
+            g,
+            // the LFE is left out
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+        ];
+        assert_eq!(stereo.len(), expected.len());
+        for (got, want) in stereo.iter().zip(expected) {
+            assert!(
+                (got - want).abs() < 1e-6,
+                "{stereo:?}"
+            );
+        }
+        let mono =
+            layout.downmix(&data, DownmixTarget::Mono);
+        assert_eq!(mono.len(), 4);
+        assert!((mono[1] - g).abs() < 1e-6);
+        assert!(
+            (mono[0] - (expected[0] + expected[1]) / 2.0)
+                .abs()
+                < 1e-6
+        );
+        assert_eq!(mono[2], 0.0);
+
+        assert_eq!(ChannelLayout::from_channels(5), None);
+    }
+
+    #[test]
+    fn stereo_width_from_mono_to_out_of_phase() {
+        let stereo =
+            |left: &dyn Fn(f32) -> f32,
+             right: &dyn Fn(f32) -> f32| {
+                let samples: Vec<f32> = (0..48000)
+                    .flat_map(|i| {
+                        let t = i as f32 / 48000.0;
+                        [left(t), right(t)]
+                    })
+                    .collect();
+                MidSide::measure(&samples, 2).unwrap()
+            };
+        let tone = |hz: f32| {
+            move |t: f32| {
+                (std::f32::consts::TAU * hz * t).sin()
+            }
+        };
+        let mono = stereo(&tone(440.0), &tone(440.0));
+        assert_eq!(mono.side, 0.0);
+        assert_eq!(mono.width(), 0.0);
+        // unrelated channels put as much in the side as the
+        // mid
+        let wide = stereo(&tone(440.0), &tone(661.0));
+        assert!((wide.width() - 0.5).abs() < 0.01);
+        // and out of phase is all side
+        let out =
+            stereo(&tone(440.0), &|t| -tone(440.0)(t));
+        assert!(out.mid < 1e-6);
+        assert!(
+            (out.side - std::f32::consts::FRAC_1_SQRT_2)
+                .abs()
+                < 1e-3
+        );
+        assert_eq!(out.width(), 1.0);
+
+        // silence reads as mono, a mono input not at all
+        assert_eq!(
+            MidSide::measure(&[0.0; 8], 2).unwrap().width(),
+            0.0
+        );
+        assert_eq!(MidSide::measure(&[0.5; 8], 1), None);
+    }
+}
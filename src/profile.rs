@@ -0,0 +1,124 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// count/min/max/mean of how long one stage took
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageStats {
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl StageStats {
+    pub fn record(&mut self, duration: Duration) {
+        if self.count == 0 {
+            self.min = duration;
+            self.max = duration;
+        } else {
+            self.min = self.min.min(duration);
+            self.max = self.max.max(duration);
+        }
+        self.count += 1;
+        self.total += duration;
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.count as u32
+        }
+    }
+}
+
+/// Timings for each stage of the analyzer, collected when
+/// running with `--profile`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// copying samples into the fft buffer
+    pub fill: StageStats,
+    pub fft: StageStats,
+    /// magnitudes and band aggregation
+    pub bands: StageStats,
+}
+
+pub type SharedProfile = Arc<Mutex<Profile>>;
+
+impl fmt::Display for Profile {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>8} {:>12} {:>12} {:>12}",
+            "stage", "count", "min", "mean", "max"
+        )?;
+        for (name, stats) in [
+            ("fill", &self.fill),
+            ("fft", &self.fft),
+            ("bands", &self.bands),
+        ] {
+            writeln!(
+                f,
+                "{:<8} {:>8} {:>12?} {:>12?} {:>12?}",
+                name,
+                stats.count,
+                stats.min,
+                stats.mean(),
+                stats.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Time since the last lap (or since the timer started) and
+/// restart it. Does nothing for a `None` timer, so
+/// instrumented code costs next to nothing when profiling
+/// is off.
+pub fn lap(timer: &mut Option<Instant>) -> Duration {
+    match timer {
+        Some(start) => {
+            let now = Instant::now();
+            let elapsed = now - *start;
+            *start = now;
+            elapsed
+        }
+        None => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_stats_track_count_min_max_and_mean() {
+        let mut stats = StageStats::default();
+        assert_eq!(stats.mean(), Duration::ZERO);
+        for micros in [30, 10, 50, 30] {
+            stats.record(Duration::from_micros(micros));
+        }
+        assert_eq!(stats.count, 4);
+        assert_eq!(stats.min, Duration::from_micros(10));
+        assert_eq!(stats.max, Duration::from_micros(50));
+        assert_eq!(stats.total, Duration::from_micros(120));
+        assert_eq!(stats.mean(), Duration::from_micros(30));
+
+        // the first reading sets the min, not the zero it
+        // starts at
+        let mut stats = StageStats::default();
+        stats.record(Duration::from_millis(2));
+        assert_eq!(stats.min, Duration::from_millis(2));
+    }
+
+    #[test]
+    fn a_stopped_timer_laps_at_zero() {
+        assert_eq!(lap(&mut None), Duration::ZERO);
+    }
+}
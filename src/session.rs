@@ -0,0 +1,233 @@
+use miette::Diagnostic;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    thread,
+    time::Instant,
+};
+use thiserror::Error;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::analysis::AnalysisFrame;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum SessionError {
+    #[error("could not read or write the session file")]
+    #[diagnostic(code(pngtubers::session::io))]
+    Io(#[from] io::Error),
+    #[error("could not serialize a frame for the session")]
+    #[diagnostic(code(pngtubers::session::encode))]
+    Encode(#[source] serde_json::Error),
+    #[error("line {line} of the session isn't a frame")]
+    #[diagnostic(
+        code(pngtubers::session::decode),
+        help(
+            "sessions are written by --record-session, one \
+             frame per line"
+        )
+    )]
+    Decode {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Frames that can wait between the recorder and whatever
+/// reads them after it, the same as the channel before it
+const RECORD_CHANNEL_CAPACITY: usize = 100;
+
+/// Writes every `AnalysisFrame` to a file as one line of
+/// JSON each, samples and spectrum included, so the session
+/// can be replayed without the audio it came from
+pub struct SessionRecorder {
+    writer: BufWriter<File>,
+}
+
+impl SessionRecorder {
+    pub fn create(
+        path: &Path,
+    ) -> Result<Self, SessionError> {
+        Ok(SessionRecorder {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Appends `frame`, flushed straight away so a session
+    /// cut short by a crash still has everything up to it
+    pub fn record(
+        &mut self,
+        frame: &AnalysisFrame,
+    ) -> Result<(), SessionError> {
+        serde_json::to_writer(&mut self.writer, frame)
+            .map_err(SessionError::Encode)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Records every frame from `rx` with `recorder` on its way
+/// through to the returned receiver. A frame that can't be
+/// written is reported and recording stops, the frames keep
+/// flowing.
+pub fn record(
+    mut rx: Receiver<AnalysisFrame>,
+    recorder: SessionRecorder,
+) -> Receiver<AnalysisFrame> {
+    let (tx, out) = channel(RECORD_CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        let mut recorder = Some(recorder);
+        while let Some(frame) = rx.recv().await {
+            if let Some(writer) = &mut recorder {
+                if let Err(err) = writer.record(&frame) {
+                    eprintln!(
+                        "could not record a frame, \
+                         recording stopped: {err}"
+                    );
+                    recorder = None;
+                }
+            }
+            if tx.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+    out
+}
+
+/// Every frame of a session written by `SessionRecorder`, in
+/// the order they were recorded
+pub fn read_session(
+    path: &Path,
+) -> Result<Vec<AnalysisFrame>, SessionError> {
+    let mut frames = vec![];
+    for (index, line) in BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        frames.push(serde_json::from_str(&line).map_err(
+            |source| SessionError::Decode {
+                line: index + 1,
+                source,
+            },
+        )?);
+    }
+    Ok(frames)
+}
+
+/// Sends recorded `frames` to `tx` at the rate they were
+/// recorded, going by their timestamps rather than when they
+/// happened to arrive, until they run out or the receiver
+/// goes away.
+pub fn replay(
+    frames: Vec<AnalysisFrame>,
+    tx: Sender<AnalysisFrame>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let start = Instant::now();
+        let first = frames
+            .first()
+            .map(|frame| frame.timestamp)
+            .unwrap_or_default();
+        for frame in frames {
+            let due = start
+                + frame.timestamp.saturating_sub(first);
+            if let Some(wait) =
+                due.checked_duration_since(Instant::now())
+            {
+                thread::sleep(wait);
+            }
+            if tx.blocking_send(frame).is_err() {
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn frame(
+        index: u64,
+        timestamp_ms: u64,
+    ) -> AnalysisFrame {
+        AnalysisFrame {
+            samples: vec![index as f32; 4],
+            bands: vec![0.5, index as f32],
+            sample_index: index * 1024,
+            timestamp: Duration::from_millis(timestamp_ms),
+            ..AnalysisFrame::default()
+        }
+    }
+
+    #[test]
+    fn a_recorded_session_reads_back_in_order() {
+        let path = std::env::temp_dir().join(format!(
+            "pngtubers-session-{}.jsonl",
+            std::process::id()
+        ));
+        let mut recorder =
+            SessionRecorder::create(&path).unwrap();
+        for index in 0..3 {
+            recorder
+                .record(&frame(index, 5000 + index * 60))
+                .unwrap();
+        }
+        drop(recorder);
+        let frames = read_session(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 3);
+        for (index, read) in frames.iter().enumerate() {
+            let written = frame(index as u64, 0);
+            assert_eq!(read.samples, written.samples);
+            assert_eq!(read.bands, written.bands);
+            assert_eq!(
+                read.sample_index,
+                written.sample_index
+            );
+            assert_eq!(
+                read.timestamp,
+                Duration::from_millis(
+                    5000 + index as u64 * 60
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn replay_keeps_the_order_and_the_spacing() {
+        // timestamps needn't start at zero
+        let frames = (0..3)
+            .map(|index| frame(index, 5000 + index * 60));
+        let (tx, mut rx) = channel(10);
+        let start = Instant::now();
+        let replayer = replay(frames.collect(), tx);
+        let mut arrived = vec![];
+        while let Some(frame) = rx.blocking_recv() {
+            arrived.push((
+                frame.sample_index,
+                start.elapsed(),
+            ));
+        }
+        replayer.join().unwrap();
+        let order: Vec<u64> = arrived
+            .iter()
+            .map(|(index, _)| *index)
+            .collect();
+        assert_eq!(order, [0, 1024, 2048]);
+        // straight away, then 60ms apart
+        assert!(arrived[0].1 < Duration::from_millis(50));
+        assert!(arrived[1].1 >= Duration::from_millis(60));
+        assert!(arrived[2].1 >= Duration::from_millis(120));
+        assert!(arrived[2].1 < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,548 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+#[derive(Error, Debug, Diagnostic, PartialEq)]
+pub enum SmoothingError {
+    #[error(
+        "got {got} {which} coefficients for {bands} bands"
+    )]
+    #[diagnostic(
+        code(pngtubers::smoothing::length),
+        help(
+            "give one coefficient per band, lowest first"
+        )
+    )]
+    Length {
+        which: &'static str,
+        got: usize,
+        bands: usize,
+    },
+    #[error("{which} coefficient {value} is out of range")]
+    #[diagnostic(
+        code(pngtubers::smoothing::coefficient),
+        help(
+            "0 follows the input instantly, anything closer \
+             to 1 is slower, 1 itself never moves"
+        )
+    )]
+    Coefficient { which: &'static str, value: f32 },
+}
+
+/// Follows each band with its own attack and release, so
+/// bass can swell slowly while treble stays snappy.
+///
+/// A coefficient is the fraction of the previous level kept
+/// each frame: attack while the band is rising, release
+/// while it's falling.
+#[derive(Debug, Clone)]
+pub struct BandSmoothing {
+    attack: Vec<f32>,
+    release: Vec<f32>,
+    levels: Vec<f32>,
+}
+
+impl BandSmoothing {
+    /// One attack and one release coefficient per band, each
+    /// in `0..1`. An empty list leaves that direction
+    /// unsmoothed.
+    pub fn new(
+        attack: &[f32],
+        release: &[f32],
+        bands: usize,
+    ) -> Result<Self, SmoothingError> {
+        let coefficients = |which, values: &[f32]| {
+            if values.is_empty() {
+                return Ok(vec![0.0; bands]);
+            }
+            if values.len() != bands {
+                return Err(SmoothingError::Length {
+                    which,
+                    got: values.len(),
+                    bands,
+                });
+            }
+            match values
+                .iter()
+                .find(|value| !(0.0..1.0).contains(*value))
+            {
+                Some(value) => {
+                    Err(SmoothingError::Coefficient {
+                        which,
+                        value: *value,
+                    })
+                }
+                None => Ok(values.to_vec()),
+            }
+        };
+        Ok(BandSmoothing {
+            attack: coefficients("attack", attack)?,
+            release: coefficients("release", release)?,
+            levels: vec![],
+        })
+    }
+
+    /// Moves each band's level towards `bands` and returns
+    /// the smoothed levels. The first frame is taken as is.
+    pub fn update(&mut self, bands: &[f32]) -> &[f32] {
+        if self.levels.len() != bands.len() {
+            self.levels = bands.to_vec();
+            return &self.levels;
+        }
+        for (((level, band), attack), release) in self
+            .levels
+            .iter_mut()
+            .zip(bands)
+            .zip(&self.attack)
+            .zip(&self.release)
+        {
+            let keep =
+                if band > level { attack } else { release };
+            *level = *level * keep + band * (1.0 - keep);
+        }
+        &self.levels
+    }
+
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+}
+
+/// Smooths the band levels across both time and the bands
+/// themselves, each with its own control, for a steadier
+/// bar chart than either gives alone.
+///
+/// Each frame the bands are first blended with their
+/// neighbours and then followed over time:
+///
+/// - across bands, `across` of each level is replaced by the
+///   mean of its neighbours. The lowest and highest bands
+///   only have one neighbour, and take just that one rather
+///   than counting a missing band as silence.
+/// - over time, a one pole low pass keeps `time` of the
+///   previous level each frame, like `BandSmoothing`'s
+///   coefficients.
+#[derive(Debug, Clone)]
+pub struct BandMatrixSmoothing {
+    time: f32,
+    across: f32,
+    levels: Vec<f32>,
+}
+
+impl BandMatrixSmoothing {
+    /// `time` and `across` are each in `0..1`, 0 leaving
+    /// that direction unsmoothed
+    pub fn new(
+        time: f32,
+        across: f32,
+    ) -> Result<Self, SmoothingError> {
+        for (which, value) in
+            [("time", time), ("across", across)]
+        {
+            if !(0.0..1.0).contains(&value) {
+                return Err(SmoothingError::Coefficient {
+                    which,
+                    value,
+                });
+            }
+        }
+        Ok(BandMatrixSmoothing {
+            time,
+            across,
+            levels: vec![],
+        })
+    }
+
+    /// Smooths `bands`, the latest frame's levels, and
+    /// returns the result. The first frame (and any with a
+    /// different number of bands) is only smoothed across.
+    pub fn update(&mut self, bands: &[f32]) -> &[f32] {
+        let across: Vec<f32> = (0..bands.len())
+            .map(|band| {
+                let neighbours: Vec<f32> = [
+                    band.checked_sub(1)
+                        .and_then(|below| bands.get(below)),
+                    bands.get(band + 1),
+                ]
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect();
+                if neighbours.is_empty() {
+                    return bands[band];
+                }
+                let mean = neighbours.iter().sum::<f32>()
+                    / neighbours.len() as f32;
+                bands[band] * (1.0 - self.across)
+                    + mean * self.across
+            })
+            .collect();
+        if self.levels.len() != across.len() {
+            self.levels = across;
+            return &self.levels;
+        }
+        for (level, band) in
+            self.levels.iter_mut().zip(across)
+        {
+            *level = *level * self.time
+                + band * (1.0 - self.time);
+        }
+        &self.levels
+    }
+}
+
+/// Running totals of `values`, one longer than it, so any
+/// window's sum is a single subtraction
+fn prefix_sums(values: &[f32]) -> Vec<f64> {
+    let mut sums = Vec::with_capacity(values.len() + 1);
+    let mut total = 0.0;
+    sums.push(total);
+    for value in values {
+        total += *value as f64;
+        sums.push(total);
+    }
+    sums
+}
+
+/// Mean of the bins in `start..end` given their prefix sums
+fn window_mean(
+    sums: &[f64],
+    start: usize,
+    end: usize,
+) -> f32 {
+    ((sums[end] - sums[start]) / (end - start) as f64)
+        as f32
+}
+
+/// Moving average over `width` bins centred on each bin,
+/// narrowed to what's there at either end of the spectrum.
+/// A width of 0 or 1 leaves it as is.
+pub fn smooth_spectrum(
+    spectrum: &[f32],
+    width: usize,
+) -> Vec<f32> {
+    if width <= 1 {
+        return spectrum.to_vec();
+    }
+    let sums = prefix_sums(spectrum);
+    let before = width / 2;
+    let after = width - 1 - before;
+    (0..spectrum.len())
+        .map(|bin| {
+            let start = bin.saturating_sub(before);
+            let end = (bin + after + 1).min(spectrum.len());
+            window_mean(&sums, start, end)
+        })
+        .collect()
+}
+
+/// Moving average over `1 / fraction` of an octave centred
+/// on each bin, e.g. 3 for third octave smoothing. The window
+/// widens with frequency the way hearing does, so the bass
+/// keeps its detail while the treble's many bins even out.
+///
+/// Octaves are counted from bin 0 as 0Hz, which makes this
+/// meant for real input: a shifted IQ spectrum has 0Hz in the
+/// middle.
+pub fn smooth_spectrum_octave(
+    spectrum: &[f32],
+    fraction: f32,
+) -> Vec<f32> {
+    if !(fraction.is_finite() && fraction > 0.0) {
+        return spectrum.to_vec();
+    }
+    let sums = prefix_sums(spectrum);
+    let half_window = 2f32.powf(0.5 / fraction);
+    (0..spectrum.len())
+        .map(|bin| {
+            let start = ((bin as f32 / half_window).floor()
+                as usize)
+                .min(bin);
+            let end = ((bin as f32 * half_window).ceil()
+                as usize
+                + 1)
+            .clamp(bin + 1, spectrum.len());
+            window_mean(&sums, start, end)
+        })
+        .collect()
+}
+
+/// How the spectrum is smoothed across frequency before the
+/// views draw it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectrumSmoothing {
+    /// the same number of bins everywhere, see
+    /// [`smooth_spectrum`]
+    Bins(usize),
+    /// a fraction of an octave, see
+    /// [`smooth_spectrum_octave`]
+    Octave(f32),
+}
+
+impl SpectrumSmoothing {
+    pub fn apply(self, spectrum: &[f32]) -> Vec<f32> {
+        match self {
+            SpectrumSmoothing::Bins(width) => {
+                smooth_spectrum(spectrum, width)
+            }
+            SpectrumSmoothing::Octave(fraction) => {
+                smooth_spectrum_octave(spectrum, fraction)
+            }
+        }
+    }
+}
+
+/// Cutoff of the low pass on the rate of change that
+/// `OneEuroFilter` adapts to, in Hz
+const DERIVATIVE_CUTOFF_HZ: f32 = 1.0;
+
+/// How a `OneEuroFilter` trades jitter for lag
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneEuroOptions {
+    /// low pass cutoff while the value holds steady, in Hz.
+    /// Lower is steadier but slower to follow.
+    pub min_cutoff: f32,
+    /// how much the cutoff rises per unit per second of
+    /// change, so a real jump is followed quickly
+    pub beta: f32,
+}
+
+impl Default for OneEuroOptions {
+    fn default() -> Self {
+        OneEuroOptions {
+            min_cutoff: 0.5,
+            beta: 0.05,
+        }
+    }
+}
+
+/// Smoothing factor of a one pole low pass at `cutoff` Hz
+/// for a sample `dt` seconds after the last
+fn low_pass_alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+/// The 1€ filter (Casiez et al.) for a scalar readout that
+/// jitters from frame to frame: a low pass whose cutoff
+/// rises with how fast the value is changing, so a value
+/// holding steady is smoothed hard while a genuine change
+/// comes through with little lag.
+#[derive(Debug, Clone)]
+pub struct OneEuroFilter {
+    options: OneEuroOptions,
+    /// the last output and its filtered rate of change
+    previous: Option<(f32, f32)>,
+}
+
+impl OneEuroFilter {
+    pub fn new(options: OneEuroOptions) -> Self {
+        OneEuroFilter {
+            options,
+            previous: None,
+        }
+    }
+
+    /// Filters `value`, which arrived `dt` seconds after the
+    /// one before. The first value (and any after a `reset`)
+    /// comes through as is.
+    pub fn filter(&mut self, value: f32, dt: f32) -> f32 {
+        let Some((previous, derivative)) = self.previous
+        else {
+            self.previous = Some((value, 0.0));
+            return value;
+        };
+        if !(dt.is_finite() && dt > 0.0) {
+            return previous;
+        }
+        let derivative = derivative
+            + low_pass_alpha(DERIVATIVE_CUTOFF_HZ, dt)
+                * ((value - previous) / dt - derivative);
+        let cutoff = self.options.min_cutoff
+            + self.options.beta * derivative.abs();
+        let filtered = previous
+            + low_pass_alpha(cutoff, dt)
+                * (value - previous);
+        self.previous = Some((filtered, derivative));
+        filtered
+    }
+
+    /// Forgets the history, e.g. when the readout goes away
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    #[test]
+    fn each_band_follows_a_step_with_its_own_coefficients()
+    {
+        // sluggish bass, snappy treble
+        let mut smoothing = BandSmoothing::new(
+            &[0.9, 0.5, 0.0],
+            &[0.8, 0.2, 0.0],
+            3,
+        )
+        .unwrap();
+        // the first frame is taken as is
+        assert_eq!(smoothing.update(&[0.0; 3]), [0.0; 3]);
+        let up = smoothing.update(&[1.0; 3]).to_vec();
+        assert!(close(&up, &[0.1, 0.5, 1.0]), "{up:?}");
+        let up = smoothing.update(&[1.0; 3]).to_vec();
+        assert!(close(&up, &[0.19, 0.75, 1.0]), "{up:?}");
+        // falling uses the release
+        let down = smoothing.update(&[0.0; 3]).to_vec();
+        assert!(
+            close(&down, &[0.152, 0.15, 0.0]),
+            "{down:?}"
+        );
+
+        // or not at all without one
+        let mut attack_only =
+            BandSmoothing::new(&[0.5, 0.5], &[], 2)
+                .unwrap();
+        attack_only.update(&[1.0, 1.0]);
+        assert_eq!(
+            attack_only.update(&[0.0, 0.5]),
+            [0.0, 0.5]
+        );
+    }
+
+    #[test]
+    fn band_coefficients_are_checked() {
+        assert_eq!(
+            BandSmoothing::new(&[0.5, 0.5], &[], 3)
+                .unwrap_err(),
+            SmoothingError::Length {
+                which: "attack",
+                got: 2,
+                bands: 3,
+            }
+        );
+        assert_eq!(
+            BandSmoothing::new(&[], &[0.5, 1.0], 2)
+                .unwrap_err(),
+            SmoothingError::Coefficient {
+                which: "release",
+                value: 1.0,
+            }
+        );
+    }
+
+    #[test]
+    fn constant_width_smoothing_spreads_a_spike_evenly() {
+        let smoothed =
+            smooth_spectrum(&[0.0, 0.0, 3.0, 0.0, 0.0], 3);
+        assert!(close(
+            &smoothed,
+            &[0.0, 1.0, 1.0, 1.0, 0.0]
+        ));
+        // a width of one leaves it alone
+        assert_eq!(
+            smooth_spectrum(&[0.0, 3.0], 1),
+            [0.0, 3.0]
+        );
+    }
+
+    #[test]
+    fn octave_smoothing_widens_with_frequency() {
+        let mut spiky = vec![0.0; 128];
+        spiky[8] = 1.0;
+        spiky[100] = 1.0;
+        let smoothed = smooth_spectrum_octave(&spiky, 1.0);
+        let reach = |around: std::ops::Range<usize>| {
+            smoothed[around]
+                .iter()
+                .filter(|level| **level > 0.0)
+                .count()
+        };
+        // the treble spike is spread over many more bins,
+        // so it's flattened more
+        assert!(reach(60..128) > reach(0..20) * 3);
+        assert!(smoothed[8] > smoothed[100] * 3.0);
+        // a flat spectrum stays flat
+        assert!(close(
+            &smooth_spectrum_octave(&[2.0; 64], 3.0),
+            &[2.0; 64]
+        ));
+    }
+
+    #[test]
+    fn one_euro_steadies_jitter_and_follows_a_step() {
+        let dt = 1.0 / 47.0;
+        let mut filter =
+            OneEuroFilter::new(OneEuroOptions::default());
+        // a steady 120 jittering by 3 either way
+        let steady: Vec<f32> = (0..200)
+            .map(|i| {
+                let noise =
+                    if i % 2 == 0 { 3.0 } else { -3.0 };
+                filter.filter(120.0 + noise, dt)
+            })
+            .collect();
+        let settled = &steady[100..];
+        let spread = settled
+            .iter()
+            .cloned()
+            .fold(f32::NEG_INFINITY, f32::max)
+            - settled
+                .iter()
+                .cloned()
+                .fold(f32::INFINITY, f32::min);
+        assert!(spread < 1.0, "{spread}");
+        // while a real jump to 160 is followed within a
+        // few frames
+        let stepped: Vec<f32> = (0..5)
+            .map(|_| filter.filter(160.0, dt))
+            .collect();
+        assert!(stepped[0] > 140.0, "{stepped:?}");
+        assert!(stepped[4] > 159.0, "{stepped:?}");
+    }
+
+    #[test]
+    fn band_matrix_smooths_over_time_and_across_bands() {
+        // over time only, half of the previous level is kept
+        let mut time =
+            BandMatrixSmoothing::new(0.5, 0.0).unwrap();
+        assert_eq!(time.update(&[1.0, 1.0, 1.0]), [1.0; 3]);
+        assert_eq!(time.update(&[0.0, 0.0, 0.0]), [0.5; 3]);
+        assert_eq!(
+            time.update(&[0.0, 0.0, 0.0]),
+            [0.25; 3]
+        );
+
+        // across only, half of each band is its neighbours'
+        // mean, and a flat set of bands stays flat at the
+        // edges
+        let mut across =
+            BandMatrixSmoothing::new(0.0, 0.5).unwrap();
+        assert_eq!(
+            across.update(&[0.0, 1.0, 0.0, 0.0]),
+            [0.5, 0.5, 0.25, 0.0]
+        );
+        assert_eq!(across.update(&[1.0; 4]), [1.0; 4]);
+
+        // both: blended across first, then followed over time
+        let mut both =
+            BandMatrixSmoothing::new(0.5, 0.5).unwrap();
+        assert_eq!(
+            both.update(&[0.0, 1.0, 0.0]),
+            [0.5, 0.5, 0.5]
+        );
+        assert_eq!(both.update(&[1.0; 3]), [0.75; 3]);
+
+        assert!(BandMatrixSmoothing::new(1.0, 0.0).is_err());
+        assert!(
+            BandMatrixSmoothing::new(0.0, -0.1).is_err()
+        );
+    }
+}
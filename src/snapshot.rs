@@ -0,0 +1,349 @@
+use miette::Diagnostic;
+use ratatui::style::Color;
+use std::{fs::File, io, io::BufWriter, path::Path};
+use thiserror::Error;
+
+use crate::{
+    analysis::AnalysisFrame,
+    colormap::Colormap,
+    tui::{normalize_magnitude, WATERFALL_FLOOR_DB},
+};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum SnapshotError {
+    #[error("could not write the snapshot")]
+    #[diagnostic(code(pngtubers::snapshot::io))]
+    Io(#[from] io::Error),
+    #[error("could not encode the snapshot as png")]
+    #[diagnostic(code(pngtubers::snapshot::encode))]
+    Encode(#[from] png::EncodingError),
+    #[error("a snapshot can't be {width}x{height}")]
+    #[diagnostic(
+        code(pngtubers::snapshot::size),
+        help("both sides need to be at least one pixel")
+    )]
+    Size { width: u32, height: u32 },
+}
+
+const BACKGROUND: (u8, u8, u8) = (0x10, 0x10, 0x18);
+/// the spectrum plot's axes and tick marks
+const AXIS: (u8, u8, u8) = (0x80, 0x80, 0x88);
+/// the spectrum plot's curve
+const CURVE: (u8, u8, u8) = (0xf0, 0xf0, 0x40);
+
+/// Lowest frequency on the spectrum plot's log axis, below
+/// it there's next to nothing an octave would tell apart
+pub const PLOT_MIN_HZ: f32 = 20.0;
+/// Spacing of the ticks up the spectrum plot's dB axis
+const PLOT_DB_STEP: f32 = 20.0;
+/// Length of a tick mark, in pixels
+const TICK_LENGTH: usize = 4;
+
+fn rgb(color: Color) -> (u8, u8, u8) {
+    match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        _ => BACKGROUND,
+    }
+}
+
+/// Draws `frame`'s spectrum as `width` by `height` rgb
+/// pixels, row by row from the top: one column per slice
+/// of the spectrum from 0Hz on the left, as tall as its
+/// loudest bin, shaded up the colormap like the waterfall.
+pub fn render_frame(
+    frame: &AnalysisFrame,
+    width: u32,
+    height: u32,
+    colormap: Colormap,
+) -> Vec<u8> {
+    let spectrum = &frame.spectrum;
+    let (width, height) = (width as usize, height as usize);
+    let levels: Vec<f32> = (0..width)
+        .map(|column| {
+            if spectrum.is_empty() {
+                return 0.0;
+            }
+            let start = column * spectrum.len() / width;
+            let end = ((column + 1) * spectrum.len()
+                / width)
+                .max(start + 1)
+                .min(spectrum.len());
+            let magnitude = spectrum[start..end]
+                .iter()
+                .copied()
+                .fold(0.0, f32::max);
+            normalize_magnitude(magnitude, spectrum.len())
+        })
+        .collect();
+    // one shade per row, every column shares them
+    let shades: Vec<(u8, u8, u8)> = (0..height)
+        .map(|row| {
+            let t = 1.0 - row as f32 / height as f32;
+            rgb(colormap.sample(t))
+        })
+        .collect();
+
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for (row, shade) in shades.iter().enumerate() {
+        // how far up this row sits, 1 at the top
+        let t = 1.0 - row as f32 / height as f32;
+        for level in &levels {
+            let (r, g, b) = if *level >= t {
+                *shade
+            } else {
+                BACKGROUND
+            };
+            pixels.extend([r, g, b]);
+        }
+    }
+    pixels
+}
+
+/// Draws `spectrum`, whose bins are `resolution_hz` apart,
+/// as a `width` by `height` rgb line plot, row by row from
+/// the top: frequency runs from [`PLOT_MIN_HZ`] up to the
+/// last bin on a log axis along the bottom, level in dB
+/// (with the waterfall's floor and full scale) up the axis
+/// on the left. Ticks mark each decade of frequency and
+/// every 20dB.
+///
+/// Each column plots the loudest bin it covers, so a narrow
+/// peak shows at its full height however wide the image.
+pub fn render_plot(
+    spectrum: &[f32],
+    resolution_hz: f32,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return vec![];
+    }
+    let (r, g, b) = BACKGROUND;
+    let mut pixels = vec![[r, g, b]; width * height];
+    let mut set = |x: usize, y: usize, (r, g, b)| {
+        if x < width && y < height {
+            pixels[y * width + x] = [r, g, b];
+        }
+    };
+
+    // the axes take the left column and bottom row, the
+    // curve gets what's above and right of them
+    let plot_width = width.saturating_sub(1).max(1);
+    let plot_height = height.saturating_sub(1).max(1);
+    let max_hz = resolution_hz
+        * spectrum.len().saturating_sub(1) as f32;
+    let decades = (max_hz / PLOT_MIN_HZ).log10();
+    let x_of = |hz: f32| {
+        1 + ((hz / PLOT_MIN_HZ).log10() / decades
+            * plot_width as f32) as usize
+    };
+    let y_of = |level: f32| {
+        ((1.0 - level) * (plot_height - 1) as f32).round()
+            as usize
+    };
+
+    for y in 0..height {
+        set(0, y, AXIS);
+    }
+    for x in 0..width {
+        set(x, height - 1, AXIS);
+    }
+    if decades.is_finite() && decades > 0.0 {
+        let mut hz = 10f32.powf(PLOT_MIN_HZ.log10().ceil());
+        while hz <= max_hz {
+            for y in 0..TICK_LENGTH {
+                set(x_of(hz), height - 1 - y, AXIS);
+            }
+            hz *= 10.0;
+        }
+    }
+    let mut db = 0.0;
+    while db >= WATERFALL_FLOOR_DB {
+        let y = y_of(1.0 - db / WATERFALL_FLOOR_DB);
+        for x in 0..TICK_LENGTH {
+            set(x, y, AXIS);
+        }
+        db -= PLOT_DB_STEP;
+    }
+    if !(decades.is_finite() && decades > 0.0) {
+        return pixels.concat();
+    }
+
+    let mut previous: Option<usize> = None;
+    for column in 0..plot_width {
+        let hz_at = |column: usize| {
+            PLOT_MIN_HZ
+                * 10f32.powf(
+                    column as f32 / plot_width as f32
+                        * decades,
+                )
+        };
+        let start =
+            ((hz_at(column) / resolution_hz).round()
+                as usize)
+                .min(spectrum.len() - 1);
+        let end = ((hz_at(column + 1) / resolution_hz)
+            .round() as usize)
+            .clamp(start + 1, spectrum.len());
+        let magnitude = spectrum[start..end]
+            .iter()
+            .copied()
+            .fold(0.0, f32::max);
+        let y = y_of(normalize_magnitude(
+            magnitude,
+            spectrum.len(),
+        ));
+        // join it to the last column so steep edges don't
+        // leave gaps in the curve
+        let (top, bottom) = match previous {
+            Some(last) => (y.min(last), y.max(last)),
+            None => (y, y),
+        };
+        for y in top..=bottom {
+            set(column + 1, y, CURVE);
+        }
+        previous = Some(y);
+    }
+    pixels.concat()
+}
+
+/// Saves `width` by `height` rgb `pixels` as a png at `path`
+fn write_png(
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(), SnapshotError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width,
+        height,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Renders `frame` with `render_frame` and the default
+/// colormap and saves it as a `width` by `height` png at
+/// `path`, for thumbnails and previews without a terminal
+/// or gpu.
+pub fn render_frame_to_png(
+    frame: &AnalysisFrame,
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(), SnapshotError> {
+    if width == 0 || height == 0 {
+        return Err(SnapshotError::Size { width, height });
+    }
+    let pixels = render_frame(
+        frame,
+        width,
+        height,
+        Colormap::default(),
+    );
+    write_png(&pixels, width, height, path)
+}
+
+/// Renders `spectrum` with `render_plot` and saves it as a
+/// `width` by `height` png at `path`, for documentation
+pub fn render_plot_to_png(
+    spectrum: &[f32],
+    resolution_hz: f32,
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(), SnapshotError> {
+    if width == 0 || height == 0 {
+        return Err(SnapshotError::Size { width, height });
+    }
+    let pixels =
+        render_plot(spectrum, resolution_hz, width, height);
+    write_png(&pixels, width, height, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_frame_renders_to_a_png_of_the_requested_size() {
+        let frame = AnalysisFrame {
+            spectrum: (0..512)
+                .map(|bin| 512.0 / (bin + 1) as f32)
+                .collect(),
+            bands: vec![1.0, 0.5, 0.25, 0.1, 0.05],
+            ..AnalysisFrame::default()
+        };
+        let path = std::env::temp_dir().join(format!(
+            "pngtubers-snapshot-{}.png",
+            std::process::id()
+        ));
+        render_frame_to_png(&frame, 320, 180, &path)
+            .unwrap();
+        let file = File::open(&path).unwrap();
+        assert!(file.metadata().unwrap().len() > 0);
+        let reader =
+            png::Decoder::new(file).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (320, 180));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            render_frame_to_png(&frame, 0, 180, &path),
+            Err(SnapshotError::Size {
+                width: 0,
+                height: 180
+            })
+        ));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_single_peak_plots_at_its_frequency() {
+        let (width, height) = (300, 100);
+        // 46.875Hz bins up to 24kHz, one full scale bin at
+        // 984Hz
+        let mut spectrum = vec![0.0; 513];
+        spectrum[21] = 512.0;
+        let pixels =
+            render_plot(&spectrum, 46.875, width, height);
+        assert_eq!(
+            pixels.len(),
+            (width * height * 3) as usize
+        );
+        let curve = |x: usize, y: usize| {
+            let at = (y * width as usize + x) * 3;
+            let (r, g, b) = CURVE;
+            pixels[at..at + 3] == [r, g, b]
+        };
+        // the curve's top is the peak, right at the top
+        let (top_x, top_y) = (0..height as usize)
+            .find_map(|y| {
+                (0..width as usize)
+                    .find(|x| curve(*x, y))
+                    .map(|x| (x, y))
+            })
+            .unwrap();
+        assert_eq!(top_y, 0);
+        // log10(984 / 20) of the 3.08 decades to 24kHz
+        let expected = 1.0
+            + (984.375f32 / PLOT_MIN_HZ).log10()
+                / (24000.0f32 / PLOT_MIN_HZ).log10()
+                * 299.0;
+        assert!(
+            (top_x as f32 - expected).abs() <= 2.0,
+            "{top_x} vs {expected}"
+        );
+        // and away from it the curve lies along the floor,
+        // just above the axis
+        assert!(curve(40, height as usize - 2));
+        assert!(curve(280, height as usize - 2));
+        assert!(!curve(280, 0));
+    }
+}
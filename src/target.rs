@@ -0,0 +1,250 @@
+//! A target frequency response, such as a house curve, to
+//! compare the spectrum against while tuning a room's EQ.
+
+use miette::Diagnostic;
+use std::{fs, io, path::Path};
+use thiserror::Error;
+
+use crate::tui::WATERFALL_FLOOR_DB;
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum TargetError {
+    #[error("could not read the target curve")]
+    #[diagnostic(code(pngtubers::target::read))]
+    Read(#[from] io::Error),
+    #[error(
+        "line {line} of the target curve isn't a point"
+    )]
+    #[diagnostic(
+        code(pngtubers::target::parse),
+        help(
+            "each line is a frequency above 0Hz and a level \
+             in dB, separated by a comma, e.g. 1000,0"
+        )
+    )]
+    Parse { line: usize },
+    #[error("the target curve has no points")]
+    #[diagnostic(
+        code(pngtubers::target::empty),
+        help("give at least one frequency,dB line")
+    )]
+    Empty,
+}
+
+/// Levels at a handful of frequencies, joined up in between.
+/// Only the shape matters, it's moved to sit on whatever
+/// it's compared with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetCurve {
+    /// (Hz, dB), lowest frequency first
+    points: Vec<(f32, f32)>,
+}
+
+impl TargetCurve {
+    /// A curve through `points` of (Hz, dB), in any order
+    pub fn new(
+        mut points: Vec<(f32, f32)>,
+    ) -> Result<Self, TargetError> {
+        if points.is_empty() {
+            return Err(TargetError::Empty);
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(TargetCurve { points })
+    }
+
+    /// Reads a CSV of `frequency,dB` lines. Blank lines and
+    /// lines starting with `#` are skipped, and so is the
+    /// first line if it isn't a point, as a header.
+    pub fn load(path: &Path) -> Result<Self, TargetError> {
+        let text = fs::read_to_string(path)?;
+        let mut points = vec![];
+        let mut first = true;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let point = line.split_once(',').and_then(
+                |(hz, db)| {
+                    let hz: f32 = hz.trim().parse().ok()?;
+                    let db: f32 = db.trim().parse().ok()?;
+                    (hz.is_finite()
+                        && hz > 0.0
+                        && db.is_finite())
+                    .then_some((hz, db))
+                },
+            );
+            let header = std::mem::take(&mut first);
+            match point {
+                Some(point) => points.push(point),
+                None if header => continue,
+                None => {
+                    return Err(TargetError::Parse {
+                        line: index + 1,
+                    })
+                }
+            }
+        }
+        Self::new(points)
+    }
+
+    /// The curve's level at `hz`: straight lines between
+    /// the points on a log frequency scale, and flat from
+    /// the first point down and the last point up
+    pub fn level_db(&self, hz: f32) -> f32 {
+        let (first, last) = (
+            self.points[0],
+            self.points[self.points.len() - 1],
+        );
+        if hz <= first.0 {
+            return first.1;
+        }
+        if hz >= last.0 {
+            return last.1;
+        }
+        let above =
+            self.points.partition_point(|(point_hz, _)| {
+                *point_hz < hz
+            });
+        let (low, high) =
+            (self.points[above - 1], self.points[above]);
+        if high.0 <= low.0 {
+            return high.1;
+        }
+        let t =
+            (hz / low.0).log2() / (high.0 / low.0).log2();
+        low.1 + t * (high.1 - low.1)
+    }
+
+    /// The curve's level at each bin of a spectrum of
+    /// `spectrum_len` bins from 0Hz to nyquist
+    pub fn on_bins(
+        &self,
+        sample_rate: u32,
+        spectrum_len: usize,
+    ) -> Vec<f32> {
+        let bin_hz = sample_rate as f32
+            / 2.0
+            / spectrum_len.saturating_sub(1).max(1) as f32;
+        (0..spectrum_len)
+            .map(|bin| self.level_db(bin as f32 * bin_hz))
+            .collect()
+    }
+
+    /// The curve as fft magnitudes lined up with `spectrum`,
+    /// a real input's spectrum at `sample_rate`, moved up or
+    /// down so its average level matches the spectrum's.
+    /// Levels are floored at `WATERFALL_FLOOR_DB` for the
+    /// average, like the views draw them, and 0Hz is left
+    /// out of it.
+    pub fn magnitudes(
+        &self,
+        sample_rate: u32,
+        spectrum: &[f32],
+    ) -> Vec<f32> {
+        let full_scale =
+            spectrum.len().saturating_sub(1).max(1) as f32;
+        let curve =
+            self.on_bins(sample_rate, spectrum.len());
+        let heard = spectrum.len().saturating_sub(1).max(1);
+        let spectrum_db: f32 = spectrum
+            .iter()
+            .skip(1)
+            .map(|magnitude| {
+                (20.0 * (magnitude / full_scale).log10())
+                    .max(WATERFALL_FLOOR_DB)
+            })
+            .sum::<f32>()
+            / heard as f32;
+        let curve_db: f32 =
+            curve.iter().skip(1).sum::<f32>()
+                / heard as f32;
+        let shift = spectrum_db - curve_db;
+        curve
+            .iter()
+            .map(|db| {
+                full_scale * 10f32.powf((db + shift) / 20.0)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sparse_curve_is_joined_up_on_the_bins() {
+        // given out of order, sorted on the way in
+        let curve = TargetCurve::new(vec![
+            (2000.0, -6.0),
+            (500.0, 0.0),
+            (3000.0, -3.0),
+        ])
+        .unwrap();
+        // bins every 500Hz from 0Hz to 4kHz
+        let bins = curve.on_bins(8000, 9);
+        let expected = [
+            // flat below the first point
+            0.0, 0.0,
+            // straight lines on a log frequency scale
+            -3.0, -4.7549, -6.0, -4.3490, -3.0,
+            // and flat above the last
+            -3.0, -3.0,
+        ];
+        assert_eq!(bins.len(), expected.len());
+        for (bin, (level, expected)) in
+            bins.iter().zip(expected).enumerate()
+        {
+            assert!(
+                (level - expected).abs() < 1e-3,
+                "bin {bin}: {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_curve_moves_to_the_spectrums_level() {
+        let curve =
+            TargetCurve::new(vec![(1000.0, 10.0)]).unwrap();
+        // a flat spectrum 20dB under full scale
+        let spectrum = vec![102.4; 1025];
+        for magnitude in curve.magnitudes(48000, &spectrum)
+        {
+            assert!((magnitude - 102.4).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn a_csv_loads_past_its_header_and_comments() {
+        let path = std::env::temp_dir().join(format!(
+            "pngtubers-target-{}.csv",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            "hz,db\n# a house curve\n1000, -3\n\n100,2\n",
+        )
+        .unwrap();
+        let curve = TargetCurve::load(&path).unwrap();
+        assert_eq!(
+            curve,
+            TargetCurve::new(vec![
+                (100.0, 2.0),
+                (1000.0, -3.0)
+            ])
+            .unwrap()
+        );
+        fs::write(&path, "1000,0\n2000,loud\n").unwrap();
+        assert!(matches!(
+            TargetCurve::load(&path),
+            Err(TargetError::Parse { line: 2 })
+        ));
+        fs::write(&path, "hz,db\n").unwrap();
+        assert!(matches!(
+            TargetCurve::load(&path),
+            Err(TargetError::Empty)
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::VecDeque;
+
+/// Onset envelope frames kept for the estimate, about eleven
+/// seconds at 48kHz with the default fft size
+pub const HISTORY_FRAMES: usize = 512;
+
+/// Tempos outside this range are folded back into it by
+/// doubling or halving
+pub const MIN_BPM: f32 = 60.0;
+pub const MAX_BPM: f32 = 180.0;
+
+/// Seconds of envelope needed before there's an estimate at
+/// all
+const MIN_HISTORY_SECONDS: f32 = 3.0;
+
+/// How strongly, relative to the envelope's own energy, the
+/// best lag has to correlate to count as a tempo
+const MIN_CORRELATION: f32 = 0.2;
+
+/// Onset envelope frames a beat is compared against, about
+/// a second at 48kHz with the default fft size
+const BEAT_WINDOW: usize = 43;
+
+/// How many times the recent average flux a frame needs to
+/// count as a beat
+const BEAT_THRESHOLD: f32 = 1.5;
+
+/// Spectral flux: how much the spectrum grew since the
+/// previous frame. Only increases count, so a note starting
+/// registers and the same note dying away doesn't.
+pub fn spectral_flux(
+    previous: &[f32],
+    current: &[f32],
+) -> f32 {
+    previous
+        .iter()
+        .zip(current)
+        .map(|(before, now)| (now - before).max(0.0))
+        .sum()
+}
+
+/// Estimates the tempo of an onset envelope sampled
+/// `frame_rate` times a second.
+///
+/// Autocorrelates the envelope over every lag between half
+/// of [`MIN_BPM`] and twice [`MAX_BPM`] and takes the
+/// strongest one, then doubles or halves the result until it
+/// lands in range, so a track whose strongest period is a
+/// whole bar still reads as its beat.
+pub fn estimate_bpm(
+    envelope: &[f32],
+    frame_rate: f32,
+) -> Option<f32> {
+    if frame_rate <= 0.0
+        || (envelope.len() as f32)
+            < MIN_HISTORY_SECONDS * frame_rate
+    {
+        return None;
+    }
+    let mean = envelope.iter().sum::<f32>()
+        / envelope.len() as f32;
+    let centered: Vec<f32> =
+        envelope.iter().map(|e| e - mean).collect();
+
+    let bpm_to_lag = |bpm: f32| 60.0 * frame_rate / bpm;
+    let shortest =
+        (bpm_to_lag(MAX_BPM * 2.0).floor() as usize).max(1);
+    // a lag needs at least two periods in the window to
+    // stand out
+    let longest = (bpm_to_lag(MIN_BPM / 2.0).ceil()
+        as usize)
+        .min(centered.len() / 2);
+    if shortest + 2 > longest {
+        return None;
+    }
+    // not normalised by the overlap, so longer lags are
+    // slightly penalised and a beat wins over its multiples
+    let correlation: Vec<f32> = (0..=longest + 1)
+        .map(|lag| {
+            centered
+                .iter()
+                .zip(&centered[lag.min(centered.len())..])
+                .map(|(a, b)| a * b)
+                .sum()
+        })
+        .collect();
+
+    let strongest =
+        |lags: std::ops::RangeInclusive<usize>| {
+            lags.map(|lag| (lag, correlation[lag]))
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        };
+    let (mut lag, mut strength) =
+        strongest(shortest..=longest)?;
+    // noise correlates a little at some lag or other by
+    // chance, a beat correlates strongly
+    if strength <= MIN_CORRELATION * correlation[0] {
+        return None;
+    }
+    // a beat that doesn't land on whole frames smears its
+    // peak over two lags while every other beat lines up
+    // again, so the bar can come out ahead. Step down while
+    // half the lag still correlates well.
+    while lag / 2 > shortest {
+        let half = lag / 2;
+        match strongest(half - 1..=half + 1) {
+            Some((half, half_strength))
+                if half_strength > 0.5 * strength =>
+            {
+                lag = half;
+                strength = half_strength;
+            }
+            _ => break,
+        }
+    }
+    // fit a parabola through the peak and its neighbours,
+    // whole frames are too coarse at typical frame rates
+    let before = correlation[lag - 1];
+    let after = correlation[lag + 1];
+    let curvature = before - 2.0 * strength + after;
+    let offset = if curvature < 0.0 {
+        0.5 * (before - after) / curvature
+    } else {
+        0.0
+    };
+
+    let mut bpm = 60.0 * frame_rate / (lag as f32 + offset);
+    while bpm < MIN_BPM {
+        bpm *= 2.0;
+    }
+    while bpm > MAX_BPM {
+        bpm /= 2.0;
+    }
+    Some(bpm)
+}
+
+/// Keeps a rolling onset envelope from successive spectra
+/// and estimates the tempo from it on demand.
+#[derive(Debug, Clone)]
+pub struct TempoTracker {
+    previous: Vec<f32>,
+    envelope: VecDeque<f32>,
+    /// beats seen so far
+    beats: u64,
+    /// how far over the threshold the latest beat went
+    beat_strength: f32,
+    /// the last frame was over the threshold, so the next
+    /// one can't start another beat
+    above: bool,
+}
+
+impl Default for TempoTracker {
+    fn default() -> Self {
+        TempoTracker::new()
+    }
+}
+
+impl TempoTracker {
+    pub fn new() -> Self {
+        TempoTracker {
+            previous: vec![],
+            envelope: VecDeque::with_capacity(
+                HISTORY_FRAMES,
+            ),
+            beats: 0,
+            beat_strength: 0.0,
+            above: false,
+        }
+    }
+
+    pub fn update(&mut self, spectrum: &[f32]) {
+        // a change of fft size isn't an onset
+        let flux = if self.previous.len() == spectrum.len()
+        {
+            spectral_flux(&self.previous, spectrum)
+        } else {
+            0.0
+        };
+        self.previous = spectrum.to_vec();
+        let recent = self.envelope.len().min(BEAT_WINDOW);
+        let mean = self
+            .envelope
+            .iter()
+            .rev()
+            .take(recent)
+            .sum::<f32>()
+            / recent.max(1) as f32;
+        let threshold = BEAT_THRESHOLD * mean;
+        let above = mean > 0.0 && flux > threshold;
+        if above && !self.above {
+            self.beats += 1;
+            self.beat_strength = 1.0 - threshold / flux;
+        }
+        self.above = above;
+        if self.envelope.len() == HISTORY_FRAMES {
+            self.envelope.pop_front();
+        }
+        self.envelope.push_back(flux);
+    }
+
+    /// How many beats have been seen, for noticing a new
+    /// one between two looks. A beat is a frame whose flux
+    /// rises past `BEAT_THRESHOLD` times the last second's
+    /// average.
+    pub fn beats(&self) -> u64 {
+        self.beats
+    }
+
+    /// How strong the latest beat was, 0 to 1: the fraction
+    /// of its flux above the threshold
+    pub fn beat_strength(&self) -> f32 {
+        self.beat_strength
+    }
+
+    /// The current estimate given how many frames arrive a
+    /// second, `None` until a few seconds have been seen or
+    /// if nothing repeats
+    pub fn bpm(&self, frame_rate: f32) -> Option<f32> {
+        let envelope: Vec<f32> =
+            self.envelope.iter().copied().collect();
+        estimate_bpm(&envelope, frame_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An onset envelope with a click at `bpm` for
+    /// `seconds`, at `frame_rate`
+    fn click_track(
+        bpm: f32,
+        frame_rate: f32,
+        seconds: f32,
+    ) -> Vec<f32> {
+        let frames = (seconds * frame_rate) as usize;
+        let mut envelope = vec![0.0; frames];
+        let mut beat: f32 = 0.0;
+        while (beat as usize) < frames {
+            envelope[beat.round() as usize % frames] = 1.0;
+            beat += 60.0 * frame_rate / bpm;
+        }
+        envelope
+    }
+
+    #[test]
+    fn a_click_track_at_120_reads_as_120() {
+        // the default fft size at 48kHz
+        let frame_rate = 48000.0 / 1024.0;
+        let bpm = estimate_bpm(
+            &click_track(120.0, frame_rate, 10.0),
+            frame_rate,
+        )
+        .unwrap();
+        assert!((bpm - 120.0).abs() < 2.0, "{bpm}");
+        // out of range tempos fold back into it, at a frame
+        // rate that puts 240's clicks on whole frames
+        let frame_rate = 48.0;
+        for (clicks, expected) in
+            [(240.0, 120.0), (50.0, 100.0)]
+        {
+            let bpm = estimate_bpm(
+                &click_track(clicks, frame_rate, 10.0),
+                frame_rate,
+            )
+            .unwrap();
+            assert!(
+                (bpm - expected).abs() < 2.0,
+                "{clicks} read as {bpm}"
+            );
+        }
+        // not enough to go on yet
+        assert_eq!(
+            estimate_bpm(
+                &click_track(120.0, frame_rate, 1.0),
+                frame_rate
+            ),
+            None
+        );
+        assert_eq!(
+            estimate_bpm(&[0.0; 1000], frame_rate),
+            None
+        );
+    }
+}
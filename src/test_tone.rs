@@ -0,0 +1,317 @@
+use std::{
+    f32::consts::TAU,
+    sync::{atomic::AtomicU32, Arc},
+    thread,
+    time::Duration,
+};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    analysis::AnalysisFrame,
+    audio::{handoff, AudioConfig},
+};
+
+/// Synthetic input for demos and for checking the display
+/// without a microphone plugged in
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum,
+)]
+pub enum TestSignal {
+    /// a sine sweeping from 20Hz to 20kHz every ten seconds
+    Sweep,
+    /// white noise
+    Noise,
+    /// a steady chord of one tone in each of bass, mid and
+    /// treble
+    MultiTone,
+}
+
+const SWEEP_LOW_HZ: f32 = 20.0;
+const SWEEP_HIGH_HZ: f32 = 20_000.0;
+const SWEEP_SECONDS: f32 = 10.0;
+const MULTI_TONE_HZ: [f32; 3] = [110.0, 1000.0, 6000.0];
+
+pub struct ToneGenerator {
+    signal: TestSignal,
+    sample_rate: u32,
+    /// samples generated so far
+    position: u64,
+    /// one phase per oscillator, in radians
+    phases: Vec<f32>,
+    /// xorshift state for the noise
+    seed: u32,
+}
+
+impl ToneGenerator {
+    pub fn new(
+        signal: TestSignal,
+        sample_rate: u32,
+    ) -> Self {
+        ToneGenerator {
+            signal,
+            sample_rate,
+            position: 0,
+            phases: vec![0.0; MULTI_TONE_HZ.len()],
+            seed: 0x9e37_79b9,
+        }
+    }
+
+    pub fn next_buffer(&mut self, len: usize) -> Vec<f32> {
+        (0..len).map(|_| self.next_sample()).collect()
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let rate = self.sample_rate as f32;
+        let sample = match self.signal {
+            TestSignal::Sweep => {
+                // exponential so every octave gets the same
+                // amount of time
+                let t = (self.position as f32 / rate)
+                    % SWEEP_SECONDS;
+                let freq = SWEEP_LOW_HZ
+                    * (SWEEP_HIGH_HZ / SWEEP_LOW_HZ)
+                        .powf(t / SWEEP_SECONDS);
+                let phase = &mut self.phases[0];
+                *phase = (*phase + TAU * freq / rate) % TAU;
+                0.5 * phase.sin()
+            }
+            TestSignal::Noise => {
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 17;
+                self.seed ^= self.seed << 5;
+                0.5 * (self.seed as f32 / u32::MAX as f32
+                    * 2.0
+                    - 1.0)
+            }
+            TestSignal::MultiTone => MULTI_TONE_HZ
+                .iter()
+                .zip(self.phases.iter_mut())
+                .map(|(freq, phase)| {
+                    *phase =
+                        (*phase + TAU * freq / rate) % TAU;
+                    0.3 * phase.sin()
+                })
+                .sum(),
+        };
+        self.position += 1;
+        sample
+    }
+}
+
+/// Feeds a generated signal through the same analysis
+/// pipeline as real input, in real time, until the receiver
+/// goes away. With `AnalysisLocation::Callback` the
+/// generator's thread stands in for the audio callback.
+pub fn run(
+    signal: TestSignal,
+    sample_rate: u32,
+    audio_config: AudioConfig,
+    tx: Sender<AnalysisFrame>,
+) -> thread::JoinHandle<()> {
+    let pipeline = audio_config.pipeline(
+        audio_config.analyzer(sample_rate),
+        Arc::new(AtomicU32::new(sample_rate)),
+        tx,
+    );
+    let buffer_len = pipeline.fft_size();
+    let (handoff, analysis_thread) = handoff(
+        pipeline,
+        audio_config.analysis_location,
+        audio_config.queue,
+    );
+    let interval = Duration::from_secs_f32(
+        buffer_len as f32 / sample_rate as f32,
+    );
+    thread::spawn(move || {
+        let mut generator =
+            ToneGenerator::new(signal, sample_rate);
+        while !handoff.is_closed()
+            && !analysis_thread.as_ref().is_some_and(
+                thread::JoinHandle::is_finished,
+            )
+        {
+            handoff.push(generator.next_buffer(buffer_len));
+            thread::sleep(interval);
+        }
+    })
+}
+
+/// How far from flat any point of `frequency_response` can
+/// be for the self test to pass, in dB
+pub const SELF_TEST_TOLERANCE_DB: f32 = 0.5;
+/// Frequencies the self test measures, spread evenly over
+/// the sweep's range on a log scale
+const SELF_TEST_POINTS: usize = 24;
+/// Level of the self test's tones, full scale being 1
+const SELF_TEST_AMPLITUDE: f64 = 0.5;
+
+/// One frequency of a measured response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResponsePoint {
+    pub hz: f32,
+    /// how far the measured bin is from what a perfectly
+    /// flat chain would give, in dB
+    pub level_db: f32,
+}
+
+/// Runs a stepped sine sweep through analyzers built from
+/// `audio_config` at `sample_rate`, one fresh analyzer per
+/// tone so nothing carries over, and measures the level of
+/// each tone's bin against what a flat chain would show.
+///
+/// The analyzer has no window, so a tone between bins
+/// smears over both and reads low. Each tone is moved onto
+/// the nearest bin centre, where a flat chain reads exactly
+/// the tone's amplitude. The sweep runs from 20Hz to
+/// 20kHz, or to just under nyquist at lower rates.
+pub fn frequency_response(
+    audio_config: &AudioConfig,
+    sample_rate: u32,
+) -> Vec<ResponsePoint> {
+    let fft_size =
+        audio_config.analyzer(sample_rate).fft_size();
+    let bin_hz = sample_rate as f32 / fft_size as f32;
+    let top = SWEEP_HIGH_HZ
+        .min(sample_rate as f32 / 2.0 - bin_hz);
+    let mut bins: Vec<usize> = (0..SELF_TEST_POINTS)
+        .map(|point| {
+            let hz = SWEEP_LOW_HZ
+                * (top / SWEEP_LOW_HZ).powf(
+                    point as f32
+                        / (SELF_TEST_POINTS - 1) as f32,
+                );
+            ((hz / bin_hz).round() as usize).clamp(
+                1,
+                (fft_size / 2).saturating_sub(1).max(1),
+            )
+        })
+        .collect();
+    bins.dedup();
+    // a full scale sine on a bin centre peaks at half the
+    // fft size
+    let expected =
+        SELF_TEST_AMPLITUDE * fft_size as f64 / 2.0;
+    bins.into_iter()
+        .map(|bin| {
+            let samples: Vec<f32> = (0..fft_size)
+                .map(|n| {
+                    (SELF_TEST_AMPLITUDE
+                        * (std::f64::consts::TAU
+                            * bin as f64
+                            * n as f64
+                            / fft_size as f64)
+                            .sin())
+                        as f32
+                })
+                .collect();
+            let frame = audio_config
+                .analyzer(sample_rate)
+                .process(&samples);
+            let magnitude = frame
+                .spectrum
+                .get(bin)
+                .copied()
+                .unwrap_or(0.0)
+                as f64;
+            ResponsePoint {
+                hz: bin as f32 * bin_hz,
+                level_db: (20.0
+                    * (magnitude / expected).log10())
+                    as f32,
+            }
+        })
+        .collect()
+}
+
+/// Whether every point is within `SELF_TEST_TOLERANCE_DB`
+/// of flat
+pub fn is_flat(response: &[ResponsePoint]) -> bool {
+    !response.is_empty()
+        && response.iter().all(|point| {
+            point.level_db.abs() <= SELF_TEST_TOLERANCE_DB
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_sweeps_loudest_band_moves_from_bass_to_treble() {
+        let config = AudioConfig::builder().build();
+        let mut analyzer = config.analyzer(48000);
+        let mut generator =
+            ToneGenerator::new(TestSignal::Sweep, 48000);
+        let buffer_len = analyzer.fft_size();
+        let buffers_per_second = 48000 / buffer_len;
+        // the loudest band a little into each second of
+        // the sweep
+        let loudest: Vec<usize> = (0..SWEEP_SECONDS
+            as usize)
+            .map(|_| {
+                let frame = analyzer.process(
+                    &generator.next_buffer(buffer_len),
+                );
+                for _ in 1..buffers_per_second {
+                    generator.next_buffer(buffer_len);
+                }
+                frame
+                    .bands
+                    .iter()
+                    .enumerate()
+                    .max_by(|a, b| a.1.total_cmp(b.1))
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        assert_eq!(loudest[0], 0);
+        assert_eq!(
+            *loudest.last().unwrap(),
+            config.bands.len() - 1
+        );
+        assert!(
+            loudest
+                .windows(2)
+                .all(|pair| pair[0] <= pair[1]),
+            "{loudest:?}"
+        );
+    }
+
+    #[test]
+    fn the_unwindowed_chain_is_flat_across_the_sweep() {
+        let config = AudioConfig::builder().build();
+        for sample_rate in [44100, 48000] {
+            let response =
+                frequency_response(&config, sample_rate);
+            assert!(response.len() > SELF_TEST_POINTS / 2);
+            // from the lowest bin the fft has to the top of
+            // the sweep
+            let bin_hz = sample_rate as f32
+                / config.analyzer(sample_rate).fft_size()
+                    as f32;
+            assert_eq!(response[0].hz, bin_hz);
+            assert!(
+                response[response.len() - 1].hz
+                    > 0.9 * SWEEP_HIGH_HZ
+            );
+            for point in &response {
+                assert!(
+                    point.level_db.abs() < 0.01,
+                    "{point:?}"
+                );
+            }
+            assert!(is_flat(&response));
+        }
+    }
+
+    #[test]
+    fn one_point_out_fails_the_self_test() {
+        let point = |level_db| ResponsePoint {
+            hz: 1000.0,
+            level_db,
+        };
+        assert!(is_flat(&[point(0.0), point(-0.5)]));
+        assert!(!is_flat(&[point(0.0), point(-0.6)]));
+        assert!(!is_flat(&[]));
+    }
+}
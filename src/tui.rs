@@ -1,5 +1,8 @@
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{
+        self, DisableFocusChange, EnableFocusChange, Event,
+        KeyCode,
+    },
     execute,
     terminal::{
         disable_raw_mode, enable_raw_mode,
@@ -9,21 +12,42 @@ use crossterm::{
 use miette::Result;
 use ratatui::{prelude::*, widgets::*};
 use std::{
+    collections::{BTreeSet, VecDeque},
     io::{self, Stdout},
-    sync::{Arc, Mutex},
-    time::Duration,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use crate::AppState;
+use crate::{
+    analysis::{AnalysisStats, FreqRange, PeakHold},
+    clock::{Clock, RateMeter},
+    colormap::{color_rgb, ColorDepth, Colormap},
+    config::{Config, ConfigError},
+    denoise::LEARN_FRAMES,
+    keymap::{key_name, Action, KeyMap},
+    smoothing::{OneEuroFilter, OneEuroOptions},
+    snapshot::render_plot_to_png,
+    target::TargetCurve,
+    tuner::freq_to_note,
+    voice::SPEECH_THRESHOLD,
+    wizard::{Thresholds, Wizard, WizardStep},
+    AppState, DeviceLevels,
+};
 
 pub fn setup_terminal(
 ) -> Result<Terminal<CrosstermBackend<Stdout>>, io::Error> {
     let mut stdout = io::stdout();
     enable_raw_mode()?;
-    execute!(stdout, EnterAlternateScreen)?;
-    Ok(Terminal::new(CrosstermBackend::new(
+    execute!(
         stdout,
-    ))?)
+        EnterAlternateScreen,
+        EnableFocusChange
+    )?;
+    Terminal::new(CrosstermBackend::new(stdout))
 }
 
 pub fn restore_terminal(
@@ -32,58 +56,5274 @@ pub fn restore_terminal(
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
+        DisableFocusChange,
         LeaveAlternateScreen,
     )?;
-    Ok(terminal.show_cursor()?)
+    terminal.show_cursor()
+}
+
+/// How the decibel history is laid out on screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orientation {
+    /// draw the newest sample on the left instead of the
+    /// right
+    pub mirror: bool,
+    /// hang the bars from the top of the area instead of
+    /// growing them up from the bottom
+    pub invert: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// what the input is called in the status bar
+    pub device: String,
+    pub orientation: Orientation,
+    /// the decibel readings have a calibration offset
+    /// applied and are in dB SPL rather than dBFS
+    pub spl_calibrated: bool,
+    pub colormap: Colormap,
+    /// contrast of the waterfall and spectrum, see
+    /// `apply_gamma`
+    pub gamma: f32,
+    /// a response to compare the waterfall view's spectrum
+    /// with, when no reference is frozen
+    pub target: Option<TargetCurve>,
+    /// how magnitudes are mapped to levels in the spectrum,
+    /// waterfall, radial and band meter views
+    pub level_scale: LevelScale,
+    /// what the terminal can show, colormap colors are
+    /// rounded to it
+    pub color_depth: ColorDepth,
+    /// how often to redraw when no input arrives
+    pub fps: f32,
+    /// show this many seconds of history across the
+    /// sparkline instead of one sample per column
+    pub seconds_per_screen: Option<f32>,
+    /// slow down redraws while the input is quiet
+    pub idle: Option<IdleOptions>,
+    /// how often to redraw while the terminal doesn't have
+    /// focus, for terminals that report it
+    pub unfocused_fps: Option<f32>,
+    /// the tuner's reference pitch, in Hz
+    pub a4_hz: f32,
+    /// average the sparkline over this many readings, 1
+    /// (or 0) leaves it raw
+    pub smoothing: usize,
+    /// freeze the display when the level crosses a threshold
+    pub trigger: Option<TriggerOptions>,
+    /// where the setup wizard saves its thresholds, it can
+    /// only show them without one
+    pub config_path: Option<PathBuf>,
+    /// show a talking indicator at or above this level
+    pub talking_threshold_db: Option<f32>,
+    /// only count it as talking if it also sounds like
+    /// speech, see `voice::VoiceDetector`
+    pub speech_gate: bool,
+    /// draw into only this part of the terminal and leave
+    /// the rest alone, e.g. to capture just the viz
+    pub region: Option<Rect>,
+    /// a fixed color per band in the bar chart, repeating if
+    /// there are more bands. Empty for the default cyan.
+    pub band_colors: Vec<Color>,
+    /// flash the background this color on every beat,
+    /// brighter for stronger beats
+    pub beat_flash: Option<Color>,
+    /// levels to mark on the level meters with dashed
+    /// lines, e.g. a target and the clip point, in the
+    /// meters' unit
+    pub reference_levels: Vec<f32>,
+    /// stops `run` when requested
+    pub shutdown: Shutdown,
+    /// which key does what
+    pub keymap: KeyMap,
+    /// open paused on an empty display, space resumes
+    pub start_paused: bool,
+    /// how the tempo readout is steadied, see
+    /// `OneEuroFilter`
+    pub readout_smoothing: OneEuroOptions,
+    /// say the input is disconnected once no frames have
+    /// arrived for this long, never when `None`
+    pub stall_timeout: Option<Duration>,
+    /// where the p key saves a plot of the spectrum on
+    /// screen, and its width and height in pixels
+    pub spectrum_image: (PathBuf, u32, u32),
+    /// the smallest width and height the views are drawn
+    /// in, anything smaller just says so
+    pub min_size: (u16, u16),
+}
+
+/// Parses a terminal size as `WIDTHxHEIGHT`
+pub fn parse_size(
+    size: &str,
+) -> Result<(u16, u16), String> {
+    let invalid = || {
+        format!(
+            "`{size}` isn't a size, expected WIDTHxHEIGHT \
+             like 40x10"
+        )
+    };
+    let (width, height) =
+        size.split_once('x').ok_or_else(invalid)?;
+    let number = |n: &str| {
+        n.trim().parse::<u16>().map_err(|_| invalid())
+    };
+    Ok((number(width)?, number(height)?))
+}
+
+/// Parses a region as `WIDTHxHEIGHT+X+Y`, like an X
+/// geometry, or just `WIDTHxHEIGHT` in the top left corner
+pub fn parse_region(region: &str) -> Result<Rect, String> {
+    let invalid = || {
+        format!(
+            "`{region}` isn't a region, expected \
+             WIDTHxHEIGHT+X+Y like 40x10+0+2"
+        )
+    };
+    let (size, offset) = region
+        .split_once('+')
+        .map_or((region, None), |(size, offset)| {
+            (size, Some(offset))
+        });
+    let (width, height) =
+        size.split_once('x').ok_or_else(invalid)?;
+    let (x, y) = match offset {
+        Some(offset) => {
+            offset.split_once('+').ok_or_else(invalid)?
+        }
+        None => ("0", "0"),
+    };
+    let number = |n: &str| {
+        n.trim().parse::<u16>().map_err(|_| invalid())
+    };
+    Ok(Rect {
+        x: number(x)?,
+        y: number(y)?,
+        width: number(width)?,
+        height: number(height)?,
+    })
+}
+
+/// The part of `frame` that `region` covers. A region that
+/// hangs off the edge is cut down to fit, one entirely
+/// outside it ends up empty.
+pub fn clamp_region(region: Rect, frame: Rect) -> Rect {
+    let x =
+        region.x.saturating_add(frame.x).min(frame.right());
+    let y = region
+        .y
+        .saturating_add(frame.y)
+        .min(frame.bottom());
+    Rect {
+        x,
+        y,
+        width: region.width.min(frame.right() - x),
+        height: region.height.min(frame.bottom() - y),
+    }
+}
+
+/// When the trigger fires and how much history it keeps
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerOptions {
+    /// fires on the first reading at or above this, in the
+    /// same units as the sparkline
+    pub threshold_db: f32,
+    /// readings kept from before the one that fired
+    pub pre_samples: usize,
+    /// readings to wait for after it before freezing
+    pub post_samples: usize,
+}
+
+enum TriggerState {
+    /// watching for a reading over the threshold
+    Armed,
+    /// fired on the reading at this index into
+    /// `AppState::decibels`, waiting for the post trigger
+    /// readings to arrive
+    Triggered { at: usize },
+    /// a copy of the state with the history cut down to
+    /// the readings around the trigger
+    Frozen(Box<AppState>),
+}
+
+/// An oscilloscope style trigger: arms, fires when the level
+/// crosses the threshold, then freezes the display on the
+/// readings around it until rearmed.
+pub struct Trigger {
+    options: TriggerOptions,
+    state: TriggerState,
+    /// how many readings have already been checked
+    seen: usize,
+}
+
+impl Trigger {
+    pub fn new(options: TriggerOptions) -> Self {
+        Trigger {
+            options,
+            state: TriggerState::Armed,
+            seen: 0,
+        }
+    }
+
+    /// Checks the readings that arrived since the last call
+    pub fn update(&mut self, s: &AppState) {
+        if let TriggerState::Armed = self.state {
+            let fired = s.decibels
+                [self.seen.min(s.decibels.len())..]
+                .iter()
+                .position(|db| {
+                    *db >= self.options.threshold_db
+                });
+            if let Some(offset) = fired {
+                self.state = TriggerState::Triggered {
+                    at: self.seen + offset,
+                };
+            }
+        }
+        self.seen = s.decibels.len();
+
+        if let TriggerState::Triggered { at } = self.state {
+            let end = at + self.options.post_samples + 1;
+            if s.decibels.len() >= end {
+                let start = at.saturating_sub(
+                    self.options.pre_samples,
+                );
+                let mut snapshot = s.clone();
+                snapshot.decibels =
+                    s.decibels[start..end].to_vec();
+                self.state = TriggerState::Frozen(
+                    Box::new(snapshot),
+                );
+            }
+        }
+    }
+
+    /// The state to draw instead of the live one, while
+    /// frozen
+    pub fn frozen(&self) -> Option<&AppState> {
+        match &self.state {
+            TriggerState::Frozen(snapshot) => {
+                Some(snapshot)
+            }
+            _ => None,
+        }
+    }
+
+    /// Unfreezes and waits for the next crossing, ignoring
+    /// anything that arrived while frozen
+    pub fn rearm(&mut self) {
+        self.state = TriggerState::Armed;
+    }
+
+    pub fn status(&self) -> &'static str {
+        match self.state {
+            TriggerState::Armed => "trigger armed",
+            TriggerState::Triggered { .. } => "triggered…",
+            TriggerState::Frozen(_) => "frozen (r rearms)",
+        }
+    }
+}
+
+/// When and how far to back off redrawing during silence
+#[derive(Debug, Clone, Copy)]
+pub struct IdleOptions {
+    /// how long the level has to stay below `threshold_db`
+    pub after: Duration,
+    /// in the same units as the sparkline, dBFS or dB SPL
+    pub threshold_db: f32,
+    /// redraw rate while idle
+    pub fps: f32,
+}
+
+/// Tracks how long the input has been quiet
+#[derive(Debug, Clone)]
+pub struct IdleTimer {
+    options: IdleOptions,
+    quiet_since: Option<Instant>,
+}
+
+impl IdleTimer {
+    pub fn new(options: IdleOptions) -> Self {
+        IdleTimer {
+            options,
+            quiet_since: None,
+        }
+    }
+
+    /// Feeds in the latest level and returns whether the
+    /// input has been quiet for long enough to idle. Any
+    /// reading at or above the threshold ends the idle
+    /// straight away.
+    pub fn update(
+        &mut self,
+        level_db: f32,
+        now: Instant,
+    ) -> bool {
+        if level_db >= self.options.threshold_db {
+            self.quiet_since = None;
+            return false;
+        }
+        let since = *self.quiet_since.get_or_insert(now);
+        now.duration_since(since) >= self.options.after
+    }
+
+    /// The poll timeout to use at `fps` given whether we're
+    /// idle
+    pub fn poll_timeout(
+        &self,
+        fps: f32,
+        idle: bool,
+    ) -> Duration {
+        poll_timeout(if idle {
+            self.options.fps
+        } else {
+            fps
+        })
+    }
+}
+
+/// How long frames can stop arriving before the display
+/// calls the input disconnected, unless configured otherwise
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Notices frames no longer arriving, e.g. a device that was
+/// unplugged without its stream reporting an error
+#[derive(Debug, Clone)]
+pub struct StallTimer {
+    timeout: Duration,
+    /// frames seen at the last update
+    frames: usize,
+    /// when `frames` last changed
+    since: Option<Instant>,
+}
+
+impl StallTimer {
+    pub fn new(timeout: Duration) -> Self {
+        StallTimer {
+            timeout,
+            frames: 0,
+            since: None,
+        }
+    }
+
+    /// Feeds in how many frames have arrived so far and
+    /// returns whether none have for `timeout`. Nothing
+    /// counts as stalled before the first frame, and the
+    /// next one to arrive ends the stall.
+    pub fn update(
+        &mut self,
+        frames: usize,
+        now: Instant,
+    ) -> bool {
+        if frames == 0 {
+            return false;
+        }
+        if frames != self.frames || self.since.is_none() {
+            self.frames = frames;
+            self.since = Some(now);
+            return false;
+        }
+        self.since.is_some_and(|since| {
+            now.duration_since(since) >= self.timeout
+        })
+    }
+}
+
+/// Time constant of the beat flash fading out
+pub const FLASH_DECAY: Duration =
+    Duration::from_millis(150);
+
+/// A flash dimmer than this is over
+const FLASH_FLOOR: f32 = 0.02;
+
+/// How bright the beat flash is, jumping up on each beat
+/// and fading back out over `FLASH_DECAY`
+#[derive(Debug, Clone, Default)]
+pub struct FlashEnvelope {
+    level: f32,
+    /// beats seen at the last update, `None` before the
+    /// first
+    beats: Option<u64>,
+}
+
+impl FlashEnvelope {
+    /// Feeds in the beat count and strength so far and how
+    /// long it's been since the last update, and returns
+    /// the brightness, 0 to 1. Beats already counted before
+    /// the first update don't flash.
+    pub fn update(
+        &mut self,
+        beats: u64,
+        strength: f32,
+        dt: Duration,
+    ) -> f32 {
+        self.level *= (-dt.as_secs_f32()
+            / FLASH_DECAY.as_secs_f32())
+        .exp();
+        if self.beats.is_some_and(|seen| beats > seen) {
+            self.level =
+                self.level.max(strength.clamp(0.0, 1.0));
+        }
+        self.beats = Some(beats);
+        if self.level < FLASH_FLOOR {
+            self.level = 0.0;
+        }
+        self.level
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+/// Fills the background of every cell in the area that
+/// doesn't have one of its own, leaving the terminal's
+/// background showing through nothing
+struct BackgroundFlash {
+    color: Color,
+}
+
+impl Widget for BackgroundFlash {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                if cell.bg == Color::Reset {
+                    cell.set_bg(self.color);
+                }
+            }
+        }
+    }
+}
+
+/// `color` at `level` of its brightness, 0 being black
+pub fn flash_color(color: Color, level: f32) -> Color {
+    let (r, g, b) = color_rgb(color);
+    let scale = |channel: u8| {
+        (channel as f32 * level.clamp(0.0, 1.0)) as u8
+    };
+    Color::Rgb(scale(r), scale(g), scale(b))
+}
+
+/// How often `run` checks for a shutdown request while it
+/// waits for input
+const SHUTDOWN_CHECK: Duration = Duration::from_millis(50);
+
+/// Asks `run` to stop as if q had been pressed, from another
+/// thread, e.g. a signal handler. Clones share the request.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Shortest time `run` will wait for input between redraws.
+/// Anything lower and a silly fps turns the loop into a
+/// busy-wait that pins a core.
+pub const MIN_POLL_TIMEOUT: Duration =
+    Duration::from_millis(1);
+/// Longest time `run` will wait, also used when fps is zero
+/// or nonsense
+pub const MAX_POLL_TIMEOUT: Duration =
+    Duration::from_secs(1);
+
+/// How long to wait for terminal events between redraws to
+/// hit `fps`, clamped to `MIN_POLL_TIMEOUT..=MAX_POLL_TIMEOUT`
+pub fn poll_timeout(fps: f32) -> Duration {
+    if fps.is_nan() || fps <= 0.0 {
+        return MAX_POLL_TIMEOUT;
+    }
+    Duration::try_from_secs_f32(1.0 / fps)
+        .unwrap_or(MIN_POLL_TIMEOUT)
+        .clamp(MIN_POLL_TIMEOUT, MAX_POLL_TIMEOUT)
+}
+
+/// How long a notice from the audio side stays up
+pub const NOTICE_DURATION: Duration =
+    Duration::from_secs(5);
+
+/// The message in `notice` if it was posted less than
+/// `NOTICE_DURATION` before `now`
+pub fn visible_notice(
+    notice: &Option<(String, Instant)>,
+    now: Instant,
+) -> Option<&str> {
+    notice.as_ref().and_then(|(message, posted)| {
+        (now.saturating_duration_since(*posted)
+            < NOTICE_DURATION)
+            .then_some(message.as_str())
+    })
+}
+
+/// The redraw rate to aim for: `unfocused_fps`, if there is
+/// one, while the terminal has lost focus, `fps` otherwise
+pub fn focus_fps(
+    fps: f32,
+    unfocused_fps: Option<f32>,
+    focused: bool,
+) -> f32 {
+    match unfocused_fps {
+        Some(unfocused_fps) if !focused => unfocused_fps,
+        _ => fps,
+    }
+}
+
+/// How many times faster either of the analysis rate and the
+/// redraw rate can be than the other before it's worth a
+/// warning
+pub const RATE_TOLERANCE: f32 = 2.0;
+/// Seconds of frames to measure the analysis rate over
+/// before comparing it
+const RATE_SETTLE_SECONDS: f32 = 3.0;
+/// How long the rate warning stays up
+const RATE_WARNING_TIMEOUT: Duration =
+    Duration::from_secs(10);
+
+/// How the redraw rate lines up with the analysis rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateMatch {
+    /// within `RATE_TOLERANCE` of each other
+    Matched,
+    /// redrawing so much faster than frames arrive that
+    /// most redraws show the same frame again
+    Repeating,
+    /// frames arriving so much faster than redraws that
+    /// most of them are never shown
+    Dropping,
+}
+
+/// Compares `analysis_rate` frames a second with redrawing
+/// `fps` times a second, `None` until both are known
+pub fn classify_rates(
+    analysis_rate: f32,
+    fps: f32,
+) -> Option<RateMatch> {
+    let known = |rate: f32| rate.is_finite() && rate > 0.0;
+    if !known(analysis_rate) || !known(fps) {
+        return None;
+    }
+    Some(if fps > analysis_rate * RATE_TOLERANCE {
+        RateMatch::Repeating
+    } else if analysis_rate > fps * RATE_TOLERANCE {
+        RateMatch::Dropping
+    } else {
+        RateMatch::Matched
+    })
+}
+
+impl RateMatch {
+    /// What to tell the user, with a setting that would
+    /// match the rates up. `None` when they already do.
+    pub fn warning(
+        self,
+        analysis_rate: f32,
+        fps: f32,
+    ) -> Option<String> {
+        let suggested = analysis_rate.round().max(1.0);
+        match self {
+            RateMatch::Matched => None,
+            RateMatch::Repeating => Some(format!(
+                "redrawing at {fps:.0} fps but audio only \
+                 analyses {analysis_rate:.0} frames/s, try \
+                 --fps {suggested} or a smaller \
+                 --buffer-size"
+            )),
+            RateMatch::Dropping => Some(format!(
+                "audio analyses {analysis_rate:.0} frames/s \
+                 but only {fps:.0} get drawn, try --fps \
+                 {suggested} or a bigger --buffer-size"
+            )),
+        }
+    }
+}
+
+/// Which view fills the screen. Tab and shift-tab cycle
+/// through them in this order, the number keys jump
+/// straight to one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VizMode {
+    #[default]
+    Sparkline,
+    Bars,
+    Waterfall,
+    Radial,
+    Meter,
+    Vectorscope,
+    Histogram,
+    Waveform,
+    Tuner,
+}
+
+impl VizMode {
+    pub const ALL: [VizMode; 9] = [
+        VizMode::Sparkline,
+        VizMode::Bars,
+        VizMode::Waterfall,
+        VizMode::Radial,
+        VizMode::Meter,
+        VizMode::Vectorscope,
+        VizMode::Histogram,
+        VizMode::Waveform,
+        VizMode::Tuner,
+    ];
+
+    fn index(self) -> usize {
+        VizMode::ALL
+            .iter()
+            .position(|mode| *mode == self)
+            .unwrap()
+    }
+
+    pub fn next(self) -> VizMode {
+        VizMode::ALL
+            [(self.index() + 1) % VizMode::ALL.len()]
+    }
+
+    pub fn previous(self) -> VizMode {
+        VizMode::ALL[(self.index() + VizMode::ALL.len()
+            - 1)
+            % VizMode::ALL.len()]
+    }
+
+    /// The mode bound to a number key, '1' being the first
+    pub fn from_digit(key: char) -> Option<VizMode> {
+        let digit = key.to_digit(10)? as usize;
+        VizMode::ALL.get(digit.checked_sub(1)?).copied()
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            VizMode::Sparkline => "sparkline",
+            VizMode::Bars => "bars",
+            VizMode::Waterfall => "waterfall",
+            VizMode::Radial => "radial",
+            VizMode::Meter => "meter",
+            VizMode::Vectorscope => "vectorscope",
+            VizMode::Histogram => "histogram",
+            VizMode::Waveform => "waveform",
+            VizMode::Tuner => "tuner",
+        }
+    }
+}
+
+/// State that belongs to the render loop rather than being
+/// shared with the audio side
+#[derive(Debug, Clone, Default)]
+pub struct TuiState {
+    /// the band the gain keys adjust
+    pub selected_band: usize,
+    pub mode: VizMode,
+    /// the keybinding overlay is open
+    pub show_help: bool,
+    /// the analysis stats panel is open
+    pub show_stats: bool,
+    /// indices of the soloed bands
+    pub soloed: BTreeSet<usize>,
+    /// indices of the muted bands
+    pub muted: BTreeSet<usize>,
+    /// the setup wizard, while it's open
+    pub wizard: Option<Wizard>,
+    /// why the wizard's thresholds couldn't be saved
+    pub wizard_error: Option<String>,
+    /// the talking indicator's threshold, starting from
+    /// `DisplayOptions::talking_threshold_db` and replaced
+    /// when the wizard saves a new one
+    pub talking_threshold_db: Option<f32>,
+    /// the display is held on what it showed when paused
+    pub paused: bool,
+    /// frames stopped arriving, the last one stays up dimmed
+    pub disconnected: bool,
+    /// the tempo readout, filtered
+    pub bpm: Option<f32>,
+    /// how bright the beat flash is right now
+    pub flash: FlashEnvelope,
+    /// redraws per second, as measured
+    pub fps: f32,
+    /// the analysis and redraw rates have been compared
+    pub rates_checked: bool,
+    /// what the comparison found and when, if the rates
+    /// were off
+    pub rate_warning: Option<(String, Instant)>,
+    /// what the last key press had to say and when, shown
+    /// like the rate warning
+    pub notice: Option<(String, Instant)>,
+    /// a spectrum frozen to compare the live one against
+    pub reference: Option<Vec<f32>>,
+    /// the terminal reported losing focus and hasn't got it
+    /// back yet
+    pub unfocused: bool,
+    /// what the level readouts are labeled in
+    pub units: DisplayUnits,
+}
+
+impl TuiState {
+    /// What the keys start out set to for `options`
+    pub fn new(options: &DisplayOptions) -> Self {
+        TuiState {
+            talking_threshold_db: options
+                .talking_threshold_db,
+            paused: options.start_paused,
+            ..TuiState::default()
+        }
+    }
+
+    /// Keeps track of the terminal's focus from `event`,
+    /// anything that isn't a focus change is left alone
+    pub fn focus_event(&mut self, event: &Event) {
+        match event {
+            Event::FocusLost => self.unfocused = true,
+            Event::FocusGained => self.unfocused = false,
+            _ => {}
+        }
+    }
+
+    /// The redraw rate to aim for with `options`, see
+    /// `focus_fps`
+    pub fn fps(&self, options: &DisplayOptions) -> f32 {
+        focus_fps(
+            options.fps,
+            options.unfocused_fps,
+            !self.unfocused,
+        )
+    }
+
+    /// Whether each of `count` bands should be shown: only
+    /// the soloed ones while anything is soloed (muted or
+    /// not), otherwise everything that isn't muted.
+    pub fn audible_bands(&self, count: usize) -> Vec<bool> {
+        (0..count)
+            .map(|band| {
+                if self.soloed.is_empty() {
+                    !self.muted.contains(&band)
+                } else {
+                    self.soloed.contains(&band)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Adds `item` to `set` if it's missing and removes it if
+/// it's there
+fn toggle(set: &mut BTreeSet<usize>, item: usize) {
+    if !set.remove(&item) {
+        set.insert(item);
+    }
+}
+
+/// The keys that do `action` under `keymap`, as the help
+/// shows them
+fn action_keys(keymap: &KeyMap, action: Action) -> String {
+    match action {
+        Action::Quit => format!(
+            "{}, ctrl-c",
+            key_name(keymap.key(action))
+        ),
+        Action::JumpToView => {
+            format!("1-{}", VizMode::ALL.len())
+        }
+        _ => key_name(keymap.key(action)),
+    }
+}
+
+/// The help overlay's lines, one per action with the keys
+/// `keymap` puts it on padded into a column
+pub fn help_lines(keymap: &KeyMap) -> Vec<String> {
+    let key_width = Action::ALL
+        .iter()
+        .map(|&action| {
+            action_keys(keymap, action).chars().count()
+        })
+        .max()
+        .unwrap_or(0);
+    Action::ALL
+        .iter()
+        .map(|&action| {
+            let keys = action_keys(keymap, action);
+            let padding = key_width - keys.chars().count();
+            format!(
+                "{keys}{}  {}",
+                " ".repeat(padding),
+                action.description()
+            )
+        })
+        .collect()
+}
+
+/// How much one press of up/down changes a band's gain
+pub const GAIN_STEP_DB: f32 = 1.0;
+
+/// The quietest a band can be turned down to. A band at
+/// zero gain steps up from here.
+pub const MIN_GAIN_DB: f32 = -60.0;
+
+/// The loudest a band can be turned up to
+pub const MAX_GAIN_DB: f32 = 40.0;
+
+/// Nudges a linear gain by `steps` * `GAIN_STEP_DB`. The
+/// step is added in dB and the result clamped to
+/// `MIN_GAIN_DB..=MAX_GAIN_DB`, so the gain can't get
+/// stuck at zero or run off to infinity.
+pub fn step_gain(gain: f32, steps: i32) -> f32 {
+    let db = (20.0 * gain.log10()).max(MIN_GAIN_DB);
+    let db = (db + steps as f32 * GAIN_STEP_DB)
+        .clamp(MIN_GAIN_DB, MAX_GAIN_DB);
+    10f32.powf(db / 20.0)
+}
+
+/// Writes the wizard's thresholds into the config at
+/// `path`, keeping everything else in it
+pub fn save_thresholds(
+    path: &Path,
+    thresholds: &Thresholds,
+) -> Result<(), ConfigError> {
+    let mut config = Config::load(path)?;
+    config.gate_db = Some(thresholds.gate_db);
+    config.talking_threshold_db =
+        Some(thresholds.talking_threshold_db);
+    config.save(path)
 }
 
+/// Draws and handles keys until quit or shutdown. Every
+/// timer in the loop (the redraw rate, idling, stalls, the
+/// beat flash, notices) goes by `clock`.
 pub fn run(
     terminal: &mut Terminal<CrosstermBackend<Stdout>>,
     state: Arc<Mutex<AppState>>,
+    options: &DisplayOptions,
+    clock: &impl Clock,
 ) -> Result<(), io::Error> {
-    Ok(loop {
-        terminal.draw(|f| ui(f, state.clone()))?;
+    let mut idle_timer = options.idle.map(IdleTimer::new);
+    let mut trigger = options.trigger.map(Trigger::new);
+    let mut stall_timer =
+        options.stall_timeout.map(StallTimer::new);
+    let mut bpm_filter =
+        OneEuroFilter::new(options.readout_smoothing);
+    // smoothed like the analysis rate, a key press redraws
+    // early and shouldn't spike it
+    let mut draw_rate = RateMeter::default();
+    let mut tui_state = TuiState::new(options);
+    // what's on screen while paused. Starting paused holds
+    // the display before any audio has arrived.
+    let mut paused_state =
+        options.start_paused.then(|| {
+            Box::new(AppState::new(
+                state.lock().unwrap().freq_ranges.clone(),
+            ))
+        });
+    loop {
+        terminal.draw(|f| {
+            // the audio side poisons the lock if it panics,
+            // keep drawing so its banner shows up
+            let s =
+                state.lock().unwrap_or_else(|poisoned| {
+                    poisoned.into_inner()
+                });
+            if let Some(trigger) = &mut trigger {
+                trigger.update(&s);
+            }
+            if let Some(wizard) = &mut tui_state.wizard {
+                wizard.feed(&s.decibels);
+            }
+            if let Some(stall_timer) = &mut stall_timer {
+                tui_state.disconnected = stall_timer
+                    .update(s.decibels.len(), clock.now());
+            }
+            // once, after the rate has had a few seconds to
+            // settle
+            if !tui_state.rates_checked
+                && s.analysis_rate > 0.0
+                && s.decibels.len() as f32
+                    >= s.analysis_rate * RATE_SETTLE_SECONDS
+            {
+                tui_state.rates_checked = true;
+                tui_state.rate_warning = classify_rates(
+                    s.analysis_rate,
+                    options.fps,
+                )
+                .and_then(|rates| {
+                    rates.warning(
+                        s.analysis_rate,
+                        options.fps,
+                    )
+                })
+                .map(|warning| (warning, clock.now()));
+            }
+            let frozen =
+                trigger.as_ref().and_then(Trigger::frozen);
+            let shown = paused_state
+                .as_deref()
+                .or(frozen)
+                .unwrap_or(&s);
+            let now = clock.now();
+            for message in [
+                &mut tui_state.rate_warning,
+                &mut tui_state.notice,
+            ] {
+                if message.as_ref().is_some_and(
+                    |(_, shown)| {
+                        now.duration_since(*shown)
+                            >= RATE_WARNING_TIMEOUT
+                    },
+                ) {
+                    *message = None;
+                }
+            }
+            let (elapsed, fps) = draw_rate.tick(now);
+            if let Some(fps) = fps {
+                tui_state.fps = fps;
+            }
+            tui_state.bpm = shown
+                .tempo
+                .bpm(shown.analysis_rate)
+                .map(|bpm| {
+                    bpm_filter
+                        .filter(bpm, elapsed.as_secs_f32())
+                });
+            if tui_state.bpm.is_none() {
+                bpm_filter.reset();
+            }
+            tui_state.flash.update(
+                shown.tempo.beats(),
+                shown.tempo.beat_strength(),
+                elapsed,
+            );
+            ui(
+                f,
+                shown,
+                options,
+                &tui_state,
+                trigger.as_ref(),
+            )
+        })?;
 
-        if event::poll(Duration::from_millis(250))? {
-            if let Event::Key(key) = event::read()? {
-                if KeyCode::Char('q') == key.code {
-                    break;
+        let fps = tui_state.fps(options);
+        // while idle the next sound only shows up at the
+        // next (slow) redraw, after that it's back to full
+        // speed
+        let timeout = match &mut idle_timer {
+            Some(idle_timer) => {
+                let level = state
+                    .lock()
+                    .unwrap()
+                    .decibels
+                    .last()
+                    .copied()
+                    .unwrap_or(f32::NEG_INFINITY);
+                let idle =
+                    idle_timer.update(level, clock.now());
+                idle_timer.poll_timeout(fps, idle)
+            }
+            None => poll_timeout(fps),
+        };
+
+        let event = next_event(timeout, &options.shutdown)?;
+        if options.shutdown.requested() {
+            break;
+        }
+        if let Some(event) = &event {
+            tui_state.focus_event(event);
+        }
+        if let Some(Event::Key(key)) = event {
+            let Some(action) = options.keymap.action(key)
+            else {
+                continue;
+            };
+            match action {
+                Action::Quit => break,
+                Action::Help => {
+                    tui_state.show_help =
+                        !tui_state.show_help
+                }
+                Action::Close => {
+                    tui_state.show_help = false;
+                    tui_state.wizard = None;
+                }
+                Action::Wizard => {
+                    let seen = state
+                        .lock()
+                        .unwrap()
+                        .decibels
+                        .len();
+                    tui_state.wizard =
+                        Some(Wizard::new(seen));
+                    tui_state.wizard_error = None;
+                }
+                Action::Pause => {
+                    if let Some(wizard) =
+                        &mut tui_state.wizard
+                    {
+                        wizard.advance();
+                    } else if paused_state.take().is_none()
+                    {
+                        paused_state = Some(Box::new(
+                            state.lock().unwrap().clone(),
+                        ));
+                    }
+                    tui_state.paused =
+                        paused_state.is_some();
+                }
+                Action::Confirm => {
+                    let Some(WizardStep::Review(
+                        thresholds,
+                    )) = tui_state
+                        .wizard
+                        .as_ref()
+                        .map(Wizard::step)
+                        .cloned()
+                    else {
+                        continue;
+                    };
+                    let saved = match &options.config_path {
+                        Some(path) => save_thresholds(
+                            path,
+                            &thresholds,
+                        )
+                        .map_err(|err| err.to_string()),
+                        None => {
+                            Err("no config file to save to"
+                                .to_string())
+                        }
+                    };
+                    match saved {
+                        Ok(()) => {
+                            tui_state.talking_threshold_db =
+                                    Some(
+                                        thresholds
+                                            .talking_threshold_db,
+                                    );
+                            tui_state.wizard = None;
+                            tui_state.wizard_error = None;
+                        }
+                        Err(err) => {
+                            tui_state.wizard_error =
+                                Some(err)
+                        }
+                    }
+                }
+                Action::Stats => {
+                    tui_state.show_stats =
+                        !tui_state.show_stats
+                }
+                Action::FreezeReference => {
+                    tui_state.reference = state
+                        .lock()
+                        .unwrap()
+                        .spectrogram
+                        .front()
+                        .cloned()
+                }
+                Action::ClearReference => {
+                    tui_state.reference = None
+                }
+                Action::Units => {
+                    tui_state.units =
+                        tui_state.units.toggle()
+                }
+                Action::SavePlot => {
+                    // whatever's on screen, held or live
+                    let (spectrum, resolution_hz) = {
+                        let live = state.lock().unwrap();
+                        let shown = paused_state
+                            .as_deref()
+                            .or(trigger
+                                .as_ref()
+                                .and_then(Trigger::frozen))
+                            .unwrap_or(&live);
+                        (
+                            shown
+                                .spectrogram
+                                .front()
+                                .cloned()
+                                .unwrap_or_default(),
+                            shown.stats.resolution_hz,
+                        )
+                    };
+                    let (path, width, height) =
+                        &options.spectrum_image;
+                    let message = match render_plot_to_png(
+                        &spectrum,
+                        resolution_hz,
+                        *width,
+                        *height,
+                        path,
+                    ) {
+                        Ok(()) => format!(
+                            "saved the spectrum to {}",
+                            path.display()
+                        ),
+                        Err(err) => format!(
+                            "couldn't save the spectrum: {err}"
+                        ),
+                    };
+                    tui_state.notice =
+                        Some((message, clock.now()));
+                }
+                Action::Solo => toggle(
+                    &mut tui_state.soloed,
+                    tui_state.selected_band,
+                ),
+                Action::Mute => toggle(
+                    &mut tui_state.muted,
+                    tui_state.selected_band,
+                ),
+                Action::Rearm => {
+                    if let Some(trigger) = &mut trigger {
+                        trigger.rearm()
+                    }
+                }
+                Action::NextView => {
+                    tui_state.mode = tui_state.mode.next()
+                }
+                Action::PreviousView => {
+                    tui_state.mode =
+                        tui_state.mode.previous()
+                }
+                Action::JumpToView => {
+                    if let KeyCode::Char(digit) = key.code {
+                        if let Some(mode) =
+                            VizMode::from_digit(digit)
+                        {
+                            tui_state.mode = mode;
+                        }
+                    }
+                }
+                Action::ResetHold => {
+                    state.lock().unwrap().peak_hold.reset()
+                }
+                Action::RelearnNoise => state
+                    .lock()
+                    .unwrap()
+                    .noise_profile
+                    .lock()
+                    .unwrap()
+                    .learn(LEARN_FRAMES),
+                Action::PreviousBand => {
+                    tui_state.selected_band = tui_state
+                        .selected_band
+                        .saturating_sub(1)
+                }
+                Action::NextBand => {
+                    let count = state
+                        .lock()
+                        .unwrap()
+                        .freq_ranges
+                        .len();
+                    tui_state.selected_band =
+                        (tui_state.selected_band + 1)
+                            .min(count.saturating_sub(1))
+                }
+                Action::RaiseGain | Action::LowerGain => {
+                    let steps =
+                        if action == Action::RaiseGain {
+                            1
+                        } else {
+                            -1
+                        };
+                    let s = state.lock().unwrap_or_else(
+                        |poisoned| poisoned.into_inner(),
+                    );
+                    let mut gains =
+                        s.band_gains.lock().unwrap();
+                    if let Some(gain) = gains
+                        .get_mut(tui_state.selected_band)
+                    {
+                        *gain = step_gain(*gain, steps);
+                    }
                 }
             }
         }
-    })
+    }
+    Ok(())
 }
 
-fn ui<B: Backend>(
-    f: &mut Frame<B>,
-    state: Arc<Mutex<AppState>>,
-) {
-    let s = state.lock().unwrap();
-    let dbs: Vec<u64> = s
-        .decibels
-        .iter()
-        .rev()
-        .take(300)
-        .map(|db| db.abs() as u64)
+/// Waits up to `timeout` for a terminal event, a little at a
+/// time so a shutdown request doesn't have to wait for a
+/// slow idle redraw. `None` if nothing arrived or shutdown
+/// was requested.
+fn next_event(
+    timeout: Duration,
+    shutdown: &Shutdown,
+) -> io::Result<Option<Event>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if shutdown.requested() {
+            return Ok(None);
+        }
+        let left = deadline
+            .saturating_duration_since(Instant::now());
+        if event::poll(left.min(SHUTDOWN_CHECK))? {
+            return event::read().map(Some);
+        }
+        if left <= SHUTDOWN_CHECK {
+            return Ok(None);
+        }
+    }
+}
+
+/// How many history samples cover `seconds_per_screen` at
+/// the current analysis rate, or `None` if either is zero
+/// or not a real number yet (e.g. before the rate has been
+/// measured).
+pub fn history_len(
+    seconds_per_screen: f32,
+    frames_per_second: f32,
+) -> Option<usize> {
+    let samples = seconds_per_screen * frames_per_second;
+    if samples.is_finite() && samples >= 1.0 {
+        Some(samples.round() as usize)
+    } else {
+        None
+    }
+}
+
+/// Spreads the most recent `samples` decibel readings over
+/// `width` columns and orders them for rendering left to
+/// right.
+///
+/// In the normal orientation the oldest visible sample is
+/// on the left and the newest sits against the right edge.
+/// Mirrored, the newest sample is in the first column.
+/// Histories shorter than `samples` leave the columns for
+/// the missing (older) readings at zero, as do readings
+/// that aren't real numbers (a silent buffer's -inf).
+pub fn orient_decibels(
+    decibels: &[f32],
+    samples: usize,
+    width: usize,
+    orientation: Orientation,
+) -> Vec<f32> {
+    // newest first, as bar heights. Kept fractional so a
+    // level hovering around a whole dB doesn't flicker
+    // between two heights.
+    let recent: Vec<f32> =
+        decibels
+            .iter()
+            .rev()
+            .take(samples)
+            .map(|db| {
+                if db.is_finite() {
+                    db.abs()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+    let mut dbs: Vec<f32> = (0..width)
+        .map(|column| {
+            recent
+                .get(column * samples / width)
+                .copied()
+                .unwrap_or(0.0)
+        })
         .collect();
 
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                // Constraint::Length(3),
-                Constraint::Min(0),
-            ]
-            .as_ref(),
-        )
-        .split(f.size());
-    let sparkline = Sparkline::default()
-        .block(
-            Block::default()
-                .title("decibels")
-                .borders(Borders::LEFT | Borders::RIGHT),
-        )
-        .data(&dbs)
-        .style(Style::default().fg(Color::Yellow));
-    f.render_widget(sparkline, chunks[0]);
+    if !orientation.mirror {
+        dbs.reverse();
+    }
+    dbs
+}
+
+/// Trailing moving average over `window` values. The first
+/// few outputs average over however many values there are
+/// so far rather than padding. Non-finite readings, like
+/// the -inf of a silent buffer, are left out of the average
+/// and only come through if a window holds nothing else.
+pub fn moving_average(
+    values: &[f32],
+    window: usize,
+) -> Vec<f32> {
+    let window = window.max(1);
+    (0..values.len())
+        .map(|i| {
+            let slice =
+                &values[(i + 1).saturating_sub(window)..=i];
+            let (sum, count) = slice
+                .iter()
+                .filter(|v| v.is_finite())
+                .fold((0.0, 0), |(sum, count), v| {
+                    (sum + v, count + 1)
+                });
+            if count == 0 {
+                values[i]
+            } else {
+                sum / count as f32
+            }
+        })
+        .collect()
+}
+
+/// Min and max of each of `buckets` equal slices of the
+/// first `span` values, so downsampling keeps the extremes
+/// that picking one value per slice would skip over.
+/// Slices that fall past the end of `values` are `None`.
+fn envelope(
+    values: &[f32],
+    span: usize,
+    buckets: usize,
+) -> Vec<Option<(f32, f32)>> {
+    (0..buckets)
+        .map(|bucket| {
+            let start = bucket * span / buckets;
+            let end = ((bucket + 1) * span / buckets)
+                .max(start + 1)
+                .min(values.len());
+            values.get(start..end).and_then(|slice| {
+                slice.iter().fold(None, |acc, v| {
+                    let (lo, hi) = acc.unwrap_or((*v, *v));
+                    Some((lo.min(*v), hi.max(*v)))
+                })
+            })
+        })
+        .collect()
+}
+
+/// Like `orient_decibels` but keeps the quietest and
+/// loudest reading behind every column rather than one of
+/// them, for histories longer than the sparkline is wide.
+/// Readings that aren't real numbers count as zero here too.
+pub fn orient_envelope(
+    decibels: &[f32],
+    samples: usize,
+    width: usize,
+    orientation: Orientation,
+) -> Vec<(u64, u64)> {
+    // newest first, as bar heights
+    let recent: Vec<f32> =
+        decibels
+            .iter()
+            .rev()
+            .take(samples)
+            .map(|db| {
+                if db.is_finite() {
+                    db.abs()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+    let mut dbs: Vec<(u64, u64)> =
+        envelope(&recent, samples, width)
+            .into_iter()
+            .map(|bucket| {
+                bucket.map_or((0, 0), |(lo, hi)| {
+                    (lo as u64, hi as u64)
+                })
+            })
+            .collect();
+
+    if !orientation.mirror {
+        dbs.reverse();
+    }
+    dbs
+}
+
+/// A sparkline of (min, max) pairs. Each column is solid
+/// up to its min and shaded on up to its max, so a spike
+/// squeezed into a column with quieter readings still
+/// shows. Hangs from the top when `invert` is set.
+struct EnvelopeSparkline<'a> {
+    data: &'a [(u64, u64)],
+    invert: bool,
+    style: Style,
+}
+
+impl<'a> Widget for EnvelopeSparkline<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        let max = self
+            .data
+            .iter()
+            .map(|(_, hi)| *hi)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let height = area.height as u64;
+
+        for (i, (lo, hi)) in self
+            .data
+            .iter()
+            .take(area.width as usize)
+            .enumerate()
+        {
+            let solid = lo.saturating_mul(height) / max;
+            // round the max up so a spike is never lost to
+            // integer division
+            let shaded =
+                hi.saturating_mul(height).div_ceil(max);
+            for cell in 0..shaded.min(height) {
+                let symbol = if cell < solid {
+                    symbols::block::FULL
+                } else {
+                    "▒"
+                };
+                let row = if self.invert {
+                    area.top() + cell as u16
+                } else {
+                    area.bottom() - 1 - cell as u16
+                };
+                buf.get_mut(area.left() + i as u16, row)
+                    .set_symbol(symbol)
+                    .set_style(self.style);
+            }
+        }
+    }
+}
+
+/// A sparkline whose bars hang down from the top of the
+/// area. ratatui's `Sparkline` only grows upwards and there
+/// are no "upper n/8th" glyphs beyond the half block, so
+/// this renders at half-cell resolution.
+struct HangingSparkline<'a> {
+    data: &'a [f32],
+    style: Style,
+}
+
+impl<'a> Widget for HangingSparkline<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        let max = sparkline_max(self.data);
+        let half_cells = area.height as f32 * 2.0;
+
+        for (i, value) in self
+            .data
+            .iter()
+            .take(area.width as usize)
+            .enumerate()
+        {
+            let height =
+                (value / max * half_cells).round() as u64;
+            for row in 0..area.height {
+                let symbol = match height
+                    .saturating_sub(row as u64 * 2)
+                {
+                    0 => break,
+                    1 => "▀",
+                    _ => symbols::block::FULL,
+                };
+                buf.get_mut(
+                    area.left() + i as u16,
+                    area.top() + row,
+                )
+                .set_symbol(symbol)
+                .set_style(self.style);
+            }
+        }
+    }
+}
+
+/// The tallest of `data`, at least 1 so an all zero
+/// history doesn't divide by zero
+fn sparkline_max(data: &[f32]) -> f32 {
+    data.iter().copied().fold(1.0, f32::max)
+}
+
+/// Where the peak dot goes over a sparkline of `data`
+/// drawn `height` rows tall, as (column, row) from the top
+/// left. The bars are dB below full scale, so the loudest
+/// reading in view is the shortest bar, and the dot sits on
+/// its tip, the newest one if several tie, so it follows
+/// the peak as it scrolls. A zero bar is a missing reading
+/// or silence rather than a peak, so an all zero window has
+/// no dot.
+pub fn peak_dot(
+    data: &[f32],
+    height: u16,
+    orientation: Orientation,
+) -> Option<(u16, u16)> {
+    if height == 0 {
+        return None;
+    }
+    let peak = data
+        .iter()
+        .copied()
+        .filter(|value| *value > 0.0)
+        .fold(f32::INFINITY, f32::min);
+    if !peak.is_finite() {
+        return None;
+    }
+    let column = if orientation.mirror {
+        data.iter().position(|value| *value == peak)
+    } else {
+        data.iter().rposition(|value| *value == peak)
+    }?;
+    let scaled = peak / sparkline_max(data);
+    // the number of cells the bar reaches into, rounded
+    // the way the sparkline widgets round their tips
+    let cells = if orientation.invert {
+        ((scaled * height as f32 * 2.0).round() / 2.0)
+            .ceil()
+    } else {
+        ((scaled * height as f32 * 8.0).round() / 8.0)
+            .ceil()
+    }
+    .clamp(1.0, height as f32) as u16;
+    let row = if orientation.invert {
+        cells - 1
+    } else {
+        height - cells
+    };
+    Some((column as u16, row))
+}
+
+/// A dot at (column, row) of the area, marking the
+/// sparkline's peak
+struct PeakDot {
+    position: (u16, u16),
+}
+
+impl Widget for PeakDot {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let (column, row) = self.position;
+        if column >= area.width || row >= area.height {
+            return;
+        }
+        buf.get_mut(area.left() + column, area.top() + row)
+            .set_symbol("●")
+            .set_style(Style::default().fg(Color::Red));
+    }
+}
+
+/// The glyph for the top of a bar that covers `fraction` of
+/// its cell, rounded to the nearest eighth: a space for
+/// nothing, ▁ to ▇ in between and a full block for all of
+/// it
+pub fn block_glyph(fraction: f32) -> &'static str {
+    const GLYPHS: [&str; 9] = [
+        " ",
+        symbols::bar::ONE_EIGHTH,
+        symbols::bar::ONE_QUARTER,
+        symbols::bar::THREE_EIGHTHS,
+        symbols::bar::HALF,
+        symbols::bar::FIVE_EIGHTHS,
+        symbols::bar::THREE_QUARTERS,
+        symbols::bar::SEVEN_EIGHTHS,
+        symbols::bar::FULL,
+    ];
+    let eighths = if fraction.is_finite() {
+        (fraction.clamp(0.0, 1.0) * 8.0).round() as usize
+    } else {
+        0
+    };
+    GLYPHS[eighths]
+}
+
+/// A sparkline of fractional heights, growing up from the
+/// bottom of the area. Like ratatui's `Sparkline` it draws
+/// the top of each bar in eighths of a cell, but from the
+/// unrounded value, so a level between two whole numbers
+/// gets a height between theirs.
+struct FractionalSparkline<'a> {
+    data: &'a [f32],
+    style: Style,
+}
+
+impl<'a> Widget for FractionalSparkline<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        let max = sparkline_max(self.data);
+
+        for (i, value) in self
+            .data
+            .iter()
+            .take(area.width as usize)
+            .enumerate()
+        {
+            let height = value / max * area.height as f32;
+            for row in 0..area.height {
+                let symbol =
+                    block_glyph(height - row as f32);
+                if symbol == " " {
+                    break;
+                }
+                buf.get_mut(
+                    area.left() + i as u16,
+                    area.bottom() - 1 - row,
+                )
+                .set_symbol(symbol)
+                .set_style(self.style);
+            }
+        }
+    }
+}
+
+/// Where `db` falls between `low` and `high` as 0..=1.
+/// Anything that isn't a real reading (silence is -inf)
+/// maps to 0.
+pub fn map_db_to_unit(db: f32, low: f32, high: f32) -> f32 {
+    if !db.is_finite() {
+        return 0.0;
+    }
+    ((db - low) / (high - low)).clamp(0.0, 1.0)
+}
+
+/// The row, counted down from the top of a meter `height`
+/// rows tall, that `ratio` of full scale lands on
+pub fn tick_row(ratio: f32, height: u16) -> u16 {
+    let last = height.saturating_sub(1) as f32;
+    (last - ratio.clamp(0.0, 1.0) * last).round() as u16
+}
+
+/// Range and labeled marks of the level meter, for dBFS
+/// and dB SPL
+const METER_DBFS: (f32, f32) = (-60.0, 0.0);
+const METER_SPL: (f32, f32) = (30.0, 130.0);
+
+/// The (lowest, highest) level the meter and the histogram
+/// show
+pub fn meter_range(spl_calibrated: bool) -> (f32, f32) {
+    if spl_calibrated {
+        METER_SPL
+    } else {
+        METER_DBFS
+    }
+}
+
+/// What the numeric level readouts are shown in. Only the
+/// labels change, the meters and the data behind them stay
+/// in dB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayUnits {
+    #[default]
+    Decibels,
+    /// 0% at the bottom of the display range, 100% at the
+    /// top
+    Percent,
+}
+
+impl DisplayUnits {
+    pub fn toggle(self) -> Self {
+        match self {
+            DisplayUnits::Decibels => DisplayUnits::Percent,
+            DisplayUnits::Percent => DisplayUnits::Decibels,
+        }
+    }
+}
+
+/// `db` as a percentage of the way up `range`, 0 to 100
+pub fn db_to_percent(db: f32, range: (f32, f32)) -> f32 {
+    map_db_to_unit(db, range.0, range.1) * 100.0
+}
+
+/// Labels levels in whichever units the readouts are set
+/// to, against the meter's range for the calibration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Readout {
+    pub units: DisplayUnits,
+    pub range: (f32, f32),
+    pub spl_calibrated: bool,
+}
+
+impl Readout {
+    pub fn new(
+        units: DisplayUnits,
+        spl_calibrated: bool,
+    ) -> Self {
+        Readout {
+            units,
+            range: meter_range(spl_calibrated),
+            spl_calibrated,
+        }
+    }
+
+    /// "dBFS", "dB SPL" or "%"
+    pub fn unit(&self) -> &'static str {
+        match self.units {
+            DisplayUnits::Percent => "%",
+            DisplayUnits::Decibels
+                if self.spl_calibrated =>
+            {
+                "dB SPL"
+            }
+            DisplayUnits::Decibels => "dBFS",
+        }
+    }
+
+    /// `db` with its unit: "-12.3 dBFS" or "80%"
+    pub fn format(&self, db: f32) -> String {
+        match self.units {
+            DisplayUnits::Decibels => {
+                format!("{db:.1} {}", self.unit())
+            }
+            DisplayUnits::Percent => format!(
+                "{:.0}%",
+                db_to_percent(db, self.range)
+            ),
+        }
+    }
+
+    /// `db` as a bare whole number, for scale marks
+    pub fn mark(&self, db: f32) -> String {
+        match self.units {
+            DisplayUnits::Decibels => format!("{db:.0}"),
+            DisplayUnits::Percent => format!(
+                "{:.0}",
+                db_to_percent(db, self.range)
+            ),
+        }
+    }
+}
+
+const DBFS_TICKS: [f32; 5] =
+    [-60.0, -40.0, -20.0, -6.0, 0.0];
+const SPL_TICKS: [f32; 5] =
+    [40.0, 60.0, 80.0, 100.0, 120.0];
+
+/// The row a reference line at `db` goes on in a vertical
+/// meter `height` rows tall showing `range`, counted down
+/// from the top, the same as the scale's ticks
+pub fn reference_row(
+    db: f32,
+    range: (f32, f32),
+    height: u16,
+) -> u16 {
+    tick_row(map_db_to_unit(db, range.0, range.1), height)
+}
+
+/// The column a reference mark at `db` goes in along a
+/// horizontal meter `width` columns wide showing `range`
+pub fn reference_column(
+    db: f32,
+    range: (f32, f32),
+    width: u16,
+) -> u16 {
+    let last = width.saturating_sub(1) as f32;
+    (map_db_to_unit(db, range.0, range.1) * last).round()
+        as u16
+}
+
+const REFERENCE_COLOR: Color = Color::White;
+
+/// Draws one dash of a reference line over `cell`, keeping
+/// a meter's filled color behind it
+fn mark_reference(
+    cell: &mut ratatui::buffer::Cell,
+    symbol: &str,
+) {
+    if cell.symbol == symbols::block::FULL {
+        cell.bg = cell.fg;
+    }
+    cell.set_symbol(symbol).set_fg(REFERENCE_COLOR);
+}
+
+/// Dashed vertical marks at each of `references` across a
+/// horizontal meter showing `range`, drawn over the meter
+struct ReferenceMarks<'a> {
+    references: &'a [f32],
+    range: (f32, f32),
+}
+
+impl<'a> Widget for ReferenceMarks<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.area() == 0 {
+            return;
+        }
+        for db in self.references {
+            let x = area.left()
+                + reference_column(
+                    *db, self.range, area.width,
+                );
+            for y in area.top()..area.bottom() {
+                mark_reference(buf.get_mut(x, y), "╎");
+            }
+        }
+    }
+}
+
+/// A vertical level meter with its dB scale printed down
+/// the left side. ratatui's `Gauge` is horizontal only and
+/// has no scale, so this draws both.
+struct LevelMeter<'a> {
+    level: f32,
+    range: (f32, f32),
+    ticks: &'a [f32],
+    /// dashed lines across the bar at these levels
+    references: &'a [f32],
+    readout: Readout,
+}
+
+impl<'a> Widget for LevelMeter<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        const LABEL_WIDTH: u16 = 3;
+        if area.height == 0 || area.width <= LABEL_WIDTH + 1
+        {
+            return;
+        }
+        let (low, high) = self.range;
+        let bar_left = area.left() + LABEL_WIDTH + 1;
+        let bar_width = area.width - LABEL_WIDTH - 1;
+        let filled = (map_db_to_unit(self.level, low, high)
+            * area.height as f32)
+            .round() as u16;
+
+        for row in 0..filled {
+            // green, then yellow within 20 dB of the top and
+            // red within 6 dB, like a mixing desk
+            let db = low
+                + (row as f32 + 1.0) / area.height as f32
+                    * (high - low);
+            let color = if db > high - 6.0 {
+                Color::Red
+            } else if db > high - 20.0 {
+                Color::Yellow
+            } else {
+                Color::Green
+            };
+            for x in bar_left..bar_left + bar_width {
+                buf.get_mut(x, area.bottom() - 1 - row)
+                    .set_symbol(symbols::block::FULL)
+                    .set_style(Style::default().fg(color));
+            }
+        }
+
+        for tick in self.ticks {
+            let row = tick_row(
+                map_db_to_unit(*tick, low, high),
+                area.height,
+            );
+            buf.set_string(
+                area.left(),
+                area.top() + row,
+                format!(
+                    "{:>width$}",
+                    self.readout.mark(*tick),
+                    width = LABEL_WIDTH as usize
+                ),
+                Style::default().fg(Color::DarkGray),
+            );
+        }
+
+        for db in self.references {
+            let y = area.top()
+                + reference_row(
+                    *db,
+                    self.range,
+                    area.height,
+                );
+            for x in bar_left..bar_left + bar_width {
+                mark_reference(buf.get_mut(x, y), "╌");
+            }
+        }
+    }
+}
+
+/// Formats a frequency compactly, switching to kHz above
+/// 1000 so labels stay short: 140, 2.6k, 14k
+pub fn format_hz(hz: usize) -> String {
+    if hz < 1000 {
+        hz.to_string()
+    } else if hz.is_multiple_of(1000) {
+        format!("{}k", hz / 1000)
+    } else {
+        format!("{:.1}k", hz as f32 / 1000.0)
+    }
+}
+
+/// Builds the label shown under a band's bar, from most to
+/// least descriptive until one fits in `width` columns:
+/// "Bass 20–140", then "20–140", then "Bass", then as
+/// much of the name as fits.
+pub fn band_label(
+    range: &FreqRange,
+    width: usize,
+) -> String {
+    let hz = format!(
+        "{}–{}",
+        format_hz(range.low),
+        format_hz(range.high)
+    );
+    [
+        format!("{} {}", range.name, hz),
+        hz,
+        range.name.to_string(),
+    ]
+    .into_iter()
+    .find(|label| label.chars().count() <= width)
+    .unwrap_or_else(|| {
+        range.name.chars().take(width).collect()
+    })
+}
+
+fn band_chart<'a>(
+    bands: &[f32],
+    ranges: &[FreqRange],
+    gains: &[f32],
+    audible: &[bool],
+    selected: usize,
+    colors: &[Color],
+    area: Rect,
+) -> BarChart<'a> {
+    const BAR_GAP: u16 = 1;
+    let title =
+        match (ranges.get(selected), gains.get(selected)) {
+            (Some(range), Some(gain)) => format!(
+                "bands ({} {:+.1} dB, ←→ select, ↑↓ gain, s solo, m mute)",
+                range.name,
+                20.0 * gain.log10()
+            ),
+            _ => "bands".to_string(),
+        };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let inner = block.inner(area);
+    let count = ranges.len().max(1) as u16;
+    let bar_width =
+        (inner.width.saturating_sub(BAR_GAP * (count - 1))
+            / count)
+            .max(1);
+
+    let bars: Vec<Bar> = ranges
+        .iter()
+        .zip(bands)
+        .enumerate()
+        .map(|(i, (range, energy))| {
+            let audible =
+                audible.get(i).copied().unwrap_or(true);
+            let color = if !audible {
+                Color::DarkGray
+            } else if !colors.is_empty() {
+                colors[i % colors.len()]
+            } else if i == selected {
+                Color::LightCyan
+            } else {
+                Color::Cyan
+            };
+            // a band's own color can't brighten to show
+            // it's selected, its label goes bold instead
+            let label_style = if i == selected {
+                Style::default()
+                    .fg(color)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(color)
+            };
+            let energy =
+                if audible { *energy } else { 0.0 };
+            Bar::default()
+                .style(Style::default().fg(color))
+                // BarChart only takes integers, scale up to
+                // keep some resolution for quiet bands
+                .value((energy * 1000.0) as u64)
+                .text_value(String::new())
+                .label(Line::styled(
+                    band_label(range, bar_width as usize),
+                    label_style,
+                ))
+        })
+        .collect();
+
+    BarChart::default()
+        .block(block)
+        .bar_width(bar_width)
+        .bar_gap(BAR_GAP)
+        .data(BarGroup::default().bars(&bars))
+}
+
+/// The quietest bin the waterfall distinguishes from
+/// silence, relative to a full scale sine
+pub const WATERFALL_FLOOR_DB: f32 = -80.0;
+
+/// How fft magnitudes are mapped to the 0..=1 levels the
+/// views draw. The default is `normalize_magnitude`'s dB
+/// scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelScale {
+    /// the log's base. The scale still spans
+    /// `WATERFALL_FLOOR_DB` but in "decibels" of this base,
+    /// so 10 is plain dB, 2 reaches down only to 1/16 of
+    /// full scale and anything above 10 reaches further. A
+    /// base with no log (not above 0, or 1) counts as 10.
+    pub base: f32,
+    /// 0 for the plain log. Above 0 a soft log instead,
+    /// `ln(1 + x / softness)` scaled so full scale is 1:
+    /// linear near silence, so there's no floor to hit,
+    /// and close to a log well above `softness` (a fraction
+    /// of full scale). The base doesn't matter to it.
+    pub softness: f32,
+}
+
+impl Default for LevelScale {
+    fn default() -> Self {
+        LevelScale {
+            base: 10.0,
+            softness: 0.0,
+        }
+    }
+}
+
+impl LevelScale {
+    /// Normalizes an fft magnitude to 0..=1, 1 being a full
+    /// scale sine.
+    ///
+    /// `spectrum_len` is the length of the spectrum the bin
+    /// came from, which is half the fft size plus one.
+    pub fn normalize(
+        self,
+        magnitude: f32,
+        spectrum_len: usize,
+    ) -> f32 {
+        // a full scale sine peaks at fft_size / 2
+        let full_scale =
+            spectrum_len.saturating_sub(1).max(1) as f32;
+        let ratio = magnitude / full_scale;
+        if self.softness.is_finite() && self.softness > 0.0
+        {
+            return ((ratio.max(0.0) / self.softness)
+                .ln_1p()
+                / self.softness.recip().ln_1p())
+            .clamp(0.0, 1.0);
+        }
+        // exactly 1 for base 10, the usual dB
+        let base = if self.base.is_finite()
+            && self.base > 0.0
+            && self.base != 1.0
+        {
+            10f32.ln() / self.base.ln()
+        } else {
+            1.0
+        };
+        let db = 20.0 * ratio.log10() * base;
+        ((db - WATERFALL_FLOOR_DB) / -WATERFALL_FLOOR_DB)
+            .clamp(0.0, 1.0)
+    }
+}
+
+/// Normalizes an fft magnitude to 0..=1 on a dB scale where
+/// 1 is a full scale sine and 0 is `WATERFALL_FLOOR_DB`.
+///
+/// `spectrum_len` is the length of the spectrum the bin came
+/// from, which is half the fft size plus one.
+pub fn normalize_magnitude(
+    magnitude: f32,
+    spectrum_len: usize,
+) -> f32 {
+    LevelScale::default().normalize(magnitude, spectrum_len)
+}
+
+/// Raises a `normalize_magnitude` level to `gamma`. Below 1
+/// lifts the quiet and middle levels, bringing out faint
+/// harmonics; above 1 pushes them down so only the loudest
+/// bins stand out. 0 and 1 stay where they are, and a gamma
+/// that isn't positive leaves every level as is.
+pub fn apply_gamma(level: f32, gamma: f32) -> f32 {
+    if !(gamma.is_finite() && gamma > 0.0) {
+        return level;
+    }
+    level.clamp(0.0, 1.0).powf(gamma)
+}
+
+/// How the spectrum is scaled before the views draw it
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+)]
+pub enum SpectrumNorm {
+    /// as measured, a full scale sine reaches the top
+    #[default]
+    None,
+    /// the loudest bin always reaches the top
+    Peak,
+    /// the bins add up to a full scale sine's peak, so the
+    /// shape reads the same however loud it is
+    Area,
+}
+
+impl SpectrumNorm {
+    /// `spectrum` scaled for `normalize_magnitude`. A silent
+    /// spectrum has nothing to scale to and comes back as
+    /// is.
+    pub fn apply(self, spectrum: Vec<f32>) -> Vec<f32> {
+        let reference = match self {
+            SpectrumNorm::None => return spectrum,
+            SpectrumNorm::Peak => {
+                spectrum.iter().copied().fold(0.0, f32::max)
+            }
+            SpectrumNorm::Area => spectrum.iter().sum(),
+        };
+        if !(reference.is_finite() && reference > 0.0) {
+            return spectrum;
+        }
+        let full_scale =
+            spectrum.len().saturating_sub(1).max(1) as f32;
+        let scale = full_scale / reference;
+        spectrum
+            .into_iter()
+            .map(|bin| bin * scale)
+            .collect()
+    }
+}
+
+/// A scrolling spectrogram. Each row is one spectrum with
+/// the newest at the top, each column a slice of the
+/// frequency range from 0Hz on the left to nyquist on the
+/// right.
+struct Waterfall<'a> {
+    spectrogram: &'a VecDeque<Vec<f32>>,
+    colormap: Colormap,
+    gamma: f32,
+    scale: LevelScale,
+    color_depth: ColorDepth,
+}
+
+impl<'a> Widget for Waterfall<'a> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for (row, spectrum) in self
+            .spectrogram
+            .iter()
+            .take(area.height as usize)
+            .enumerate()
+        {
+            if spectrum.is_empty() {
+                continue;
+            }
+            for column in 0..area.width {
+                // loudest bin in the slice this column covers
+                let start = column as usize
+                    * spectrum.len()
+                    / area.width as usize;
+                let end = ((column as usize + 1)
+                    * spectrum.len()
+                    / area.width as usize)
+                    .max(start + 1)
+                    .min(spectrum.len());
+                let magnitude = spectrum[start..end]
+                    .iter()
+                    .copied()
+                    .fold(0.0, f32::max);
+                let color = self.color_depth.quantize(
+                    self.colormap.sample(apply_gamma(
+                        self.scale.normalize(
+                            magnitude,
+                            spectrum.len(),
+                        ),
+                        self.gamma,
+                    )),
+                );
+                buf.get_mut(
+                    area.left() + column,
+                    area.top() + row as u16,
+                )
+                .set_symbol(" ")
+                .set_bg(color);
+            }
+        }
+    }
+}
+
+fn spectrum_points(
+    spectrum: &[f32],
+    gamma: f32,
+    scale: LevelScale,
+) -> Vec<(f64, f64)> {
+    spectrum
+        .iter()
+        .enumerate()
+        .map(|(bin, magnitude)| {
+            (
+                bin as f64,
+                apply_gamma(
+                    scale.normalize(
+                        *magnitude,
+                        spectrum.len(),
+                    ),
+                    gamma,
+                ) as f64,
+            )
+        })
+        .collect()
+}
+
+/// How far above or below the reference the difference
+/// chart reaches, in dB
+pub const DELTA_RANGE_DB: f32 = 24.0;
+
+/// How much louder each bin of `live` is than the same bin
+/// of `reference`, in dB over the waterfall's range: both
+/// are floored at `WATERFALL_FLOOR_DB` so silence against
+/// silence is no difference. `None` when they don't line
+/// up bin for bin, e.g. when the fft size has changed since
+/// the reference was frozen.
+pub fn spectrum_delta(
+    live: &[f32],
+    reference: &[f32],
+) -> Option<Vec<f32>> {
+    if live.len() != reference.len() {
+        return None;
+    }
+    let len = live.len();
+    Some(
+        live.iter()
+            .zip(reference)
+            .map(|(live, reference)| {
+                (normalize_magnitude(*live, len)
+                    - normalize_magnitude(*reference, len))
+                    * -WATERFALL_FLOOR_DB
+            })
+            .collect(),
+    )
+}
+
+/// The live spectrum's difference from the reference,
+/// clamped to `DELTA_RANGE_DB` either way
+fn delta_chart<'a>(
+    delta: &'a [(f64, f64)],
+    zero: &'a [(f64, f64)],
+    name: &'a str,
+) -> Chart<'a> {
+    let bins = delta.len().max(1) as f64;
+    let range = DELTA_RANGE_DB as f64;
+    Chart::new(vec![
+        Dataset::default()
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::DarkGray))
+            .data(zero),
+        Dataset::default()
+            .name(name)
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Yellow))
+            .data(delta),
+    ])
+    .block(
+        Block::default()
+            .title(format!("difference ±{range}dB"))
+            .borders(Borders::LEFT | Borders::RIGHT),
+    )
+    .x_axis(Axis::default().bounds([0.0, bins - 1.0]))
+    .y_axis(Axis::default().bounds([-range, range]))
+}
+
+/// The live spectrum with the peak-hold trace, and the
+/// frozen reference if there is one, over it
+fn spectrum_chart<'a>(
+    live: &'a [(f64, f64)],
+    held: &'a [(f64, f64)],
+    reference: &'a [(f64, f64)],
+    reference_name: &'a str,
+) -> Chart<'a> {
+    let bins = live.len().max(held.len()).max(1) as f64;
+    let reference = (!reference.is_empty()).then(|| {
+        Dataset::default()
+            .name(reference_name)
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Gray))
+            .data(reference)
+    });
+    Chart::new(
+        reference
+            .into_iter()
+            .chain([
+                Dataset::default()
+                    .name("live")
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(
+                        Style::default().fg(Color::Green),
+                    )
+                    .data(live),
+                Dataset::default()
+                    .name("hold (h resets)")
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(
+                        Style::default().fg(Color::Magenta),
+                    )
+                    .data(held),
+            ])
+            .collect(),
+    )
+    .block(
+        Block::default()
+            .title("spectrum")
+            .borders(Borders::LEFT | Borders::RIGHT),
+    )
+    // the reference's entry shouldn't be what pushes the
+    // legend out of a short chart
+    .hidden_legend_constraints((
+        Constraint::Ratio(1, 2),
+        Constraint::Ratio(1, 2),
+    ))
+    .x_axis(Axis::default().bounds([0.0, bins - 1.0]))
+    .y_axis(Axis::default().bounds([0.0, 1.0]))
+}
+
+/// Stands in for a view until the data it needs arrives
+fn waiting<'a>(title: &'a str) -> Paragraph<'a> {
+    Paragraph::new("waiting for audio…")
+        .alignment(Alignment::Center)
+        .block(
+            Block::default()
+                .title(title)
+                .borders(Borders::LEFT | Borders::RIGHT),
+        )
+}
+
+fn draw_sparkline<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+) {
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [Constraint::Min(0), Constraint::Length(8)]
+                .as_ref(),
+        )
+        .split(area);
+    let area = columns[0];
+    let (range, ticks) = if options.spl_calibrated {
+        (METER_SPL, &SPL_TICKS)
+    } else {
+        (METER_DBFS, &DBFS_TICKS)
+    };
+    let meter_block = Block::default()
+        .title("level")
+        .borders(Borders::RIGHT);
+    let meter_area = meter_block.inner(columns[1]);
+    f.render_widget(meter_block, columns[1]);
+    f.render_widget(
+        LevelMeter {
+            level: s
+                .decibels
+                .last()
+                .copied()
+                .unwrap_or(f32::NEG_INFINITY),
+            range,
+            ticks,
+            references: &options.reference_levels,
+            readout: Readout::new(
+                tui_state.units,
+                options.spl_calibrated,
+            ),
+        },
+        meter_area,
+    );
+
+    let mut block = Block::default()
+        .title(if options.spl_calibrated {
+            "dB SPL"
+        } else {
+            "decibels"
+        })
+        .borders(Borders::LEFT | Borders::RIGHT);
+    if s.dropped_samples > 0 {
+        block = block.title(
+            block::Title::from(Span::styled(
+                format!(
+                    "dropped {} samples",
+                    s.dropped_samples
+                ),
+                Style::default().fg(Color::Red),
+            ))
+            .alignment(Alignment::Right),
+        );
+    }
+    if let Some(seconds) = options.seconds_per_screen {
+        let (left, right) = if options.orientation.mirror {
+            ("now".to_string(), format!("-{seconds}s"))
+        } else {
+            (format!("-{seconds}s"), "now".to_string())
+        };
+        block = block
+            .borders(
+                Borders::LEFT
+                    | Borders::RIGHT
+                    | Borders::BOTTOM,
+            )
+            .title(
+                block::Title::from(left)
+                    .position(block::Position::Bottom)
+                    .alignment(Alignment::Left),
+            )
+            .title(
+                block::Title::from(right)
+                    .position(block::Position::Bottom)
+                    .alignment(Alignment::Right),
+            );
+    }
+    let inner = block.inner(area);
+    let samples = options
+        .seconds_per_screen
+        .and_then(|seconds| {
+            history_len(seconds, s.analysis_rate)
+        })
+        .unwrap_or(inner.width as usize);
+    // smooth a little more than is shown so even the oldest
+    // visible reading gets a full window
+    let taken = s.decibels.len().saturating_sub(
+        samples + options.smoothing.saturating_sub(1),
+    );
+    let decibels = moving_average(
+        &s.decibels[taken..],
+        options.smoothing,
+    );
+    let style = Style::default().fg(Color::Yellow);
+    if samples > inner.width as usize {
+        let envelope = orient_envelope(
+            &decibels,
+            samples,
+            inner.width as usize,
+            options.orientation,
+        );
+        f.render_widget(block, area);
+        f.render_widget(
+            EnvelopeSparkline {
+                data: &envelope,
+                invert: options.orientation.invert,
+                style,
+            },
+            inner,
+        );
+        // each column's loudest reading is its lowest dB
+        let loudest: Vec<f32> = envelope
+            .iter()
+            .map(|(lo, _)| *lo as f32)
+            .collect();
+        if let Some(position) = peak_dot(
+            &loudest,
+            inner.height,
+            options.orientation,
+        ) {
+            f.render_widget(PeakDot { position }, inner);
+        }
+        return;
+    }
+    let dbs = orient_decibels(
+        &decibels,
+        samples,
+        inner.width as usize,
+        options.orientation,
+    );
+
+    if options.orientation.invert {
+        f.render_widget(block, area);
+        f.render_widget(
+            HangingSparkline { data: &dbs, style },
+            inner,
+        );
+    } else {
+        f.render_widget(block, area);
+        f.render_widget(
+            FractionalSparkline { data: &dbs, style },
+            inner,
+        );
+    }
+    if let Some(position) =
+        peak_dot(&dbs, inner.height, options.orientation)
+    {
+        f.render_widget(PeakDot { position }, inner);
+    }
+}
+
+fn draw_bars<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+) {
+    let gains = s.band_gains.lock().unwrap().clone();
+    let colors: Vec<Color> = options
+        .band_colors
+        .iter()
+        .map(|color| options.color_depth.quantize(*color))
+        .collect();
+    f.render_widget(
+        band_chart(
+            &s.bands,
+            &s.freq_ranges,
+            &gains,
+            &tui_state.audible_bands(s.freq_ranges.len()),
+            tui_state.selected_band,
+            &colors,
+            area,
+        ),
+        area,
+    );
+}
+
+/// The live spectrum and peak hold over the waterfall, and
+/// the difference from the reference between them while
+/// one is frozen
+fn draw_waterfall<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+    reference: Option<&[f32]>,
+) {
+    let Some(spectrum) = s.spectrogram.front() else {
+        f.render_widget(waiting("waterfall"), area);
+        return;
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(40),
+                Constraint::Percentage(60),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    let trail = s
+        .persistence
+        .as_ref()
+        .map_or(&spectrum[..], PeakHold::levels);
+    let live = spectrum_points(
+        trail,
+        options.gamma,
+        options.level_scale,
+    );
+    let held = spectrum_points(
+        s.peak_hold.levels(),
+        options.gamma,
+        options.level_scale,
+    );
+    // a frozen reference takes over from the target curve
+    // until it's cleared
+    let target;
+    let (reference, names) =
+        match (reference, &options.target) {
+            (Some(reference), _) => (
+                reference,
+                (
+                    "reference",
+                    "live − reference (x clears)",
+                ),
+            ),
+            (None, Some(curve)) => {
+                target = curve.magnitudes(
+                    s.stats.sample_rate,
+                    spectrum,
+                );
+                (&target[..], ("target", "live − target"))
+            }
+            (None, None) => {
+                f.render_widget(
+                    spectrum_chart(&live, &held, &[], ""),
+                    chunks[0],
+                );
+                draw_waterfall_rows(
+                    f, chunks[1], s, options,
+                );
+                return;
+            }
+        };
+    let halves = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(60),
+                Constraint::Percentage(40),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[0]);
+    let frozen = spectrum_points(
+        reference,
+        options.gamma,
+        options.level_scale,
+    );
+    f.render_widget(
+        spectrum_chart(&live, &held, &frozen, names.0),
+        halves[0],
+    );
+    match spectrum_delta(spectrum, reference) {
+        Some(delta) => {
+            let range = DELTA_RANGE_DB as f64;
+            let delta: Vec<(f64, f64)> = delta
+                .iter()
+                .enumerate()
+                .map(|(bin, db)| {
+                    (bin as f64, (*db as f64).clamp(-range, range))
+                })
+                .collect();
+            let last = delta.len().saturating_sub(1) as f64;
+            let zero = [(0.0, 0.0), (last, 0.0)];
+            f.render_widget(
+                delta_chart(&delta, &zero, names.1),
+                halves[1],
+            );
+        }
+        None => f.render_widget(
+            Paragraph::new(
+                "the reference doesn't match the fft size, \
+                 f freezes a new one",
+            )
+            .alignment(Alignment::Center)
+            .block(
+                Block::default()
+                    .title("difference")
+                    .borders(Borders::LEFT | Borders::RIGHT),
+            ),
+            halves[1],
+        ),
+    }
+    draw_waterfall_rows(f, chunks[1], s, options);
+}
+
+/// The scrolling waterfall itself, under its title
+fn draw_waterfall_rows<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+) {
+    let waterfall_block = Block::default()
+        .title("waterfall")
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let waterfall_area = waterfall_block.inner(area);
+    f.render_widget(waterfall_block, area);
+    f.render_widget(
+        Waterfall {
+            spectrogram: &s.spectrogram,
+            colormap: options.colormap,
+            gamma: options.gamma,
+            scale: options.level_scale,
+            color_depth: options.color_depth,
+        },
+        waterfall_area,
+    );
+}
+
+/// How many spokes the radial view draws around the circle
+const RADIAL_SPOKES: usize = 96;
+
+/// The latest spectrum as spokes around a circle, 0Hz at
+/// the top going clockwise up to nyquist
+fn draw_radial<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+) {
+    let Some(spectrum) =
+        s.spectrogram.front().filter(|s| !s.is_empty())
+    else {
+        f.render_widget(waiting("radial"), area);
+        return;
+    };
+    let block = Block::default()
+        .title("radial")
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let inner = block.inner(area);
+    // cells are roughly twice as tall as they are wide,
+    // widen the x bounds to match so the circle stays round
+    let aspect = inner.width as f64
+        / (inner.height as f64 * 2.0).max(1.0);
+
+    let spokes: Vec<(f64, f64, Color)> = (0..RADIAL_SPOKES)
+        .map(|spoke| {
+            let start =
+                spoke * spectrum.len() / RADIAL_SPOKES;
+            let end = ((spoke + 1) * spectrum.len()
+                / RADIAL_SPOKES)
+                .max(start + 1)
+                .min(spectrum.len());
+            let magnitude = spectrum[start..end]
+                .iter()
+                .copied()
+                .fold(0.0, f32::max);
+            let level = options
+                .level_scale
+                .normalize(magnitude, spectrum.len());
+            let angle = spoke as f64 / RADIAL_SPOKES as f64
+                * std::f64::consts::TAU;
+            let radius = 0.1 + 0.9 * level as f64;
+            (
+                radius * angle.sin(),
+                radius * angle.cos(),
+                options.color_depth.quantize(
+                    options.colormap.sample(level),
+                ),
+            )
+        })
+        .collect();
+
+    let canvas = canvas::Canvas::default()
+        .block(block)
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-aspect, aspect])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+            for (x, y, color) in &spokes {
+                ctx.draw(&canvas::Line {
+                    x1: 0.0,
+                    y1: 0.0,
+                    x2: *x,
+                    y2: *y,
+                    color: *color,
+                });
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+/// How much of the recent past the level spent in each
+/// bin, quietest on the left, as a percentage of the window
+fn draw_histogram<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+) {
+    const BAR_GAP: u16 = 1;
+    let histogram = &s.level_histogram;
+    if histogram.total() == 0 {
+        f.render_widget(waiting("histogram"), area);
+        return;
+    }
+    let readout = Readout::new(
+        tui_state.units,
+        options.spl_calibrated,
+    );
+    let block = Block::default()
+        .title(format!(
+            "histogram ({}, last {:.0}s)",
+            readout.unit(),
+            histogram.total() as f32
+                / s.analysis_rate.max(1.0)
+        ))
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let inner = block.inner(area);
+    let count = histogram.counts().len() as u16;
+    let bar_width =
+        (inner.width.saturating_sub(BAR_GAP * (count - 1))
+            / count)
+            .max(1);
+    let total = histogram.total() as f32;
+    let bars: Vec<Bar> = histogram
+        .counts()
+        .iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let percent = *count as f32 / total * 100.0;
+            let (low, _) = histogram.edges(i);
+            Bar::default()
+                .style(Style::default().fg(Color::Yellow))
+                // tenths of a percent, BarChart only takes
+                // integers
+                .value((percent * 10.0).round() as u64)
+                .text_value(format!("{percent:.0}%"))
+                .label(Line::from(readout.mark(low)))
+        })
+        .collect();
+    f.render_widget(
+        BarChart::default()
+            .block(block)
+            .bar_width(bar_width)
+            .bar_gap(BAR_GAP)
+            .value_style(
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow),
+            )
+            .data(BarGroup::default().bars(&bars)),
+        area,
+    );
+}
+
+/// How wide each compared input's column is
+const DEVICE_COLUMN_WIDTH: u16 = 16;
+
+/// One of the inputs from `--compare-device`: its level as a
+/// meter with a gauge per band underneath. Its readings are
+/// always dBFS, the calibration is only for the main input.
+fn draw_device_column<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    device: &DeviceLevels,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+) {
+    let block = Block::default()
+        .title(device.name.as_str())
+        .borders(Borders::LEFT | Borders::RIGHT);
+    if let Some(reason) = &device.audio_stopped {
+        f.render_widget(
+            Paragraph::new(reason.as_str())
+                .wrap(Wrap { trim: true })
+                .block(block),
+            area,
+        );
+        return;
+    }
+    let Some(level) = device.level else {
+        f.render_widget(waiting(&device.name), area);
+        return;
+    };
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let mut constraints = vec![Constraint::Min(0)];
+    constraints.extend(
+        device.bands.iter().map(|_| Constraint::Length(1)),
+    );
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(inner);
+    f.render_widget(
+        LevelMeter {
+            level,
+            range: METER_DBFS,
+            ticks: &DBFS_TICKS,
+            references: &[],
+            readout: Readout::new(tui_state.units, false),
+        },
+        rows[0],
+    );
+    for (band, row) in device.bands.iter().zip(&rows[1..]) {
+        f.render_widget(
+            LineGauge::default()
+                .gauge_style(
+                    Style::default().fg(Color::Cyan),
+                )
+                .ratio(
+                    options.level_scale.normalize(
+                        *band,
+                        device.spectrum_len,
+                    ) as f64,
+                ),
+            *row,
+        );
+    }
+}
+
+/// The latest level as a gauge with one smaller gauge per
+/// band underneath
+fn draw_meter<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+) {
+    let Some(level) = s.decibels.last().copied() else {
+        f.render_widget(waiting("meter"), area);
+        return;
+    };
+    let readout = Readout::new(
+        tui_state.units,
+        options.spl_calibrated,
+    );
+    let (low, high) = readout.range;
+    let mut constraints =
+        vec![Constraint::Length(3), Constraint::Length(3)];
+    constraints.extend(
+        s.freq_ranges.iter().map(|_| Constraint::Length(2)),
+    );
+    constraints.push(Constraint::Min(0));
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+
+    let ratio = map_db_to_unit(level, low, high);
+    let meter_block = Block::default()
+        .title("meter")
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let gauge_area = meter_block.inner(rows[0]);
+    f.render_widget(
+        Gauge::default()
+            .block(meter_block)
+            .gauge_style(Style::default().fg(Color::Yellow))
+            .ratio(ratio as f64)
+            .label(readout.format(level)),
+        rows[0],
+    );
+    f.render_widget(
+        ReferenceMarks {
+            references: &options.reference_levels,
+            range: (low, high),
+        },
+        gauge_area,
+    );
+    f.render_widget(
+        Gauge::default()
+            .block(
+                Block::default().title("activity").borders(
+                    Borders::LEFT | Borders::RIGHT,
+                ),
+            )
+            .gauge_style(
+                Style::default().fg(Color::Magenta),
+            )
+            .ratio(s.activity.clamp(0.0, 1.0) as f64)
+            .label(format!("{:.2}", s.activity)),
+        rows[1],
+    );
+
+    // bands are only meaningful once a spectrum exists to
+    // scale them against
+    let spectrum_len =
+        s.spectrogram.front().map_or(0, |s| s.len());
+    if spectrum_len == 0 {
+        return;
+    }
+    let audible =
+        tui_state.audible_bands(s.freq_ranges.len());
+    for (i, (range, energy)) in
+        s.freq_ranges.iter().zip(&s.bands).enumerate()
+    {
+        let energy = if audible[i] { *energy } else { 0.0 };
+        f.render_widget(
+            LineGauge::default()
+                .block(Block::default().borders(
+                    Borders::LEFT | Borders::RIGHT,
+                ))
+                .gauge_style(
+                    Style::default().fg(Color::Cyan),
+                )
+                .label(range.name.to_string())
+                .ratio(
+                    options
+                        .level_scale
+                        .normalize(energy, spectrum_len)
+                        as f64,
+                ),
+            rows[i + 2],
+        );
+    }
+}
+
+/// Where a (left, right) sample pair lands on the
+/// vectorscope. The axes are turned 45° so mid (L + R) / 2
+/// is up and side (R - L) / 2 is across: mono draws a
+/// vertical line, out of phase channels a horizontal one.
+/// Halving keeps full scale pairs inside the ±1 bounds.
+pub fn vectorscope_point(
+    left: f32,
+    right: f32,
+) -> (f64, f64) {
+    (
+        (right - left) as f64 * 0.5,
+        (left + right) as f64 * 0.5,
+    )
+}
+
+/// Most sample pairs the vectorscope plots per frame, more
+/// than this and braille cells just fill in solid
+const VECTORSCOPE_POINTS: usize = 2048;
+
+/// The quietest peak the waveform view scales up to fill the
+/// height, so silence doesn't get blown up into noise
+pub const WAVEFORM_MIN_SCALE: f32 = 0.001;
+
+/// Where a sample lands vertically on the waveform canvas:
+/// 0 in the middle, `scale` and `-scale` at the top and
+/// bottom edges. Anything beyond them is clipped to the
+/// edge rather than drawn off the canvas.
+pub fn waveform_y(sample: f32, scale: f32) -> f64 {
+    if scale <= 0.0 || !sample.is_finite() {
+        return 0.0;
+    }
+    (sample / scale).clamp(-1.0, 1.0) as f64
+}
+
+/// Splits `samples` into at most `columns` runs and gives
+/// the lowest and highest sample of each, so nothing is
+/// skipped when there are more samples than columns. Each
+/// run's range is stretched to include the sample before
+/// it, so neighbouring columns join up.
+pub fn waveform_columns(
+    samples: &[f32],
+    columns: usize,
+) -> Vec<(f32, f32)> {
+    let runs = samples.len().min(columns);
+    let mut previous: Option<f32> = None;
+    (0..runs)
+        .map(|run| {
+            let start = run * samples.len() / runs;
+            let end = (run + 1) * samples.len() / runs;
+            let run = &samples[start..end];
+            let (low, high) = run.iter().fold(
+                previous.map_or(
+                    (f32::INFINITY, f32::NEG_INFINITY),
+                    |p| (p, p),
+                ),
+                |(low, high), sample| {
+                    (low.min(*sample), high.max(*sample))
+                },
+            );
+            previous = run.last().copied();
+            (low, high)
+        })
+        .collect()
+}
+
+/// How far either side of in tune the tuner's scale goes,
+/// in cents: half a semitone, as far as a note can be off
+/// before it's nearer the next one
+const TUNER_RANGE_CENTS: f32 = 50.0;
+
+/// Cents off that still count as in tune, and as close
+const IN_TUNE_CENTS: f32 = 5.0;
+const CLOSE_CENTS: f32 = 15.0;
+
+/// The column the tuner's needle points at for `cents` off,
+/// across a scale `width` columns wide with in tune in the
+/// middle
+pub fn needle_column(cents: f32, width: u16) -> u16 {
+    let last = width.saturating_sub(1) as f32;
+    let ratio = (cents / TUNER_RANGE_CENTS * 0.5 + 0.5)
+        .clamp(0.0, 1.0);
+    (ratio * last).round() as u16
+}
+
+/// A guitar tuner's scale from flat on the left to sharp on
+/// the right, marked every 10 cents, with a needle at
+/// `cents`
+struct TunerScale {
+    cents: f32,
+    color: Color,
+}
+
+impl Widget for TunerScale {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.height < 2 || area.width < 3 {
+            return;
+        }
+        let dim = Style::default().fg(Color::DarkGray);
+        for mark in -5..=5 {
+            let x = area.left()
+                + needle_column(
+                    mark as f32 * 10.0,
+                    area.width,
+                );
+            let symbol =
+                if mark == 0 { "┃" } else { "│" };
+            buf.get_mut(x, area.top())
+                .set_symbol(symbol)
+                .set_style(dim);
+        }
+        let x = area.left()
+            + needle_column(self.cents, area.width);
+        buf.get_mut(x, area.top() + 1)
+            .set_symbol("▲")
+            .set_style(Style::default().fg(self.color));
+        let labels = [
+            (area.left(), "-50"),
+            (area.left() + area.width / 2, "0"),
+            (area.right().saturating_sub(3), "+50"),
+        ];
+        if area.height >= 3 {
+            for (x, label) in labels {
+                buf.set_string(
+                    x,
+                    area.top() + 2,
+                    label,
+                    dim,
+                );
+            }
+        }
+    }
+}
+
+/// The nearest note to the loudest frequency, how many
+/// cents off it is and a needle showing which way to tune
+fn draw_tuner<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    options: &DisplayOptions,
+) {
+    let title =
+        format!("tuner, A4 = {:.1} Hz", options.a4_hz);
+    let Some((hz, (note, octave, cents))) =
+        s.peak_hz.and_then(|hz| {
+            Some((hz, freq_to_note(hz, options.a4_hz)?))
+        })
+    else {
+        f.render_widget(waiting(&title), area);
+        return;
+    };
+    let color = if cents.abs() <= IN_TUNE_CENTS {
+        Color::Green
+    } else if cents.abs() <= CLOSE_CENTS {
+        Color::Yellow
+    } else {
+        Color::Red
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::LEFT | Borders::RIGHT);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Min(0),
+        ])
+        .split(inner);
+    f.render_widget(
+        Paragraph::new(vec![
+            Line::styled(
+                format!("{note}{octave}"),
+                Style::default()
+                    .fg(color)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Line::from(format!(
+                "{cents:+.0} cents, {hz:.1} Hz"
+            )),
+        ])
+        .alignment(Alignment::Center),
+        rows[1],
+    );
+    // a bit in from the sides so the needle has room and
+    // the scale stays readable on a wide terminal
+    let scale = centered_rect(
+        rows[2].width.saturating_sub(4).min(61),
+        rows[2].height,
+        rows[2],
+    );
+    f.render_widget(TunerScale { cents, color }, scale);
+}
+
+fn draw_waveform<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+    units: DisplayUnits,
+) {
+    if s.waveform.is_empty() {
+        f.render_widget(waiting("waveform"), area);
+        return;
+    }
+    let peak =
+        s.waveform.iter().fold(0.0f32, |peak, sample| {
+            peak.max(sample.abs())
+        });
+    let scale = peak.max(WAVEFORM_MIN_SCALE);
+    let block = Block::default()
+        .title(format!(
+            "waveform, ±{}",
+            // samples are always relative to full scale
+            Readout::new(units, false)
+                .format(20.0 * scale.log10())
+        ))
+        .borders(Borders::LEFT | Borders::RIGHT);
+    // braille packs two dots across each cell
+    let columns = block.inner(area).width as usize * 2;
+    let columns = waveform_columns(&s.waveform, columns);
+    let right =
+        columns.len().saturating_sub(1).max(1) as f64;
+
+    let canvas = canvas::Canvas::default()
+        .block(block)
+        .marker(symbols::Marker::Braille)
+        .x_bounds([0.0, right])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+            ctx.draw(&canvas::Line {
+                x1: 0.0,
+                y1: 0.0,
+                x2: right,
+                y2: 0.0,
+                color: Color::DarkGray,
+            });
+            ctx.layer();
+            for (x, (low, high)) in
+                columns.iter().enumerate()
+            {
+                ctx.draw(&canvas::Line {
+                    x1: x as f64,
+                    y1: waveform_y(*low, scale),
+                    x2: x as f64,
+                    y2: waveform_y(*high, scale),
+                    color: Color::Green,
+                });
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+fn draw_vectorscope<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    s: &AppState,
+) {
+    let block = Block::default()
+        .title("vectorscope")
+        .borders(Borders::LEFT | Borders::RIGHT);
+    if s.stereo.is_empty() {
+        f.render_widget(
+            Paragraph::new("needs a stereo input")
+                .alignment(Alignment::Center)
+                .block(block),
+            area,
+        );
+        return;
+    }
+    // the width meter goes along the bottom, under the
+    // scope
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .split(area);
+    let (area, meter) = (rows[0], rows[1]);
+    f.render_widget(
+        LineGauge::default()
+            .block(
+                Block::default().borders(
+                    Borders::LEFT | Borders::RIGHT,
+                ),
+            )
+            .gauge_style(Style::default().fg(Color::Cyan))
+            .label(format!(
+                "width {:>3.0}% mono→wide",
+                s.stereo_width * 100.0
+            ))
+            .ratio(s.stereo_width.clamp(0.0, 1.0) as f64),
+        meter,
+    );
+    let inner = block.inner(area);
+    // same trick as the radial view to keep it square
+    let aspect = inner.width as f64
+        / (inner.height as f64 * 2.0).max(1.0);
+    let stride =
+        s.stereo.len().div_ceil(VECTORSCOPE_POINTS).max(1);
+    let points: Vec<(f64, f64)> = s
+        .stereo
+        .iter()
+        .step_by(stride)
+        .map(|(left, right)| {
+            vectorscope_point(*left, *right)
+        })
+        .collect();
+
+    let canvas = canvas::Canvas::default()
+        .block(block)
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-aspect, aspect])
+        .y_bounds([-1.0, 1.0])
+        .paint(|ctx| {
+            ctx.draw(&canvas::Line {
+                x1: 0.0,
+                y1: -1.0,
+                x2: 0.0,
+                y2: 1.0,
+                color: Color::DarkGray,
+            });
+            ctx.draw(&canvas::Line {
+                x1: -aspect,
+                y1: 0.0,
+                x2: aspect,
+                y2: 0.0,
+                color: Color::DarkGray,
+            });
+            ctx.layer();
+            ctx.draw(&canvas::Points {
+                coords: &points,
+                color: Color::Green,
+            });
+        });
+    f.render_widget(canvas, area);
+}
+
+fn ui<B: Backend>(
+    f: &mut Frame<B>,
+    s: &AppState,
+    options: &DisplayOptions,
+    tui_state: &TuiState,
+    trigger: Option<&Trigger>,
+) {
+    let root = options.region.map_or(f.size(), |region| {
+        clamp_region(region, f.size())
+    });
+    let (min_width, min_height) = options.min_size;
+    if root.width < min_width || root.height < min_height {
+        draw_too_small(f, root, options.min_size);
+        return;
+    }
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Min(0),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(root);
+    f.render_widget(
+        Paragraph::new(status_bar(
+            &options.device,
+            &s.stats,
+            tui_state.fps,
+            chunks[2].width as usize,
+        ))
+        .style(Style::default().fg(Color::DarkGray)),
+        chunks[2],
+    );
+
+    let titles = VizMode::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, mode)| {
+            Line::from(format!(
+                "{} {}",
+                i + 1,
+                mode.title()
+            ))
+        })
+        .collect();
+    f.render_widget(
+        Tabs::new(titles)
+            .select(tui_state.mode.index())
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        chunks[0],
+    );
+
+    let mut status = vec![];
+    let talking = match (
+        tui_state.talking_threshold_db,
+        s.decibels.last(),
+    ) {
+        (Some(threshold), Some(level)) => {
+            *level >= threshold
+                && (!options.speech_gate
+                    || s.speech >= SPEECH_THRESHOLD)
+        }
+        _ => false,
+    };
+    if talking {
+        status.push(Span::styled(
+            "● talking",
+            Style::default()
+                .fg(Color::Green)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    if tui_state.paused {
+        if !status.is_empty() {
+            status.push(Span::raw("  "));
+        }
+        status.push(Span::styled(
+            "⏸ paused (space resumes)",
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    if let Some(trigger) = trigger {
+        if !status.is_empty() {
+            status.push(Span::raw("  "));
+        }
+        let color = if trigger.frozen().is_some() {
+            Color::Red
+        } else {
+            Color::Yellow
+        };
+        status.push(Span::styled(
+            trigger.status(),
+            Style::default().fg(color),
+        ));
+    }
+    let denoise = {
+        let noise_profile = s.noise_profile.lock().unwrap();
+        if noise_profile.is_learning() {
+            Some("learning noise…")
+        } else if noise_profile.profile().is_some() {
+            Some("denoise on (n relearns)")
+        } else {
+            None
+        }
+    };
+    if let Some(bpm) = tui_state.bpm {
+        if !status.is_empty() {
+            status.push(Span::raw("  "));
+        }
+        status.push(Span::styled(
+            format!("♩ {bpm:.0} bpm"),
+            Style::default().fg(Color::Green),
+        ));
+    }
+    if let Some(hz) = s.peak_hz {
+        if !status.is_empty() {
+            status.push(Span::raw("  "));
+        }
+        status.push(Span::styled(
+            format!("♪ {hz:.1} Hz"),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    if let Some(denoise) = denoise {
+        if !status.is_empty() {
+            status.push(Span::raw("  "));
+        }
+        status.push(Span::styled(
+            denoise,
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    f.render_widget(
+        Paragraph::new(Line::from(status))
+            .alignment(Alignment::Right),
+        chunks[0],
+    );
+
+    // the compared inputs each take a column on the right
+    let area = match s.devices.len() {
+        0 => chunks[1],
+        devices => {
+            let mut constraints = vec![Constraint::Min(0)];
+            constraints.extend((0..devices).map(|_| {
+                Constraint::Length(DEVICE_COLUMN_WIDTH)
+            }));
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(constraints)
+                .split(chunks[1]);
+            for (device, column) in
+                s.devices.iter().zip(&columns[1..])
+            {
+                draw_device_column(
+                    f, *column, device, options, tui_state,
+                );
+            }
+            columns[0]
+        }
+    };
+    // a region only tall enough for the tabs leaves nothing
+    // to draw into, and the canvases can't cope with that
+    let mode = (area.area() > 0).then_some(tui_state.mode);
+    match mode {
+        Some(VizMode::Sparkline) => {
+            draw_sparkline(f, area, s, options, tui_state)
+        }
+        Some(VizMode::Bars) => {
+            draw_bars(f, area, s, options, tui_state)
+        }
+        Some(VizMode::Waterfall) => draw_waterfall(
+            f,
+            area,
+            s,
+            options,
+            tui_state.reference.as_deref(),
+        ),
+        Some(VizMode::Radial) => {
+            draw_radial(f, area, s, options)
+        }
+        Some(VizMode::Meter) => {
+            draw_meter(f, area, s, options, tui_state)
+        }
+        Some(VizMode::Waveform) => {
+            draw_waveform(f, area, s, tui_state.units)
+        }
+        Some(VizMode::Vectorscope) => {
+            draw_vectorscope(f, area, s)
+        }
+        Some(VizMode::Histogram) => {
+            draw_histogram(f, area, s, options, tui_state)
+        }
+        Some(VizMode::Tuner) => {
+            draw_tuner(f, area, s, options)
+        }
+        None => {}
+    }
+    if let Some(color) = options.beat_flash {
+        let level = tui_state.flash.level();
+        if level > 0.0 {
+            f.render_widget(
+                BackgroundFlash {
+                    color: options.color_depth.quantize(
+                        flash_color(color, level),
+                    ),
+                },
+                root,
+            );
+        }
+    }
+
+    if tui_state.show_stats {
+        draw_stats(f, area, &s.stats, s.analysis_rate);
+    }
+    // the render loop clears these once they've been up
+    // long enough
+    if let Some((warning, _)) = &tui_state.rate_warning {
+        draw_notice(f, area, warning);
+    }
+    if let Some((notice, _)) = &tui_state.notice {
+        draw_notice(f, area, notice);
+    }
+    if let Some(reason) = &s.audio_stopped {
+        draw_banner(f, area, reason);
+    } else if tui_state.disconnected {
+        draw_disconnected(f, area);
+    }
+    if let Some(wizard) = &tui_state.wizard {
+        draw_wizard(
+            f,
+            area,
+            wizard,
+            s.decibels.last().copied(),
+            options,
+            tui_state.units,
+            tui_state.wizard_error.as_deref(),
+        );
+    }
+    let notice = s.stream_notice.lock().unwrap();
+    if let Some(notice) =
+        visible_notice(&notice, Instant::now())
+    {
+        draw_notice(f, area, notice);
+    }
+    if tui_state.show_help {
+        draw_help(f, root, &options.keymap);
+    }
+}
+
+/// The wizard's instructions for its current step
+pub fn wizard_lines(
+    wizard: &Wizard,
+    readout: &Readout,
+    can_save: bool,
+) -> Vec<String> {
+    match wizard.step() {
+        WizardStep::SilencePrompt => vec![
+            "Step 1 of 2: silence".to_string(),
+            "Stay quiet and let the room settle,"
+                .to_string(),
+            "then press space to measure it.".to_string(),
+        ],
+        WizardStep::MeasuringSilence { .. } => vec![
+            "Step 1 of 2: silence".to_string(),
+            "Measuring, keep quiet…".to_string(),
+        ],
+        WizardStep::SpeechPrompt { retry, .. } => {
+            let mut lines =
+                vec!["Step 2 of 2: speech".to_string()];
+            if *retry {
+                lines.push(
+                    "That wasn't louder than the silence."
+                        .to_string(),
+                );
+            }
+            lines.push(
+                "Press space, then talk the way you"
+                    .to_string(),
+            );
+            lines.push(
+                "normally would until it's done."
+                    .to_string(),
+            );
+            lines
+        }
+        WizardStep::MeasuringSpeech { .. } => vec![
+            "Step 2 of 2: speech".to_string(),
+            "Measuring, keep talking…".to_string(),
+        ],
+        WizardStep::Review(thresholds) => vec![
+            format!(
+                "noise floor  {}",
+                readout.format(thresholds.noise_floor_db)
+            ),
+            format!(
+                "gate         {}",
+                readout.format(thresholds.gate_db)
+            ),
+            format!(
+                "talking      {}",
+                readout.format(
+                    thresholds.talking_threshold_db
+                )
+            ),
+            if can_save {
+                "Enter saves to the config, esc cancels."
+                    .to_string()
+            } else {
+                "No config to save to, esc closes."
+                    .to_string()
+            },
+        ],
+    }
+}
+
+fn draw_wizard<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    wizard: &Wizard,
+    level: Option<f32>,
+    options: &DisplayOptions,
+    units: DisplayUnits,
+    error: Option<&str>,
+) {
+    let readout =
+        Readout::new(units, options.spl_calibrated);
+    let mut lines: Vec<Line> = wizard_lines(
+        wizard,
+        &readout,
+        options.config_path.is_some(),
+    )
+    .into_iter()
+    .map(Line::from)
+    .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(match level {
+        Some(level) => {
+            format!(
+                "level        {}",
+                readout.format(level)
+            )
+        }
+        None => {
+            "level        waiting for audio".to_string()
+        }
+    }));
+    if let Some(progress) = wizard.progress() {
+        const WIDTH: usize = 30;
+        let filled = (progress * WIDTH as f32) as usize;
+        lines.push(Line::from(format!(
+            "{}{} {:.0}%",
+            "█".repeat(filled),
+            "░".repeat(WIDTH - filled.min(WIDTH)),
+            progress * 100.0
+        )));
+    }
+    if let Some(error) = error {
+        lines.push(Line::styled(
+            format!("could not save: {error}"),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    let width = lines
+        .iter()
+        .map(|line| line.width())
+        .max()
+        .unwrap_or(0) as u16
+        // borders and a space either side
+        + 4;
+    let popup =
+        centered_rect(width, lines.len() as u16 + 2, area);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(lines).block(
+            Block::default()
+                .title("setup (esc cancels)")
+                .borders(Borders::ALL)
+                .padding(Padding::horizontal(1)),
+        ),
+        popup,
+    );
+}
+
+/// Says the terminal is too small for the views, centered
+/// in `area`. It's wrapped to fit, and cut down to just the
+/// size needed when even that doesn't fit.
+fn draw_too_small<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    (width, height): (u16, u16),
+) {
+    if area.width == 0 || area.height == 0 {
+        return;
+    }
+    let lines_for = |message: &str| {
+        (message.chars().count() as u16)
+            .div_ceil(area.width)
+    };
+    let message = [
+        format!(
+            "terminal too small (need {width}x{height})"
+        ),
+        format!("too small, need {width}x{height}"),
+        format!("need {width}x{height}"),
+    ]
+    .into_iter()
+    .find(|message| lines_for(message) <= area.height)
+    .unwrap_or_else(|| format!("{width}x{height}"));
+    let lines = lines_for(&message).min(area.height);
+    let text = Rect {
+        y: area.y + (area.height - lines) / 2,
+        height: lines,
+        ..area
+    };
+    f.render_widget(
+        Paragraph::new(message)
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true }),
+        text,
+    );
+}
+
+/// Dims whatever `area` last showed and puts a banner over
+/// it, until frames start arriving again
+fn draw_disconnected<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+) {
+    f.render_widget(
+        Block::default().style(
+            Style::default().add_modifier(Modifier::DIM),
+        ),
+        area,
+    );
+    let message = "DISCONNECTED, waiting for audio…";
+    let popup = centered_rect(
+        message.chars().count() as u16 + 4,
+        3,
+        area,
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(message)
+            .alignment(Alignment::Center)
+            .style(
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(Block::default().borders(Borders::ALL)),
+        popup,
+    );
+}
+
+/// A red box across the middle of `area` for failures the
+/// display can't recover from
+fn draw_banner<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    reason: &str,
+) {
+    let message = format!("{reason}, press q to quit");
+    let popup = centered_rect(
+        message.chars().count() as u16 + 4,
+        3,
+        area,
+    );
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(message)
+            .alignment(Alignment::Center)
+            .style(
+                Style::default()
+                    .fg(Color::White)
+                    .bg(Color::Red)
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(Block::default().borders(Borders::ALL)),
+        popup,
+    );
+}
+
+/// A `width` by `height` rect in the middle of `area`,
+/// shrunk to fit if `area` is smaller
+pub fn centered_rect(
+    width: u16,
+    height: u16,
+    area: Rect,
+) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// The status bar's text, at most `width` characters: the
+/// input, its sample rate, the fft size and the redraw
+/// rate actually `measured_fps`. The device name is cut
+/// short to make room for the rest.
+pub fn status_bar(
+    device: &str,
+    stats: &AnalysisStats,
+    measured_fps: f32,
+    width: usize,
+) -> String {
+    let config = if stats.sample_rate == 0 {
+        "no audio yet".to_string()
+    } else {
+        format!(
+            "{} Hz │ fft {}",
+            stats.sample_rate, stats.fft_size
+        )
+    };
+    let rest =
+        format!(" │ {config} │ {measured_fps:.0} fps");
+    let room = width.saturating_sub(rest.chars().count());
+    let device = if device.chars().count() <= room {
+        device.to_string()
+    } else if room == 0 {
+        String::new()
+    } else {
+        device.chars().take(room - 1).chain(['…']).collect()
+    };
+    format!("{device}{rest}").chars().take(width).collect()
+}
+
+/// The stats panel's lines, with what the analysis rate
+/// should be next to what was `measured_hz`
+pub fn stats_lines(
+    stats: &AnalysisStats,
+    measured_hz: f32,
+) -> Vec<String> {
+    let percent = |fraction: f32| fraction * 100.0;
+    vec![
+        format!("resolution  {:.1} Hz", stats.resolution_hz),
+        format!(
+            "bin width   {:.1} Hz",
+            stats.bin_bandwidth_hz
+        ),
+        format!(
+            "window      {:.1} ms",
+            stats.window_seconds * 1000.0
+        ),
+        format!("hop         {} samples", stats.hop),
+        format!("overlap     {:.0}%", percent(stats.overlap)),
+        format!(
+            "padding     {:.0}%",
+            percent(stats.zero_padding)
+        ),
+        format!("dropped     {:.0}%", percent(stats.dropped)),
+        format!(
+            "update      {:.1} Hz ({measured_hz:.1} measured)",
+            stats.update_hz
+        ),
+    ]
+}
+
+/// The stats panel, in the top right corner of `area`
+fn draw_stats<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    stats: &AnalysisStats,
+    measured_hz: f32,
+) {
+    let lines = stats_lines(stats, measured_hz);
+    let width = (lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as u16
+        // borders and a space either side
+        + 4)
+    .min(area.width);
+    let height = (lines.len() as u16 + 2).min(area.height);
+    let panel = Rect {
+        x: area.x + area.width - width,
+        y: area.y,
+        width,
+        height,
+    };
+    f.render_widget(Clear, panel);
+    f.render_widget(
+        Paragraph::new(
+            lines
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<_>>(),
+        )
+        .block(
+            Block::default()
+                .title("analysis (d closes)")
+                .borders(Borders::ALL)
+                .padding(Padding::horizontal(1)),
+        ),
+        panel,
+    );
+}
+
+fn draw_help<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    keymap: &KeyMap,
+) {
+    let lines = help_lines(keymap);
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as u16
+        // borders and a space either side
+        + 4;
+    let popup =
+        centered_rect(width, lines.len() as u16 + 2, area);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Paragraph::new(
+            lines
+                .into_iter()
+                .map(Line::from)
+                .collect::<Vec<_>>(),
+        )
+        .block(
+            Block::default()
+                .title("keys (? or esc closes)")
+                .borders(Borders::ALL)
+                .padding(Padding::horizontal(1)),
+        ),
+        popup,
+    );
+}
+
+/// A line of yellow along the bottom of `area` for advice
+/// that goes away by itself
+fn draw_notice<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    message: &str,
+) {
+    if area.height == 0 {
+        return;
+    }
+    let line = Rect {
+        y: area.bottom() - 1,
+        height: 1,
+        ..area
+    };
+    f.render_widget(Clear, line);
+    f.render_widget(
+        Paragraph::new(message)
+            .alignment(Alignment::Center)
+            .style(
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow),
+            ),
+        line,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{default_bands, AnalysisFrame};
+
+    /// Renders `widget` into a fresh buffer the size of
+    /// `width` by `height`
+    fn render(
+        widget: impl Widget,
+        width: u16,
+        height: u16,
+    ) -> Buffer {
+        let area = Rect::new(0, 0, width, height);
+        let mut buf = Buffer::empty(area);
+        widget.render(area, &mut buf);
+        buf
+    }
+
+    /// Each row of `buf`, top first
+    fn rows(buf: &Buffer) -> Vec<String> {
+        (0..buf.area.height)
+            .map(|y| {
+                (0..buf.area.width)
+                    .map(|x| buf.get(x, y).symbol.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mirroring_flips_the_sparkline() {
+        // oldest first, the newest (-40) is the tallest
+        let decibels = [-10.0, -20.0, -30.0, -40.0];
+        let mirrored = Orientation {
+            mirror: true,
+            invert: false,
+        };
+        let normal_data = orient_decibels(
+            &decibels,
+            4,
+            4,
+            Orientation::default(),
+        );
+        let mirrored_data =
+            orient_decibels(&decibels, 4, 4, mirrored);
+        assert_eq!(normal_data, [10.0, 20.0, 30.0, 40.0]);
+        assert_eq!(mirrored_data, [40.0, 30.0, 20.0, 10.0]);
+
+        let style = Style::default();
+        let normal = rows(&render(
+            FractionalSparkline {
+                data: &normal_data,
+                style,
+            },
+            4,
+            4,
+        ));
+        let mirrored = rows(&render(
+            FractionalSparkline {
+                data: &mirrored_data,
+                style,
+            },
+            4,
+            4,
+        ));
+        assert_eq!(
+            normal,
+            ["   █", "  ██", " ███", "████"]
+        );
+        assert_eq!(
+            mirrored,
+            ["█   ", "██  ", "███ ", "████"]
+        );
+
+        let hanging = rows(&render(
+            HangingSparkline {
+                data: &normal_data,
+                style,
+            },
+            4,
+            4,
+        ));
+        assert_eq!(
+            hanging,
+            ["████", " ███", "  ██", "   █"]
+        );
+    }
+
+    #[test]
+    fn a_short_history_keeps_the_newest_on_the_edge() {
+        let decibels = [-10.0, -20.0];
+        assert_eq!(
+            orient_decibels(
+                &decibels,
+                4,
+                4,
+                Orientation::default()
+            ),
+            [0.0, 0.0, 10.0, 20.0]
+        );
+        assert_eq!(
+            orient_decibels(
+                &decibels,
+                4,
+                4,
+                Orientation {
+                    mirror: true,
+                    invert: false
+                }
+            ),
+            [20.0, 10.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn band_labels_shorten_to_fit() {
+        let bass = FreqRange {
+            name: "Bass".into(),
+            low: 20,
+            high: 140,
+        };
+        let treble = FreqRange {
+            name: "Treble".into(),
+            low: 6000,
+            high: 20000,
+        };
+        assert_eq!(band_label(&bass, 20), "Bass 20–140");
+        assert_eq!(band_label(&bass, 11), "Bass 20–140");
+        assert_eq!(band_label(&bass, 10), "20–140");
+        assert_eq!(band_label(&bass, 5), "Bass");
+        assert_eq!(band_label(&bass, 2), "Ba");
+        assert_eq!(
+            band_label(&treble, 20),
+            "Treble 6k–20k"
+        );
+        assert_eq!(band_label(&treble, 6), "6k–20k");
+        assert_eq!(format_hz(2600), "2.6k");
+    }
+
+    #[test]
+    fn the_poll_timeout_never_drops_below_the_minimum() {
+        for fps in [
+            0.5,
+            1.0,
+            30.0,
+            999.0,
+            1000.0,
+            1e6,
+            f32::MAX,
+            f32::INFINITY,
+            0.0,
+            -60.0,
+            f32::NAN,
+        ] {
+            let timeout = poll_timeout(fps);
+            assert!(
+                (MIN_POLL_TIMEOUT..=MAX_POLL_TIMEOUT)
+                    .contains(&timeout),
+                "{fps}: {timeout:?}"
+            );
+        }
+        assert_eq!(poll_timeout(1e6), MIN_POLL_TIMEOUT);
+        assert_eq!(
+            poll_timeout(50.0),
+            Duration::from_millis(20)
+        );
+        assert_eq!(poll_timeout(0.0), MAX_POLL_TIMEOUT);
+    }
+
+    #[test]
+    fn gain_steps_add_in_db_and_stay_in_range() {
+        let db = |gain: f32| 20.0 * gain.log10();
+        let up = step_gain(1.0, 3);
+        assert!((db(up) - 3.0 * GAIN_STEP_DB).abs() < 1e-4);
+        assert!((db(step_gain(up, -3))).abs() < 1e-4);
+        // a muted band comes back up from the floor
+        let unmuted = step_gain(0.0, 1);
+        assert!(
+            (db(unmuted) - (MIN_GAIN_DB + GAIN_STEP_DB))
+                .abs()
+                < 1e-3
+        );
+        assert!(
+            (db(step_gain(1.0, -1000)) - MIN_GAIN_DB).abs()
+                < 1e-3
+        );
+        assert!(
+            (db(step_gain(1.0, 1000)) - MAX_GAIN_DB).abs()
+                < 1e-3
+        );
+    }
+
+    #[test]
+    fn seconds_per_screen_sets_the_history_length() {
+        assert_eq!(history_len(10.0, 46.875), Some(469));
+        assert_eq!(history_len(5.0, 60.0), Some(300));
+        assert_eq!(history_len(0.5, 30.0), Some(15));
+        assert_eq!(history_len(1.0, 0.7), None);
+        // the rate isn't known before the first frames
+        assert_eq!(history_len(10.0, 0.0), None);
+        assert_eq!(history_len(10.0, f32::NAN), None);
+        assert_eq!(history_len(0.0, 60.0), None);
+    }
+
+    #[test]
+    fn notices_go_away_after_a_while() {
+        let posted = Instant::now();
+        let notice = Some((
+            "reopened the input".to_string(),
+            posted,
+        ));
+        assert_eq!(
+            visible_notice(&notice, posted),
+            Some("reopened the input")
+        );
+        assert_eq!(
+            visible_notice(
+                &notice,
+                posted + NOTICE_DURATION
+            ),
+            None
+        );
+        assert_eq!(visible_notice(&None, posted), None);
+    }
+
+    #[test]
+    fn viz_modes_cycle_both_ways_and_select_by_digit() {
+        let mut mode = VizMode::default();
+        assert_eq!(mode, VizMode::Sparkline);
+        for expected in
+            VizMode::ALL.iter().cycle().skip(1).take(10)
+        {
+            mode = mode.next();
+            assert_eq!(mode, *expected);
+        }
+        assert_eq!(
+            VizMode::Sparkline.previous(),
+            *VizMode::ALL.last().unwrap()
+        );
+        for mode in VizMode::ALL {
+            assert_eq!(mode.next().previous(), mode);
+        }
+        assert_eq!(
+            VizMode::from_digit('1'),
+            Some(VizMode::Sparkline)
+        );
+        assert_eq!(
+            VizMode::from_digit('5'),
+            Some(VizMode::Meter)
+        );
+        assert_eq!(VizMode::from_digit('0'), None);
+        assert_eq!(VizMode::from_digit('x'), None);
+    }
+
+    #[test]
+    fn levels_map_onto_the_meter_and_its_ticks() {
+        assert_eq!(map_db_to_unit(-30.0, -60.0, 0.0), 0.5);
+        assert_eq!(map_db_to_unit(-90.0, -60.0, 0.0), 0.0);
+        assert_eq!(map_db_to_unit(3.0, -60.0, 0.0), 1.0);
+        // silence
+        assert_eq!(
+            map_db_to_unit(f32::NEG_INFINITY, -60.0, 0.0),
+            0.0
+        );
+
+        let rows_of = |height| {
+            DBFS_TICKS.map(|db| {
+                let (low, high) = METER_DBFS;
+                tick_row(
+                    map_db_to_unit(db, low, high),
+                    height,
+                )
+            })
+        };
+        assert_eq!(rows_of(13), [12, 8, 4, 1, 0]);
+        assert_eq!(rows_of(1), [0; 5]);
+
+        let meter = LevelMeter {
+            level: -30.0,
+            range: METER_DBFS,
+            ticks: &DBFS_TICKS,
+            references: &[],
+            readout: Readout::new(
+                DisplayUnits::Decibels,
+                false,
+            ),
+        };
+        let rows = rows(&render(meter, 6, 13));
+        for (row, label) in [
+            (12, "-60"),
+            (8, "-40"),
+            (4, "-20"),
+            (1, " -6"),
+            (0, "  0"),
+        ] {
+            assert!(
+                rows[row].starts_with(label),
+                "{rows:?}"
+            );
+        }
+        // half way up is half of the 13 rows, rounded
+        let filled = rows
+            .iter()
+            .filter(|row| {
+                row.ends_with(symbols::block::FULL)
+            })
+            .count();
+        assert_eq!(filled, 7);
+    }
+
+    #[test]
+    fn the_envelope_keeps_a_spikes_extremes() {
+        // 40 readings into 4 columns, one spike each way
+        let mut decibels = vec![-20.0; 40];
+        decibels[3] = -80.0;
+        decibels[33] = -1.0;
+        let columns = orient_envelope(
+            &decibels,
+            40,
+            4,
+            Orientation::default(),
+        );
+        // oldest on the left
+        assert_eq!(
+            columns,
+            [(20, 80), (20, 20), (20, 20), (1, 20)]
+        );
+        let mirrored = orient_envelope(
+            &decibels,
+            40,
+            4,
+            Orientation {
+                mirror: true,
+                invert: false,
+            },
+        );
+        assert_eq!(mirrored[0], (1, 20));
+        assert_eq!(mirrored[3], (20, 80));
+    }
+
+    #[test]
+    fn silence_in_the_envelope_reads_as_zero() {
+        let decibels =
+            [f32::NEG_INFINITY, -30.0, f32::NAN, -10.0];
+        let columns = orient_envelope(
+            &decibels,
+            4,
+            2,
+            Orientation::default(),
+        );
+        assert_eq!(columns, [(0, 30), (0, 10)]);
+        // and even an unmapped extreme renders
+        let sparkline = EnvelopeSparkline {
+            data: &[(0, u64::MAX), (u64::MAX, u64::MAX)],
+            invert: false,
+            style: Style::default(),
+        };
+        let rows = rows(&render(sparkline, 2, 3));
+        assert_eq!(rows[2], "▒█");
+    }
+
+    #[test]
+    fn the_idle_timer_idles_after_quiet_and_wakes_on_sound()
+    {
+        let mut timer = IdleTimer::new(IdleOptions {
+            after: Duration::from_secs(2),
+            threshold_db: -50.0,
+            fps: 2.0,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        // (time, level, idle after it)
+        let levels = [
+            (0, -20.0, false),
+            (500, -60.0, false),
+            (1500, -70.0, false),
+            // two seconds after the quiet started
+            (2500, -65.0, true),
+            (4000, -80.0, true),
+            // a sound wakes it up at once
+            (4100, -30.0, false),
+            // and the quiet has to last all over again
+            (4200, -60.0, false),
+            (6100, -60.0, false),
+            (6200, -60.0, true),
+        ];
+        for (ms, level, idle) in levels {
+            assert_eq!(
+                timer.update(level, at(ms)),
+                idle,
+                "{level}dB at {ms}ms"
+            );
+        }
+        assert_eq!(
+            timer.poll_timeout(60.0, true),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            timer.poll_timeout(60.0, false),
+            poll_timeout(60.0)
+        );
+    }
+
+    #[test]
+    fn the_vectorscope_turns_mono_up_and_out_of_phase_across(
+    ) {
+        let close =
+            |(x, y): (f64, f64), (ex, ey): (f64, f64)| {
+                (x - ex).abs() < 1e-6
+                    && (y - ey).abs() < 1e-6
+            };
+        // mono: on the vertical axis, up or down with the
+        // sign
+        assert!(close(
+            vectorscope_point(0.5, 0.5),
+            (0.0, 0.5)
+        ));
+        assert!(close(
+            vectorscope_point(-1.0, -1.0),
+            (0.0, -1.0)
+        ));
+        // out of phase: on the horizontal axis
+        assert!(close(
+            vectorscope_point(-0.5, 0.5),
+            (0.5, 0.0)
+        ));
+        assert!(close(
+            vectorscope_point(1.0, -1.0),
+            (-1.0, 0.0)
+        ));
+        // one channel alone sits on a diagonal
+        assert!(close(
+            vectorscope_point(0.0, 1.0),
+            (0.5, 0.5)
+        ));
+        // and nothing at full scale falls outside the
+        // bounds
+        for left in [-1.0, 0.0, 1.0] {
+            for right in [-1.0, 0.0, 1.0] {
+                let (x, y) = vectorscope_point(left, right);
+                assert!(x.abs() <= 1.0 && y.abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn the_moving_average_fills_edge_windows_with_what_there_is(
+    ) {
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(
+            moving_average(&values, 3),
+            [1.0, 1.5, 2.0, 3.0, 4.0]
+        );
+        // a window longer than the data averages it all
+        assert_eq!(
+            moving_average(&values, 10),
+            [1.0, 1.5, 2.0, 2.5, 3.0]
+        );
+        // no smoothing
+        assert_eq!(moving_average(&values, 1), values);
+        assert_eq!(moving_average(&values, 0), values);
+        assert!(moving_average(&[], 3).is_empty());
+        // silence drops out, unless it's all there is
+        let silence = f32::NEG_INFINITY;
+        assert_eq!(
+            moving_average(&[silence, -20.0, silence], 2),
+            [silence, -20.0, -20.0]
+        );
+    }
+
+    #[test]
+    fn the_help_comes_from_the_keymap() {
+        let keymap = KeyMap::default();
+        let lines = help_lines(&keymap);
+        assert_eq!(lines.len(), Action::ALL.len());
+        for (&action, line) in
+            Action::ALL.iter().zip(&lines)
+        {
+            assert!(line.starts_with(&action_keys(
+                &keymap, action
+            )));
+            assert!(line.ends_with(action.description()));
+        }
+        assert!(lines.iter().any(|line| line
+            .starts_with("?")
+            && line.ends_with("show or hide this help")));
+
+        // and moves when it's rebound
+        let keymap =
+            KeyMap::new(&std::collections::BTreeMap::from(
+                [("help".to_string(), "k".to_string())],
+            ))
+            .unwrap();
+        let lines = help_lines(&keymap);
+        assert!(!lines
+            .iter()
+            .any(|line| line.starts_with("?")));
+        assert!(lines.iter().any(|line| {
+            line.starts_with("k ")
+                && line.ends_with("show or hide this help")
+        }));
+    }
+
+    #[test]
+    fn the_trigger_freezes_around_the_crossing_until_rearmed(
+    ) {
+        let mut trigger = Trigger::new(TriggerOptions {
+            threshold_db: -10.0,
+            pre_samples: 3,
+            post_samples: 2,
+        });
+        let mut s = AppState::new(default_bands());
+        let feed = |s: &mut AppState,
+                    trigger: &mut Trigger,
+                    readings: &[f32]| {
+            s.decibels.extend_from_slice(readings);
+            trigger.update(s);
+        };
+        feed(&mut s, &mut trigger, &[-40.0, -30.0, -35.0]);
+        assert_eq!(trigger.status(), "trigger armed");
+        feed(
+            &mut s,
+            &mut trigger,
+            &[-20.0, -25.0, -5.0, -30.0],
+        );
+        // fired, one of the two after readings is still to come
+        assert_eq!(trigger.status(), "triggered…");
+        assert!(trigger.frozen().is_none());
+        feed(&mut s, &mut trigger, &[-32.0, 0.0]);
+        let frozen = trigger.frozen().unwrap();
+        // three before, the crossing, two after
+        assert_eq!(
+            frozen.decibels,
+            [-35.0, -20.0, -25.0, -5.0, -30.0, -32.0]
+        );
+        // the live history carries on underneath
+        assert_eq!(s.decibels.len(), 9);
+
+        // loud readings while frozen don't fire it again
+        feed(&mut s, &mut trigger, &[-1.0]);
+        trigger.rearm();
+        feed(&mut s, &mut trigger, &[-50.0]);
+        assert_eq!(trigger.status(), "trigger armed");
+        // and a short history keeps what pre-trigger
+        // readings there are
+        let mut trigger = Trigger::new(TriggerOptions {
+            threshold_db: -10.0,
+            pre_samples: 3,
+            post_samples: 0,
+        });
+        let mut s = AppState::new(default_bands());
+        feed(&mut s, &mut trigger, &[-30.0, -2.0]);
+        assert_eq!(
+            trigger.frozen().unwrap().decibels,
+            [-30.0, -2.0]
+        );
+    }
+
+    #[test]
+    fn solo_overrides_mute() {
+        let audible =
+            |muted: &[usize], soloed: &[usize]| {
+                let state = TuiState {
+                    muted: muted.iter().copied().collect(),
+                    soloed: soloed
+                        .iter()
+                        .copied()
+                        .collect(),
+                    ..TuiState::default()
+                };
+                state.audible_bands(4)
+            };
+        assert_eq!(audible(&[], &[]), [true; 4]);
+        assert_eq!(
+            audible(&[1, 3], &[]),
+            [true, false, true, false]
+        );
+        assert_eq!(
+            audible(&[], &[2]),
+            [false, false, true, false]
+        );
+        // several solos all show
+        assert_eq!(
+            audible(&[], &[0, 2]),
+            [true, false, true, false]
+        );
+        // soloing a muted band shows it, and muting the
+        // others changes nothing while something's soloed
+        assert_eq!(
+            audible(&[1, 2], &[1]),
+            [false, true, false, false]
+        );
+
+        let mut set = BTreeSet::new();
+        toggle(&mut set, 2);
+        assert!(set.contains(&2));
+        toggle(&mut set, 2);
+        assert!(set.is_empty());
+    }
+
+    /// Draws the whole ui for a fresh state onto a `width`
+    /// by `height` test terminal
+    fn draw(
+        options: &DisplayOptions,
+        width: u16,
+        height: u16,
+    ) -> Buffer {
+        draw_with(
+            options,
+            &TuiState::default(),
+            width,
+            height,
+        )
+    }
+
+    /// `draw`, with the keys set to `tui_state`
+    fn draw_with(
+        options: &DisplayOptions,
+        tui_state: &TuiState,
+        width: u16,
+        height: u16,
+    ) -> Buffer {
+        let mut terminal = Terminal::new(
+            backend::TestBackend::new(width, height),
+        )
+        .unwrap();
+        let s = AppState::new(default_bands());
+        terminal
+            .draw(|f| ui(f, &s, options, tui_state, None))
+            .unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    #[test]
+    fn a_region_leaves_the_rest_of_the_terminal_alone() {
+        let region = Rect::new(10, 4, 40, 10);
+        let options = DisplayOptions {
+            region: Some(region),
+            ..DisplayOptions::default()
+        };
+        let buf = draw(&options, 80, 24);
+        let mut drawn = false;
+        for y in 0..24 {
+            for x in 0..80 {
+                let cell = buf.get(x, y);
+                if region.intersects(Rect::new(x, y, 1, 1))
+                {
+                    drawn |= cell.symbol != " ";
+                } else {
+                    assert_eq!(
+                        *cell,
+                        buffer::Cell::default(),
+                        "({x}, {y})"
+                    );
+                }
+            }
+        }
+        assert!(drawn);
+
+        // a region hanging off the edge is cut down to fit
+        assert_eq!(
+            clamp_region(region, Rect::new(0, 0, 30, 8)),
+            Rect::new(10, 4, 20, 4)
+        );
+        assert_eq!(
+            clamp_region(region, Rect::new(0, 0, 5, 3))
+                .area(),
+            0
+        );
+        // and drawing into it doesn't panic
+        draw(&options, 30, 8);
+        draw(&options, 5, 3);
+    }
+
+    #[test]
+    fn a_shutdown_request_cuts_the_wait_short() {
+        let shutdown = Shutdown::default();
+        let handler = shutdown.clone();
+        std::thread::spawn(move || handler.request())
+            .join()
+            .unwrap();
+        assert!(shutdown.requested());
+        let start = Instant::now();
+        assert!(next_event(
+            Duration::from_secs(10),
+            &shutdown
+        )
+        .unwrap()
+        .is_none());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn waveform_samples_are_centred_and_clipped() {
+        assert_eq!(waveform_y(0.0, 0.5), 0.0);
+        assert_eq!(waveform_y(0.5, 0.5), 1.0);
+        assert_eq!(waveform_y(-0.25, 0.5), -0.5);
+        // beyond the scale sits on the edge
+        assert_eq!(waveform_y(2.0, 0.5), 1.0);
+        assert_eq!(waveform_y(-2.0, 0.5), -1.0);
+        assert_eq!(waveform_y(f32::NAN, 0.5), 0.0);
+        assert_eq!(waveform_y(0.3, 0.0), 0.0);
+    }
+
+    #[test]
+    fn waveform_columns_keep_every_peak() {
+        let mut samples = vec![0.0; 100];
+        samples[37] = 0.9;
+        samples[62] = -0.7;
+        let columns = waveform_columns(&samples, 10);
+        assert_eq!(columns.len(), 10);
+        assert_eq!(columns[3], (0.0, 0.9));
+        assert_eq!(columns[6], (-0.7, 0.0));
+        // fewer samples than columns: one each, joined up
+        let columns = waveform_columns(&[0.1, -0.1], 10);
+        assert_eq!(columns, vec![(0.1, 0.1), (-0.1, 0.1)]);
+    }
+
+    #[test]
+    fn rates_are_classified_against_the_fps() {
+        // a 1024 sample hop at 48 kHz
+        let rate = 48_000.0 / 1024.0;
+        assert_eq!(
+            classify_rates(rate, 60.0),
+            Some(RateMatch::Matched)
+        );
+        assert_eq!(
+            classify_rates(rate, 144.0),
+            Some(RateMatch::Repeating)
+        );
+        assert_eq!(
+            classify_rates(rate, 15.0),
+            Some(RateMatch::Dropping)
+        );
+        assert_eq!(classify_rates(0.0, 60.0), None);
+        assert_eq!(classify_rates(rate, f32::NAN), None);
+        assert!(RateMatch::Matched
+            .warning(rate, 60.0)
+            .is_none());
+        assert!(RateMatch::Dropping
+            .warning(rate, 15.0)
+            .unwrap()
+            .contains("--fps 47"));
+    }
+
+    #[test]
+    fn starting_paused_shows_the_indicator() {
+        let paused = |start_paused| {
+            let options = DisplayOptions {
+                start_paused,
+                ..DisplayOptions::default()
+            };
+            let tui_state = TuiState::new(&options);
+            let shown = rows(&draw_with(
+                &options, &tui_state, 80, 24,
+            ))
+            .iter()
+            .any(|row| row.contains("⏸ paused"));
+            (tui_state.paused, shown)
+        };
+        assert_eq!(paused(true), (true, true));
+        assert_eq!(paused(false), (false, false));
+    }
+
+    #[test]
+    fn fractions_round_to_the_nearest_eighth_block() {
+        assert_eq!(block_glyph(0.0), " ");
+        assert_eq!(block_glyph(0.04), " ");
+        assert_eq!(block_glyph(0.1), "▁");
+        assert_eq!(block_glyph(0.5), "▄");
+        assert_eq!(block_glyph(0.7), "▆");
+        assert_eq!(block_glyph(0.95), "█");
+        // out of range is clamped, nonsense is empty
+        assert_eq!(block_glyph(3.0), "█");
+        assert_eq!(block_glyph(-1.0), " ");
+        assert_eq!(block_glyph(f32::NAN), " ");
+    }
+
+    #[test]
+    fn each_band_is_drawn_in_its_own_color() {
+        let ranges = &default_bands()[..3];
+        let buf = render(
+            band_chart(
+                &[1.0; 3],
+                ranges,
+                &[1.0; 3],
+                &[true; 3],
+                0,
+                // shorter than the bands, so it repeats
+                &[Color::Red, Color::Blue],
+                Rect::new(0, 0, 16, 8),
+            ),
+            16,
+            8,
+        );
+        // the colour of each run of full blocks across the
+        // bottom of the bars
+        let bottom = (0..8)
+            .rev()
+            .find(|y| {
+                (0..16)
+                    .any(|x| buf.get(x, *y).symbol == "█")
+            })
+            .unwrap();
+        let mut colors: Vec<Color> = (0..16)
+            .map(|x| buf.get(x, bottom))
+            .filter(|cell| cell.symbol == "█")
+            .map(|cell| cell.fg)
+            .collect();
+        colors.dedup();
+        assert_eq!(
+            colors,
+            [Color::Red, Color::Blue, Color::Red]
+        );
+    }
+
+    #[test]
+    fn a_stall_is_called_after_the_timeout_and_cleared() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut timer =
+            StallTimer::new(Duration::from_millis(500));
+        // no frames yet isn't a stall, however long
+        assert!(!timer.update(0, at(0)));
+        assert!(!timer.update(0, at(5000)));
+        assert!(!timer.update(1, at(5000)));
+        assert!(!timer.update(1, at(5400)));
+        assert!(timer.update(1, at(5500)));
+        assert!(timer.update(1, at(9000)));
+        // the next frame ends it, and the wait starts again
+        assert!(!timer.update(2, at(9010)));
+        assert!(!timer.update(2, at(9500)));
+        assert!(timer.update(2, at(9510)));
+
+        let banner = |disconnected| {
+            let tui_state = TuiState {
+                disconnected,
+                ..TuiState::default()
+            };
+            rows(&draw_with(
+                &DisplayOptions::default(),
+                &tui_state,
+                80,
+                24,
+            ))
+            .iter()
+            .any(|row| row.contains("DISCONNECTED"))
+        };
+        assert!(banner(true));
+        assert!(!banner(false));
+    }
+
+    #[test]
+    fn the_flash_jumps_on_a_beat_and_fades() {
+        let frame = Duration::from_millis(20);
+        let mut flash = FlashEnvelope::default();
+        // beats from before the first look don't flash
+        assert_eq!(flash.update(5, 0.8, frame), 0.0);
+        assert_eq!(flash.update(6, 0.8, frame), 0.8);
+        // fading by e every FLASH_DECAY, one frame at a time
+        let fade = (-frame.as_secs_f32()
+            / FLASH_DECAY.as_secs_f32())
+        .exp();
+        let mut expected = 0.8;
+        let mut levels = vec![];
+        for _ in 0..10 {
+            expected *= fade;
+            let level = flash.update(6, 0.8, frame);
+            assert!((level - expected).abs() < 1e-5);
+            levels.push(level);
+        }
+        assert!(levels.windows(2).all(|w| w[1] < w[0]));
+        // and out altogether once it's dim
+        for _ in 0..100 {
+            flash.update(6, 0.8, frame);
+        }
+        assert_eq!(flash.level(), 0.0);
+        // a weaker beat flashes dimmer
+        assert_eq!(flash.update(7, 0.3, frame), 0.3);
+    }
+
+    #[test]
+    fn reference_lines_land_on_their_level() {
+        let range = (-60.0, 0.0);
+        // 21 rows puts a row on every 3dB
+        assert_eq!(reference_row(-12.0, range, 21), 4);
+        assert_eq!(reference_row(0.0, range, 21), 0);
+        assert_eq!(reference_row(-60.0, range, 21), 20);
+        // off the scale sits on its end
+        assert_eq!(reference_row(-80.0, range, 21), 20);
+        assert_eq!(
+            reference_row(94.0, (30.0, 130.0), 11),
+            4
+        );
+
+        let buf = render(
+            ReferenceMarks {
+                references: &[-12.0, 0.0],
+                range,
+            },
+            61,
+            2,
+        );
+        for row in rows(&buf) {
+            let marked: Vec<usize> = row
+                .chars()
+                .enumerate()
+                .filter(|(_, c)| *c == '╎')
+                .map(|(x, _)| x)
+                .collect();
+            assert_eq!(marked, [48, 60]);
+        }
+    }
+
+    #[test]
+    fn peak_and_area_normalization_of_two_peaks() {
+        // 9 bins, so a full scale sine peaks at 8
+        let two_peaks = vec![
+            0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0,
+        ];
+        let louder: Vec<f32> = two_peaks
+            .iter()
+            .map(|bin| bin * 10.0)
+            .collect();
+        let peak =
+            SpectrumNorm::Peak.apply(two_peaks.clone());
+        assert_eq!(peak[2], 8.0);
+        assert_eq!(peak[6], 4.0);
+        let area =
+            SpectrumNorm::Area.apply(two_peaks.clone());
+        assert!(
+            (area.iter().sum::<f32>() - 8.0).abs() < 1e-5
+        );
+        assert!((area[2] - 16.0 / 3.0).abs() < 1e-5);
+        assert!((area[6] - 8.0 / 3.0).abs() < 1e-5);
+        // neither cares how loud it is
+        assert_eq!(
+            SpectrumNorm::Peak.apply(louder.clone()),
+            peak
+        );
+        let louder_area =
+            SpectrumNorm::Area.apply(louder.clone());
+        for (a, b) in louder_area.iter().zip(&area) {
+            assert!((a - b).abs() < 1e-5);
+        }
+        assert_eq!(
+            SpectrumNorm::None.apply(louder.clone()),
+            louder
+        );
+        // a third peak leaves the tallest at the top with
+        // peak normalization, but shares out the area
+        let mut three_peaks = two_peaks;
+        three_peaks[4] = 2.0;
+        assert_eq!(
+            SpectrumNorm::Peak.apply(three_peaks.clone())
+                [2],
+            8.0
+        );
+        assert!(
+            (SpectrumNorm::Area.apply(three_peaks)[2]
+                - 4.0)
+                .abs()
+                < 1e-5
+        );
+        // silence comes back as is
+        assert_eq!(
+            SpectrumNorm::Peak.apply(vec![0.0; 4]),
+            [0.0; 4]
+        );
+        assert_eq!(
+            SpectrumNorm::Area.apply(vec![0.0; 4]),
+            [0.0; 4]
+        );
+    }
+
+    #[test]
+    fn the_status_bar_shows_the_config_and_fps() {
+        let stats = AnalysisStats {
+            sample_rate: 48000,
+            fft_size: 2048,
+            ..AnalysisStats::default()
+        };
+        assert_eq!(
+            status_bar("USB Microphone", &stats, 59.6, 80),
+            "USB Microphone │ 48000 Hz │ fft 2048 │ 60 fps"
+        );
+        // the device name gives way first
+        assert_eq!(
+            status_bar("USB Microphone", &stats, 59.6, 37),
+            "USB M… │ 48000 Hz │ fft 2048 │ 60 fps"
+        );
+        assert_eq!(
+            status_bar(
+                "default",
+                &AnalysisStats::default(),
+                30.0,
+                80
+            ),
+            "default │ no audio yet │ 30 fps"
+        );
+        assert_eq!(
+            status_bar("USB Microphone", &stats, 59.6, 10)
+                .chars()
+                .count(),
+            10
+        );
+    }
+
+    #[test]
+    fn a_tiny_terminal_says_its_too_small() {
+        let options = DisplayOptions {
+            min_size: (40, 10),
+            ..DisplayOptions::default()
+        };
+        let shown = rows(&draw(&options, 5, 3));
+        assert_eq!(shown[0].trim(), "need");
+        assert_eq!(shown[1].trim(), "40x10");
+        // with room for the whole message
+        assert!(rows(&draw(&options, 39, 24))
+            .iter()
+            .any(|row| row
+                .contains("terminal too small (need")));
+        // and the views once it's big enough
+        assert!(!rows(&draw(&options, 40, 10))
+            .iter()
+            .any(|row| row.contains("need 40x10")));
+    }
+
+    #[test]
+    fn gamma_brightens_or_darkens_the_middle() {
+        for level in [0.1, 0.25, 0.5, 0.75] {
+            assert!(apply_gamma(level, 0.5) > level);
+            assert!(apply_gamma(level, 2.0) < level);
+            assert_eq!(apply_gamma(level, 1.0), level);
+        }
+        assert_eq!(apply_gamma(0.25, 0.5), 0.5);
+        assert_eq!(apply_gamma(0.5, 2.0), 0.25);
+        // the ends stay put
+        for gamma in [0.5, 2.0] {
+            assert_eq!(apply_gamma(0.0, gamma), 0.0);
+            assert_eq!(apply_gamma(1.0, gamma), 1.0);
+        }
+        // and a nonsense gamma does nothing
+        assert_eq!(apply_gamma(0.5, 0.0), 0.5);
+        assert_eq!(apply_gamma(0.5, f32::NAN), 0.5);
+    }
+
+    #[test]
+    fn the_delta_is_live_over_reference_in_db() {
+        // five bins, a full scale sine peaks at 4
+        let live = [4.0, 0.4, 0.0, 0.04, 4.0];
+        let reference = [0.4, 0.4, 0.0, 0.4, 4.0];
+        let delta =
+            spectrum_delta(&live, &reference).unwrap();
+        let expected = [20.0, 0.0, 0.0, -20.0, 0.0];
+        for (got, want) in delta.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-3, "{delta:?}");
+        }
+        // silence against far below the floor is no
+        // difference
+        let delta =
+            spectrum_delta(&[0.0, 0.0], &[1e-9, 0.0])
+                .unwrap();
+        assert_eq!(delta, [0.0, 0.0]);
+        // a reference frozen at another fft size doesn't
+        // line up
+        assert_eq!(spectrum_delta(&live, &[0.0; 9]), None);
+    }
+
+    #[test]
+    fn readouts_convert_between_db_and_percent() {
+        let range = (-60.0, 0.0);
+        for (db, percent) in [
+            (-60.0, 0.0),
+            (-45.0, 25.0),
+            (-30.0, 50.0),
+            (-6.0, 90.0),
+            (0.0, 100.0),
+            // clamped to the range
+            (-90.0, 0.0),
+            (6.0, 100.0),
+        ] {
+            assert_eq!(
+                db_to_percent(db, range),
+                percent,
+                "{db}"
+            );
+        }
+        assert_eq!(
+            db_to_percent(f32::NEG_INFINITY, range),
+            0.0
+        );
+
+        let dbfs =
+            Readout::new(DisplayUnits::Decibels, false);
+        let percent =
+            Readout::new(DisplayUnits::Percent, false);
+        assert_eq!(dbfs.format(-12.34), "-12.3 dBFS");
+        assert_eq!(percent.format(-12.34), "79%");
+        assert_eq!(percent.mark(-30.0), "50");
+        // against the spl meter's range once calibrated
+        let spl = Readout::new(DisplayUnits::Percent, true);
+        assert_eq!(spl.format(80.0), "50%");
+        assert_eq!(
+            DisplayUnits::Decibels.toggle(),
+            DisplayUnits::Percent
+        );
+    }
+
+    #[test]
+    fn losing_focus_slows_the_redraws() {
+        let options = DisplayOptions {
+            fps: 60.0,
+            unfocused_fps: Some(5.0),
+            ..DisplayOptions::default()
+        };
+        let mut tui_state = TuiState::new(&options);
+        assert_eq!(tui_state.fps(&options), 60.0);
+        tui_state.focus_event(&Event::FocusLost);
+        assert_eq!(tui_state.fps(&options), 5.0);
+        // other events don't touch it
+        tui_state.focus_event(&Event::Resize(80, 24));
+        assert_eq!(tui_state.fps(&options), 5.0);
+        tui_state.focus_event(&Event::FocusGained);
+        assert_eq!(tui_state.fps(&options), 60.0);
+
+        // without a rate for it, focus makes no difference
+        let options = DisplayOptions {
+            fps: 60.0,
+            ..DisplayOptions::default()
+        };
+        tui_state.focus_event(&Event::FocusLost);
+        assert_eq!(tui_state.fps(&options), 60.0);
+    }
+
+    #[test]
+    fn a_transient_leaves_a_trail_fading_at_the_set_rate() {
+        let mut s = AppState::new(default_bands());
+        s.persistence = Some(PeakHold::new(0.8));
+        let trail = s.persistence.as_mut().unwrap();
+        let mut transient = vec![0.0; 8];
+        transient[3] = 100.0;
+        trail.update(&transient);
+        // gone from the input, the peak stays on screen and
+        // loses a fifth of itself each frame
+        for frame in 1..=10 {
+            trail.update(&[0.0; 8]);
+            let expected = 100.0 * 0.8f32.powi(frame);
+            assert!(
+                (trail.levels()[3] - expected).abs() < 1e-3,
+                "{frame}"
+            );
+            assert_eq!(trail.levels()[2], 0.0);
+        }
+        // a new peak at or above the trail takes over
+        let mut louder = vec![0.0; 8];
+        louder[3] = 50.0;
+        trail.update(&louder);
+        assert_eq!(trail.levels()[3], 50.0);
+    }
+
+    #[test]
+    fn soft_log_is_linear_near_silence_and_meets_full_scale(
+    ) {
+        // a 2048 point fft, so full scale is 1024
+        let len = 1025;
+        let at = |scale: LevelScale, ratio: f32| {
+            scale.normalize(ratio * 1024.0, len)
+        };
+        let standard = LevelScale::default();
+        let soft = LevelScale {
+            softness: 0.001,
+            ..LevelScale::default()
+        };
+        for ratio in [0.0, 1e-5, 0.01, 0.5, 1.0] {
+            assert_eq!(
+                at(standard, ratio),
+                normalize_magnitude(ratio * 1024.0, len)
+            );
+        }
+        assert_eq!(at(standard, 1.0), 1.0);
+        assert_eq!(at(soft, 1.0), 1.0);
+        assert!((at(standard, 0.01) - 0.5).abs() < 1e-6);
+
+        // the plain log hits its floor, the soft one doesn't
+        // and doubles with the input
+        assert_eq!(at(standard, 1e-5), 0.0);
+        assert_eq!(at(soft, 0.0), 0.0);
+        let quiet = at(soft, 1e-5);
+        assert!(quiet > 0.0);
+        assert!(
+            (at(soft, 2e-5) / quiet - 2.0).abs() < 0.01
+        );
+
+        // base 2 spans the same 80 units down to a 16th
+        let base_2 = LevelScale {
+            base: 2.0,
+            ..LevelScale::default()
+        };
+        assert!(at(base_2, 1.0 / 16.0).abs() < 1e-6);
+        assert!((at(base_2, 0.25) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn compared_devices_are_updated_and_drawn_apart() {
+        let mut s = AppState::new(default_bands());
+        s.devices = vec![
+            DeviceLevels::new("Desk"),
+            DeviceLevels::new("Stream"),
+        ];
+        let frame =
+            |peak: f32, bands: Vec<f32>| AnalysisFrame {
+                samples: vec![peak, -peak / 2.0],
+                spectrum: vec![0.0; 1025],
+                bands,
+                ..AnalysisFrame::default()
+            };
+        s.devices[0].update(&frame(0.5, vec![1.0, 2.0]));
+        assert!(
+            (s.devices[0].level.unwrap() + 6.02).abs()
+                < 0.01
+        );
+        assert_eq!(s.devices[0].bands, [1.0, 2.0]);
+        assert_eq!(s.devices[0].spectrum_len, 1025);
+        // the other input hasn't sent anything yet
+        assert_eq!(s.devices[1].level, None);
+        assert!(s.devices[1].bands.is_empty());
+
+        s.devices[1].update(&frame(1.0, vec![3.0]));
+        assert_eq!(s.devices[1].level, Some(0.0));
+        assert_eq!(s.devices[1].bands, [3.0]);
+        assert_eq!(s.devices[0].bands, [1.0, 2.0]);
+
+        s.devices[0].audio_stopped =
+            Some("audio input stopped".to_string());
+        let mut terminal = Terminal::new(
+            backend::TestBackend::new(80, 20),
+        )
+        .unwrap();
+        terminal
+            .draw(|f| {
+                ui(
+                    f,
+                    &s,
+                    &DisplayOptions::default(),
+                    &TuiState::default(),
+                    None,
+                )
+            })
+            .unwrap();
+        let screen =
+            rows(terminal.backend().buffer()).concat();
+        // a column each, one stopped and one still going
+        assert!(screen.contains("Desk"));
+        assert!(screen.contains("Stream"));
+        assert!(screen.contains("audio input"));
+    }
+
+    #[test]
+    fn the_peak_dot_marks_the_loudest_reading_in_view() {
+        // dB below full scale, oldest on the left: -10dB is
+        // the loudest, twice, and the zeros are gaps
+        let window = [0.0, 30.0, 10.0, 20.0, 10.0, 0.0];
+        let normal = Orientation::default();
+        // the newer of the two, a third of the way up a
+        // four row sparkline
+        assert_eq!(
+            peak_dot(&window, 4, normal),
+            Some((4, 2))
+        );
+        // mirrored, the newest is on the left
+        let mut mirrored: Vec<f32> = window.to_vec();
+        mirrored.reverse();
+        assert_eq!(
+            peak_dot(
+                &mirrored,
+                4,
+                Orientation {
+                    mirror: true,
+                    invert: false,
+                }
+            ),
+            Some((1, 2))
+        );
+        // hanging from the top, the tip is near the top
+        assert_eq!(
+            peak_dot(
+                &window,
+                4,
+                Orientation {
+                    mirror: false,
+                    invert: true,
+                }
+            ),
+            Some((4, 1))
+        );
+        assert_eq!(peak_dot(&[0.0; 6], 4, normal), None);
+        assert_eq!(peak_dot(&window, 0, normal), None);
+    }
 }
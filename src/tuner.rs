@@ -0,0 +1,143 @@
+use std::fmt;
+
+/// The A above middle C unless configured otherwise, in Hz
+pub const A4_HZ: f32 = 440.0;
+
+/// The twelve notes of the equal tempered octave, from C,
+/// written with sharps the way a tuner shows them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteName {
+    C,
+    CSharp,
+    D,
+    DSharp,
+    E,
+    F,
+    FSharp,
+    G,
+    GSharp,
+    A,
+    ASharp,
+    B,
+}
+
+impl NoteName {
+    pub const ALL: [NoteName; 12] = [
+        NoteName::C,
+        NoteName::CSharp,
+        NoteName::D,
+        NoteName::DSharp,
+        NoteName::E,
+        NoteName::F,
+        NoteName::FSharp,
+        NoteName::G,
+        NoteName::GSharp,
+        NoteName::A,
+        NoteName::ASharp,
+        NoteName::B,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteName::C => "C",
+            NoteName::CSharp => "C#",
+            NoteName::D => "D",
+            NoteName::DSharp => "D#",
+            NoteName::E => "E",
+            NoteName::F => "F",
+            NoteName::FSharp => "F#",
+            NoteName::G => "G",
+            NoteName::GSharp => "G#",
+            NoteName::A => "A",
+            NoteName::ASharp => "A#",
+            NoteName::B => "B",
+        }
+    }
+}
+
+impl fmt::Display for NoteName {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The equal tempered note nearest `freq`, tuned with A4 at
+/// `a4_hz`: its name, its octave (4 being the one from
+/// middle C up) and how far `freq` is from it in cents,
+/// -50 (flat) to 50 (sharp). `None` for anything that isn't
+/// a positive frequency.
+pub fn freq_to_note(
+    freq: f32,
+    a4_hz: f32,
+) -> Option<(NoteName, i32, f32)> {
+    if !(freq.is_finite()
+        && freq > 0.0
+        && a4_hz.is_finite()
+        && a4_hz > 0.0)
+    {
+        return None;
+    }
+    let semitones = 12.0 * (freq / a4_hz).log2();
+    let nearest = semitones.round();
+    let cents = (semitones - nearest) * 100.0;
+    // counted like midi, where A4 is 69 and C4 is 60
+    let note = 69 + nearest as i32;
+    Some((
+        NoteName::ALL[note.rem_euclid(12) as usize],
+        note.div_euclid(12) - 1,
+        cents,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(
+        freq: f32,
+        a4_hz: f32,
+    ) -> (&'static str, i32, f32) {
+        let (name, octave, cents) =
+            freq_to_note(freq, a4_hz).unwrap();
+        (name.name(), octave, cents)
+    }
+
+    #[test]
+    fn known_frequencies_map_to_their_notes() {
+        for (freq, name, octave) in [
+            (440.0, "A", 4),
+            (466.16, "A#", 4),
+            (261.63, "C", 4),
+            (82.41, "E", 2),
+            (4186.01, "C", 8),
+            (27.5, "A", 0),
+        ] {
+            let (got, got_octave, cents) =
+                note(freq, A4_HZ);
+            assert_eq!(
+                (got, got_octave),
+                (name, octave),
+                "{freq}"
+            );
+            assert!(cents.abs() < 0.1, "{freq}: {cents}");
+        }
+        // nearly a quarter tone sharp of A4, and a little
+        // flat of B3
+        let (name, octave, cents) = note(451.0, A4_HZ);
+        assert_eq!((name, octave), ("A", 4));
+        assert!((cents - 42.74).abs() < 0.1, "{cents}");
+        let (name, octave, cents) = note(245.0, A4_HZ);
+        assert_eq!((name, octave), ("B", 3));
+        assert!((cents + 13.66).abs() < 0.1, "{cents}");
+        // tuned to 432, 440 is a third of a semitone sharp
+        let (name, _, cents) = note(440.0, 432.0);
+        assert_eq!(name, "A");
+        assert!((cents - 31.77).abs() < 0.1, "{cents}");
+
+        assert_eq!(freq_to_note(0.0, A4_HZ), None);
+        assert_eq!(freq_to_note(440.0, f32::NAN), None);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+
+use crate::analysis::{rms_dbfs, AnalysisFrame};
+
+/// Where most of speech's energy sits, the old telephone
+/// band
+pub const VOICE_BAND_HZ: (f32, f32) = (300.0, 3400.0);
+
+/// Zero crossing rates, as the frequency of a sine crossing
+/// as often, that voiced and unvoiced speech fall between
+pub const SPEECH_ZCR_HZ: (f32, f32) = (100.0, 3000.0);
+
+/// How far back the level's modulation is measured, long
+/// enough to span a couple of syllables
+const MODULATION_SECONDS: f32 = 0.5;
+/// Level swing, as a standard deviation in dB, that counts
+/// as fully speech-like. A held tone barely moves.
+const FULL_MODULATION_DB: f32 = 6.0;
+/// Levels are floored here so silence doesn't read as an
+/// infinite swing
+const LEVEL_FLOOR_DBFS: f32 = -100.0;
+
+/// A speech score at or above this lets the mouth open
+pub const SPEECH_THRESHOLD: f32 = 0.6;
+
+/// How long a speech-like stretch keeps counting after it
+/// ends, so the mouth doesn't snap shut between syllables
+const HOLD_SECONDS: f32 = 0.3;
+
+/// How much each feature counts towards the speech score.
+/// Only their proportions matter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoiceWeights {
+    /// zero crossing rate within `SPEECH_ZCR_HZ`
+    pub zcr: f32,
+    /// share of the energy in `VOICE_BAND_HZ`
+    pub voice_band: f32,
+    /// how much the level swings, see `FULL_MODULATION_DB`
+    pub modulation: f32,
+}
+
+impl Default for VoiceWeights {
+    fn default() -> Self {
+        VoiceWeights {
+            zcr: 0.2,
+            voice_band: 0.3,
+            modulation: 0.5,
+        }
+    }
+}
+
+/// How often the first channel of interleaved `samples`
+/// crosses zero, as the frequency of a sine that would
+/// cross as often
+pub fn zero_crossing_hz(
+    samples: &[f32],
+    channels: u16,
+    sample_rate: u32,
+) -> f32 {
+    let channel: Vec<f32> = samples
+        .iter()
+        .step_by(channels.max(1) as usize)
+        .copied()
+        .collect();
+    if channel.len() < 2 {
+        return 0.0;
+    }
+    let crossings = channel
+        .windows(2)
+        .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+        .count();
+    let seconds =
+        channel.len() as f32 / sample_rate.max(1) as f32;
+    crossings as f32 / seconds / 2.0
+}
+
+/// 1 inside `low..=high`, falling to 0 an octave outside it
+fn in_range(value: f32, (low, high): (f32, f32)) -> f32 {
+    if value <= 0.0 {
+        return 0.0;
+    }
+    let octaves_out = if value < low {
+        (low / value).log2()
+    } else if value > high {
+        (value / high).log2()
+    } else {
+        0.0
+    };
+    (1.0 - octaves_out).clamp(0.0, 1.0)
+}
+
+/// The share of `spectrum`'s energy (0Hz up to nyquist)
+/// within `VOICE_BAND_HZ`, 0 for silence. Dc is left out,
+/// it says nothing about a voice.
+pub fn voice_band_share(
+    spectrum: &[f32],
+    sample_rate: u32,
+) -> f32 {
+    if spectrum.len() < 2 {
+        return 0.0;
+    }
+    let bin_hz = sample_rate as f32
+        / (2 * (spectrum.len() - 1)) as f32;
+    let (mut voice, mut total) = (0.0, 0.0);
+    for (bin, magnitude) in
+        spectrum.iter().enumerate().skip(1)
+    {
+        let energy = magnitude * magnitude;
+        let hz = bin as f32 * bin_hz;
+        if (VOICE_BAND_HZ.0..=VOICE_BAND_HZ.1).contains(&hz)
+        {
+            voice += energy;
+        }
+        total += energy;
+    }
+    if total > 0.0 {
+        voice / total
+    } else {
+        0.0
+    }
+}
+
+/// Standard deviation of `levels`, in their own units
+fn spread(levels: &VecDeque<f32>) -> f32 {
+    if levels.len() < 2 {
+        return 0.0;
+    }
+    let mean =
+        levels.iter().sum::<f32>() / levels.len() as f32;
+    (levels.iter().map(|l| (l - mean).powi(2)).sum::<f32>()
+        / levels.len() as f32)
+        .sqrt()
+}
+
+/// Scores how much the input sounds like speech rather than
+/// steady music or a held tone, from 0 to 1, so the talking
+/// indicator can ignore a loud backing track.
+///
+/// Combines the zero crossing rate, how much of the energy
+/// is in the voice band and how much the level swings from
+/// syllable to syllable, weighted by `VoiceWeights`. The
+/// score is held at its best for a moment after it drops.
+#[derive(Debug, Clone)]
+pub struct VoiceDetector {
+    weights: VoiceWeights,
+    /// recent rms levels in dBFS, newest last
+    levels: VecDeque<f32>,
+    /// recent scores, newest last, for the hold
+    scores: VecDeque<f32>,
+}
+
+impl VoiceDetector {
+    pub fn new(weights: VoiceWeights) -> Self {
+        VoiceDetector {
+            weights,
+            levels: VecDeque::new(),
+            scores: VecDeque::new(),
+        }
+    }
+
+    /// Scores `frame` and returns the held score
+    pub fn update(&mut self, frame: &AnalysisFrame) -> f32 {
+        let rate = frame.stats.sample_rate;
+        let frames_for = |seconds: f32| {
+            ((seconds * frame.stats.update_hz).ceil()
+                as usize)
+                .max(1)
+        };
+
+        self.levels.push_back(
+            rms_dbfs(&frame.samples).max(LEVEL_FLOOR_DBFS),
+        );
+        let window = frames_for(MODULATION_SECONDS);
+        while self.levels.len() > window {
+            self.levels.pop_front();
+        }
+
+        let VoiceWeights {
+            zcr,
+            voice_band,
+            modulation,
+        } = self.weights;
+        let total = zcr + voice_band + modulation;
+        let score = if total > 0.0 {
+            (zcr * in_range(
+                zero_crossing_hz(
+                    &frame.samples,
+                    frame.channels,
+                    rate,
+                ),
+                SPEECH_ZCR_HZ,
+            ) + voice_band
+                * voice_band_share(&frame.spectrum, rate)
+                + modulation
+                    * (spread(&self.levels)
+                        / FULL_MODULATION_DB)
+                        .min(1.0))
+                / total
+        } else {
+            1.0
+        };
+
+        self.scores.push_back(score);
+        let hold = frames_for(HOLD_SECONDS);
+        while self.scores.len() > hold {
+            self.scores.pop_front();
+        }
+        self.scores.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::analysis::AnalysisStats;
+
+    /// One 1024 sample frame of a 1kHz tone at `amplitude`,
+    /// its spectrum reduced to the one bin
+    fn tone_frame(amplitude: f32) -> AnalysisFrame {
+        let rate = 48_000;
+        let samples = (0..1024)
+            .map(|i| {
+                amplitude
+                    * (std::f32::consts::TAU
+                        * 1000.0
+                        * i as f32
+                        / rate as f32)
+                        .sin()
+            })
+            .collect();
+        let mut spectrum = vec![0.0; 513];
+        // 46.875Hz a bin
+        spectrum[21] = amplitude * 512.0;
+        AnalysisFrame {
+            samples,
+            channels: 1,
+            spectrum,
+            stats: AnalysisStats {
+                sample_rate: rate,
+                update_hz: rate as f32 / 1024.0,
+                ..AnalysisStats::default()
+            },
+            ..AnalysisFrame::default()
+        }
+    }
+
+    #[test]
+    fn a_held_tone_keeps_the_mouth_shut() {
+        let mut detector =
+            VoiceDetector::new(VoiceWeights::default());
+        for _ in 0..200 {
+            let score = detector.update(&tone_frame(0.5));
+            assert!(score < SPEECH_THRESHOLD, "{score}");
+        }
+    }
+
+    #[test]
+    fn syllable_like_swings_open_it() {
+        let mut detector =
+            VoiceDetector::new(VoiceWeights::default());
+        // the same tone, loud and quiet four times a second
+        let scores: Vec<f32> =
+            (0..200)
+                .map(|i| {
+                    let syllable = (i / 6) % 2 == 0;
+                    detector.update(&tone_frame(
+                        if syllable { 0.5 } else { 0.02 },
+                    ))
+                })
+                .collect();
+        // once there's been a syllable or two to measure
+        assert!(
+            scores[50..]
+                .iter()
+                .all(|score| *score >= SPEECH_THRESHOLD),
+            "{scores:?}"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use miette::Diagnostic;
+use std::{
+    path::Path,
+    sync::{atomic::AtomicU32, Arc},
+    thread,
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    analysis::AnalysisFrame,
+    audio::{handoff, AudioConfig},
+};
+
+#[derive(Error, Debug, Diagnostic)]
+pub enum WavError {
+    #[error("could not read the wav file")]
+    #[diagnostic(code(pngtubers::wav::read))]
+    Read(#[from] hound::Error),
+    #[error("the wav file has no samples")]
+    #[diagnostic(
+        code(pngtubers::wav::empty),
+        help(
+            "there's nothing to analyze in an empty file"
+        )
+    )]
+    Empty,
+}
+
+/// A wav file's samples, played out a buffer at a time as if
+/// they were coming in from a device
+#[derive(Debug, Clone)]
+pub struct WavSource {
+    /// every sample in the file, interleaved, as -1 to 1
+    samples: Vec<f32>,
+    channels: u16,
+    sample_rate: u32,
+    /// the next sample to play
+    position: usize,
+    /// start again from the top at the end of the file
+    looping: bool,
+}
+
+impl WavSource {
+    /// Reads all of `path` into memory, converting integer
+    /// samples to floats
+    pub fn open(
+        path: &Path,
+        looping: bool,
+    ) -> Result<Self, WavError> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader
+                .samples::<f32>()
+                .collect::<Result<Vec<_>, _>>()?,
+            hound::SampleFormat::Int => {
+                // full scale for the file's bit depth
+                let scale = (1i64
+                    << (spec.bits_per_sample - 1))
+                    as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| {
+                        sample.map(|sample| {
+                            sample as f32 / scale
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        Self::from_samples(
+            samples,
+            spec.channels,
+            spec.sample_rate,
+            looping,
+        )
+    }
+
+    /// A source for interleaved `samples` already in memory
+    pub fn from_samples(
+        samples: Vec<f32>,
+        channels: u16,
+        sample_rate: u32,
+        looping: bool,
+    ) -> Result<Self, WavError> {
+        let channels = channels.max(1);
+        // a trailing partial frame would put every loop
+        // after the first out of step with the channels
+        let whole = samples.len()
+            - samples.len() % channels as usize;
+        if whole == 0 {
+            return Err(WavError::Empty);
+        }
+        let mut samples = samples;
+        samples.truncate(whole);
+        Ok(WavSource {
+            samples,
+            channels,
+            sample_rate,
+            position: 0,
+            looping,
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The next `frames` frames of every channel,
+    /// interleaved.
+    ///
+    /// When looping, the end of the file runs straight on
+    /// into its start within the same buffer, so there's no
+    /// gap at the loop point, and a file shorter than a
+    /// buffer repeats as many times as it takes to fill it.
+    /// Without looping the last buffer is whatever's left,
+    /// and after that there's `None`.
+    pub fn next_buffer(
+        &mut self,
+        frames: usize,
+    ) -> Option<Vec<f32>> {
+        let wanted = frames * self.channels as usize;
+        let mut buffer = Vec::with_capacity(wanted);
+        while buffer.len() < wanted {
+            if self.position == self.samples.len() {
+                if !self.looping {
+                    break;
+                }
+                self.position = 0;
+            }
+            let take = (wanted - buffer.len())
+                .min(self.samples.len() - self.position);
+            buffer.extend_from_slice(
+                &self.samples
+                    [self.position..self.position + take],
+            );
+            self.position += take;
+        }
+        (!buffer.is_empty()).then_some(buffer)
+    }
+}
+
+/// Feeds `source` through the same analysis pipeline as
+/// real input, in real time, one fft's worth of samples per
+/// buffer, until the file runs out (never, when looping) or
+/// the receiver goes away.
+pub fn run(
+    mut source: WavSource,
+    audio_config: AudioConfig,
+    tx: Sender<AnalysisFrame>,
+) -> thread::JoinHandle<()> {
+    let sample_rate = source.sample_rate();
+    let pipeline = audio_config.pipeline(
+        audio_config
+            .analyzer(sample_rate)
+            .with_channels(source.channels()),
+        Arc::new(AtomicU32::new(sample_rate)),
+        tx,
+    );
+    // one fft's worth of samples across all the channels,
+    // like a device buffer the fft size long
+    let frames = (pipeline.fft_size()
+        / source.channels() as usize)
+        .max(1);
+    let (handoff, analysis_thread) = handoff(
+        pipeline,
+        audio_config.analysis_location,
+        audio_config.queue,
+    );
+    let interval = Duration::from_secs_f32(
+        frames as f32 / sample_rate.max(1) as f32,
+    );
+    thread::spawn(move || {
+        while !handoff.is_closed()
+            && !analysis_thread.as_ref().is_some_and(
+                thread::JoinHandle::is_finished,
+            )
+        {
+            let Some(buffer) = source.next_buffer(frames)
+            else {
+                break;
+            };
+            handoff.push(buffer);
+            thread::sleep(interval);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looping_runs_the_end_into_the_start() {
+        // three stereo frames
+        let samples = vec![1.0, -1.0, 2.0, -2.0, 3.0, -3.0];
+        let mut looping = WavSource::from_samples(
+            samples.clone(),
+            2,
+            8000,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            looping.next_buffer(2).unwrap(),
+            [1.0, -1.0, 2.0, -2.0]
+        );
+        // past the end, straight back round in the same
+        // buffer
+        assert_eq!(
+            looping.next_buffer(2).unwrap(),
+            [3.0, -3.0, 1.0, -1.0]
+        );
+        // and a file shorter than a buffer repeats to fill it
+        assert_eq!(
+            looping.next_buffer(5).unwrap(),
+            [
+                2.0, -2.0, 3.0, -3.0, 1.0, -1.0, 2.0, -2.0,
+                3.0, -3.0
+            ]
+        );
+        for _ in 0..100 {
+            assert_eq!(
+                looping.next_buffer(4).unwrap().len(),
+                8
+            );
+        }
+
+        let mut once = WavSource::from_samples(
+            samples, 2, 8000, false,
+        )
+        .unwrap();
+        assert_eq!(once.next_buffer(2).unwrap().len(), 4);
+        assert_eq!(
+            once.next_buffer(2).unwrap(),
+            [3.0, -3.0]
+        );
+        assert_eq!(once.next_buffer(2), None);
+
+        // a partial frame is all there is
+        assert!(matches!(
+            WavSource::from_samples(
+                vec![0.5],
+                2,
+                8000,
+                true
+            ),
+            Err(WavError::Empty)
+        ));
+    }
+}
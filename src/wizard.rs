@@ -0,0 +1,289 @@
+/// Readings each measurement collects, about three seconds
+/// at 48kHz with the default fft size
+pub const MEASURE_READINGS: usize = 150;
+
+/// How far above the measured noise floor the gate sits, so
+/// the room on a slightly noisier day still counts as quiet
+pub const GATE_MARGIN_DB: f32 = 3.0;
+
+/// What the wizard works out from the two measurements, in
+/// the same units as the levels it was fed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    /// the level silence stays under nine times out of ten
+    pub noise_floor_db: f32,
+    /// anything below this is treated as silence
+    pub gate_db: f32,
+    /// anything at or above this counts as talking
+    pub talking_threshold_db: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WizardStep {
+    /// waiting for the room to go quiet
+    SilencePrompt,
+    MeasuringSilence {
+        levels: Vec<f32>,
+    },
+    /// waiting for the user to start talking. `retry` is
+    /// set when the last attempt wasn't any louder than
+    /// the silence.
+    SpeechPrompt {
+        silence: Vec<f32>,
+        retry: bool,
+    },
+    MeasuringSpeech {
+        silence: Vec<f32>,
+        levels: Vec<f32>,
+    },
+    /// showing the result, waiting to save or cancel
+    Review(Thresholds),
+}
+
+/// Walks a new user through measuring silence and normal
+/// speech to pick a gate and a talking threshold.
+///
+/// Space moves on from each prompt, the measurements end by
+/// themselves once they have `MEASURE_READINGS` readings.
+#[derive(Debug, Clone)]
+pub struct Wizard {
+    step: WizardStep,
+    /// how many readings of the level history have been
+    /// looked at, so each is only counted once
+    seen: usize,
+}
+
+impl Wizard {
+    /// Starts at the silence prompt. `seen` is the length of
+    /// the level history so far, none of which counts.
+    pub fn new(seen: usize) -> Self {
+        Wizard {
+            step: WizardStep::SilencePrompt,
+            seen,
+        }
+    }
+
+    pub fn step(&self) -> &WizardStep {
+        &self.step
+    }
+
+    /// Space was pressed: start whichever measurement the
+    /// current prompt is for. Does nothing mid measurement
+    /// or at the review.
+    pub fn advance(&mut self) {
+        self.step = match std::mem::replace(
+            &mut self.step,
+            WizardStep::SilencePrompt,
+        ) {
+            WizardStep::SilencePrompt => {
+                WizardStep::MeasuringSilence {
+                    levels: vec![],
+                }
+            }
+            WizardStep::SpeechPrompt {
+                silence, ..
+            } => WizardStep::MeasuringSpeech {
+                silence,
+                levels: vec![],
+            },
+            step => step,
+        };
+    }
+
+    /// Counts whatever of `history`, the full level history,
+    /// hasn't been seen yet towards the measurement in
+    /// progress, moving on when it's done
+    pub fn feed(&mut self, history: &[f32]) {
+        let new = history.get(self.seen..).unwrap_or(&[]);
+        self.seen = history.len();
+        let levels = match &mut self.step {
+            WizardStep::MeasuringSilence { levels }
+            | WizardStep::MeasuringSpeech {
+                levels, ..
+            } => levels,
+            _ => return,
+        };
+        let wanted = MEASURE_READINGS - levels.len();
+        levels.extend(new.iter().take(wanted));
+        if levels.len() < MEASURE_READINGS {
+            return;
+        }
+
+        self.step = match std::mem::replace(
+            &mut self.step,
+            WizardStep::SilencePrompt,
+        ) {
+            WizardStep::MeasuringSilence { levels } => {
+                WizardStep::SpeechPrompt {
+                    silence: levels,
+                    retry: false,
+                }
+            }
+            WizardStep::MeasuringSpeech {
+                silence,
+                levels,
+            } => match thresholds(&silence, &levels) {
+                Some(thresholds) => {
+                    WizardStep::Review(thresholds)
+                }
+                None => WizardStep::SpeechPrompt {
+                    silence,
+                    retry: true,
+                },
+            },
+            step => step,
+        };
+    }
+
+    /// How far through the current measurement it is, 0 to
+    /// 1, or `None` between measurements
+    pub fn progress(&self) -> Option<f32> {
+        match &self.step {
+            WizardStep::MeasuringSilence { levels }
+            | WizardStep::MeasuringSpeech {
+                levels, ..
+            } => Some(
+                levels.len() as f32
+                    / MEASURE_READINGS as f32,
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// The level `fraction` of the readings are at or below,
+/// ignoring silence so deep it reads as -inf
+pub fn percentile(
+    levels: &[f32],
+    fraction: f32,
+) -> Option<f32> {
+    let mut sorted: Vec<f32> = levels
+        .iter()
+        .copied()
+        .filter(|l| l.is_finite())
+        .collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(f32::total_cmp);
+    let index = ((sorted.len() - 1) as f32
+        * fraction.clamp(0.0, 1.0))
+    .round() as usize;
+    Some(sorted[index])
+}
+
+/// Thresholds from the readings taken during silence and
+/// during speech.
+///
+/// The noise floor is the 90th percentile of the silence
+/// and the gate sits `GATE_MARGIN_DB` above it. Speech is
+/// summarised by its median, which skips the pauses
+/// between words without chasing the loudest syllables, and
+/// the talking threshold goes halfway (in dB) between the
+/// gate and that. `None` if the speech wasn't louder than
+/// the gate.
+pub fn thresholds(
+    silence: &[f32],
+    speech: &[f32],
+) -> Option<Thresholds> {
+    let noise_floor_db = percentile(silence, 0.9)?;
+    let gate_db = noise_floor_db + GATE_MARGIN_DB;
+    let speech_db = percentile(speech, 0.5)?;
+    if speech_db <= gate_db {
+        return None;
+    }
+    Some(Thresholds {
+        noise_floor_db,
+        gate_db,
+        talking_threshold_db: (gate_db + speech_db) / 2.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_wizard_measures_silence_then_speech() {
+        // five readings from before the wizard started
+        let mut history = vec![0.0; 5];
+        let mut wizard = Wizard::new(history.len());
+        let mut read = |wizard: &mut Wizard,
+                        level,
+                        count| {
+            history.resize(history.len() + count, level);
+            wizard.feed(&history);
+        };
+        // nothing counts until space is pressed
+        read(&mut wizard, -50.0, 10);
+        assert_eq!(
+            wizard.step(),
+            &WizardStep::SilencePrompt
+        );
+        assert_eq!(wizard.progress(), None);
+
+        wizard.advance();
+        read(&mut wizard, -50.0, MEASURE_READINGS / 2);
+        assert_eq!(wizard.progress(), Some(0.5));
+        // more than it needs, the extra is ignored
+        read(&mut wizard, -50.0, MEASURE_READINGS);
+        assert!(matches!(
+            wizard.step(),
+            WizardStep::SpeechPrompt { retry: false, .. }
+        ));
+
+        // "speech" no louder than the gate has to be redone
+        wizard.advance();
+        read(&mut wizard, -48.0, MEASURE_READINGS);
+        assert!(matches!(
+            wizard.step(),
+            WizardStep::SpeechPrompt { retry: true, .. }
+        ));
+        wizard.advance();
+        read(&mut wizard, -20.0, MEASURE_READINGS);
+        assert_eq!(
+            wizard.step(),
+            &WizardStep::Review(Thresholds {
+                noise_floor_db: -50.0,
+                gate_db: -47.0,
+                talking_threshold_db: -33.5,
+            })
+        );
+        // and the review waits to be saved or cancelled
+        wizard.advance();
+        assert!(matches!(
+            wizard.step(),
+            WizardStep::Review(_)
+        ));
+    }
+
+    #[test]
+    fn thresholds_come_from_the_silence_and_speech_levels()
+    {
+        // the room mostly at -60 with the odd noise, and
+        // dropouts that read as -inf
+        let mut silence = vec![-60.0; 80];
+        silence.extend([-45.0; 10]);
+        silence.extend([-52.0; 10]);
+        silence.extend([f32::NEG_INFINITY; 20]);
+        assert_eq!(percentile(&silence, 0.9), Some(-52.0));
+        // pauses between words and a few shouts
+        let mut speech = vec![-70.0; 30];
+        speech.extend([-24.0; 40]);
+        speech.extend([-6.0; 20]);
+        let thresholds =
+            thresholds(&silence, &speech).unwrap();
+        assert_eq!(thresholds.noise_floor_db, -52.0);
+        assert_eq!(thresholds.gate_db, -49.0);
+        assert_eq!(thresholds.talking_threshold_db, -36.5);
+
+        assert_eq!(
+            percentile(&[f32::NEG_INFINITY], 0.5),
+            None
+        );
+        assert_eq!(
+            super::thresholds(&silence, &[-50.0]),
+            None
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use miette::Diagnostic;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    f64::consts::{PI, TAU},
+    sync::Arc,
+};
+use thiserror::Error;
+
+use crate::analysis::fft_shift;
+
+/// The zoom fft's size unless configured otherwise. At 48kHz
+/// zoomed on 20 to 60Hz that's 0.16Hz bins, filled in a
+/// little over 6 seconds.
+pub const ZOOM_FFT_SIZE: usize = 256;
+
+/// Low pass taps per step of decimation, either side of the
+/// centre tap. More is a sharper cutoff at the band edges.
+const TAPS_PER_FACTOR: usize = 4;
+
+#[derive(Error, Debug, Diagnostic, PartialEq)]
+pub enum ZoomError {
+    #[error(
+        "can't zoom on {bandwidth_hz}Hz around {center_hz}Hz"
+    )]
+    #[diagnostic(
+        code(pngtubers::zoom::band),
+        help(
+            "the bandwidth has to be above 0 and no more \
+             than twice the centre, so the band doesn't \
+             reach below 0Hz"
+        )
+    )]
+    Band { center_hz: f32, bandwidth_hz: f32 },
+    #[error("a zoom fft of {0} can't resolve anything")]
+    #[diagnostic(
+        code(pngtubers::zoom::fft_size),
+        help("use at least 2")
+    )]
+    FftSize(usize),
+}
+
+/// The narrow band a `ZoomAnalyzer` looks at closely, e.g.
+/// 40Hz wide around 40Hz for the sub bass from 20 to 60Hz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoomBand {
+    center_hz: f32,
+    bandwidth_hz: f32,
+    fft_size: usize,
+}
+
+impl ZoomBand {
+    pub fn new(
+        center_hz: f32,
+        bandwidth_hz: f32,
+        fft_size: usize,
+    ) -> Result<Self, ZoomError> {
+        if !(center_hz.is_finite()
+            && bandwidth_hz.is_finite()
+            && bandwidth_hz > 0.0
+            && bandwidth_hz / 2.0 <= center_hz)
+        {
+            return Err(ZoomError::Band {
+                center_hz,
+                bandwidth_hz,
+            });
+        }
+        if fft_size < 2 {
+            return Err(ZoomError::FftSize(fft_size));
+        }
+        Ok(ZoomBand {
+            center_hz,
+            bandwidth_hz,
+            fft_size,
+        })
+    }
+
+    pub fn center_hz(&self) -> f32 {
+        self.center_hz
+    }
+
+    pub fn bandwidth_hz(&self) -> f32 {
+        self.bandwidth_hz
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    /// How many input samples go into each decimated one at
+    /// `sample_rate`: as many as leaves at least the
+    /// bandwidth
+    pub fn decimation(&self, sample_rate: u32) -> usize {
+        ((sample_rate as f32 / self.bandwidth_hz) as usize)
+            .max(1)
+    }
+}
+
+/// A zoomed in slice of the spectrum
+#[derive(
+    Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+pub struct ZoomSpectrum {
+    /// the frequency of the first bin
+    pub low_hz: f32,
+    /// how far apart the bins are
+    pub resolution_hz: f32,
+    /// fft magnitude of each bin, lowest first, scaled the
+    /// same as a plain fft of the zoom's size
+    pub magnitudes: Vec<f32>,
+}
+
+impl ZoomSpectrum {
+    /// The frequency of `bin`
+    pub fn bin_hz(&self, bin: usize) -> f32 {
+        self.low_hz + bin as f32 * self.resolution_hz
+    }
+
+    /// The frequency of the strongest bin, `None` before
+    /// there's anything there
+    pub fn peak_hz(&self) -> Option<f32> {
+        self.magnitudes
+            .iter()
+            .enumerate()
+            .filter(|(_, magnitude)| **magnitude > 0.0)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(bin, _)| self.bin_hz(bin))
+    }
+}
+
+/// A Hann windowed sinc low pass cutting off at half of
+/// `1 / decimation` of the sample rate, with unity gain at
+/// 0Hz
+fn low_pass(decimation: usize) -> Vec<f32> {
+    let half = TAPS_PER_FACTOR * decimation;
+    let cutoff = 0.5 / decimation as f64;
+    let taps: Vec<f64> = (0..=2 * half)
+        .map(|tap| {
+            let n = tap as f64 - half as f64;
+            let sinc = if n == 0.0 {
+                2.0 * cutoff
+            } else {
+                (TAU * cutoff * n).sin() / (PI * n)
+            };
+            let hann = 0.5
+                - 0.5
+                    * (TAU * tap as f64
+                        / (2 * half) as f64)
+                        .cos();
+            sinc * hann
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    taps.iter().map(|tap| (tap / sum) as f32).collect()
+}
+
+/// A zoom fft: resolves a narrow band far more finely than
+/// an fft of the same size over the whole spectrum could.
+///
+/// The input is shifted down so the band's centre sits at
+/// 0Hz, low passed to the band and decimated, and only then
+/// goes through a small complex fft. The bins are the
+/// decimated rate over the fft size apart, rather than the
+/// full sample rate over it, at the cost of the fft taking
+/// the decimation times longer to fill.
+pub struct ZoomAnalyzer {
+    band: ZoomBand,
+    fft: Arc<dyn Fft<f32>>,
+    sample_rate: u32,
+    decimation: usize,
+    taps: Vec<f32>,
+    /// where the oscillator shifting the band down is, in
+    /// cycles
+    phase: f64,
+    /// the shifted input the low pass runs over, newest
+    /// last
+    history: VecDeque<Complex<f32>>,
+    /// input samples until the next decimated one
+    countdown: usize,
+    /// the last `band.fft_size()` decimated samples, oldest
+    /// first
+    ring: VecDeque<Complex<f32>>,
+}
+
+impl ZoomAnalyzer {
+    pub fn new(band: ZoomBand, sample_rate: u32) -> Self {
+        let fft = FftPlanner::new()
+            .plan_fft_forward(band.fft_size());
+        let mut zoom = ZoomAnalyzer {
+            band,
+            fft,
+            sample_rate,
+            decimation: 1,
+            taps: vec![],
+            phase: 0.0,
+            history: VecDeque::new(),
+            countdown: 0,
+            ring: VecDeque::new(),
+        };
+        zoom.set_sample_rate(sample_rate);
+        zoom
+    }
+
+    pub fn band(&self) -> &ZoomBand {
+        &self.band
+    }
+
+    /// Starts over at `sample_rate`, which changes the
+    /// decimation and the filter
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.decimation = self.band.decimation(sample_rate);
+        self.taps = low_pass(self.decimation);
+        self.phase = 0.0;
+        // silence until enough input has arrived
+        self.history =
+            VecDeque::from(vec![
+                Complex::new(0.0, 0.0);
+                self.taps.len()
+            ]);
+        self.countdown = self.decimation;
+        self.ring =
+            VecDeque::from(vec![
+                Complex::new(0.0, 0.0);
+                self.band.fft_size()
+            ]);
+    }
+
+    /// The rate of the decimated samples, which the zoomed
+    /// spectrum spans
+    pub fn decimated_rate(&self) -> f32 {
+        self.sample_rate as f32 / self.decimation as f32
+    }
+
+    /// How far apart the zoomed spectrum's bins are
+    pub fn resolution_hz(&self) -> f32 {
+        self.decimated_rate() / self.band.fft_size() as f32
+    }
+
+    /// Shifts, filters and decimates `samples` into the
+    /// ring buffer
+    pub fn push(&mut self, samples: &[f32]) {
+        let step = self.band.center_hz() as f64
+            / self.sample_rate.max(1) as f64;
+        for sample in samples {
+            let (sin, cos) = (TAU * self.phase).sin_cos();
+            self.phase = (self.phase + step).fract();
+            self.history.pop_front();
+            self.history.push_back(
+                Complex::new(cos as f32, -sin as f32)
+                    * *sample,
+            );
+            self.countdown -= 1;
+            if self.countdown > 0 {
+                continue;
+            }
+            self.countdown = self.decimation;
+            let filtered = self
+                .history
+                .iter()
+                .zip(&self.taps)
+                .map(|(value, tap)| value * tap)
+                .sum();
+            self.ring.pop_front();
+            self.ring.push_back(filtered);
+        }
+    }
+
+    /// The zoomed spectrum of the samples pushed so far,
+    /// centred on the band
+    pub fn spectrum(&self) -> ZoomSpectrum {
+        let mut buffer: Vec<Complex<f32>> =
+            self.ring.iter().copied().collect();
+        self.fft.process(&mut buffer);
+        let magnitudes: Vec<f32> = buffer
+            .iter()
+            .map(|value| value.norm())
+            .collect();
+        let size = self.band.fft_size();
+        let resolution_hz = self.resolution_hz();
+        ZoomSpectrum {
+            // after the shift the bin at 0Hz is half way
+            // along, on the centre
+            low_hz: self.band.center_hz()
+                - (size / 2) as f32 * resolution_hz,
+            resolution_hz,
+            magnitudes: fft_shift(&magnitudes),
+        }
+    }
+
+    /// `push` then `spectrum`
+    pub fn process(
+        &mut self,
+        samples: &[f32],
+    ) -> ZoomSpectrum {
+        self.push(samples);
+        self.spectrum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seven seconds of the sum of sines at `hz`, long enough
+    /// to fill the zoom fft at 48kHz
+    fn tones(hz: &[f32]) -> Vec<f32> {
+        (0..7 * 48000)
+            .map(|i| {
+                hz.iter()
+                    .map(|hz| {
+                        (TAU * *hz as f64 * i as f64
+                            / 48000.0)
+                            .sin()
+                            as f32
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_low_tone_is_resolved_finer_than_a_plain_fft() {
+        let band = ZoomBand::new(40.0, 40.0, 256).unwrap();
+        let mut zoom = ZoomAnalyzer::new(band, 48000);
+        // a plain 256 point fft's bins are 187.5Hz apart
+        let plain = 48000.0 / 256.0;
+        assert!(zoom.resolution_hz() < plain / 1000.0);
+
+        let spectrum = zoom.process(&tones(&[43.3]));
+        let peak = spectrum.peak_hz().unwrap();
+        assert!(
+            (peak - 43.3).abs() <= spectrum.resolution_hz,
+            "{peak}"
+        );
+
+        // two tones that would share a plain fft's first bin
+        // come out as two peaks with a dip between
+        let mut zoom = ZoomAnalyzer::new(band, 48000);
+        let spectrum = zoom.process(&tones(&[35.0, 45.0]));
+        let at = |hz: f32| {
+            let bin = ((hz - spectrum.low_hz)
+                / spectrum.resolution_hz)
+                .round() as usize;
+            spectrum.magnitudes[bin]
+        };
+        assert!(at(35.0) > 10.0 * at(40.0));
+        assert!(at(45.0) > 10.0 * at(40.0));
+    }
+}